@@ -0,0 +1,73 @@
+//! Embeds the git revision, build time, compiler and enabled features of the build,
+//! read back by `src/build_info.rs`, and generates the gRPC service of `src/grpc.rs`
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+use std::time::SystemTime;
+
+fn main() {
+    // Builds without a checkout (e.g. Docker) pass the revision in `GIT_SHA`
+    let git_sha = env::var("GIT_SHA")
+        .ok()
+        .filter(|sha| !sha.trim().is_empty())
+        .or_else(|| output_of("git", &["rev-parse", "--short=12", "HEAD"]))
+        .unwrap_or_else(|| "unknown".to_string());
+
+    // `SOURCE_DATE_EPOCH` keeps reproducible builds reproducible
+    let timestamp = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.trim().parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or_default()
+        });
+
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = output_of(&rustc, &["--version"]).unwrap_or_else(|| "unknown".to_string());
+
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(key, _)| {
+            let feature = key.strip_prefix("CARGO_FEATURE_")?;
+            Some(feature.to_ascii_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+
+    println!("cargo:rustc-env=BUILD_GIT_SHA={}", git_sha);
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", timestamp);
+    println!("cargo:rustc-env=BUILD_RUSTC_VERSION={}", rustc_version);
+    println!("cargo:rustc-env=BUILD_FEATURES={}", features.join(","));
+
+    println!("cargo:rerun-if-env-changed=GIT_SHA");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "grpc")]
+    compile_protos();
+    // A missing path would rerun the script on every build
+    for path in [".git/HEAD", ".git/refs/heads", ".git/packed-refs"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+}
+
+/// Compiles the proto definitions with protox, so builds don't need `protoc`
+#[cfg(feature = "grpc")]
+fn compile_protos() {
+    const PROTO: &str = "proto/libreoffice_rest.proto";
+    let descriptors = protox::compile([PROTO], ["proto"])
+        .unwrap_or_else(|e| panic!("Cannot compile {}: {}", PROTO, e));
+    tonic_build::configure()
+        .compile_fds(descriptors)
+        .unwrap_or_else(|e| panic!("Cannot generate the gRPC service: {}", e));
+    println!("cargo:rerun-if-changed={}", PROTO);
+}
+
+fn output_of(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    Some(stdout.trim().to_string()).filter(|stdout| output.status.success() && !stdout.is_empty())
+}
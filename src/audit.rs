@@ -0,0 +1,435 @@
+//! Audit trail of conversions: one structured record per conversion request, handed
+//! to the sink configured with `AUDIT_SINK`. Records identify the caller and the
+//! document by its filename hash, never by content. The periodic usage export of
+//! [`crate::usage`] is written to the same sink.
+
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    checksum,
+    config::{AuditSinkKind, Config},
+    error::{LibreOfficeError, Result},
+    usage::UsageRecord,
+};
+
+/// One conversion as written to the audit trail, one JSON object per line
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuditRecord {
+    /// RFC 3339 time in UTC the conversion finished
+    pub timestamp: String,
+    pub request_id: String,
+    /// Label of the API key the request authenticated with, unset while the
+    /// service has no authentication
+    pub api_key: Option<String>,
+    /// Peer address, unset on unix sockets
+    pub client_ip: Option<IpAddr>,
+    /// Hex SHA-256 of the uploaded filename
+    pub filename_sha256: String,
+    pub input_format: String,
+    pub output_format: String,
+    pub input_bytes: u64,
+    /// Size of the primary output, unset when the conversion failed
+    pub output_bytes: Option<u64>,
+    pub duration_ms: u64,
+    /// `success` or the error code
+    pub outcome: &'static str,
+}
+
+impl AuditRecord {
+    /// Record of a conversion of `filename` finishing now, for the current request.
+    /// Sizes, duration and outcome are filled in by the caller.
+    pub fn new(
+        client_ip: Option<IpAddr>,
+        filename: &str,
+        input_format: &str,
+        output_format: &str,
+    ) -> Self {
+        Self {
+            timestamp: rfc3339(SystemTime::now()),
+            request_id: crate::request_id::current().unwrap_or_default(),
+            api_key: None,
+            client_ip,
+            filename_sha256: checksum::hex(&Sha256::digest(filename.as_bytes())),
+            input_format: input_format.to_string(),
+            output_format: output_format.to_string(),
+            input_bytes: 0,
+            output_bytes: None,
+            duration_ms: 0,
+            outcome: "success",
+        }
+    }
+}
+
+/// Destination of audit records
+pub trait AuditSink: Send + Sync {
+    /// Writes the record durably enough to count as audited, called off the async
+    /// runtime
+    fn write(&self, record: &AuditRecord) -> io::Result<()>;
+
+    /// Writes the usage of an API key over an export interval
+    fn write_usage(&self, record: &UsageRecord) -> io::Result<()>;
+}
+
+/// Writes each record as a JSON line to standard output
+pub struct StdoutSink;
+
+impl StdoutSink {
+    fn write_line(&self, record: &impl Serialize) -> io::Result<()> {
+        let mut stdout = io::stdout().lock();
+        serde_json::to_writer(&mut stdout, record)?;
+        stdout.write_all(b"\n")?;
+        stdout.flush()
+    }
+}
+
+impl AuditSink for StdoutSink {
+    fn write(&self, record: &AuditRecord) -> io::Result<()> {
+        self.write_line(record)
+    }
+
+    fn write_usage(&self, record: &UsageRecord) -> io::Result<()> {
+        self.write_line(record)
+    }
+}
+
+/// Appends records to a JSONL file, moving it to `{path}.1` once it would grow
+/// beyond `max_bytes` and keeping `max_files` rotated files
+pub struct JsonlFileSink {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: u32,
+    /// Open file and its length, opened on the first write
+    file: Mutex<Option<(File, u64)>>,
+}
+
+impl JsonlFileSink {
+    pub fn new(path: PathBuf, max_bytes: u64, max_files: u32) -> Self {
+        Self {
+            path,
+            max_bytes,
+            max_files,
+            file: Mutex::new(None),
+        }
+    }
+
+    fn open(&self) -> io::Result<(File, u64)> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        let len = file.metadata()?.len();
+        Ok((file, len))
+    }
+
+    /// Shifts `{path}.N` to `{path}.N+1`, dropping the oldest, and moves the
+    /// current file to `{path}.1`
+    fn rotate(&self) -> io::Result<()> {
+        if self.max_files == 0 {
+            return std::fs::remove_file(&self.path);
+        }
+        for n in (1..self.max_files).rev() {
+            match std::fs::rename(rotated(&self.path, n), rotated(&self.path, n + 1)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        std::fs::rename(&self.path, rotated(&self.path, 1))
+    }
+
+    fn write_line(&self, record: &impl Serialize) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+
+        let mut file = self.file.lock().unwrap();
+        let (_, len) = match &mut *file {
+            Some(open) => open,
+            None => file.insert(self.open()?),
+        };
+        if *len > 0 && *len + line.len() as u64 > self.max_bytes {
+            *file = None;
+            self.rotate()?;
+        }
+
+        let (file, len) = match &mut *file {
+            Some(open) => open,
+            None => file.insert(self.open()?),
+        };
+        file.write_all(&line)?;
+        *len += line.len() as u64;
+        Ok(())
+    }
+}
+
+impl AuditSink for JsonlFileSink {
+    fn write(&self, record: &AuditRecord) -> io::Result<()> {
+        self.write_line(record)
+    }
+
+    fn write_usage(&self, record: &UsageRecord) -> io::Result<()> {
+        self.write_line(record)
+    }
+}
+
+fn rotated(path: &Path, n: u32) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+/// What happens to a conversion whose record can't be written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureMode {
+    /// The response goes out regardless, the failure is logged and counted
+    Open,
+    /// The response is replaced by an `audit_failed` error
+    Closed,
+}
+
+/// Hands records to the sink, applying the failure mode
+pub struct Auditor {
+    sink: Arc<dyn AuditSink>,
+    failure_mode: FailureMode,
+}
+
+impl Auditor {
+    pub fn new(sink: Arc<dyn AuditSink>, failure_mode: FailureMode) -> Self {
+        Self { sink, failure_mode }
+    }
+
+    /// Auditor of the configured sink, none when auditing is off
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let sink: Arc<dyn AuditSink> = match config.audit_sink? {
+            AuditSinkKind::Stdout => Arc::new(StdoutSink),
+            AuditSinkKind::File => Arc::new(JsonlFileSink::new(
+                config.audit_log_path.clone(),
+                config.audit_log_max_bytes,
+                config.audit_log_max_files,
+            )),
+        };
+        let failure_mode = if config.audit_fail_closed {
+            FailureMode::Closed
+        } else {
+            FailureMode::Open
+        };
+        Some(Self::new(sink, failure_mode))
+    }
+
+    /// Writes the record, failing only when audit failures fail closed
+    pub async fn record(&self, record: AuditRecord) -> Result<()> {
+        let sink = self.sink.clone();
+        let written = tokio::task::spawn_blocking(move || sink.write(&record))
+            .await
+            .unwrap_or_else(|e| Err(io::Error::other(e)));
+
+        let Err(e) = written else {
+            return Ok(());
+        };
+        metrics::counter!("audit_write_failures_total").increment(1);
+        tracing::error!("Could not write audit record: {}", e);
+        match self.failure_mode {
+            FailureMode::Open => Ok(()),
+            FailureMode::Closed => Err(LibreOfficeError::AuditFailed(e.to_string())),
+        }
+    }
+
+    /// Writes a usage record, failures are only logged and counted as no response
+    /// waits for it
+    pub async fn record_usage(&self, record: UsageRecord) {
+        let sink = self.sink.clone();
+        let written = tokio::task::spawn_blocking(move || sink.write_usage(&record))
+            .await
+            .unwrap_or_else(|e| Err(io::Error::other(e)));
+        if let Err(e) = written {
+            metrics::counter!("audit_write_failures_total").increment(1);
+            tracing::error!("Could not write usage record: {}", e);
+        }
+    }
+}
+
+/// `time` as RFC 3339 in UTC with milliseconds
+pub fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let seconds_of_day = secs % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60,
+        seconds_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Gregorian date of a day count since 1970-01-01, after Howard Hinnant's
+/// `civil_from_days`
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+pub mod fake {
+    use super::*;
+
+    /// Sink keeping records in memory, or failing every write
+    #[derive(Default)]
+    pub struct MemorySink {
+        pub records: Mutex<Vec<AuditRecord>>,
+        pub usage: Mutex<Vec<UsageRecord>>,
+        pub failing: bool,
+    }
+
+    impl AuditSink for MemorySink {
+        fn write(&self, record: &AuditRecord) -> io::Result<()> {
+            if self.failing {
+                return Err(io::Error::other("audit disk gone"));
+            }
+            self.records.lock().unwrap().push(record.clone());
+            Ok(())
+        }
+
+        fn write_usage(&self, record: &UsageRecord) -> io::Result<()> {
+            if self.failing {
+                return Err(io::Error::other("audit disk gone"));
+            }
+            self.usage.lock().unwrap().push(record.clone());
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fake::MemorySink;
+    use super::*;
+    use std::time::Duration;
+
+    fn record() -> AuditRecord {
+        AuditRecord {
+            timestamp: "2024-02-29T13:05:09.042Z".to_string(),
+            request_id: "req-1".to_string(),
+            api_key: None,
+            client_ip: Some("192.0.2.7".parse().unwrap()),
+            filename_sha256: "ab".repeat(32),
+            input_format: "docx".to_string(),
+            output_format: "pdf".to_string(),
+            input_bytes: 1024,
+            output_bytes: Some(2048),
+            duration_ms: 350,
+            outcome: "success",
+        }
+    }
+
+    #[test]
+    fn test_record_format() {
+        assert_eq!(
+            serde_json::to_value(record()).unwrap(),
+            serde_json::json!({
+                "timestamp": "2024-02-29T13:05:09.042Z",
+                "request_id": "req-1",
+                "api_key": null,
+                "client_ip": "192.0.2.7",
+                "filename_sha256": "ab".repeat(32),
+                "input_format": "docx",
+                "output_format": "pdf",
+                "input_bytes": 1024,
+                "output_bytes": 2048,
+                "duration_ms": 350,
+                "outcome": "success",
+            })
+        );
+
+        let failed = AuditRecord {
+            outcome: "timeout",
+            ..AuditRecord::new(
+                Some("2001:db8::1".parse().unwrap()),
+                "report.docx",
+                "docx",
+                "pdf",
+            )
+        };
+        let value = serde_json::to_value(&failed).unwrap();
+        assert_eq!(value["client_ip"], "2001:db8::1");
+        assert_eq!(value["output_bytes"], serde_json::Value::Null);
+        assert_eq!(
+            value["filename_sha256"],
+            "0eb2ecf5593ce953b3a0ab34b8c7fea3809bb239e2ea27bd81b5ed40e4cbb6d1"
+        );
+    }
+
+    #[test]
+    fn test_rfc3339() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_millis(1_709_211_909_042);
+        assert_eq!(rfc3339(time), "2024-02-29T13:05:09.042Z");
+        assert_eq!(rfc3339(SystemTime::UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn test_file_sink_rotates() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let line_len = serde_json::to_vec(&record()).unwrap().len() as u64 + 1;
+        let sink = JsonlFileSink::new(path.clone(), 2 * line_len, 2);
+
+        for _ in 0..7 {
+            sink.write(&record()).unwrap();
+        }
+
+        let lines = |path: &Path| std::fs::read_to_string(path).unwrap().lines().count();
+        assert_eq!(lines(&path), 1);
+        assert_eq!(lines(&rotated(&path, 1)), 2);
+        assert_eq!(lines(&rotated(&path, 2)), 2);
+        assert!(!rotated(&path, 3).exists());
+        let line = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&line).unwrap(),
+            serde_json::to_value(record()).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failure_modes() {
+        let failing = Arc::new(MemorySink {
+            failing: true,
+            ..MemorySink::default()
+        });
+
+        let open = Auditor::new(failing.clone(), FailureMode::Open);
+        assert!(open.record(record()).await.is_ok());
+
+        let closed = Auditor::new(failing, FailureMode::Closed);
+        assert!(matches!(
+            closed.record(record()).await,
+            Err(LibreOfficeError::AuditFailed(_))
+        ));
+
+        let sink = Arc::new(MemorySink::default());
+        let auditor = Auditor::new(sink.clone(), FailureMode::Closed);
+        auditor.record(record()).await.unwrap();
+        assert_eq!(*sink.records.lock().unwrap(), [record()]);
+    }
+}
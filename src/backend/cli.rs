@@ -0,0 +1,65 @@
+//! Backend spawning `soffice --convert-to` per conversion
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use super::ConversionBackend;
+use crate::{
+    config::Config,
+    converter::ConversionOptions,
+    error::{LibreOfficeError, Result},
+    formats::{InputFormat, OutputFormat},
+    libreoffice::{self, ConversionOutput},
+};
+
+/// Spawns a headless LibreOffice process per conversion
+pub struct CliBackend {
+    config: Arc<Config>,
+    /// LibreOffice executable of the config, resolved once
+    program: Option<PathBuf>,
+}
+
+impl CliBackend {
+    /// Backend running the LibreOffice executable of `config`
+    pub fn new(config: Arc<Config>) -> Self {
+        let program = libreoffice::find_libreoffice(&config);
+        Self { config, program }
+    }
+}
+
+#[async_trait]
+impl ConversionBackend for CliBackend {
+    fn name(&self) -> &'static str {
+        "cli"
+    }
+
+    fn is_available(&self) -> bool {
+        self.program.is_some()
+    }
+
+    async fn convert(
+        &self,
+        input_path: &Path,
+        output_dir: &Path,
+        from: &InputFormat,
+        to: &OutputFormat,
+        options: &ConversionOptions,
+    ) -> Result<ConversionOutput> {
+        let program = self
+            .program
+            .as_deref()
+            .ok_or(LibreOfficeError::BinaryNotFound)?;
+        libreoffice::convert_file(
+            &self.config,
+            program,
+            input_path,
+            output_dir,
+            from,
+            to,
+            options,
+        )
+        .await
+    }
+}
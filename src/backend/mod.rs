@@ -0,0 +1,260 @@
+//! Engines running LibreOffice, tried in the order of `CONVERSION_BACKENDS`
+
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+
+use async_trait::async_trait;
+
+use crate::{
+    config::{self, BackendKind, Config},
+    converter::ConversionOptions,
+    error::Result,
+    formats::{InputFormat, OutputFormat},
+    libreoffice::ConversionOutput,
+};
+
+pub mod cli;
+pub mod unoserver;
+
+/// Engine performing the actual document conversion
+#[async_trait]
+pub trait ConversionBackend: Send + Sync {
+    /// Identifier used in /version and metric labels
+    fn name(&self) -> &'static str;
+
+    /// Whether the backend's executables or libraries can be found
+    fn is_available(&self) -> bool;
+
+    /// Converts `input_path` (named `document.{from}`) to `to`, using `output_dir` as scratch space
+    async fn convert(
+        &self,
+        input_path: &Path,
+        output_dir: &Path,
+        from: &InputFormat,
+        to: &OutputFormat,
+        options: &ConversionOptions,
+    ) -> Result<ConversionOutput>;
+}
+
+/// Configured backends in order of preference
+pub struct BackendChain {
+    backends: Vec<Box<dyn ConversionBackend>>,
+}
+
+impl BackendChain {
+    /// Chain of the given backends, which must not be empty
+    pub fn new(backends: Vec<Box<dyn ConversionBackend>>) -> Self {
+        assert!(!backends.is_empty(), "backend chain is never empty");
+        Self { backends }
+    }
+
+    /// Chain of the backends named by `CONVERSION_BACKENDS` of `config`, which
+    /// they run with
+    pub fn from_config(config: Arc<Config>) -> Self {
+        let backends = config
+            .backends
+            .iter()
+            .map(|kind| -> Box<dyn ConversionBackend> {
+                match kind {
+                    BackendKind::Cli => Box::new(cli::CliBackend::new(config.clone())),
+                    BackendKind::Unoserver => {
+                        Box::new(unoserver::UnoserverBackend::new(config.clone()))
+                    }
+                }
+            })
+            .collect();
+
+        Self { backends }
+    }
+
+    /// Backends in order of preference
+    pub fn backends(&self) -> impl Iterator<Item = &dyn ConversionBackend> {
+        self.backends.iter().map(|backend| backend.as_ref())
+    }
+
+    /// Backend tried first
+    pub fn primary(&self) -> &dyn ConversionBackend {
+        self.backends[0].as_ref()
+    }
+
+    /// Converts with the first backend that can run, falling through to the next one
+    /// only when a backend is unavailable. Returns the name of the backend that
+    /// produced the result.
+    pub async fn convert(
+        &self,
+        input_path: &Path,
+        output_dir: &Path,
+        from: &InputFormat,
+        to: &OutputFormat,
+        options: &ConversionOptions,
+    ) -> (Result<ConversionOutput>, &'static str) {
+        let mut backends = self.backends().peekable();
+
+        while let Some(backend) = backends.next() {
+            let result = backend
+                .convert(input_path, output_dir, from, to, options)
+                .await;
+
+            match &result {
+                Err(e) if e.is_backend_unavailable() && backends.peek().is_some() => {
+                    tracing::warn!(
+                        "{} backend unavailable ({}), falling back",
+                        backend.name(),
+                        e
+                    );
+                    metrics::counter!("libreoffice_backend_fallbacks_total", "backend" => backend.name())
+                        .increment(1);
+                }
+                _ => {
+                    tracing::info!("Conversion handled by {} backend", backend.name());
+                    return (result, backend.name());
+                }
+            }
+        }
+
+        unreachable!("backend chain is never empty")
+    }
+}
+
+static CHAIN: OnceLock<Arc<BackendChain>> = OnceLock::new();
+
+/// Returns the backend chain configured by `CONVERSION_BACKENDS`
+pub fn chain() -> &'static Arc<BackendChain> {
+    CHAIN.get_or_init(|| Arc::new(BackendChain::from_config(Arc::new(config::get().clone()))))
+}
+
+/// Backends for tests
+#[cfg(test)]
+pub mod fake {
+    use super::*;
+    use crate::libreoffice::{ConvertedOutput, OutputFile};
+
+    /// Backend answering every conversion with the same PDF
+    pub struct CannedBackend;
+
+    #[async_trait]
+    impl ConversionBackend for CannedBackend {
+        fn name(&self) -> &'static str {
+            "canned"
+        }
+
+        fn is_available(&self) -> bool {
+            true
+        }
+
+        async fn convert(
+            &self,
+            _input_path: &Path,
+            _output_dir: &Path,
+            _from: &InputFormat,
+            _to: &OutputFormat,
+            _options: &ConversionOptions,
+        ) -> Result<ConversionOutput> {
+            Ok(ConversionOutput::new(OutputFile {
+                name: "document.pdf".to_string(),
+                data: ConvertedOutput::Bytes(b"%PDF-1.7 canned".to_vec()),
+            }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::LibreOfficeError;
+    use crate::libreoffice::{ConvertedOutput, OutputFile};
+
+    fn converted() -> Result<ConversionOutput> {
+        Ok(ConversionOutput::new(OutputFile {
+            name: "document.pdf".to_string(),
+            data: ConvertedOutput::Bytes(b"converted".to_vec()),
+        }))
+    }
+
+    struct FakeBackend {
+        name: &'static str,
+        result: fn() -> Result<ConversionOutput>,
+    }
+
+    #[async_trait]
+    impl ConversionBackend for FakeBackend {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn is_available(&self) -> bool {
+            true
+        }
+
+        async fn convert(
+            &self,
+            _input_path: &Path,
+            _output_dir: &Path,
+            _from: &InputFormat,
+            _to: &OutputFormat,
+            _options: &ConversionOptions,
+        ) -> Result<ConversionOutput> {
+            (self.result)()
+        }
+    }
+
+    fn chain_of(backends: Vec<FakeBackend>) -> BackendChain {
+        BackendChain::new(
+            backends
+                .into_iter()
+                .map(|backend| Box::new(backend) as Box<dyn ConversionBackend>)
+                .collect(),
+        )
+    }
+
+    async fn convert(chain: &BackendChain) -> (Result<ConversionOutput>, &'static str) {
+        chain
+            .convert(
+                Path::new("document.txt"),
+                Path::new("."),
+                &"txt".parse().unwrap(),
+                &"pdf".parse().unwrap(),
+                &ConversionOptions::default(),
+            )
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_when_backend_unavailable() {
+        let chain = chain_of(vec![
+            FakeBackend {
+                name: "first",
+                result: || Err(LibreOfficeError::BinaryNotFound),
+            },
+            FakeBackend {
+                name: "second",
+                result: converted,
+            },
+        ]);
+
+        let (result, backend) = convert(&chain).await;
+        assert_eq!(
+            result.unwrap().primary.data.into_bytes().await.unwrap(),
+            b"converted"
+        );
+        assert_eq!(backend, "second");
+    }
+
+    #[tokio::test]
+    async fn test_document_errors_do_not_fall_back() {
+        let chain = chain_of(vec![
+            FakeBackend {
+                name: "first",
+                result: || Err(LibreOfficeError::PasswordProtected),
+            },
+            FakeBackend {
+                name: "second",
+                result: converted,
+            },
+        ]);
+
+        let (result, backend) = convert(&chain).await;
+        assert!(matches!(result, Err(LibreOfficeError::PasswordProtected)));
+        assert_eq!(backend, "first");
+    }
+}
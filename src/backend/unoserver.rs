@@ -0,0 +1,359 @@
+//! Backend converting through a resident unoserver
+
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use tokio::net::TcpStream;
+use tokio::process::{Child, Command as TokioCommand};
+use tokio::sync::Mutex;
+
+use super::ConversionBackend;
+use crate::{
+    config::Config,
+    converter::ConversionOptions,
+    error::{LibreOfficeError, Result},
+    formats::{InputFormat, OutputFormat},
+    libreoffice::{self, ConversionOutput, ConvertedOutput, OutputFile},
+    reaper,
+};
+
+/// How long a freshly started unoserver gets to accept connections
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Interval between connection attempts while unoserver starts
+const HEALTH_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+const UNOSERVER_HOST: &str = "127.0.0.1";
+
+/// A running unoserver, leading the process group of the soffice.bin it starts
+struct Server {
+    child: Child,
+    /// Unset once the group was killed and the leader waited for
+    pgid: Option<u32>,
+}
+
+impl Server {
+    /// Kills unoserver together with its soffice.bin, which would otherwise keep
+    /// the port and the profile locked, and waits for it
+    async fn stop(mut self) {
+        if let Some(pgid) = self.pgid.take() {
+            reaper::kill_group(pgid);
+        }
+        let _ = self.child.wait().await;
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        if let Some(pgid) = self.pgid {
+            reaper::kill_group(pgid);
+        }
+    }
+}
+
+/// Converts through a resident unoserver instance using `unoconvert`
+pub struct UnoserverBackend {
+    config: Arc<Config>,
+    server: Mutex<Option<Server>>,
+}
+
+impl UnoserverBackend {
+    /// Backend starting unoserver on its first conversion
+    pub fn new(config: Arc<Config>) -> Self {
+        Self {
+            config,
+            server: Mutex::new(None),
+        }
+    }
+
+    /// Starts unoserver unless it is already running, and waits until it accepts connections
+    async fn ensure_running(&self) -> Result<()> {
+        let mut server = self.server.lock().await;
+
+        if let Some(running) = server.as_mut() {
+            match running.child.try_wait() {
+                Ok(None) => return Ok(()),
+                Ok(Some(status)) => tracing::warn!("unoserver exited with {}, restarting", status),
+                Err(e) => tracing::warn!("Failed to check unoserver status: {}, restarting", e),
+            }
+        }
+        // soffice.bin may outlive unoserver
+        if let Some(exited) = server.take() {
+            exited.stop().await;
+        }
+
+        let config = &self.config;
+        tracing::info!("Starting unoserver on port {}", config.unoserver_port);
+        let child = TokioCommand::new(&config.unoserver_bin)
+            .args([
+                "--interface",
+                UNOSERVER_HOST,
+                "--port",
+                &config.unoserver_port.to_string(),
+            ])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .process_group(0)
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => LibreOfficeError::BinaryNotFound,
+                _ => LibreOfficeError::from_io(e),
+            })?;
+        *server = Some(Server {
+            pgid: child.id(),
+            child,
+        });
+
+        let started = Instant::now();
+        while started.elapsed() < STARTUP_TIMEOUT {
+            if TcpStream::connect((UNOSERVER_HOST, config.unoserver_port))
+                .await
+                .is_ok()
+            {
+                tracing::info!("unoserver ready after {:?}", started.elapsed());
+                return Ok(());
+            }
+            tokio::time::sleep(HEALTH_POLL_INTERVAL).await;
+        }
+
+        if let Some(unready) = server.take() {
+            unready.stop().await;
+        }
+        Err(LibreOfficeError::BackendUnavailable(
+            "unoserver did not start accepting connections".to_string(),
+        ))
+    }
+
+    /// Kills a hanging unoserver so the next conversion starts a fresh one
+    async fn restart(&self) {
+        if let Some(server) = self.server.lock().await.take() {
+            tracing::warn!("Killing unresponsive unoserver");
+            server.stop().await;
+        }
+    }
+}
+
+#[async_trait]
+impl ConversionBackend for UnoserverBackend {
+    fn name(&self) -> &'static str {
+        "unoserver"
+    }
+
+    fn is_available(&self) -> bool {
+        let config = &self.config;
+        libreoffice::resolve_executable(&config.unoserver_bin).is_some()
+            && libreoffice::resolve_executable(&config.unoconvert_bin).is_some()
+    }
+
+    async fn convert(
+        &self,
+        input_path: &Path,
+        _output_dir: &Path,
+        from: &InputFormat,
+        to: &OutputFormat,
+        options: &ConversionOptions,
+    ) -> Result<ConversionOutput> {
+        self.ensure_running().await?;
+
+        let config = &self.config;
+        let mut child = TokioCommand::new(&config.unoconvert_bin)
+            .args([
+                "--host",
+                UNOSERVER_HOST,
+                "--port",
+                &config.unoserver_port.to_string(),
+                "--convert-to",
+                to.as_str(),
+            ])
+            .args(filter_args(from, to, options))
+            .args(["-", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => LibreOfficeError::BinaryNotFound,
+                _ => LibreOfficeError::from_io(e),
+            })?;
+
+        // Feed the input while collecting the output to avoid pipe deadlocks
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let mut input = tokio::fs::File::open(input_path)
+            .await
+            .map_err(LibreOfficeError::from_io)?;
+        let writer = tokio::spawn(async move { tokio::io::copy(&mut input, &mut stdin).await });
+
+        let output =
+            match tokio::time::timeout(config.conversion_timeout, child.wait_with_output()).await {
+                Ok(Ok(output)) => output,
+                Ok(Err(e)) => return Err(LibreOfficeError::from_io(e)),
+                Err(_) => {
+                    // A conversion exceeding the timeout usually means unoserver hangs
+                    self.restart().await;
+                    return Err(LibreOfficeError::Timeout { queue_wait: None });
+                }
+            };
+        let _ = writer.await;
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        tracing::debug!("unoconvert stderr: {}", stderr);
+
+        if !output.status.success() {
+            return Err(libreoffice::analyze_libreoffice_error(
+                &stderr,
+                "",
+                from.as_str(),
+                to.as_str(),
+            ));
+        }
+
+        if output.stdout.is_empty() {
+            return Err(LibreOfficeError::OutputNotFound);
+        }
+
+        tracing::debug!(
+            "unoserver conversion completed, output size: {} bytes",
+            output.stdout.len()
+        );
+        Ok(ConversionOutput::new(OutputFile {
+            name: format!("document.{}", to),
+            data: ConvertedOutput::Bytes(output.stdout),
+        }))
+    }
+}
+
+/// Import and export filters and the export options for unoconvert, empty unless
+/// options need them
+fn filter_args(from: &InputFormat, to: &OutputFormat, options: &ConversionOptions) -> Vec<String> {
+    let mut args = Vec::new();
+    if let Some(input_filter) = options.input_filter {
+        args.extend(["--input-filter".to_string(), input_filter.to_string()]);
+    }
+
+    let filter_data = options.pdf_filter_data();
+    if to.as_str() != "pdf" || filter_data.is_empty() {
+        if let Some(filter) = &options.output_filter {
+            args.extend(["--filter".to_string(), filter.clone()]);
+        }
+        return args;
+    }
+
+    let filter = options
+        .output_filter
+        .as_deref()
+        .unwrap_or(from.pdf_export_filter());
+    args.extend(["--filter".to_string(), filter.to_string()]);
+    // The flag takes a single name=value and is repeated for every option
+    for (name, value) in filter_data {
+        args.push("--filter-options".to_string());
+        args.push(format!("{}={}", name, value));
+    }
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+
+    #[tokio::test]
+    async fn test_restart_kills_the_process_group() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Stands in for unoserver, with a sleep as the soffice.bin it starts
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("soffice.pid");
+        let program = dir.path().join("unoserver");
+        std::fs::write(
+            &program,
+            format!(
+                "#!/bin/sh
+sleep 300 &
+echo $! > {:?}
+wait
+",
+                pid_file
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let listener = std::net::TcpListener::bind((UNOSERVER_HOST, 0)).unwrap();
+
+        let backend = UnoserverBackend::new(Arc::new(config::Config {
+            unoserver_bin: program.to_string_lossy().to_string(),
+            unoserver_port: listener.local_addr().unwrap().port(),
+            ..config::get().clone()
+        }));
+        backend.ensure_running().await.unwrap();
+        let pgid = backend.server.lock().await.as_ref().unwrap().pgid.unwrap();
+        for _ in 0..100 {
+            if pid_file.exists() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(pid_file.exists(), "fake unoserver should have forked");
+
+        backend.restart().await;
+        assert!(backend.server.lock().await.is_none());
+        let mut alive = true;
+        for _ in 0..100 {
+            alive = reaper::group_alive(pgid);
+            if !alive {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(
+            !alive,
+            "the forked soffice.bin should be killed with unoserver"
+        );
+    }
+
+    #[test]
+    fn test_filter_args() {
+        let options = ConversionOptions {
+            include_comments: Some(true),
+            embed_fonts: Some(true),
+            ..ConversionOptions::default()
+        };
+        let (xlsx, pdf) = ("xlsx".parse().unwrap(), "pdf".parse().unwrap());
+
+        assert_eq!(
+            filter_args(&xlsx, &pdf, &options),
+            [
+                "--filter",
+                "calc_pdf_Export",
+                "--filter-options",
+                "ExportNotes=true",
+                "--filter-options",
+                "EmbedStandardFonts=true"
+            ]
+        );
+        assert!(filter_args(&xlsx, &pdf, &ConversionOptions::default()).is_empty());
+        assert!(filter_args(&xlsx, &"ods".parse().unwrap(), &options).is_empty());
+
+        let options = ConversionOptions {
+            input_filter: Some("Calc MS Excel 2007 XML"),
+            ..ConversionOptions::default()
+        };
+        assert_eq!(
+            filter_args(&xlsx, &"ods".parse().unwrap(), &options),
+            ["--input-filter", "Calc MS Excel 2007 XML"]
+        );
+
+        let options = ConversionOptions {
+            output_filter: Some("Calc MS Excel 2007 XML".to_string()),
+            ..ConversionOptions::default()
+        };
+        assert_eq!(
+            filter_args(&xlsx, &"xlsx".parse().unwrap(), &options),
+            ["--filter", "Calc MS Excel 2007 XML"]
+        );
+    }
+}
@@ -0,0 +1,62 @@
+//! Which build is running, as embedded by `build.rs`
+
+use std::time::{Duration, SystemTime};
+
+use serde::Serialize;
+
+use crate::{audit, config::Config};
+
+#[derive(Debug, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    /// Abbreviated commit the binary was built from, `unknown` outside a checkout
+    pub git_sha: &'static str,
+    pub build_time: String,
+    pub rustc_version: &'static str,
+    pub features: Vec<&'static str>,
+}
+
+pub fn get() -> BuildInfo {
+    let timestamp = env!("BUILD_TIMESTAMP").parse().unwrap_or_default();
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_sha: env!("BUILD_GIT_SHA"),
+        build_time: audit::rfc3339(SystemTime::UNIX_EPOCH + Duration::from_secs(timestamp)),
+        rustc_version: env!("BUILD_RUSTC_VERSION"),
+        features: env!("BUILD_FEATURES")
+            .split(',')
+            .filter(|feature| !feature.is_empty())
+            .collect(),
+    }
+}
+
+/// Logs the build and the effective configuration as one event
+pub fn log_startup(config: &Config) {
+    let build = get();
+    tracing::info!(
+        version = build.version,
+        git_sha = build.git_sha,
+        build_time = build.build_time,
+        rustc_version = build.rustc_version,
+        features = build.features.join(","),
+        config = serde_json::to_string(&config.summary()).unwrap_or_default(),
+        "Starting libreoffice-rest {} ({})",
+        build.version,
+        build.git_sha
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_info() {
+        let build = get();
+        assert_eq!(build.version, env!("CARGO_PKG_VERSION"));
+        assert!(!build.git_sha.is_empty());
+        assert!(build.build_time.ends_with('Z'));
+        assert!(build.rustc_version.starts_with("rustc") || build.rustc_version == "unknown");
+        assert_eq!(build.features.contains(&"clamav"), cfg!(feature = "clamav"));
+    }
+}
@@ -0,0 +1,301 @@
+//! Minimal reader of Compound File Binary (OLE2) containers, the format of legacy
+//! Office documents and encrypted OOXML. Only lists the streams of the root storage
+//! and reads the start of a stream, defensively: corrupt sector numbers and cyclic
+//! chains end up as errors instead of panics or endless loops.
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+pub const SIGNATURE: &[u8] = b"\xD0\xCF\x11\xE0\xA1\xB1\x1A\xE1";
+
+const HEADER_LEN: usize = 512;
+const DIRECTORY_ENTRY_LEN: usize = 128;
+/// DIFAT entries stored in the header itself
+const HEADER_DIFAT_ENTRIES: usize = 109;
+/// Sector numbers above this are special values (end of chain, free, ...)
+const MAX_REGULAR_SECTOR: u32 = 0xFFFF_FFFA;
+const NO_STREAM: u32 = 0xFFFF_FFFF;
+
+const ENTRY_STREAM: u8 = 2;
+const ENTRY_ROOT: u8 = 5;
+
+/// Directory entries read at most, bounding the work spent on hostile files
+const MAX_DIRECTORY_ENTRIES: usize = 4096;
+
+/// A stream or storage of the directory
+#[derive(Debug, Clone)]
+pub struct DirectoryEntry {
+    pub name: String,
+    kind: u8,
+    left: u32,
+    right: u32,
+    child: u32,
+    start: u32,
+    size: u64,
+}
+
+impl DirectoryEntry {
+    pub fn is_stream(&self) -> bool {
+        self.kind == ENTRY_STREAM
+    }
+}
+
+/// An opened compound file
+pub struct CompoundFile<R> {
+    reader: R,
+    len: u64,
+    sector_size: usize,
+    mini_sector_size: usize,
+    mini_stream_cutoff: u64,
+    fat: Vec<u32>,
+    mini_fat: Vec<u32>,
+    entries: Vec<DirectoryEntry>,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("compound file: {}", message),
+    )
+}
+
+fn u16_at(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+impl<R: Read + Seek> CompoundFile<R> {
+    pub fn open(mut reader: R) -> io::Result<Self> {
+        let len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+        let mut header = [0; HEADER_LEN];
+        reader.read_exact(&mut header)?;
+
+        if !header.starts_with(SIGNATURE) {
+            return Err(invalid("bad signature"));
+        }
+
+        let sector_size = match u16_at(&header, 0x1E) {
+            9 => 512,
+            12 => 4096,
+            _ => return Err(invalid("bad sector size")),
+        };
+        let mini_sector_size = match u16_at(&header, 0x20) {
+            6 => 64,
+            _ => return Err(invalid("bad mini sector size")),
+        };
+
+        let mut file = Self {
+            reader,
+            len,
+            sector_size,
+            mini_sector_size,
+            mini_stream_cutoff: u32_at(&header, 0x38) as u64,
+            fat: Vec::new(),
+            mini_fat: Vec::new(),
+            entries: Vec::new(),
+        };
+
+        let fat_sectors = file.difat(&header)?;
+        for sector in fat_sectors {
+            let data = file.read_sector(sector)?;
+            file.fat
+                .extend(data.chunks_exact(4).map(|chunk| u32_at(chunk, 0)));
+        }
+
+        let mini_fat = file.read_chain(u32_at(&header, 0x3C))?;
+        file.mini_fat = mini_fat
+            .chunks_exact(4)
+            .map(|chunk| u32_at(chunk, 0))
+            .collect();
+
+        let directory = file.read_chain(u32_at(&header, 0x30))?;
+        file.entries = directory
+            .chunks_exact(DIRECTORY_ENTRY_LEN)
+            .take(MAX_DIRECTORY_ENTRIES)
+            .map(parse_entry)
+            .collect();
+        if sector_size == 512 {
+            // Version 3 files may leave garbage in the high half of the size
+            for entry in &mut file.entries {
+                entry.size &= 0xFFFF_FFFF;
+            }
+        }
+
+        match file.entries.first() {
+            Some(root) if root.kind == ENTRY_ROOT => Ok(file),
+            _ => Err(invalid("missing root entry")),
+        }
+    }
+
+    /// Sectors holding the FAT, from the header and the DIFAT sector chain
+    fn difat(&mut self, header: &[u8]) -> io::Result<Vec<u32>> {
+        let fat_sector_count = u32_at(header, 0x2C) as usize;
+        if fat_sector_count > self.sector_count() {
+            return Err(invalid("too many FAT sectors"));
+        }
+
+        let mut sectors: Vec<u32> = (0..HEADER_DIFAT_ENTRIES)
+            .map(|i| u32_at(header, 0x4C + i * 4))
+            .collect();
+
+        let mut next = u32_at(header, 0x44);
+        let per_sector = self.sector_size / 4 - 1;
+        while next <= MAX_REGULAR_SECTOR && sectors.len() < fat_sector_count {
+            let data = self.read_sector(next)?;
+            sectors.extend((0..per_sector).map(|i| u32_at(&data, i * 4)));
+            next = u32_at(&data, per_sector * 4);
+        }
+
+        sectors.truncate(fat_sector_count);
+        Ok(sectors)
+    }
+
+    /// Sectors after the header, which takes the place of sector -1
+    fn sector_count(&self) -> usize {
+        (self.len / self.sector_size as u64).saturating_sub(1) as usize
+    }
+
+    fn read_sector(&mut self, sector: u32) -> io::Result<Vec<u8>> {
+        if sector > MAX_REGULAR_SECTOR || sector as usize >= self.sector_count() {
+            return Err(invalid("sector out of range"));
+        }
+
+        let mut data = vec![0; self.sector_size];
+        self.reader.seek(SeekFrom::Start(
+            (sector as u64 + 1) * self.sector_size as u64,
+        ))?;
+        self.reader.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    /// Sector numbers of the chain starting at `start`, rejecting cycles
+    fn chain(table: &[u32], start: u32, limit: usize) -> io::Result<Vec<u32>> {
+        let mut sectors = Vec::new();
+        let mut next = start;
+        while next <= MAX_REGULAR_SECTOR {
+            if sectors.len() >= limit {
+                return Err(invalid("sector chain too long"));
+            }
+            sectors.push(next);
+            next = *table
+                .get(next as usize)
+                .ok_or_else(|| invalid("sector chain leaves the table"))?;
+        }
+        Ok(sectors)
+    }
+
+    fn read_chain(&mut self, start: u32) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        for sector in Self::chain(&self.fat, start, self.sector_count())? {
+            data.extend(self.read_sector(sector)?);
+        }
+        Ok(data)
+    }
+
+    /// Streams directly inside the root storage
+    pub fn root_streams(&self) -> Vec<&DirectoryEntry> {
+        let mut streams = Vec::new();
+        let mut pending = vec![self.entries[0].child];
+        let mut visited = 0;
+
+        // Siblings form a tree through left and right, every entry is visited once
+        while let Some(index) = pending.pop() {
+            let Some(entry) = self.entries.get(index as usize) else {
+                continue;
+            };
+            visited += 1;
+            if visited > self.entries.len() {
+                break;
+            }
+
+            if entry.is_stream() {
+                streams.push(entry);
+            }
+            pending.extend(
+                [entry.left, entry.right]
+                    .into_iter()
+                    .filter(|&i| i != NO_STREAM),
+            );
+        }
+
+        streams
+    }
+
+    /// Up to `max_len` bytes from the start of the root storage stream `name`
+    pub fn read_stream_prefix(
+        &mut self,
+        name: &str,
+        max_len: usize,
+    ) -> io::Result<Option<Vec<u8>>> {
+        let Some(entry) = self
+            .root_streams()
+            .into_iter()
+            .find(|entry| entry.name == name)
+            .cloned()
+        else {
+            return Ok(None);
+        };
+
+        let len = (entry.size.min(max_len as u64)) as usize;
+        let mut data = if entry.size < self.mini_stream_cutoff {
+            self.read_mini_stream(&entry, len)?
+        } else {
+            let sectors_needed = len.div_ceil(self.sector_size);
+            let mut data = Vec::with_capacity(sectors_needed * self.sector_size);
+            let chain = Self::chain(&self.fat, entry.start, self.sector_count())?;
+            for &sector in chain.iter().take(sectors_needed) {
+                data.extend(self.read_sector(sector)?);
+            }
+            data
+        };
+
+        data.truncate(len);
+        Ok(Some(data))
+    }
+
+    /// Reads a small stream, stored in mini sectors inside the root entry's stream
+    fn read_mini_stream(&mut self, entry: &DirectoryEntry, len: usize) -> io::Result<Vec<u8>> {
+        let root = self.entries[0].clone();
+        let mini_sectors = Self::chain(&self.mini_fat, entry.start, self.mini_fat.len())?;
+        let root_sectors = Self::chain(&self.fat, root.start, self.sector_count())?;
+        let per_sector = self.sector_size / self.mini_sector_size;
+
+        let mut data = Vec::with_capacity(len);
+        for mini_sector in mini_sectors {
+            if data.len() >= len {
+                break;
+            }
+            let mini_sector = mini_sector as usize;
+            let sector = *root_sectors
+                .get(mini_sector / per_sector)
+                .ok_or_else(|| invalid("mini sector outside the mini stream"))?;
+            let offset = (mini_sector % per_sector) * self.mini_sector_size;
+            let sector_data = self.read_sector(sector)?;
+            data.extend_from_slice(&sector_data[offset..offset + self.mini_sector_size]);
+        }
+        Ok(data)
+    }
+}
+
+fn parse_entry(raw: &[u8]) -> DirectoryEntry {
+    // Name length in bytes including the terminating NUL
+    let name_len = (u16_at(raw, 0x40) as usize).min(64);
+    let units: Vec<u16> = raw[..name_len]
+        .chunks_exact(2)
+        .map(|unit| u16_at(unit, 0))
+        .take_while(|&unit| unit != 0)
+        .collect();
+
+    DirectoryEntry {
+        name: String::from_utf16_lossy(&units),
+        kind: raw[0x42],
+        left: u32_at(raw, 0x44),
+        right: u32_at(raw, 0x48),
+        child: u32_at(raw, 0x4C),
+        start: u32_at(raw, 0x74),
+        size: u64::from_le_bytes(raw[0x78..0x80].try_into().unwrap()),
+    }
+}
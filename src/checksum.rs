@@ -0,0 +1,89 @@
+//! SHA-256 of converted outputs, sent along with them so downstream systems can
+//! verify an artifact without hashing it again
+
+use axum::http::{HeaderName, HeaderValue};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncReadExt;
+
+use crate::{
+    headers::{CONTENT_SHA256_HEADER, DIGEST_HEADER},
+    libreoffice::ConvertedOutput,
+};
+
+/// Read size when hashing a file-backed output
+const CHUNK_LEN: usize = 64 * 1024;
+
+/// SHA-256 of `data`, a file is hashed chunk by chunk instead of read into memory
+pub async fn sha256(data: &ConvertedOutput) -> std::io::Result<[u8; 32]> {
+    match data {
+        ConvertedOutput::Bytes(bytes) => Ok(Sha256::digest(bytes).into()),
+        ConvertedOutput::File { path, .. } => {
+            let mut file = tokio::fs::File::open(path).await?;
+            let mut hasher = Sha256::new();
+            let mut chunk = vec![0; CHUNK_LEN];
+            loop {
+                let read = file.read(&mut chunk).await?;
+                if read == 0 {
+                    break;
+                }
+                hasher.update(&chunk[..read]);
+            }
+            Ok(hasher.finalize().into())
+        }
+    }
+}
+
+/// Lowercase hex of `bytes`
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// [`CONTENT_SHA256_HEADER`] and the RFC 3230 [`DIGEST_HEADER`] of a body hashing
+/// to `digest`
+pub fn headers(digest: &[u8; 32]) -> [(HeaderName, HeaderValue); 2] {
+    let sha256 = HeaderValue::try_from(hex(digest)).expect("hex is a valid header value");
+    let rfc3230 = HeaderValue::try_from(format!("sha-256={}", STANDARD.encode(digest)))
+        .expect("base64 is a valid header value");
+    [
+        (HeaderName::from_static(CONTENT_SHA256_HEADER), sha256),
+        (HeaderName::from_static(DIGEST_HEADER), rfc3230),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_file_and_bytes_hash_alike() {
+        let data = vec![7; 3 * CHUNK_LEN + 5];
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("document.pdf");
+        std::fs::write(&path, &data).unwrap();
+
+        let file = sha256(&ConvertedOutput::File {
+            path,
+            len: data.len() as u64,
+        })
+        .await
+        .unwrap();
+        assert_eq!(file, sha256(&ConvertedOutput::Bytes(data)).await.unwrap());
+    }
+
+    #[test]
+    fn test_headers() {
+        let digest: [u8; 32] = Sha256::digest(b"abc").into();
+        let [(sha256, hex), (digest_name, rfc3230)] = headers(&digest);
+        assert_eq!(sha256, CONTENT_SHA256_HEADER);
+        assert_eq!(
+            hex,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(digest_name, "digest");
+        assert_eq!(
+            rfc3230,
+            "sha-256=ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0="
+        );
+    }
+}
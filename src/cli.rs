@@ -0,0 +1,259 @@
+//! Command line: `serve` runs the HTTP API, `convert` runs a single conversion
+//! through the same pipeline, e.g. to debug a deployment from inside its container
+
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+
+use clap::{Args, Parser, Subcommand};
+use serde::Serialize;
+
+use crate::{
+    config::{self, Config},
+    converter::ConversionOptions,
+    error::{LibreOfficeError, Result},
+    libreoffice::{ConvertedOutput, InputFile},
+    logging, pipeline,
+    state::AppState,
+};
+
+/// Arguments of the `libreoffice-rest` binary
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// What to run, `serve` when omitted
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Subcommands of the binary
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Serves the HTTP API, the default
+    Serve,
+    /// Converts a local file like POST /convert and prints its stats as JSON
+    Convert(ConvertArgs),
+}
+
+/// Arguments of `convert`
+#[derive(Debug, Args)]
+pub struct ConvertArgs {
+    /// Document to convert, its extension names the input format
+    pub input: PathBuf,
+    /// Where the output is written
+    pub output: PathBuf,
+    /// Output format, the extension of OUTPUT when unset
+    #[arg(long)]
+    pub format: Option<String>,
+    /// Seconds LibreOffice may take, CONVERSION_TIMEOUT_SECS when unset
+    #[arg(long, value_name = "SECS")]
+    pub timeout: Option<u64>,
+    /// Conversion option named like its form field, e.g. strip_metadata=true
+    #[arg(long = "option", value_name = "NAME=VALUE", value_parser = parse_option)]
+    pub options: Vec<(String, String)>,
+}
+
+/// What `convert` prints on success
+#[derive(Debug, Serialize)]
+pub struct ConversionStats {
+    /// Input format, from the extension or sniffed
+    pub input_format: String,
+    /// Output format
+    pub output_format: String,
+    /// Media type sniffed from the input
+    pub detected_type: &'static str,
+    /// Size of the input
+    pub input_bytes: u64,
+    /// Size of the output
+    pub output_bytes: u64,
+    /// Time the converter took, queue and scan included
+    pub duration_ms: u64,
+    /// Time LibreOffice took once the conversion left the queue
+    pub conversion_ms: Option<u64>,
+    /// Lane the conversion was scheduled in
+    pub queue_lane: Option<&'static str>,
+    /// Time the conversion waited for LibreOffice
+    pub queue_wait_ms: Option<u64>,
+    /// Time the malware scan took, unset when scanning is off
+    pub scan_ms: Option<u64>,
+    /// Fonts the input uses that were substituted
+    pub missing_fonts: Vec<String>,
+    /// Whether the input only converted after a repair
+    pub repaired: bool,
+}
+
+/// Runs `convert`, exiting non-zero with the error body on stderr on failure
+pub async fn run_convert(args: ConvertArgs) -> ExitCode {
+    let mut config = Config::from_env();
+    if let Some(timeout) = args.timeout {
+        config.conversion_timeout = Duration::from_secs(timeout);
+    }
+    config::set(config);
+    logging::init_stderr();
+
+    let state = AppState::builder().build();
+    match convert(&state, &args).await {
+        Ok(stats) => {
+            println!("{}", serde_json::to_string(&stats).unwrap_or_default());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            let error = serde_json::json!({
+                "code": e.code(),
+                "message": e.to_string(),
+                "retryable": e.is_retryable(),
+            });
+            eprintln!("{}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Converts `args.input` into `args.output`
+pub async fn convert(state: &AppState, args: &ConvertArgs) -> Result<ConversionStats> {
+    let mut options = ConversionOptions::default();
+    for (name, value) in &args.options {
+        // A typo on the command line is refused whatever REJECT_UNKNOWN_FIELDS says
+        if !ConversionOptions::is_field(name) {
+            return Err(LibreOfficeError::UnknownField(name.clone()));
+        }
+        options.set_field(name, value)?;
+    }
+    let output_format = match &args.format {
+        Some(format) => format.clone(),
+        None => extension(&args.output),
+    };
+
+    let mut reader = tokio::fs::File::open(&args.input).await?;
+    let input_file = InputFile::from_reader(&mut reader, &state.config().work_dir).await?;
+    let input_filename = args
+        .input
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let conversion = pipeline::convert(
+        state,
+        None,
+        input_file,
+        &input_filename,
+        Some(&output_format),
+        &options,
+    )
+    .await?;
+
+    let output = conversion.output;
+    match &output.primary.data {
+        ConvertedOutput::Bytes(data) => tokio::fs::write(&args.output, data).await?,
+        ConvertedOutput::File { path, .. } => {
+            tokio::fs::copy(path, &args.output).await?;
+        }
+    }
+
+    Ok(ConversionStats {
+        input_format: conversion.input_format.to_string(),
+        output_format: conversion.output_format.to_string(),
+        detected_type: conversion.detected.mime,
+        input_bytes: conversion.input_bytes,
+        output_bytes: output.primary.data.len(),
+        duration_ms: conversion.duration.as_millis() as u64,
+        conversion_ms: output
+            .conversion_duration
+            .map(|duration| duration.as_millis() as u64),
+        queue_lane: output.queue.as_ref().map(|queue| queue.lane.as_str()),
+        queue_wait_ms: output
+            .queue
+            .as_ref()
+            .map(|queue| queue.wait.as_millis() as u64),
+        scan_ms: output
+            .scan_duration
+            .map(|duration| duration.as_millis() as u64),
+        missing_fonts: output.missing_fonts,
+        repaired: output.repaired,
+    })
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+fn parse_option(value: &str) -> std::result::Result<(String, String), String> {
+    value
+        .split_once('=')
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected NAME=VALUE, got {:?}", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::converter::fake::FakeConverter;
+    use std::sync::Arc;
+
+    fn args(dir: &Path, output: &str, extra: &[&str]) -> ConvertArgs {
+        let input = dir.join("report.docx");
+        std::fs::write(&input, b"not really a document").unwrap();
+        let mut argv = vec![
+            "libreoffice-rest".to_string(),
+            "convert".to_string(),
+            input.display().to_string(),
+            dir.join(output).display().to_string(),
+        ];
+        argv.extend(extra.iter().map(|arg| arg.to_string()));
+        match Cli::parse_from(argv).command {
+            Some(Command::Convert(args)) => args,
+            command => panic!("unexpected command {:?}", command),
+        }
+    }
+
+    #[test]
+    fn test_serve_is_the_default() {
+        assert!(Cli::parse_from(["libreoffice-rest"]).command.is_none());
+        assert!(matches!(
+            Cli::parse_from(["libreoffice-rest", "serve"]).command,
+            Some(Command::Serve)
+        ));
+        assert!(Cli::try_parse_from(["libreoffice-rest", "convert", "in.docx"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_convert_writes_the_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let converter = Arc::new(FakeConverter::returning_bytes("pdf", b"%PDF-1.7"));
+        let state = AppState::builder().converter(converter.clone()).build();
+        let args = args(
+            dir.path(),
+            "report.pdf",
+            &["--timeout", "120", "--option", "strip_metadata=true"],
+        );
+        assert_eq!(args.timeout, Some(120));
+
+        let stats = convert(&state, &args).await.unwrap();
+        assert_eq!(
+            std::fs::read(dir.path().join("report.pdf")).unwrap(),
+            b"%PDF-1.7"
+        );
+        assert_eq!(stats.input_format, "docx");
+        assert_eq!(stats.output_format, "pdf");
+        assert_eq!(stats.output_bytes, 8);
+        assert!(converter.last_options().unwrap().strip_metadata);
+    }
+
+    #[tokio::test]
+    async fn test_convert_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let converter = Arc::new(FakeConverter::returning_bytes("pdf", b"%PDF-1.7"));
+        let state = AppState::builder().converter(converter.clone()).build();
+
+        let unknown_format = convert(&state, &args(dir.path(), "report", &[])).await;
+        assert_eq!(unknown_format.unwrap_err().code(), "invalid_format");
+
+        let unknown_option = args(dir.path(), "report.pdf", &["--option", "strip=true"]);
+        let unknown_option = convert(&state, &unknown_option).await;
+        assert_eq!(unknown_option.unwrap_err().code(), "unknown_field");
+        assert_eq!(converter.calls(), 0);
+        assert!(!dir.path().join("report.pdf").exists());
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+
+use tokio::sync::watch;
+
+use crate::{converter::ConversionOptions, error::Result, libreoffice::ConversionOutput};
+
+/// Identifies conversions that produce the same result
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ConversionKey {
+    /// SHA-256 of the uploaded document
+    pub content_hash: [u8; 32],
+    pub from: String,
+    pub to: String,
+    pub options: ConversionOptions,
+}
+
+type SharedResult = Option<Result<ConversionOutput>>;
+
+/// Conversions currently running, so identical ones wait for the running one
+/// instead of converting again. Only conversions of the same converter, and so
+/// the same configuration, are coalesced.
+#[derive(Default)]
+pub struct Coalescer {
+    // Receivers for the conversions currently running, one per key
+    in_flight: Mutex<HashMap<ConversionKey, watch::Receiver<SharedResult>>>,
+}
+
+/// Removes the in-flight entry once the leading conversion finished or was dropped
+struct InFlightGuard<'a> {
+    coalescer: &'a Coalescer,
+    key: &'a ConversionKey,
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.coalescer.in_flight.lock().unwrap().remove(self.key);
+    }
+}
+
+impl Coalescer {
+    /// Runs `convert` unless an identical conversion is already running, in which
+    /// case its result (or error) is shared instead
+    pub async fn run<F, Fut>(&self, key: ConversionKey, convert: F) -> Result<ConversionOutput>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<ConversionOutput>>,
+    {
+        loop {
+            let (sender, mut receiver) = {
+                let mut in_flight = self.in_flight.lock().unwrap();
+                match in_flight.get(&key) {
+                    Some(receiver) => (None, receiver.clone()),
+                    None => {
+                        let (sender, receiver) = watch::channel(None);
+                        in_flight.insert(key.clone(), receiver.clone());
+                        (Some(sender), receiver)
+                    }
+                }
+            };
+
+            if let Some(sender) = sender {
+                return self.lead(&key, sender, convert).await;
+            }
+
+            // Errors when the leading request was dropped before finishing,
+            // try again and possibly run the conversion ourselves
+            if let Ok(result) = receiver.wait_for(Option::is_some).await {
+                tracing::debug!("Coalesced conversion {} -> {}", key.from, key.to);
+                metrics::counter!("conversions_coalesced_total").increment(1);
+                return result.clone().expect("waited for a result");
+            }
+        }
+    }
+
+    async fn lead<F, Fut>(
+        &self,
+        key: &ConversionKey,
+        sender: watch::Sender<SharedResult>,
+        convert: F,
+    ) -> Result<ConversionOutput>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<ConversionOutput>>,
+    {
+        // Locals drop before parameters, so the entry is gone before waiters see the sender close
+        let _guard = InFlightGuard {
+            coalescer: self,
+            key,
+        };
+
+        let result = convert().await;
+        sender.send_replace(Some(result.clone()));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        error::LibreOfficeError,
+        libreoffice::{ConvertedOutput, OutputFile},
+    };
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    fn key(content: u8) -> ConversionKey {
+        ConversionKey {
+            content_hash: [content; 32],
+            from: "docx".to_string(),
+            to: "pdf".to_string(),
+            options: ConversionOptions::default(),
+        }
+    }
+
+    fn output() -> ConversionOutput {
+        ConversionOutput::new(OutputFile {
+            name: "document.pdf".to_string(),
+            data: ConvertedOutput::Bytes(b"%PDF-1.7".to_vec()),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_identical_conversions_run_once() {
+        let coalescer = Arc::new(Coalescer::default());
+        let runs = Arc::new(AtomicUsize::new(0));
+
+        let tasks: Vec<_> = (0..5)
+            .map(|_| {
+                let (coalescer, runs) = (coalescer.clone(), runs.clone());
+                tokio::spawn(async move {
+                    coalescer
+                        .run(key(1), || async {
+                            runs.fetch_add(1, Ordering::SeqCst);
+                            tokio::time::sleep(Duration::from_millis(200)).await;
+                            Ok(output())
+                        })
+                        .await
+                })
+            })
+            .collect();
+
+        for task in tasks {
+            let output = task.await.unwrap().unwrap();
+            assert_eq!(output.primary.data.into_bytes().await.unwrap(), b"%PDF-1.7");
+        }
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert!(coalescer.in_flight.lock().unwrap().get(&key(1)).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_failure_is_shared() {
+        let coalescer = Arc::new(Coalescer::default());
+        let tasks: Vec<_> = (0..3)
+            .map(|_| {
+                let coalescer = coalescer.clone();
+                tokio::spawn(async move {
+                    coalescer
+                        .run(key(2), || async {
+                            tokio::time::sleep(Duration::from_millis(200)).await;
+                            Err(LibreOfficeError::PasswordProtected)
+                        })
+                        .await
+                })
+            })
+            .collect();
+
+        for task in tasks {
+            assert!(matches!(
+                task.await.unwrap(),
+                Err(LibreOfficeError::PasswordProtected)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_waiter_takes_over_dropped_leader() {
+        let coalescer = Arc::new(Coalescer::default());
+        let leading = coalescer.clone();
+        let leader = tokio::spawn(async move {
+            leading
+                .run(key(3), || async {
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                    Ok(output())
+                })
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let waiting = coalescer.clone();
+        let waiter =
+            tokio::spawn(async move { waiting.run(key(3), || async { Ok(output()) }).await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        leader.abort();
+
+        let result = tokio::time::timeout(Duration::from_secs(5), waiter)
+            .await
+            .expect("waiter should run the conversion itself");
+        assert!(result.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_coalescers_do_not_share_results() {
+        let first = Arc::new(Coalescer::default());
+        let runs = Arc::new(AtomicUsize::new(0));
+
+        let leading = (first.clone(), runs.clone());
+        let leader = tokio::spawn(async move {
+            let (coalescer, runs) = leading;
+            coalescer
+                .run(key(4), || async {
+                    runs.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                    Ok(output())
+                })
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let second = Coalescer::default();
+        let result = second
+            .run(key(4), || async {
+                runs.fetch_add(1, Ordering::SeqCst);
+                Err(LibreOfficeError::PasswordProtected)
+            })
+            .await;
+        assert!(matches!(result, Err(LibreOfficeError::PasswordProtected)));
+        assert!(leader.await.unwrap().is_ok());
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,530 @@
+//! Settings read from the environment
+
+use std::env;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::queue::MAX_PARALLEL_CONVERSIONS;
+
+const DEFAULT_PORT: u16 = 1234;
+const DEFAULT_HOST: &str = "0.0.0.0";
+const DEFAULT_UNIX_SOCKET_MODE: u32 = 0o660;
+const DEFAULT_MAX_UPLOAD_SIZE_MB: usize = 250;
+const DEFAULT_UNOSERVER_PORT: u16 = 2003;
+const DEFAULT_MAX_PROFILE_RESETS: u32 = 3;
+const DEFAULT_CONVERSION_TIMEOUT_SECS: u64 = 60;
+const DEFAULT_MAX_QUEUE_WAIT_SECS: u64 = 120;
+const DEFAULT_FREE_SPACE_MULTIPLIER: u64 = 3;
+const DEFAULT_TEMP_DIR_MAX_AGE_SECS: u64 = 60 * 60;
+const DEFAULT_INTERACTIVE_MAX_BYTES: u64 = 1024 * 1024;
+const DEFAULT_INTERACTIVE_WEIGHT: u32 = 4;
+const DEFAULT_SCANNER_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_AUDIT_LOG_PATH: &str = "audit.jsonl";
+const DEFAULT_AUDIT_LOG_MAX_MB: u64 = 100;
+const DEFAULT_AUDIT_LOG_MAX_FILES: u32 = 10;
+const DEFAULT_RESULT_STORE_MAX_MB: u64 = 256;
+const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 300;
+const DEFAULT_HEADER_READ_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_BODY_READ_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_IDLE_TIMEOUT_SECS: u64 = 60;
+const DEFAULT_MAX_CONNECTIONS: usize = 1024;
+
+/// Engine used for conversions, selected with `CONVERSION_BACKEND`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    /// One headless LibreOffice process per conversion
+    Cli,
+    /// Resident unoserver instance driven through unoconvert
+    Unoserver,
+}
+
+impl std::str::FromStr for BackendKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "cli" => Ok(Self::Cli),
+            "unoserver" => Ok(Self::Unoserver),
+            other => Err(format!("unknown conversion backend {:?}", other)),
+        }
+    }
+}
+
+/// Destination of audit records, selected with `AUDIT_SINK`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditSinkKind {
+    /// JSON lines on standard output
+    Stdout,
+    /// JSON lines appended to `AUDIT_LOG_PATH`, rotated by size
+    File,
+}
+
+impl std::str::FromStr for AuditSinkKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "stdout" => Ok(Self::Stdout),
+            "file" => Ok(Self::File),
+            other => Err(format!("unknown audit sink {:?}", other)),
+        }
+    }
+}
+
+/// rlimits applied to each LibreOffice process, unlimited when unset
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ResourceLimits {
+    /// Address space limit (`RLIMIT_AS`)
+    pub memory_bytes: Option<u64>,
+    /// CPU time limit (`RLIMIT_CPU`)
+    pub cpu_secs: Option<u64>,
+}
+
+impl ResourceLimits {
+    /// Whether any limit is set
+    pub fn is_enabled(&self) -> bool {
+        self.memory_bytes.is_some() || self.cpu_secs.is_some()
+    }
+}
+
+/// Protections of the API listener against slow or idle clients
+#[derive(Debug, Clone, Copy)]
+pub struct ServerLimits {
+    /// Longest a request may take, from its headers to the response, queue wait included
+    pub request_timeout: Duration,
+    /// Longest a client may take to send the headers of a request
+    pub header_read_timeout: Duration,
+    /// Longest gap allowed between two chunks of a request body
+    pub body_read_timeout: Duration,
+    /// Connections without a request in flight or traffic for this long are closed
+    pub idle_timeout: Duration,
+    /// Connections served at once, further clients wait to be accepted
+    pub max_connections: usize,
+}
+
+/// Connection and request limits of the API listener, as reported by /status and /info
+#[derive(Debug, Serialize)]
+pub struct LimitsSummary {
+    request_timeout_secs: u64,
+    header_read_timeout_secs: u64,
+    body_read_timeout_secs: u64,
+    idle_timeout_secs: u64,
+    max_connections: usize,
+}
+
+impl From<ServerLimits> for LimitsSummary {
+    fn from(limits: ServerLimits) -> Self {
+        Self {
+            request_timeout_secs: limits.request_timeout.as_secs(),
+            header_read_timeout_secs: limits.header_read_timeout.as_secs(),
+            body_read_timeout_secs: limits.body_read_timeout.as_secs(),
+            idle_timeout_secs: limits.idle_timeout.as_secs(),
+            max_connections: limits.max_connections,
+        }
+    }
+}
+
+/// Effective configuration reported by /info and logged at startup. Settings are
+/// picked one by one so nothing that may hold credentials, like
+/// `LIBREOFFICE_EXTRA_ARGS`, is exposed.
+#[derive(Debug, Serialize)]
+pub(crate) struct ConfigSummary {
+    port: u16,
+    host: String,
+    admin_port: Option<u16>,
+    grpc_port: Option<u16>,
+    unix_socket: Option<PathBuf>,
+    max_upload_bytes: usize,
+    conversion_timeout_secs: u64,
+    max_queue_wait_secs: u64,
+    limits: LimitsSummary,
+    backends: Vec<BackendKind>,
+    max_parallel_conversions: usize,
+    interactive_max_bytes: u64,
+    interactive_weight: u32,
+    work_dir: PathBuf,
+    free_space_multiplier: u64,
+    temp_dir_max_age_secs: u64,
+    latency_profile_persist: bool,
+    memory_limit_bytes: Option<u64>,
+    cpu_limit_secs: Option<u64>,
+    scanner_addr: Option<String>,
+    audit_sink: Option<AuditSinkKind>,
+    result_retention_secs: Option<u64>,
+    usage_export_interval_secs: Option<u64>,
+    reject_macro_documents: bool,
+    reject_unknown_fields: bool,
+    max_pages: Option<u64>,
+    allowed_input_types: Option<Vec<String>>,
+    allowed_output_formats: Option<Vec<String>>,
+    scratch_home: bool,
+    warmup: bool,
+    selftest: bool,
+    selftest_soft: bool,
+}
+
+/// Service configuration, read once from the environment by [`Config::from_env`]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct Config {
+    /// Port of the API
+    pub port: u16,
+    /// IPv4 or IPv6 address to listen on, validated at startup
+    pub host: String,
+    /// Separate port for the admin routes (`/status`), served with the API when unset
+    pub admin_port: Option<u16>,
+    /// Port of the gRPC service, bound on `host`; off when unset or built without
+    /// the `grpc` feature
+    pub grpc_port: Option<u16>,
+    /// Unix domain socket to listen on instead of `host`:`port`
+    pub unix_socket: Option<PathBuf>,
+    /// Permissions of the unix socket file
+    pub unix_socket_mode: u32,
+    /// Maximum size of a conversion upload in bytes
+    pub max_upload_size: usize,
+    /// LibreOffice user profile directory (`-env:UserInstallation`), defaults to
+    /// LibreOffice's own `$HOME/.config/libreoffice` when unset
+    pub profile_dir: Option<PathBuf>,
+    /// Consecutive profile resets after which conversions give up
+    pub max_profile_resets: u32,
+    /// Maximum runtime of a single LibreOffice process
+    pub conversion_timeout: Duration,
+    /// Longest a conversion may wait for LibreOffice before it fails with 503
+    pub max_queue_wait: Duration,
+    /// Base directory for per-conversion temp directories
+    pub work_dir: PathBuf,
+    /// Free space required in the work dir, as a multiple of the input size
+    pub free_space_multiplier: u64,
+    /// Keep the latency profile behind `/estimate` in the work dir across restarts
+    pub latency_profile_persist: bool,
+    /// Age after which unused temp directories are reclaimed
+    pub temp_dir_max_age: Duration,
+    /// Inputs smaller than this are scheduled in the interactive lane
+    pub interactive_max_bytes: u64,
+    /// Interactive conversions run in a row before a waiting bulk conversion
+    pub interactive_weight: u32,
+    /// Extensions converted even when the content is not recognized, lowercase;
+    /// unrecognized content with any other extension is rejected with 400
+    pub unrecognized_content_extensions: Vec<String>,
+    /// Refuse macro-enabled documents (docm, xlsm, pptm) with 422
+    pub reject_macro_documents: bool,
+    /// Refuse form fields the route doesn't know with 400 instead of logging them
+    pub reject_unknown_fields: bool,
+    /// Refuse documents whose stored page, slide or sheet count is higher, off when unset
+    pub max_pages: Option<u64>,
+    /// Input formats conversions are accepted from, lowercase; any supported one when unset
+    pub allowed_input_types: Option<Vec<String>>,
+    /// Formats conversions are accepted to, lowercase; any supported one when unset
+    pub allowed_output_formats: Option<Vec<String>>,
+    /// Give each LibreOffice process its own HOME inside the conversion's temp dir
+    pub scratch_home: bool,
+    /// Origins allowed to call the conversion routes from a browser, `*` for any;
+    /// CORS is off when empty
+    pub cors_allowed_origins: Vec<String>,
+    /// Methods allowed to cross-origin requests
+    pub cors_allowed_methods: Vec<String>,
+    /// Request headers allowed to cross-origin requests
+    pub cors_allowed_headers: Vec<String>,
+    /// Run a warmup conversion at startup, /ready is 503 until it finished
+    pub warmup: bool,
+    /// Check the prerequisites of conversions before serving
+    pub selftest: bool,
+    /// Serve with /ready at 503 when the self-test fails instead of exiting
+    pub selftest_soft: bool,
+    /// LibreOffice executable, searched on PATH when it isn't a path
+    pub libreoffice_bin: Option<String>,
+    /// Extra LibreOffice arguments inserted before the input path
+    pub libreoffice_extra_args: Vec<String>,
+    /// Memory and CPU limits of LibreOffice processes
+    pub resource_limits: ResourceLimits,
+    /// Backends tried in order, later ones only when earlier ones are unavailable
+    pub backends: Vec<BackendKind>,
+    /// unoserver executable, started by the unoserver backend
+    pub unoserver_bin: String,
+    /// unoconvert executable, run per conversion by the unoserver backend
+    pub unoconvert_bin: String,
+    /// Port unoserver listens on for unoconvert clients
+    pub unoserver_port: u16,
+    /// clamd uploads are scanned with before conversion, `host:port` or a unix
+    /// socket path; scanning is off when unset
+    pub scanner_addr: Option<String>,
+    /// Longest a scan may take before it counts as failed
+    pub scanner_timeout: Duration,
+    /// Reject uploads that could not be scanned instead of converting them anyway
+    pub scanner_fail_closed: bool,
+    /// Where a record of every conversion goes, auditing is off when unset
+    pub audit_sink: Option<AuditSinkKind>,
+    /// File of the `file` audit sink
+    pub audit_log_path: PathBuf,
+    /// Size at which the audit log is rotated
+    pub audit_log_max_bytes: u64,
+    /// Rotated audit logs kept next to the current one
+    pub audit_log_max_files: u32,
+    /// Fail conversions whose audit record can't be written instead of only logging it
+    pub audit_fail_closed: bool,
+    /// How long outputs stay downloadable from `/results/{id}`, none are kept when unset
+    pub result_retention: Option<Duration>,
+    /// Total size of the kept outputs, the oldest are evicted beyond it
+    pub result_store_max_bytes: u64,
+    /// Interval the per-key usage counters are written to the audit sink and reset
+    /// at, kept until restart when unset
+    pub usage_export_interval: Option<Duration>,
+    /// Timeouts and connection limits of the HTTP server
+    pub server_limits: ServerLimits,
+}
+
+impl Config {
+    pub(crate) fn summary(&self) -> ConfigSummary {
+        ConfigSummary {
+            port: self.port,
+            host: self.host.clone(),
+            admin_port: self.admin_port,
+            grpc_port: self.grpc_port,
+            unix_socket: self.unix_socket.clone(),
+            max_upload_bytes: self.max_upload_size,
+            conversion_timeout_secs: self.conversion_timeout.as_secs(),
+            max_queue_wait_secs: self.max_queue_wait.as_secs(),
+            limits: self.server_limits.into(),
+            backends: self.backends.clone(),
+            max_parallel_conversions: MAX_PARALLEL_CONVERSIONS,
+            interactive_max_bytes: self.interactive_max_bytes,
+            interactive_weight: self.interactive_weight,
+            work_dir: self.work_dir.clone(),
+            free_space_multiplier: self.free_space_multiplier,
+            latency_profile_persist: self.latency_profile_persist,
+            temp_dir_max_age_secs: self.temp_dir_max_age.as_secs(),
+            memory_limit_bytes: self.resource_limits.memory_bytes,
+            cpu_limit_secs: self.resource_limits.cpu_secs,
+            scanner_addr: self.scanner_addr.clone(),
+            audit_sink: self.audit_sink,
+            result_retention_secs: self.result_retention.map(|retention| retention.as_secs()),
+            usage_export_interval_secs: self
+                .usage_export_interval
+                .map(|interval| interval.as_secs()),
+            reject_macro_documents: self.reject_macro_documents,
+            reject_unknown_fields: self.reject_unknown_fields,
+            max_pages: self.max_pages,
+            allowed_input_types: self.allowed_input_types.clone(),
+            allowed_output_formats: self.allowed_output_formats.clone(),
+            scratch_home: self.scratch_home,
+            warmup: self.warmup,
+            selftest: self.selftest,
+            selftest_soft: self.selftest_soft,
+        }
+    }
+
+    /// Configuration of the environment variables listed in the README, defaults
+    /// for unset or unparseable ones
+    pub fn from_env() -> Self {
+        Self {
+            port: env_parse("PORT").unwrap_or(DEFAULT_PORT),
+            host: env::var("HOST").unwrap_or_else(|_| DEFAULT_HOST.to_string()),
+            admin_port: env_parse("ADMIN_PORT"),
+            grpc_port: env_parse("GRPC_PORT"),
+            unix_socket: env::var_os("LISTEN_UNIX_SOCKET")
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from),
+            unix_socket_mode: env::var("LISTEN_UNIX_SOCKET_MODE")
+                .ok()
+                .and_then(|mode| u32::from_str_radix(mode.trim(), 8).ok())
+                .unwrap_or(DEFAULT_UNIX_SOCKET_MODE),
+            max_upload_size: env_parse::<usize>("MAX_UPLOAD_SIZE_MB")
+                .unwrap_or(DEFAULT_MAX_UPLOAD_SIZE_MB)
+                .saturating_mul(1024 * 1024),
+            profile_dir: env::var_os("LIBREOFFICE_PROFILE_DIR").map(PathBuf::from),
+            max_profile_resets: env_parse("MAX_PROFILE_RESETS")
+                .unwrap_or(DEFAULT_MAX_PROFILE_RESETS),
+            conversion_timeout: Duration::from_secs(
+                env_parse("CONVERSION_TIMEOUT_SECS").unwrap_or(DEFAULT_CONVERSION_TIMEOUT_SECS),
+            ),
+            max_queue_wait: Duration::from_secs(
+                env_parse("MAX_QUEUE_WAIT_SECS").unwrap_or(DEFAULT_MAX_QUEUE_WAIT_SECS),
+            ),
+            work_dir: env::var_os("WORK_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(env::temp_dir),
+            free_space_multiplier: env_parse("FREE_SPACE_MULTIPLIER")
+                .unwrap_or(DEFAULT_FREE_SPACE_MULTIPLIER),
+            latency_profile_persist: env_parse("LATENCY_PROFILE_PERSIST").unwrap_or(false),
+            temp_dir_max_age: Duration::from_secs(
+                env_parse("TEMP_DIR_MAX_AGE_SECS").unwrap_or(DEFAULT_TEMP_DIR_MAX_AGE_SECS),
+            ),
+            interactive_max_bytes: env_parse("INTERACTIVE_MAX_BYTES")
+                .unwrap_or(DEFAULT_INTERACTIVE_MAX_BYTES),
+            interactive_weight: env_parse("INTERACTIVE_WEIGHT")
+                .unwrap_or(DEFAULT_INTERACTIVE_WEIGHT),
+            unrecognized_content_extensions: env_list("UNRECOGNIZED_CONTENT_EXTENSIONS")
+                .map(|extensions| {
+                    extensions
+                        .iter()
+                        .map(|extension| extension.to_ascii_lowercase())
+                        .collect()
+                })
+                .unwrap_or_else(|| vec!["dif".to_string()]),
+            reject_macro_documents: env_parse("REJECT_MACRO_DOCUMENTS").unwrap_or(false),
+            reject_unknown_fields: env_parse("REJECT_UNKNOWN_FIELDS").unwrap_or(false),
+            max_pages: env_parse::<u64>("MAX_PAGES").filter(|max| *max > 0),
+            allowed_input_types: env_formats("ALLOWED_INPUT_TYPES"),
+            allowed_output_formats: env_formats("ALLOWED_OUTPUT_FORMATS"),
+            scratch_home: env_parse("SCRATCH_HOME").unwrap_or(true),
+            cors_allowed_origins: env_list("CORS_ALLOWED_ORIGINS").unwrap_or_default(),
+            cors_allowed_methods: env_list("CORS_ALLOWED_METHODS")
+                .unwrap_or_else(|| vec!["GET".to_string(), "POST".to_string()]),
+            cors_allowed_headers: env_list("CORS_ALLOWED_HEADERS")
+                .unwrap_or_else(|| vec!["content-type".to_string()]),
+            warmup: env_parse("WARMUP").unwrap_or(false),
+            selftest: env_parse("SELFTEST").unwrap_or(true),
+            selftest_soft: env_parse("SELFTEST_SOFT").unwrap_or(false),
+            libreoffice_bin: env::var("LIBREOFFICE_BIN")
+                .ok()
+                .filter(|v| !v.trim().is_empty()),
+            libreoffice_extra_args: env_shell_words("LIBREOFFICE_EXTRA_ARGS"),
+            resource_limits: ResourceLimits {
+                memory_bytes: env_parse::<u64>("LIBREOFFICE_MEMORY_LIMIT_MB")
+                    .map(|mb| mb.saturating_mul(1024 * 1024)),
+                cpu_secs: env_parse("LIBREOFFICE_CPU_LIMIT_SECS"),
+            },
+            backends: env_backends(),
+            unoserver_bin: env::var("UNOSERVER_BIN").unwrap_or_else(|_| "unoserver".to_string()),
+            unoconvert_bin: env::var("UNOCONVERT_BIN").unwrap_or_else(|_| "unoconvert".to_string()),
+            unoserver_port: env_parse("UNOSERVER_PORT").unwrap_or(DEFAULT_UNOSERVER_PORT),
+            scanner_addr: env::var("SCANNER_ADDR")
+                .ok()
+                .filter(|v| !v.trim().is_empty()),
+            scanner_timeout: Duration::from_secs(
+                env_parse("SCANNER_TIMEOUT_SECS").unwrap_or(DEFAULT_SCANNER_TIMEOUT_SECS),
+            ),
+            scanner_fail_closed: env_parse("SCANNER_FAIL_CLOSED").unwrap_or(true),
+            audit_sink: env_audit_sink(),
+            audit_log_path: env::var_os("AUDIT_LOG_PATH")
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_AUDIT_LOG_PATH)),
+            audit_log_max_bytes: env_parse::<u64>("AUDIT_LOG_MAX_MB")
+                .unwrap_or(DEFAULT_AUDIT_LOG_MAX_MB)
+                .saturating_mul(1024 * 1024),
+            audit_log_max_files: env_parse("AUDIT_LOG_MAX_FILES")
+                .unwrap_or(DEFAULT_AUDIT_LOG_MAX_FILES),
+            audit_fail_closed: env_parse("AUDIT_FAIL_CLOSED").unwrap_or(false),
+            result_retention: env_parse("RESULT_RETENTION_SECS")
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            result_store_max_bytes: env_parse::<u64>("RESULT_STORE_MAX_MB")
+                .unwrap_or(DEFAULT_RESULT_STORE_MAX_MB)
+                .saturating_mul(1024 * 1024),
+            usage_export_interval: env_parse("USAGE_EXPORT_INTERVAL_SECS")
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            server_limits: ServerLimits {
+                request_timeout: Duration::from_secs(
+                    env_parse("REQUEST_TIMEOUT_SECS").unwrap_or(DEFAULT_REQUEST_TIMEOUT_SECS),
+                ),
+                header_read_timeout: Duration::from_secs(
+                    env_parse("HEADER_READ_TIMEOUT_SECS")
+                        .unwrap_or(DEFAULT_HEADER_READ_TIMEOUT_SECS),
+                ),
+                body_read_timeout: Duration::from_secs(
+                    env_parse("BODY_READ_TIMEOUT_SECS").unwrap_or(DEFAULT_BODY_READ_TIMEOUT_SECS),
+                ),
+                idle_timeout: Duration::from_secs(
+                    env_parse("IDLE_TIMEOUT_SECS").unwrap_or(DEFAULT_IDLE_TIMEOUT_SECS),
+                ),
+                max_connections: env_parse::<usize>("MAX_CONNECTIONS")
+                    .filter(|max| *max > 0)
+                    .unwrap_or(DEFAULT_MAX_CONNECTIONS),
+            },
+        }
+    }
+}
+
+fn env_parse<T: std::str::FromStr>(key: &str) -> Option<T> {
+    env::var(key).ok().and_then(|v| v.trim().parse::<T>().ok())
+}
+
+/// Splits a comma-separated variable, `None` when unset
+fn env_list(key: &str) -> Option<Vec<String>> {
+    let value = env::var(key).ok()?;
+    Some(
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(str::to_string)
+            .collect(),
+    )
+}
+
+/// Lowercase format list, `None` when unset or empty
+fn env_formats(key: &str) -> Option<Vec<String>> {
+    let formats: Vec<String> = env_list(key)?
+        .iter()
+        .map(|format| format.trim_start_matches('.').to_ascii_lowercase())
+        .collect();
+    (!formats.is_empty()).then_some(formats)
+}
+
+/// Reads the backend chain from `CONVERSION_BACKENDS`, or the single `CONVERSION_BACKEND`
+fn env_backends() -> Vec<BackendKind> {
+    let value = env::var("CONVERSION_BACKENDS")
+        .or_else(|_| env::var("CONVERSION_BACKEND"))
+        .unwrap_or_default();
+
+    let backends: Vec<BackendKind> = value
+        .split(',')
+        .filter(|name| !name.trim().is_empty())
+        .filter_map(|name| {
+            name.parse()
+                .map_err(|e| tracing::warn!("Ignoring {}", e))
+                .ok()
+        })
+        .collect();
+
+    if backends.is_empty() {
+        vec![BackendKind::Cli]
+    } else {
+        backends
+    }
+}
+
+/// Reads `AUDIT_SINK`, `none` or unset turning auditing off
+fn env_audit_sink() -> Option<AuditSinkKind> {
+    let value = env::var("AUDIT_SINK").ok()?;
+    if value.trim().is_empty() || value.trim().eq_ignore_ascii_case("none") {
+        return None;
+    }
+    value
+        .parse()
+        .map_err(|e| tracing::warn!("Ignoring {}, auditing is off", e))
+        .ok()
+}
+
+/// Splits a variable shell-style, ignoring (and logging) unparseable values
+fn env_shell_words(key: &str) -> Vec<String> {
+    let Ok(value) = env::var(key) else {
+        return Vec::new();
+    };
+
+    shlex::split(&value).unwrap_or_else(|| {
+        tracing::warn!("Ignoring {}: unbalanced quotes in {:?}", key, value);
+        Vec::new()
+    })
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Configuration of the process, read from the environment on first use
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::from_env)
+}
+
+/// Replaces the configuration read from the environment, before anything read it
+pub fn set(config: Config) {
+    if CONFIG.set(config).is_err() {
+        tracing::warn!("Configuration already in use, ignoring the override");
+    }
+}
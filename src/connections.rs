@@ -0,0 +1,364 @@
+//! Accept loop of the API listener. Unlike `axum::serve` it bounds how many
+//! connections are served at once and closes connections whose client is slow to
+//! send headers or leaves them idle.
+
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use axum::{
+    Router,
+    body::Body,
+    extract::{ConnectInfo, Request},
+    serve::Listener,
+};
+use hyper::body::Incoming;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::conn::auto::Builder,
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, watch};
+use tokio::task::JoinSet;
+use tower::ServiceExt;
+
+use crate::config::ServerLimits;
+
+/// Listener addresses handlers see as the peer, none on unix sockets
+pub trait PeerAddr {
+    fn peer(self) -> Option<SocketAddr>;
+}
+
+impl PeerAddr for SocketAddr {
+    fn peer(self) -> Option<SocketAddr> {
+        Some(self)
+    }
+}
+
+impl PeerAddr for tokio::net::unix::SocketAddr {
+    fn peer(self) -> Option<SocketAddr> {
+        None
+    }
+}
+
+/// Serves `app` on `listener` until `shutdown` turns true, then waits for the
+/// requests in flight. Handlers get TCP peer addresses as [`ConnectInfo`].
+pub async fn serve<L>(
+    mut listener: L,
+    app: Router,
+    limits: ServerLimits,
+    mut shutdown: watch::Receiver<bool>,
+) where
+    L: Listener,
+    L::Addr: PeerAddr,
+{
+    let slots = Arc::new(Semaphore::new(limits.max_connections));
+    let mut connections = JoinSet::new();
+
+    loop {
+        // Clients over the limit wait in the listen backlog until a slot frees up
+        let slot = tokio::select! {
+            slot = slots.clone().acquire_owned() => slot,
+            _ = shutdown.wait_for(|requested| *requested) => break,
+        };
+        let Ok(slot) = slot else { break };
+        let (io, addr) = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown.wait_for(|requested| *requested) => break,
+        };
+
+        connections.spawn(serve_connection(
+            io,
+            addr.peer(),
+            app.clone(),
+            limits,
+            shutdown.clone(),
+            slot,
+        ));
+        while connections.try_join_next().is_some() {}
+    }
+
+    drop(listener);
+    while connections.join_next().await.is_some() {}
+}
+
+async fn serve_connection<I>(
+    io: I,
+    peer: Option<SocketAddr>,
+    app: Router,
+    limits: ServerLimits,
+    mut shutdown: watch::Receiver<bool>,
+    _slot: OwnedSemaphorePermit,
+) where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    metrics::gauge!("http_open_connections").increment(1);
+    let activity = Arc::new(Activity::new());
+
+    let service = {
+        let activity = activity.clone();
+        hyper::service::service_fn(move |mut request: Request<Incoming>| {
+            if let Some(peer) = peer {
+                request.extensions_mut().insert(ConnectInfo(peer));
+            }
+            let in_flight = InFlight::start(activity.clone());
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request.map(Body::new)).await;
+                drop(in_flight);
+                response
+            }
+        })
+    };
+
+    let mut builder = Builder::new(TokioExecutor::new());
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .header_read_timeout(limits.header_read_timeout);
+    let io = TokioIo::new(TrackedIo {
+        io,
+        activity: activity.clone(),
+    });
+    let connection = builder.serve_connection_with_upgrades(io, service);
+    tokio::pin!(connection);
+
+    let mut closing = false;
+    loop {
+        let idle_check = match activity.idle_for() {
+            Some(idle) => limits.idle_timeout.saturating_sub(idle),
+            None => limits.idle_timeout,
+        };
+        tokio::select! {
+            result = connection.as_mut() => {
+                if let Err(e) = result {
+                    tracing::debug!("Connection closed with an error: {}", e);
+                }
+                break;
+            }
+            _ = shutdown.wait_for(|requested| *requested), if !closing => {
+                connection.as_mut().graceful_shutdown();
+                closing = true;
+            }
+            _ = tokio::time::sleep(idle_check), if !closing => {
+                if activity.idle_for().is_some_and(|idle| idle >= limits.idle_timeout) {
+                    tracing::debug!("Closing connection idle for {:?}", limits.idle_timeout);
+                    connection.as_mut().graceful_shutdown();
+                    closing = true;
+                }
+            }
+        }
+    }
+
+    metrics::gauge!("http_open_connections").decrement(1);
+}
+
+/// Last traffic and requests in flight on a connection
+struct Activity {
+    started: Instant,
+    last_ms: AtomicU64,
+    in_flight: AtomicUsize,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            last_ms: AtomicU64::new(0),
+            in_flight: AtomicUsize::new(0),
+        }
+    }
+
+    fn touch(&self) {
+        let elapsed = self.started.elapsed().as_millis() as u64;
+        self.last_ms.store(elapsed, Ordering::Relaxed);
+    }
+
+    /// Time since the last traffic, none while a request is being handled
+    fn idle_for(&self) -> Option<Duration> {
+        if self.in_flight.load(Ordering::Relaxed) > 0 {
+            return None;
+        }
+        let last = Duration::from_millis(self.last_ms.load(Ordering::Relaxed));
+        Some(self.started.elapsed().saturating_sub(last))
+    }
+}
+
+/// Marks a request as being handled until dropped
+struct InFlight(Arc<Activity>);
+
+impl InFlight {
+    fn start(activity: Arc<Activity>) -> Self {
+        activity.in_flight.fetch_add(1, Ordering::Relaxed);
+        Self(activity)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.touch();
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Connection IO recording when bytes last went through
+struct TrackedIo<I> {
+    io: I,
+    activity: Arc<Activity>,
+}
+
+impl<I: AsyncRead + Unpin> AsyncRead for TrackedIo<I> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut this.io).poll_read(cx, buf);
+        if buf.filled().len() > filled {
+            this.activity.touch();
+        }
+        poll
+    }
+}
+
+impl<I: AsyncWrite + Unpin> AsyncWrite for TrackedIo<I> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.io).poll_write(cx, buf);
+        if matches!(poll, Poll::Ready(Ok(written)) if written > 0) {
+            this.activity.touch();
+        }
+        poll
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.io).poll_write_vectored(cx, bufs);
+        if matches!(poll, Poll::Ready(Ok(written)) if written > 0) {
+            this.activity.touch();
+        }
+        poll
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.io.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    const REQUEST: &[u8] = b"GET / HTTP/1.1\r\nHost: test\r\n\r\n";
+
+    /// Serves a route answering with the peer address, returning where it listens
+    async fn start(limits: ServerLimits) -> (SocketAddr, watch::Sender<bool>) {
+        let app = Router::new().route(
+            "/",
+            get(|ConnectInfo(peer): ConnectInfo<SocketAddr>| async move { peer.to_string() }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown, receiver) = watch::channel(false);
+        tokio::spawn(serve(listener, app, limits, receiver));
+        (addr, shutdown)
+    }
+
+    fn limits() -> ServerLimits {
+        ServerLimits {
+            request_timeout: Duration::from_secs(30),
+            header_read_timeout: Duration::from_secs(30),
+            body_read_timeout: Duration::from_secs(30),
+            idle_timeout: Duration::from_secs(30),
+            max_connections: 16,
+        }
+    }
+
+    /// Reads until the peer closes the connection, failing after a few seconds
+    async fn read_until_closed(stream: &mut TcpStream) -> String {
+        let mut received = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut received))
+            .await
+            .expect("connection should be closed")
+            .unwrap_or_default();
+        String::from_utf8_lossy(&received).into_owned()
+    }
+
+    #[tokio::test]
+    async fn test_slow_headers_and_idle_connections_are_closed() {
+        let (addr, _shutdown) = start(ServerLimits {
+            header_read_timeout: Duration::from_millis(200),
+            ..limits()
+        })
+        .await;
+        let mut slow = TcpStream::connect(addr).await.unwrap();
+        slow.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+        assert!(!read_until_closed(&mut slow).await.contains("200 OK"));
+
+        let (addr, _shutdown) = start(ServerLimits {
+            idle_timeout: Duration::from_millis(200),
+            ..limits()
+        })
+        .await;
+        let mut idle = TcpStream::connect(addr).await.unwrap();
+        idle.write_all(REQUEST).await.unwrap();
+        let response = read_until_closed(&mut idle).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.ends_with(&idle.local_addr().unwrap().to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_connections_over_the_limit_wait() {
+        let (addr, shutdown) = start(ServerLimits {
+            max_connections: 1,
+            ..limits()
+        })
+        .await;
+        let first = TcpStream::connect(addr).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let mut second = TcpStream::connect(addr).await.unwrap();
+        second.write_all(REQUEST).await.unwrap();
+        let mut received = [0; 16];
+        let read = tokio::time::timeout(Duration::from_millis(300), second.read(&mut received));
+        assert!(
+            read.await.is_err(),
+            "second connection served over the limit"
+        );
+
+        drop(first);
+        let mut received = vec![0; 16];
+        let read = tokio::time::timeout(Duration::from_secs(5), second.read(&mut received));
+        assert!(read.await.unwrap().unwrap() > 0);
+        assert!(received.starts_with(b"HTTP/1.1 200 OK"));
+
+        // Shutting down closes the connection kept alive after the response
+        shutdown.send(true).unwrap();
+        read_until_closed(&mut second).await;
+    }
+}
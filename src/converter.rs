@@ -0,0 +1,220 @@
+//! The [`Converter`] trait and the per-request [`ConversionOptions`]
+
+use async_trait::async_trait;
+
+use crate::{
+    error::{LibreOfficeError, Result},
+    formats::{InputFormat, OutputFormat},
+    libreoffice::{ConversionOutput, InputFile},
+    page_setup::{self, PageSetup},
+    pdf_encryption::{self, PdfEncryption},
+    tracked_changes::TrackedChanges,
+};
+
+/// Form fields of the options not covered by [`PDF_FIELDS`], [`page_setup::FIELDS`]
+/// or [`pdf_encryption::FIELDS`]
+const FIELDS: &[&str] = &[
+    "changes",
+    "strip_metadata",
+    "repair",
+    "sanitize",
+    "output_filter",
+];
+
+/// Longest `output_filter`, LibreOffice's filter names are a few words
+const MAX_FILTER_NAME_LEN: usize = 64;
+
+/// Form fields of the options passed to the PDF export filter
+pub const PDF_FIELDS: &[&str] = &["include_comments", "embed_fonts", "tagged_pdf"];
+
+/// Per-request conversion settings beyond the input and output formats
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ConversionOptions {
+    /// Print settings of spreadsheet inputs
+    pub page_setup: PageSetup,
+    /// Unset leaves the changes to LibreOffice, which shows them
+    pub changes: Option<TrackedChanges>,
+    /// Whether comments become PDF annotations
+    pub include_comments: Option<bool>,
+    /// Whether the 14 standard PDF fonts are embedded as well, all others always are
+    pub embed_fonts: Option<bool>,
+    /// Whether the PDF carries the document structure for accessibility
+    pub tagged_pdf: Option<bool>,
+    /// Removes author, company and revision metadata from the output
+    pub strip_metadata: bool,
+    /// Password protection of PDF output
+    pub encryption: PdfEncryption,
+    /// Retries inputs failing as corrupted after rebuilding their package
+    pub repair: bool,
+    /// Re-saves the input in its own format with the metadata stripped, to rid
+    /// untrusted documents of constructs LibreOffice doesn't write back
+    pub sanitize: bool,
+    /// Export filter named on LibreOffice instead of the default of the output format
+    pub output_filter: Option<String>,
+    /// Import filter forced on LibreOffice, set for the retry of a repair rather
+    /// than by a form field
+    pub input_filter: Option<&'static str>,
+}
+
+impl ConversionOptions {
+    /// Whether the form field `name` sets an option
+    pub fn is_field(name: &str) -> bool {
+        FIELDS.contains(&name)
+            || PDF_FIELDS.contains(&name)
+            || page_setup::FIELDS.contains(&name)
+            || pdf_encryption::FIELDS.contains(&name)
+    }
+
+    /// Applies the form field `name`, rejecting values that don't parse
+    pub fn set_field(&mut self, name: &str, value: &str) -> Result<()> {
+        match name {
+            "changes" => self.changes = Some(value.parse()?),
+            "include_comments" => self.include_comments = Some(parse_bool(name, value)?),
+            "embed_fonts" => self.embed_fonts = Some(parse_bool(name, value)?),
+            "tagged_pdf" => self.tagged_pdf = Some(parse_bool(name, value)?),
+            "strip_metadata" => self.strip_metadata = parse_bool(name, value)?,
+            "repair" => self.repair = parse_bool(name, value)?,
+            "sanitize" => self.sanitize = parse_bool(name, value)?,
+            "output_filter" => self.output_filter = Some(parse_filter_name(value)?),
+            _ if pdf_encryption::FIELDS.contains(&name) => {
+                self.encryption.set_field(name, value)?
+            }
+            _ => self.page_setup.set_field(name, value)?,
+        }
+        Ok(())
+    }
+
+    /// Whether the output's metadata is stripped, asked for or implied by `sanitize`
+    pub fn strips_metadata(&self) -> bool {
+        self.strip_metadata || self.sanitize
+    }
+
+    /// FilterData of the PDF export requested by the options
+    pub fn pdf_filter_data(&self) -> Vec<(&'static str, bool)> {
+        [
+            ("ExportNotes", self.include_comments),
+            ("EmbedStandardFonts", self.embed_fonts),
+            ("UseTaggedPDF", self.tagged_pdf),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| Some((name, value?)))
+        .collect()
+    }
+}
+
+/// Value of a `true`/`false` form field
+pub fn parse_bool(name: &str, value: &str) -> Result<bool> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| LibreOfficeError::InvalidOption(format!("{} must be true or false", name)))
+}
+
+/// Value of `output_filter`, a LibreOffice filter name like `MS Word 2007 XML`.
+/// Colons would end the name inside the `--convert-to` argument.
+fn parse_filter_name(value: &str) -> Result<String> {
+    let value = value.trim();
+    let valid = !value.is_empty()
+        && value.len() <= MAX_FILTER_NAME_LEN
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || " _-().".contains(c));
+    if !valid {
+        return Err(LibreOfficeError::InvalidOption(format!(
+            "output_filter must be a LibreOffice filter name of at most {} letters, digits, spaces and _-().",
+            MAX_FILTER_NAME_LEN
+        )));
+    }
+    Ok(value.to_string())
+}
+
+/// Turns an upload into the requested format, what the HTTP handlers depend on
+#[async_trait]
+pub trait Converter: Send + Sync {
+    /// Converts `input`, whose content is in format `from`, to `to`
+    async fn convert(
+        &self,
+        input: InputFile,
+        from: &InputFormat,
+        to: &OutputFormat,
+        options: &ConversionOptions,
+    ) -> Result<ConversionOutput>;
+}
+
+/// Converters for tests
+#[cfg(test)]
+pub mod fake {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::libreoffice::{ConvertedOutput, OutputFile};
+
+    /// In-memory converter answering every conversion with the same result
+    pub struct FakeConverter {
+        result: Result<ConversionOutput>,
+        /// Source and target format of every conversion requested
+        requests: Mutex<Vec<(String, String)>>,
+        last_options: Mutex<Option<ConversionOptions>>,
+    }
+
+    impl FakeConverter {
+        /// Converter answering every conversion with `output`
+        pub fn returning(output: ConversionOutput) -> Self {
+            Self {
+                result: Ok(output),
+                requests: Mutex::default(),
+                last_options: Mutex::default(),
+            }
+        }
+
+        /// Converter producing `data` as `document.{extension}`
+        pub fn returning_bytes(extension: &str, data: &[u8]) -> Self {
+            Self::returning(ConversionOutput::new(OutputFile {
+                name: format!("document.{}", extension),
+                data: ConvertedOutput::Bytes(data.to_vec()),
+            }))
+        }
+
+        /// Converter failing every conversion with `error`
+        pub fn failing(error: crate::error::LibreOfficeError) -> Self {
+            Self {
+                result: Err(error),
+                requests: Mutex::default(),
+                last_options: Mutex::default(),
+            }
+        }
+
+        /// Conversions requested so far
+        pub fn calls(&self) -> usize {
+            self.requests.lock().unwrap().len()
+        }
+
+        /// Source and target format of the conversions requested so far
+        pub fn requests(&self) -> Vec<(String, String)> {
+            self.requests.lock().unwrap().clone()
+        }
+
+        /// Options of the latest conversion
+        pub fn last_options(&self) -> Option<ConversionOptions> {
+            self.last_options.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl Converter for FakeConverter {
+        async fn convert(
+            &self,
+            _input: InputFile,
+            from: &InputFormat,
+            to: &OutputFormat,
+            options: &ConversionOptions,
+        ) -> Result<ConversionOutput> {
+            self.requests
+                .lock()
+                .unwrap()
+                .push((from.to_string(), to.to_string()));
+            *self.last_options.lock().unwrap() = Some(options.clone());
+            self.result.clone()
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use axum::http::{HeaderName, HeaderValue, Method};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+
+use crate::{config::Config, headers};
+
+/// Parses the configured values, logging and skipping invalid ones
+fn parse_all<T, E: std::fmt::Display>(
+    values: &[String],
+    kind: &str,
+    parse: impl Fn(&str) -> Result<T, E>,
+) -> Vec<T> {
+    values
+        .iter()
+        .filter_map(|value| {
+            parse(value)
+                .map_err(|e| tracing::warn!("Ignoring CORS {} {:?}: {}", kind, value, e))
+                .ok()
+        })
+        .collect()
+}
+
+/// CORS layer for the conversion routes, `None` unless `CORS_ALLOWED_ORIGINS` is set.
+/// Preflight requests are answered by the layer itself and never reach the handlers.
+pub fn layer(config: &Config) -> Option<CorsLayer> {
+    if config.cors_allowed_origins.is_empty() {
+        return None;
+    }
+
+    let origins = if config
+        .cors_allowed_origins
+        .iter()
+        .any(|origin| origin == "*")
+    {
+        AllowOrigin::from(Any)
+    } else {
+        AllowOrigin::list(parse_all(
+            &config.cors_allowed_origins,
+            "origin",
+            HeaderValue::from_str,
+        ))
+    };
+
+    Some(
+        CorsLayer::new()
+            .allow_origin(origins)
+            .allow_methods(parse_all(
+                &config.cors_allowed_methods,
+                "method",
+                |method| Method::from_bytes(method.to_ascii_uppercase().as_bytes()),
+            ))
+            .allow_headers(parse_all(&config.cors_allowed_headers, "header", |name| {
+                HeaderName::from_bytes(name.as_bytes())
+            }))
+            .expose_headers(headers::exposed()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        Router,
+        body::Body,
+        http::{Request, StatusCode, header},
+        routing::post,
+    };
+    use tower::ServiceExt;
+
+    fn config(origins: &[&str]) -> Config {
+        let mut config = Config::from_env();
+        config.cors_allowed_origins = origins.iter().map(|origin| origin.to_string()).collect();
+        config
+    }
+
+    fn preflight(origin: &str) -> Request<Body> {
+        Request::builder()
+            .method("OPTIONS")
+            .uri("/convert")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_disabled_by_default() {
+        assert!(layer(&config(&[])).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_preflight_for_allowed_origin() {
+        let cors = layer(&config(&["https://admin.example.com"])).unwrap();
+        let app = Router::new().route(
+            "/convert",
+            post(|| async { StatusCode::IM_A_TEAPOT }).layer(cors),
+        );
+
+        let response = app
+            .clone()
+            .oneshot(preflight("https://admin.example.com"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://admin.example.com"
+        );
+
+        let response = app
+            .oneshot(preflight("https://evil.example.com"))
+            .await
+            .unwrap();
+        assert!(
+            !response
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_exposes_conversion_headers() {
+        let cors = layer(&config(&["*"])).unwrap();
+        let app = Router::new().route("/convert", post(|| async { "converted" }).layer(cors));
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/convert")
+            .header(header::ORIGIN, "https://admin.example.com")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        let exposed = response.headers()[header::ACCESS_CONTROL_EXPOSE_HEADERS]
+            .to_str()
+            .unwrap();
+        assert!(exposed.contains("x-request-id"));
+        assert!(exposed.contains(headers::QUEUE_WAIT_HEADER));
+        assert!(exposed.contains(headers::CONTENT_SHA256_HEADER));
+    }
+}
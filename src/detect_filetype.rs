@@ -1,95 +1,960 @@
-#[derive(Debug, PartialEq)]
+//! Sniffing the type of a document from its content rather than its name
+
+use std::borrow::Cow;
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+
+use serde::Serialize;
+
+use crate::cfb::{self, CompoundFile};
+
+/// Kind of document told apart by its content, the `type` reported by `/detect`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
 pub enum FileType {
+    /// Word document (docx)
     Word,
+    /// PowerPoint presentation (pptx)
     PowerPoint,
+    /// Excel workbook (xlsx)
     Excel,
+    /// Word 97-2003 document (doc)
+    LegacyWord,
+    /// PowerPoint 97-2003 presentation (ppt)
+    LegacyPowerPoint,
+    /// Excel 97-2003 workbook (xls)
+    LegacyExcel,
+    /// Macro-enabled Word document (docm)
+    WordMacro,
+    /// Macro-enabled PowerPoint presentation (pptm)
+    PowerPointMacro,
+    /// Macro-enabled Excel workbook (xlsm)
+    ExcelMacro,
+    /// WordPerfect document (wpd)
+    WordPerfect,
+    /// Microsoft Works document (wps)
+    Works,
+    /// StarOffice Writer document (sdw)
+    StarWriter,
+    /// StarOffice Calc spreadsheet (sdc)
+    StarCalc,
+    /// StarOffice Impress presentation (sdd)
+    StarImpress,
+    /// StarOffice Draw drawing (sda)
+    StarDraw,
+    /// Email message (eml)
+    Email,
+    /// Outlook message (msg), which LibreOffice can't open
+    OutlookMessage,
+    /// PDF document
     Pdf,
+    /// Rich Text Format (rtf)
     RichText,
+    /// Plain text (txt)
     PlainText,
-    OpenDocument, // ODT, ODS, ODP files
-    Unknown,      // For unsupported formats
+    /// Comma-separated values (csv)
+    Csv,
+    /// Tab-separated values (tsv)
+    Tsv,
+    /// HTML page
+    Html,
+    /// XML document
+    Xml,
+    /// PNG image
+    Png,
+    /// JPEG image
+    Jpeg,
+    /// TIFF image
+    Tiff,
+    /// BMP image
+    Bmp,
+    /// GIF image
+    Gif,
+    /// WebP image
+    Webp,
+    /// OpenDocument text (odt)
+    OpenDocumentText,
+    /// OpenDocument spreadsheet (ods)
+    OpenDocumentSpreadsheet,
+    /// OpenDocument presentation (odp)
+    OpenDocumentPresentation,
+    /// OpenDocument drawing (odg)
+    OpenDocumentGraphics,
+    /// Compound file holding none of the known documents, left for LibreOffice to judge
+    CompoundFile,
+    /// Content not recognized
+    Unknown,
 }
 
-pub fn detect_openoffice_file_type(content: &[u8]) -> FileType {
-    if content.is_empty() {
-        return FileType::Unknown;
+impl FileType {
+    /// Canonical extension of the type, none for [`FileType::CompoundFile`] and
+    /// [`FileType::Unknown`]
+    pub fn extension(&self) -> Option<&'static str> {
+        match self {
+            FileType::Word => Some("docx"),
+            FileType::PowerPoint => Some("pptx"),
+            FileType::Excel => Some("xlsx"),
+            FileType::LegacyWord => Some("doc"),
+            FileType::LegacyPowerPoint => Some("ppt"),
+            FileType::LegacyExcel => Some("xls"),
+            FileType::WordMacro => Some("docm"),
+            FileType::PowerPointMacro => Some("pptm"),
+            FileType::ExcelMacro => Some("xlsm"),
+            FileType::WordPerfect => Some("wpd"),
+            FileType::Works => Some("wps"),
+            FileType::StarWriter => Some("sdw"),
+            FileType::StarCalc => Some("sdc"),
+            FileType::StarImpress => Some("sdd"),
+            FileType::StarDraw => Some("sda"),
+            FileType::Email => Some("eml"),
+            FileType::OutlookMessage => Some("msg"),
+            FileType::Pdf => Some("pdf"),
+            FileType::RichText => Some("rtf"),
+            FileType::PlainText => Some("txt"),
+            FileType::Csv => Some("csv"),
+            FileType::Tsv => Some("tsv"),
+            FileType::Html => Some("html"),
+            FileType::Xml => Some("xml"),
+            FileType::Png => Some("png"),
+            FileType::Jpeg => Some("jpg"),
+            FileType::Tiff => Some("tiff"),
+            FileType::Bmp => Some("bmp"),
+            FileType::Gif => Some("gif"),
+            FileType::Webp => Some("webp"),
+            FileType::OpenDocumentText => Some("odt"),
+            FileType::OpenDocumentSpreadsheet => Some("ods"),
+            FileType::OpenDocumentPresentation => Some("odp"),
+            FileType::OpenDocumentGraphics => Some("odg"),
+            FileType::CompoundFile | FileType::Unknown => None,
+        }
     }
 
-    let content_slice = content.get(..1024).unwrap_or(content);
-    match content_slice {
-        b if b.starts_with(b"%PDF-") => FileType::Pdf,
-        b if b.starts_with(b"{\\rtf1") => FileType::RichText,
-        b if b.starts_with(b"PK\x03\x04") || b.starts_with(b"PK\x05\x06") => {
-            detect_zip_based_format(content_slice)
+    /// Media type of the type, `application/octet-stream` for [`FileType::Unknown`]
+    pub fn mime(&self) -> &'static str {
+        match self {
+            FileType::Word => {
+                "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+            }
+            FileType::PowerPoint => {
+                "application/vnd.openxmlformats-officedocument.presentationml.presentation"
+            }
+            FileType::Excel => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+            FileType::LegacyWord => "application/msword",
+            FileType::LegacyPowerPoint => "application/vnd.ms-powerpoint",
+            FileType::LegacyExcel => "application/vnd.ms-excel",
+            FileType::WordMacro => "application/vnd.ms-word.document.macroEnabled.12",
+            FileType::PowerPointMacro => {
+                "application/vnd.ms-powerpoint.presentation.macroEnabled.12"
+            }
+            FileType::ExcelMacro => "application/vnd.ms-excel.sheet.macroEnabled.12",
+            FileType::WordPerfect => "application/vnd.wordperfect",
+            FileType::Works => "application/vnd.ms-works",
+            FileType::StarWriter => "application/vnd.stardivision.writer",
+            FileType::StarCalc => "application/vnd.stardivision.calc",
+            FileType::StarImpress => "application/vnd.stardivision.impress",
+            FileType::StarDraw => "application/vnd.stardivision.draw",
+            FileType::Email => "message/rfc822",
+            FileType::OutlookMessage => "application/vnd.ms-outlook",
+            FileType::Pdf => "application/pdf",
+            FileType::RichText => "application/rtf",
+            FileType::PlainText => "text/plain",
+            FileType::Csv => "text/csv",
+            FileType::Tsv => "text/tab-separated-values",
+            FileType::Html => "text/html",
+            FileType::Xml => "application/xml",
+            FileType::Png => "image/png",
+            FileType::Jpeg => "image/jpeg",
+            FileType::Tiff => "image/tiff",
+            FileType::Bmp => "image/bmp",
+            FileType::Gif => "image/gif",
+            FileType::Webp => "image/webp",
+            FileType::OpenDocumentText => "application/vnd.oasis.opendocument.text",
+            FileType::OpenDocumentSpreadsheet => "application/vnd.oasis.opendocument.spreadsheet",
+            FileType::OpenDocumentPresentation => "application/vnd.oasis.opendocument.presentation",
+            FileType::OpenDocumentGraphics => "application/vnd.oasis.opendocument.graphics",
+            FileType::CompoundFile => "application/x-ole-storage",
+            FileType::Unknown => "application/octet-stream",
         }
-        b if b.starts_with(b"\xD0\xCF\x11\xE0\xA1\xB1\x1A\xE1") => {
-            detect_ole2_format(content_slice)
+    }
+
+    /// OOXML documents carrying a VBA project
+    pub fn has_macros(&self) -> bool {
+        matches!(
+            self,
+            FileType::WordMacro | FileType::PowerPointMacro | FileType::ExcelMacro
+        )
+    }
+
+    /// Raster images, which LibreOffice opens in Draw
+    pub fn is_image(&self) -> bool {
+        matches!(
+            self,
+            FileType::Png
+                | FileType::Jpeg
+                | FileType::Tiff
+                | FileType::Bmp
+                | FileType::Gif
+                | FileType::Webp
+        )
+    }
+}
+
+/// How far a detection result can be trusted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Confidence {
+    /// Identified by magic bytes or the container structure
+    Certain,
+    /// Identified by heuristics, e.g. markup or a consistent delimiter in text
+    Likely,
+    /// Content not recognized
+    Unknown,
+}
+
+/// Outcome of sniffing a file's content
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DetectedType {
+    /// Kind of document
+    pub file_type: FileType,
+    /// Media type of the file type, `application/octet-stream` when unrecognized
+    pub mime: &'static str,
+    /// Canonical extension, `bin` for unrecognized content
+    pub extension: &'static str,
+    /// How far the detection can be trusted
+    pub confidence: Confidence,
+    /// Character encoding of text content, none for binary formats
+    pub encoding: Option<TextEncoding>,
+}
+
+impl DetectedType {
+    fn new(file_type: FileType, confidence: Confidence) -> Self {
+        Self {
+            file_type,
+            mime: file_type.mime(),
+            extension: file_type.extension().unwrap_or("bin"),
+            confidence: match file_type {
+                FileType::Unknown => Confidence::Unknown,
+                _ => confidence,
+            },
+            encoding: None,
+        }
+    }
+
+    fn text(file_type: FileType, encoding: TextEncoding) -> Self {
+        Self {
+            encoding: Some(encoding),
+            ..Self::new(file_type, Confidence::Likely)
         }
-        _b if is_likely_text(content_slice) => FileType::PlainText,
-        _ => FileType::Unknown,
     }
 }
 
-fn detect_zip_based_format(content: &[u8]) -> FileType {
-    // Look for specific content type strings in ZIP central directory
-    let content_str = String::from_utf8_lossy(content);
+/// Character encoding of a text file, from its BOM or guessed from the content
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum TextEncoding {
+    /// UTF-8, with or without BOM
+    #[serde(rename = "utf-8")]
+    Utf8,
+    /// UTF-16, little-endian
+    #[serde(rename = "utf-16le")]
+    Utf16Le,
+    /// UTF-16, big-endian
+    #[serde(rename = "utf-16be")]
+    Utf16Be,
+    /// UTF-32, little-endian
+    #[serde(rename = "utf-32le")]
+    Utf32Le,
+    /// UTF-32, big-endian
+    #[serde(rename = "utf-32be")]
+    Utf32Be,
+}
 
-    // Office Open XML formats
-    if content_str.contains("word/")
-        || content_str.contains("application/vnd.openxmlformats-officedocument.wordprocessingml")
-    {
-        return FileType::Word;
+/// Detects raster images by their magic bytes
+fn detect_image(content: &[u8]) -> Option<FileType> {
+    match content {
+        b if b.starts_with(b"\x89PNG\r\n\x1a\n") => Some(FileType::Png),
+        b if b.starts_with(b"\xFF\xD8\xFF") => Some(FileType::Jpeg),
+        b if b.starts_with(b"II*\x00") || b.starts_with(b"MM\x00*") => Some(FileType::Tiff),
+        b if b.starts_with(b"GIF87a") || b.starts_with(b"GIF89a") => Some(FileType::Gif),
+        b if b.starts_with(b"RIFF") && b.get(8..12) == Some(b"WEBP") => Some(FileType::Webp),
+        b if is_bmp(b) => Some(FileType::Bmp),
+        _ => None,
     }
+}
 
-    if content_str.contains("ppt/")
-        || content_str.contains("application/vnd.openxmlformats-officedocument.presentationml")
-    {
-        return FileType::PowerPoint;
+/// "BM" alone is too weak (text may start with it), also check the reserved
+/// fields and the DIB header size
+fn is_bmp(content: &[u8]) -> bool {
+    const DIB_HEADER_SIZES: &[u32] = &[12, 40, 52, 56, 64, 108, 124];
+
+    content.starts_with(b"BM")
+        && content.get(6..10) == Some(&[0; 4])
+        && read_u32(content, 14).is_some_and(|size| DIB_HEADER_SIZES.contains(&size))
+}
+
+/// Prefix of the ODF media types, followed by the document kind
+const ODF_MIMETYPE_PREFIX: &[u8] = b"application/vnd.oasis.opendocument.";
+
+/// Maps an ODF media type (templates included) to its document type
+fn open_document_type(mimetype: &[u8]) -> Option<FileType> {
+    let kind = mimetype.trim_ascii().strip_prefix(ODF_MIMETYPE_PREFIX)?;
+    match kind {
+        k if k.starts_with(b"text") => Some(FileType::OpenDocumentText),
+        k if k.starts_with(b"spreadsheet") => Some(FileType::OpenDocumentSpreadsheet),
+        k if k.starts_with(b"presentation") => Some(FileType::OpenDocumentPresentation),
+        k if k.starts_with(b"graphics") => Some(FileType::OpenDocumentGraphics),
+        _ => None,
+    }
+}
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+const UTF16LE_BOM: &[u8] = b"\xFF\xFE";
+const UTF16BE_BOM: &[u8] = b"\xFE\xFF";
+const UTF32LE_BOM: &[u8] = b"\xFF\xFE\x00\x00";
+const UTF32BE_BOM: &[u8] = b"\x00\x00\xFE\xFF";
+
+/// Bytes looked at when guessing UTF-16 without a BOM
+const UTF16_SAMPLE_LEN: usize = 512;
+
+/// Root element of flat (single XML file) ODF documents
+const FLAT_ODF_ROOT: &[u8] = b"office:document";
+const FLAT_ODF_MIMETYPE_ATTRIBUTE: &[u8] = b"office:mimetype=\"";
+
+/// Detects HTML and XML from the first markup after an optional BOM, whitespace,
+/// XML declaration, processing instructions and comments. Flat ODF gets its
+/// document type, SVG is not a supported input and yields [`FileType::Unknown`].
+fn detect_markup(content: &[u8]) -> Option<FileType> {
+    let mut rest = content.strip_prefix(UTF8_BOM).unwrap_or(content);
+    let mut declared = false;
+
+    loop {
+        rest = rest.trim_ascii_start();
+        if starts_with_ignore_case(rest, b"<?") {
+            declared |= starts_with_ignore_case(rest, b"<?xml");
+            rest = skip_past(rest, b"?>")?;
+        } else if rest.starts_with(b"<!--") {
+            rest = skip_past(rest, b"-->")?;
+        } else {
+            break;
+        }
     }
 
-    if content_str.contains("xl/")
-        || content_str.contains("application/vnd.openxmlformats-officedocument.spreadsheetml")
+    if starts_with_ignore_case(rest, b"<!doctype") {
+        let name = element_name(rest[b"<!doctype".len()..].trim_ascii_start());
+        return Some(markup_type(name));
+    }
+
+    match rest.strip_prefix(b"<") {
+        Some(root)
+            if root
+                .first()
+                .is_some_and(|b| b.is_ascii_alphabetic() || *b == b'_') =>
+        {
+            let name = element_name(root);
+            if name == FLAT_ODF_ROOT {
+                return Some(detect_flat_odf(root).unwrap_or(FileType::Xml));
+            }
+            Some(markup_type(name))
+        }
+        _ if declared => Some(FileType::Xml),
+        _ => None,
+    }
+}
+
+/// Type of a document by its doctype or root element name
+fn markup_type(name: &[u8]) -> FileType {
+    let local_name = name.rsplit(|&b| b == b':').next().unwrap_or(name);
+    if local_name.eq_ignore_ascii_case(b"html") {
+        FileType::Html
+    } else if local_name.eq_ignore_ascii_case(b"svg") {
+        FileType::Unknown
+    } else {
+        FileType::Xml
+    }
+}
+
+/// Leading name of an element or doctype declaration
+fn element_name(content: &[u8]) -> &[u8] {
+    let end = content
+        .iter()
+        .position(|b| b.is_ascii_whitespace() || matches!(b, b'>' | b'/' | b'['))
+        .unwrap_or(content.len());
+    &content[..end]
+}
+
+fn starts_with_ignore_case(content: &[u8], prefix: &[u8]) -> bool {
+    content
+        .get(..prefix.len())
+        .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+}
+
+/// The content after the first `terminator`, none if it is cut off
+fn skip_past<'a>(content: &'a [u8], terminator: &[u8]) -> Option<&'a [u8]> {
+    find(content, terminator).map(|position| &content[position + terminator.len()..])
+}
+
+/// Detects flat ODF (.fodt, .fods, ...) from the `office:mimetype` attribute of
+/// the `office:document` root element
+fn detect_flat_odf(root: &[u8]) -> Option<FileType> {
+    let root = &root[..root.iter().position(|&b| b == b'>')?];
+    let value_start = find(root, FLAT_ODF_MIMETYPE_ATTRIBUTE)? + FLAT_ODF_MIMETYPE_ATTRIBUTE.len();
+    let value = &root[value_start..];
+    open_document_type(&value[..value.iter().position(|&b| b == b'"')?])
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Local file header signature, the start of every zip archive
+const ZIP_LOCAL_HEADER: &[u8] = b"PK\x03\x04";
+const ZIP_CENTRAL_HEADER: &[u8] = b"PK\x01\x02";
+const ZIP_END_OF_CENTRAL_DIRECTORY: &[u8] = b"PK\x05\x06";
+const ZIP_LOCAL_HEADER_LEN: usize = 30;
+const ZIP_CENTRAL_HEADER_LEN: usize = 46;
+const ZIP_END_OF_CENTRAL_DIRECTORY_LEN: usize = 22;
+/// Longest archive comment, bounding the search for the end of central directory record
+const ZIP_MAX_COMMENT_LEN: usize = u16::MAX as usize;
+/// Sizes are only known after the data when this general purpose flag is set
+const ZIP_FLAG_DATA_DESCRIPTOR: u16 = 1 << 3;
+const ZIP_METHOD_STORED: u16 = 0;
+const ZIP_METHOD_DEFLATED: u16 = 8;
+/// Longer `mimetype` entries are not ODF
+const ODF_MAX_MIMETYPE_LEN: usize = 128;
+
+/// Bytes read from the start of a file for detection
+pub const DETECTION_HEADER_LEN: usize = 8 * 1024;
+
+/// Bytes at the end of a file needed to find a zip's central directory, see
+/// [`detect_file_type_with_tail`]
+const ZIP_TAIL_LEN: usize = 64 * 1024 + ZIP_END_OF_CENTRAL_DIRECTORY_LEN;
+
+/// Detects the type from the first [`DETECTION_HEADER_LEN`] bytes, seeking further
+/// only where the format needs it: to the central directory at the end of a zip
+/// archive and through the directory of a compound file
+pub fn detect_file_type_from_reader<R: Read + Seek>(reader: &mut R) -> io::Result<DetectedType> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    let mut header = Vec::with_capacity(DETECTION_HEADER_LEN);
+    reader
+        .take(DETECTION_HEADER_LEN as u64)
+        .read_to_end(&mut header)?;
+
+    if len <= header.len() as u64 {
+        return Ok(detect_file_type_with_tail(&header, &header));
+    }
+
+    if header.starts_with(cfb::SIGNATURE) {
+        return Ok(detect_compound_file_type(reader));
+    }
+
+    if !header.starts_with(ZIP_LOCAL_HEADER) && !header.starts_with(ZIP_END_OF_CENTRAL_DIRECTORY) {
+        return Ok(detect_file_type_with_tail(&header, &[]));
+    }
+
+    let tail_len = len.min(ZIP_TAIL_LEN as u64);
+    reader.seek(SeekFrom::Start(len - tail_len))?;
+    let mut tail = Vec::with_capacity(tail_len as usize);
+    reader.read_to_end(&mut tail)?;
+    Ok(detect_file_type_with_tail(&header, &tail))
+}
+
+/// Detects the type from the start of a file and, for zip archives whose central
+/// directory lies beyond `header`, its last [`ZIP_TAIL_LEN`] bytes
+fn detect_file_type_with_tail(header: &[u8], tail: &[u8]) -> DetectedType {
+    if header.is_empty() {
+        return DetectedType::new(FileType::Unknown, Confidence::Unknown);
+    }
+
+    let content_slice = header.get(..1024).unwrap_or(header);
+    let (file_type, confidence) = match content_slice {
+        b if b.starts_with(b"%PDF-") => (FileType::Pdf, Confidence::Certain),
+        b if b.starts_with(b"{\\rtf1") => (FileType::RichText, Confidence::Certain),
+        b if b.starts_with(WORDPERFECT_SIGNATURE) => (FileType::WordPerfect, Confidence::Certain),
+        b if b.starts_with(ZIP_LOCAL_HEADER) || b.starts_with(ZIP_END_OF_CENTRAL_DIRECTORY) => {
+            (detect_zip_based_format(header, tail), Confidence::Certain)
+        }
+        // Only complete when the directory lies within `header`, see `detect_compound_file_type`
+        b if b.starts_with(cfb::SIGNATURE) => {
+            return detect_compound_file_type(Cursor::new(header));
+        }
+        b if let Some(image) = detect_image(b) => (image, Confidence::Certain),
+        b if let Some(encoding) = detect_text_encoding(b) => {
+            let text = decode_text(header, encoding);
+            let file_type = detect_markup(&text)
+                .or_else(|| detect_email(&text))
+                .or_else(|| detect_delimited(&text))
+                .unwrap_or(FileType::PlainText);
+            return DetectedType::text(file_type, encoding);
+        }
+        _ => (FileType::Unknown, Confidence::Unknown),
+    };
+
+    DetectedType::new(file_type, confidence)
+}
+
+/// Entry of a zip archive, `data` is only known for local headers
+struct ZipEntry<'a> {
+    name: &'a [u8],
+    method: u16,
+    data: Option<&'a [u8]>,
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> Option<u16> {
+    let bytes = buf.get(offset..offset + 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> Option<u32> {
+    let bytes = buf.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Entries of the local file headers at the start of `content`, stopping at the
+/// first one that is cut off or whose size is only given after its data
+fn zip_local_entries(content: &[u8]) -> impl Iterator<Item = ZipEntry<'_>> {
+    let mut next = Some(0);
+    std::iter::from_fn(move || {
+        let offset = next?;
+        let header = content.get(offset..offset + ZIP_LOCAL_HEADER_LEN)?;
+        if !header.starts_with(ZIP_LOCAL_HEADER) {
+            return None;
+        }
+
+        let flags = read_u16(header, 6)?;
+        let method = read_u16(header, 8)?;
+        let compressed_len = read_u32(header, 18)? as usize;
+        let name_len = read_u16(header, 26)? as usize;
+        let extra_len = read_u16(header, 28)? as usize;
+
+        let name_start = offset + ZIP_LOCAL_HEADER_LEN;
+        let data_start = name_start + name_len + extra_len;
+        let name = content.get(name_start..name_start + name_len)?;
+        let data = content.get(data_start..data_start + compressed_len);
+
+        next = (flags & ZIP_FLAG_DATA_DESCRIPTOR == 0).then_some(data_start + compressed_len);
+
+        Some(ZipEntry { name, method, data })
+    })
+}
+
+/// Entries of the central directory, when `tail` ends with the whole of it
+fn zip_central_entries(tail: &[u8]) -> impl Iterator<Item = ZipEntry<'_>> {
+    let directory = find_central_directory(tail).unwrap_or_default();
+    let mut offset = 0;
+    std::iter::from_fn(move || {
+        let header = directory.get(offset..offset + ZIP_CENTRAL_HEADER_LEN)?;
+        if !header.starts_with(ZIP_CENTRAL_HEADER) {
+            return None;
+        }
+
+        let method = read_u16(header, 10)?;
+        let name_len = read_u16(header, 28)? as usize;
+        let extra_len = read_u16(header, 30)? as usize;
+        let comment_len = read_u16(header, 32)? as usize;
+
+        let name_start = offset + ZIP_CENTRAL_HEADER_LEN;
+        let name = directory.get(name_start..name_start + name_len)?;
+        offset = name_start + name_len + extra_len + comment_len;
+
+        Some(ZipEntry {
+            name,
+            method,
+            data: None,
+        })
+    })
+}
+
+/// Locates the central directory through the end of central directory record,
+/// which sits at the very end of the archive followed only by the comment
+fn find_central_directory(tail: &[u8]) -> Option<&[u8]> {
+    let last_record = tail.len().checked_sub(ZIP_END_OF_CENTRAL_DIRECTORY_LEN)?;
+    let first_record = last_record.saturating_sub(ZIP_MAX_COMMENT_LEN);
+
+    (first_record..=last_record).rev().find_map(|position| {
+        let record = &tail[position..];
+        if !record.starts_with(ZIP_END_OF_CENTRAL_DIRECTORY)
+            || ZIP_END_OF_CENTRAL_DIRECTORY_LEN + read_u16(record, 20)? as usize != record.len()
+        {
+            return None;
+        }
+
+        // Measured back from the record, so a tail not starting at offset 0 works too
+        let directory_len = read_u32(record, 12)? as usize;
+        let directory_start = position.checked_sub(directory_len)?;
+        tail.get(directory_start..position)
+    })
+}
+
+fn detect_zip_based_format(header: &[u8], tail: &[u8]) -> FileType {
+    // ODF requires a `mimetype` entry first in the archive
+    if let Some(first) = zip_local_entries(header).next()
+        && first.name == b"mimetype"
     {
-        return FileType::Excel;
+        return first
+            .data
+            .and_then(|data| read_odf_mimetype(data, first.method))
+            .unwrap_or(FileType::Unknown);
     }
 
-    // OpenDocument formats
-    if content_str.contains("application/vnd.oasis.opendocument") {
-        return FileType::OpenDocument;
+    let mut has_content_types = false;
+    let mut has_vba_project = false;
+    let (mut word, mut presentation, mut spreadsheet) = (false, false, false);
+
+    for entry in zip_local_entries(header).chain(zip_central_entries(tail)) {
+        match entry.name {
+            b"[Content_Types].xml" => has_content_types = true,
+            b"word/vbaProject.bin" | b"ppt/vbaProject.bin" | b"xl/vbaProject.bin" => {
+                has_vba_project = true;
+                word |= entry.name.starts_with(b"word/");
+                presentation |= entry.name.starts_with(b"ppt/");
+                spreadsheet |= entry.name.starts_with(b"xl/");
+            }
+            name if name.starts_with(b"word/") => word = true,
+            name if name.starts_with(b"ppt/") => presentation = true,
+            name if name.starts_with(b"xl/") => spreadsheet = true,
+            _ => {}
+        }
     }
 
-    // Check for [Content_Types].xml which is present in Office Open XML files
-    if content_str.contains("[Content_Types].xml") {
-        // This is likely an Office document, but we couldn't determine the specific type
-        // Default to Word as it's most common
-        return FileType::Word;
+    // Office Open XML parts only count in a package with a content types part
+    if has_content_types {
+        return match (word, presentation, spreadsheet, has_vba_project) {
+            (true, _, _, true) => FileType::WordMacro,
+            (_, true, _, true) => FileType::PowerPointMacro,
+            (_, _, true, true) => FileType::ExcelMacro,
+            (true, _, _, false) => FileType::Word,
+            (_, true, _, false) => FileType::PowerPoint,
+            (_, _, true, false) => FileType::Excel,
+            // Main part not seen, default to Word as it's most common
+            _ => FileType::Word,
+        };
     }
 
     FileType::Unknown
 }
 
-fn detect_ole2_format(content: &[u8]) -> FileType {
-    // For OLE2 documents, we need to look deeper into the structure
-    // This is a simplified detection - in practice, you'd parse the OLE2 structure
-    let content_str = String::from_utf8_lossy(content);
+/// Reads the ODF `mimetype` entry, which should be stored but is tolerated compressed
+fn read_odf_mimetype(data: &[u8], method: u16) -> Option<FileType> {
+    match method {
+        ZIP_METHOD_STORED => open_document_type(data),
+        ZIP_METHOD_DEFLATED => {
+            let mimetype =
+                miniz_oxide::inflate::decompress_to_vec_with_limit(data, ODF_MAX_MIMETYPE_LEN)
+                    .ok()?;
+            open_document_type(&mimetype)
+        }
+        _ => None,
+    }
+}
+
+/// WordPerfect 5 and later, followed by the offset of the document area
+const WORDPERFECT_SIGNATURE: &[u8] = b"\xFFWPC";
 
-    // Look for application-specific signatures
-    if content_str.contains("Microsoft Office Word") || content_str.contains("Word.Document") {
-        return FileType::Word;
+/// Root streams of StarOffice 3 to 5 documents
+const STAR_OFFICE_STREAMS: &[(&str, FileType)] = &[
+    ("StarWriterDocument", FileType::StarWriter),
+    ("StarCalcDocument", FileType::StarCalc),
+    ("StarImpressDocument", FileType::StarImpress),
+    ("StarDrawDocument3", FileType::StarDraw),
+    ("StarDrawDocument", FileType::StarDraw),
+];
+
+/// Start of the `CONTENTS` stream of Works 5 and later word processor documents
+const WORKS_CONTENTS_SIGNATURE: &[u8] = b"CHNKWKS ";
+
+/// Classifies a compound file (legacy Office, Works, StarOffice) by the streams of
+/// its root storage. Files that can't be parsed or hold none of the known streams
+/// are a [`FileType::CompoundFile`] of unknown confidence.
+pub fn detect_compound_file_type<R: Read + Seek>(reader: R) -> DetectedType {
+    match CompoundFile::open(reader).and_then(classify_compound_file) {
+        Ok(Some(file_type)) => DetectedType::new(file_type, Confidence::Certain),
+        Ok(None) => DetectedType::new(FileType::CompoundFile, Confidence::Unknown),
+        Err(e) => {
+            tracing::debug!("Could not read compound file: {}", e);
+            DetectedType::new(FileType::CompoundFile, Confidence::Unknown)
+        }
     }
+}
+
+fn classify_compound_file<R: Read + Seek>(
+    mut file: CompoundFile<R>,
+) -> io::Result<Option<FileType>> {
+    let streams: Vec<String> = file
+        .root_streams()
+        .into_iter()
+        .map(|entry| entry.name.clone())
+        .collect();
+    let has_stream = |name: &str| streams.iter().any(|stream| stream == name);
 
-    if content_str.contains("Microsoft Office PowerPoint")
-        || content_str.contains("PowerPoint Document")
+    if has_stream("WordDocument") {
+        return Ok(Some(FileType::LegacyWord));
+    }
+    if has_stream("Workbook") || has_stream("Book") {
+        return Ok(Some(FileType::LegacyExcel));
+    }
+    if has_stream("PowerPoint Document") {
+        return Ok(Some(FileType::LegacyPowerPoint));
+    }
+    if has_stream("__properties_version1.0") {
+        return Ok(Some(FileType::OutlookMessage));
+    }
+    if let Some(&(_, file_type)) = STAR_OFFICE_STREAMS
+        .iter()
+        .find(|(name, _)| has_stream(name))
+    {
+        return Ok(Some(file_type));
+    }
+    // Works 4 keeps its text in `MN0`, later versions in chunks of `CONTENTS`
+    if has_stream("MN0")
+        || file
+            .read_stream_prefix("CONTENTS", WORKS_CONTENTS_SIGNATURE.len())?
+            .is_some_and(|contents| contents == WORKS_CONTENTS_SIGNATURE)
     {
-        return FileType::PowerPoint;
+        return Ok(Some(FileType::Works));
     }
 
-    if content_str.contains("Microsoft Excel") || content_str.contains("Workbook") {
-        return FileType::Excel;
+    Ok(None)
+}
+
+/// Header fields of which an email is expected to carry at least two
+const EMAIL_HEADERS: &[&[u8]] = &[
+    b"received",
+    b"return-path",
+    b"from",
+    b"to",
+    b"subject",
+    b"date",
+    b"message-id",
+    b"mime-version",
+];
+
+/// Detects an RFC 822 message by its header block: every line up to the first
+/// blank one is a header field or its continuation, with at least two well-known
+/// fields among them
+fn detect_email(content: &[u8]) -> Option<FileType> {
+    let mut known = 0;
+
+    for line in content.split(|&b| b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() {
+            break;
+        }
+        if line[0] == b' ' || line[0] == b'\t' {
+            continue;
+        }
+
+        let name = &line[..find(line, b":")?];
+        if name.is_empty() || !name.iter().all(|b| b.is_ascii_graphic()) {
+            return None;
+        }
+        if EMAIL_HEADERS
+            .iter()
+            .any(|header| name.eq_ignore_ascii_case(header))
+        {
+            known += 1;
+        }
     }
 
-    // Generic OLE2 document - could be any Office format
-    FileType::Word // Default assumption
+    (known >= 2).then_some(FileType::Email)
+}
+
+/// Records sampled when looking for a delimiter
+const DELIMITED_SAMPLE_RECORDS: usize = 20;
+
+/// Candidate delimiters in order of preference when several fit
+const DELIMITERS: &[u8] = b"\t;,|";
+
+/// Detects CSV and TSV: a delimiter splitting the sampled records into the same
+/// number of columns, at least two of them
+fn detect_delimited(content: &[u8]) -> Option<FileType> {
+    DELIMITERS
+        .iter()
+        .rev()
+        .filter_map(|&delimiter| {
+            column_count(content, delimiter)
+                .filter(|&columns| columns >= 2)
+                .map(|columns| (delimiter, columns))
+        })
+        // The last maximum wins, which is the most preferred delimiter
+        .max_by_key(|&(_, columns)| columns)
+        .map(|(delimiter, _)| match delimiter {
+            b'\t' => FileType::Tsv,
+            _ => FileType::Csv,
+        })
+}
+
+/// Column count shared by all sampled records when split on `delimiter`, none if
+/// it varies or fewer than two records were seen. Quoted fields may contain
+/// delimiters and line breaks, blank lines are skipped.
+fn column_count(content: &[u8], delimiter: u8) -> Option<usize> {
+    let mut expected = None;
+    let mut records = 0;
+    let mut columns = 1;
+    let mut blank = true;
+    let mut quoted = false;
+
+    for &b in content {
+        match b {
+            b'"' => quoted = !quoted,
+            b'\n' if !quoted => {
+                if !blank {
+                    if *expected.get_or_insert(columns) != columns {
+                        return None;
+                    }
+                    records += 1;
+                    if records == DELIMITED_SAMPLE_RECORDS {
+                        break;
+                    }
+                }
+                columns = 1;
+                blank = true;
+                continue;
+            }
+            b'\r' => continue,
+            b if b == delimiter && !quoted => columns += 1,
+            _ => {}
+        }
+        blank = false;
+    }
+
+    // An unterminated last record may have been cut off by the sniffed header
+    if !blank && records < DELIMITED_SAMPLE_RECORDS {
+        match expected {
+            Some(expected) if columns > expected => return None,
+            Some(expected) if columns == expected && !quoted => records += 1,
+            _ => {}
+        }
+    }
+
+    expected.filter(|_| records >= 2)
+}
+
+/// Streams of OOXML documents encrypted with a password, which are wrapped in a
+/// compound file, and of encrypted PowerPoint 97 presentations
+const ENCRYPTED_STREAMS: &[&str] = &["EncryptionInfo", "EncryptedPackage", "EncryptedSummary"];
+
+/// Bytes of the Excel workbook stream searched for a FILEPASS record
+const WORKBOOK_PROBE_LEN: usize = 16 * 1024;
+
+const BIFF_EOF: u16 = 0x000A;
+const BIFF_FILEPASS: u16 = 0x002F;
+
+/// Whether a compound file is a password protected document: encrypted OOXML, or a
+/// legacy Word or Excel file with its encryption flag set
+pub fn is_encrypted_compound_file<R: Read + Seek>(reader: R) -> io::Result<bool> {
+    let mut file = CompoundFile::open(reader)?;
+    if file
+        .root_streams()
+        .iter()
+        .any(|entry| ENCRYPTED_STREAMS.contains(&entry.name.as_str()))
+    {
+        return Ok(true);
+    }
+
+    // fEncrypted, bit 8 of the flags at offset 0x0A of the FIB
+    if let Some(fib) = file.read_stream_prefix("WordDocument", 0x0C)? {
+        return Ok(fib.get(0x0B).is_some_and(|flags| flags & 0x01 != 0));
+    }
+
+    for name in ["Workbook", "Book"] {
+        if let Some(workbook) = file.read_stream_prefix(name, WORKBOOK_PROBE_LEN)? {
+            return Ok(has_filepass_record(&workbook));
+        }
+    }
+
+    Ok(false)
+}
+
+/// Scans the BIFF records of the workbook globals, which start with FILEPASS
+/// right after BOF when the workbook is encrypted
+fn has_filepass_record(workbook: &[u8]) -> bool {
+    let mut offset = 0;
+    while let (Some(kind), Some(len)) = (read_u16(workbook, offset), read_u16(workbook, offset + 2))
+    {
+        match kind {
+            BIFF_FILEPASS => return true,
+            BIFF_EOF => return false,
+            _ => offset += 4 + len as usize,
+        }
+    }
+    false
+}
+
+/// Encoding of text content, by its BOM, the zero bytes of UTF-16 or as (mostly)
+/// printable UTF-8. None for binary content.
+fn detect_text_encoding(content: &[u8]) -> Option<TextEncoding> {
+    let encoding = match content {
+        // Before UTF-16LE, whose BOM is a prefix of it
+        b if b.starts_with(UTF32LE_BOM) => TextEncoding::Utf32Le,
+        b if b.starts_with(UTF32BE_BOM) => TextEncoding::Utf32Be,
+        b if b.starts_with(UTF8_BOM) => TextEncoding::Utf8,
+        b if b.starts_with(UTF16LE_BOM) => TextEncoding::Utf16Le,
+        b if b.starts_with(UTF16BE_BOM) => TextEncoding::Utf16Be,
+        b => return guess_utf16(b).or_else(|| is_likely_text(b).then_some(TextEncoding::Utf8)),
+    };
+    Some(encoding)
+}
+
+/// Recognizes UTF-16 without BOM by one byte of nearly every code unit being zero,
+/// as in Latin text, and the decoded sample being printable
+fn guess_utf16(content: &[u8]) -> Option<TextEncoding> {
+    let sample = &content[..content.len().min(UTF16_SAMPLE_LEN) & !1];
+    let units = sample.len() / 2;
+    if units < 2 {
+        return None;
+    }
+
+    let zeros = |offset: usize| {
+        sample
+            .iter()
+            .skip(offset)
+            .step_by(2)
+            .filter(|&&b| b == 0)
+            .count()
+    };
+    let (even_zeros, odd_zeros) = (zeros(0), zeros(1));
+    let encoding = if even_zeros == 0 && odd_zeros * 10 >= units * 9 {
+        TextEncoding::Utf16Le
+    } else if odd_zeros == 0 && even_zeros * 10 >= units * 9 {
+        TextEncoding::Utf16Be
+    } else {
+        return None;
+    };
+
+    is_likely_text(&decode_text(sample, encoding)).then_some(encoding)
+}
+
+/// Content as UTF-8 without BOM, invalid code units become U+FFFD
+fn decode_text(content: &[u8], encoding: TextEncoding) -> Cow<'_, [u8]> {
+    let text: String = match encoding {
+        TextEncoding::Utf8 => {
+            return Cow::Borrowed(content.strip_prefix(UTF8_BOM).unwrap_or(content));
+        }
+        TextEncoding::Utf16Le | TextEncoding::Utf16Be => {
+            let units = content.chunks_exact(2).map(|unit| match encoding {
+                TextEncoding::Utf16Le => u16::from_le_bytes([unit[0], unit[1]]),
+                _ => u16::from_be_bytes([unit[0], unit[1]]),
+            });
+            char::decode_utf16(units)
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect()
+        }
+        TextEncoding::Utf32Le | TextEncoding::Utf32Be => content
+            .chunks_exact(4)
+            .map(|unit| {
+                let unit = unit.try_into().unwrap();
+                let code = match encoding {
+                    TextEncoding::Utf32Le => u32::from_le_bytes(unit),
+                    _ => u32::from_be_bytes(unit),
+                };
+                char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+            })
+            .collect(),
+    };
+
+    Cow::Owned(text.trim_start_matches('\u{FEFF}').as_bytes().to_vec())
 }
 
 fn is_likely_text(content: &[u8]) -> bool {
@@ -111,9 +976,10 @@ fn is_likely_text(content: &[u8]) -> bool {
     (printable_count as f32 / total_checked as f32) > 0.9
 }
 
-/// Convenience function to detect file type from byte slice
-pub fn detect_file_type_from_bytes(bytes: &[u8]) -> FileType {
-    detect_openoffice_file_type(bytes)
+/// Detects the type of a file held in memory, see [`detect_file_type_from_reader`]
+pub fn detect_file_type_from_bytes(bytes: &[u8]) -> DetectedType {
+    detect_file_type_from_reader(&mut Cursor::new(bytes))
+        .expect("reading from memory does not fail")
 }
 
 #[cfg(test)]
@@ -123,26 +989,379 @@ mod tests {
     #[test]
     fn test_pdf_detection() {
         let pdf_header = b"%PDF-1.4\n1 0 obj\n<<\n/Type /Catalog";
-        assert_eq!(detect_file_type_from_bytes(pdf_header), FileType::Pdf);
+        assert_eq!(
+            detect_file_type_from_bytes(pdf_header).file_type,
+            FileType::Pdf
+        );
     }
 
     #[test]
     fn test_rtf_detection() {
         let rtf_content = b"{\\rtf1\\ansi\\deff0 Hello World}";
-        assert_eq!(detect_file_type_from_bytes(rtf_content), FileType::RichText);
+        assert_eq!(
+            detect_file_type_from_bytes(rtf_content).file_type,
+            FileType::RichText
+        );
+    }
+
+    #[test]
+    fn test_detected_type_details() {
+        let docx = detect_file_type_from_bytes(&fixture("sample.docx"));
+        assert_eq!(docx.extension, "docx");
+        assert_eq!(
+            docx.mime,
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+        );
+        assert_eq!(docx.confidence, Confidence::Certain);
+
+        let csv = detect_file_type_from_bytes(&fixture("sample.csv"));
+        assert_eq!((csv.extension, csv.mime), ("csv", "text/csv"));
+        assert_eq!(csv.confidence, Confidence::Likely);
+
+        let unknown = detect_file_type_from_bytes(b"\x00\x01\x02\x03");
+        assert_eq!(unknown.file_type, FileType::Unknown);
+        assert_eq!(
+            (unknown.extension, unknown.mime),
+            ("bin", "application/octet-stream")
+        );
+        assert_eq!(unknown.confidence, Confidence::Unknown);
+    }
+
+    #[test]
+    fn test_utf16_fixtures() {
+        let txt = detect_file_type_from_bytes(&fixture("utf16le.txt"));
+        assert_eq!(txt.file_type, FileType::PlainText);
+        assert_eq!(txt.encoding, Some(TextEncoding::Utf16Le));
+
+        // Exported without BOM
+        let csv = detect_file_type_from_bytes(&fixture("utf16le.csv"));
+        assert_eq!(csv.file_type, FileType::Csv);
+        assert_eq!(csv.encoding, Some(TextEncoding::Utf16Le));
+    }
+
+    #[test]
+    fn test_text_encodings() {
+        let encode = |text: &str, bom: &[u8], unit: fn(char) -> Vec<u8>| {
+            let mut bytes = bom.to_vec();
+            bytes.extend(text.chars().flat_map(unit));
+            bytes
+        };
+        let text = "name;city\nJosé;Zürich\nAnna;Gent\n";
+
+        for (bytes, expected) in [
+            (
+                encode(text, UTF16BE_BOM, |c| (c as u16).to_be_bytes().to_vec()),
+                TextEncoding::Utf16Be,
+            ),
+            (
+                encode(text, b"", |c| (c as u16).to_be_bytes().to_vec()),
+                TextEncoding::Utf16Be,
+            ),
+            (
+                encode(text, UTF32LE_BOM, |c| (c as u32).to_le_bytes().to_vec()),
+                TextEncoding::Utf32Le,
+            ),
+            (
+                encode(text, UTF32BE_BOM, |c| (c as u32).to_be_bytes().to_vec()),
+                TextEncoding::Utf32Be,
+            ),
+            (
+                encode(text, UTF8_BOM, |c| c.to_string().into_bytes()),
+                TextEncoding::Utf8,
+            ),
+        ] {
+            let detected = detect_file_type_from_bytes(&bytes);
+            assert_eq!(detected.file_type, FileType::Csv, "{:?}", expected);
+            assert_eq!(detected.encoding, Some(expected));
+        }
+
+        assert_eq!(
+            detect_file_type_from_bytes(&fixture("sample.docx")).encoding,
+            None
+        );
+    }
+
+    #[test]
+    fn test_reader_and_bytes_agree_on_fixtures() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let bytes = std::fs::read(&path).unwrap();
+            let mut file = std::fs::File::open(&path).unwrap();
+            assert_eq!(
+                detect_file_type_from_reader(&mut file).unwrap(),
+                detect_file_type_from_bytes(&bytes),
+                "{:?}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn test_reader_seeks_to_zip_central_directory() {
+        // word/ only appears after a 12KB thumbnail, beyond the sniffed header
+        let mut docx = Cursor::new(fixture("docprops-first.docx"));
+        docx.seek(SeekFrom::Start(100)).unwrap();
+        assert_eq!(
+            detect_file_type_from_reader(&mut docx).unwrap().file_type,
+            FileType::Word
+        );
     }
 
     #[test]
     fn test_zip_signature() {
         let zip_header = b"PK\x03\x04\x14\x00\x00\x00\x08\x00";
         // This will be None because it's just a ZIP header without Office-specific content
-        assert_eq!(detect_file_type_from_bytes(zip_header), FileType::Unknown);
+        assert_eq!(
+            detect_file_type_from_bytes(zip_header).file_type,
+            FileType::Unknown
+        );
+    }
+
+    fn fixture(name: &str) -> Vec<u8> {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name);
+        std::fs::read(path).unwrap()
+    }
+
+    #[test]
+    fn test_ooxml_and_odf_fixtures() {
+        for (name, expected) in [
+            ("sample.docx", FileType::Word),
+            ("sample.xlsx", FileType::Excel),
+            ("sample.pptx", FileType::PowerPoint),
+            ("sample.odt", FileType::OpenDocumentText),
+        ] {
+            assert_eq!(
+                detect_file_type_from_bytes(&fixture(name)).file_type,
+                expected,
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_macro_enabled_fixtures() {
+        for (name, expected) in [
+            ("macros.docm", FileType::WordMacro),
+            ("macros.xlsm", FileType::ExcelMacro),
+            ("macros.pptm", FileType::PowerPointMacro),
+        ] {
+            let detected = detect_file_type_from_bytes(&fixture(name)).file_type;
+            assert_eq!(detected, expected, "{}", name);
+            assert!(detected.has_macros());
+        }
+        assert!(!FileType::Word.has_macros());
+    }
+
+    #[test]
+    fn test_stored_mimetype_is_enough_for_odf() {
+        let odt = fixture("sample.odt");
+        assert_eq!(
+            detect_file_type_from_bytes(&odt[..100]).file_type,
+            FileType::OpenDocumentText
+        );
+    }
+
+    /// Zip holding only a stored `mimetype` entry
+    fn odf_archive(mimetype: &str) -> Vec<u8> {
+        let mut archive = b"PK\x03\x04\x14\x00\x00\x00\x00\x00".to_vec();
+        archive.extend_from_slice(&[0; 8]);
+        archive.extend_from_slice(&(mimetype.len() as u32).to_le_bytes());
+        archive.extend_from_slice(&(mimetype.len() as u32).to_le_bytes());
+        archive.extend_from_slice(&8u16.to_le_bytes());
+        archive.extend_from_slice(&0u16.to_le_bytes());
+        archive.extend_from_slice(b"mimetype");
+        archive.extend_from_slice(mimetype.as_bytes());
+        archive
+    }
+
+    #[test]
+    fn test_odf_variants() {
+        for (mimetype, expected) in [
+            ("text", FileType::OpenDocumentText),
+            ("text-template", FileType::OpenDocumentText),
+            ("spreadsheet", FileType::OpenDocumentSpreadsheet),
+            ("presentation", FileType::OpenDocumentPresentation),
+            ("graphics", FileType::OpenDocumentGraphics),
+            ("formula", FileType::Unknown),
+        ] {
+            let archive = odf_archive(&format!("application/vnd.oasis.opendocument.{}", mimetype));
+            assert_eq!(
+                detect_file_type_from_bytes(&archive).file_type,
+                expected,
+                "{}",
+                mimetype
+            );
+        }
+        assert_eq!(
+            detect_file_type_from_bytes(&odf_archive("application/epub+zip")).file_type,
+            FileType::Unknown
+        );
+    }
+
+    #[test]
+    fn test_flat_odf() {
+        let fods = br#"<?xml version="1.0" encoding="UTF-8"?>
+<office:document xmlns:office="urn:oasis:names:tc:opendocument:xmlns:office:1.0" office:version="1.3" office:mimetype="application/vnd.oasis.opendocument.spreadsheet">
+<office:body><office:spreadsheet/></office:body></office:document>"#;
+        assert_eq!(
+            detect_file_type_from_bytes(fods).file_type,
+            FileType::OpenDocumentSpreadsheet
+        );
+
+        let fodt = br#"<?xml version="1.0"?>
+<office:document office:mimetype="application/vnd.oasis.opendocument.text" office:version="1.3">"#;
+        assert_eq!(
+            detect_file_type_from_bytes(fodt).file_type,
+            FileType::OpenDocumentText
+        );
+
+        let plain_xml = br#"<?xml version="1.0"?><office:document-content/>"#;
+        assert_eq!(
+            detect_file_type_from_bytes(plain_xml).file_type,
+            FileType::Xml
+        );
+    }
+
+    #[test]
+    fn test_deflated_mimetype_odf() {
+        assert_eq!(
+            detect_file_type_from_bytes(&fixture("deflated-mimetype.odt")).file_type,
+            FileType::OpenDocumentText
+        );
+    }
+
+    #[test]
+    fn test_ooxml_main_part_in_central_directory() {
+        // word/ only appears after a 12KB thumbnail, beyond the sniffed header
+        let docx = fixture("docprops-first.docx");
+        let header = &docx[..8 * 1024];
+        let tail = &docx[docx.len() - 1024..];
+        assert_eq!(
+            detect_file_type_with_tail(header, tail).file_type,
+            FileType::Word
+        );
+        assert_eq!(detect_file_type_from_bytes(&docx).file_type, FileType::Word);
+    }
+
+    #[test]
+    fn test_plain_zip_with_office_folder_names() {
+        assert_eq!(
+            detect_file_type_from_bytes(&fixture("word-folder.zip")).file_type,
+            FileType::Unknown
+        );
     }
 
     #[test]
     fn test_ole2_signature() {
+        // Application names in the first bytes don't make a compound file
         let ole2_header = b"\xD0\xCF\x11\xE0\xA1\xB1\x1A\xE1Microsoft Office Word Document";
-        assert_eq!(detect_file_type_from_bytes(ole2_header), FileType::Word);
+        let detected = detect_file_type_from_bytes(ole2_header);
+        assert_eq!(detected.file_type, FileType::CompoundFile);
+        assert_eq!(detected.confidence, Confidence::Unknown);
+    }
+
+    #[test]
+    fn test_legacy_office_fixtures() {
+        for (name, expected) in [
+            ("legacy.doc", FileType::LegacyWord),
+            ("legacy.xls", FileType::LegacyExcel),
+            ("legacy.ppt", FileType::LegacyPowerPoint),
+            ("large.xls", FileType::LegacyExcel),
+        ] {
+            let detected = detect_file_type_from_bytes(&fixture(name));
+            assert_eq!(detected.file_type, expected, "{}", name);
+            assert_eq!(detected.confidence, Confidence::Certain);
+        }
+
+        // Encrypted OOXML holds none of the document streams
+        assert_eq!(
+            detect_file_type_from_bytes(&fixture("encrypted.docx")).file_type,
+            FileType::CompoundFile
+        );
+    }
+
+    #[test]
+    fn test_word_processor_fixtures() {
+        for (name, expected) in [
+            ("sample.wpd", FileType::WordPerfect),
+            ("works.wps", FileType::Works),
+            ("starwriter.sdw", FileType::StarWriter),
+            ("starcalc.sdc", FileType::StarCalc),
+        ] {
+            let detected = detect_file_type_from_bytes(&fixture(name));
+            assert_eq!(detected.file_type, expected, "{}", name);
+            assert_eq!(detected.confidence, Confidence::Certain);
+        }
+
+        let unknown = detect_file_type_from_bytes(&fixture("unknown-streams.ole"));
+        assert_eq!(unknown.file_type, FileType::CompoundFile);
+        assert_eq!(unknown.extension, "bin");
+    }
+
+    #[test]
+    fn test_email_fixtures() {
+        assert_eq!(
+            detect_file_type_from_bytes(&fixture("sample.eml")).file_type,
+            FileType::Email
+        );
+        assert_eq!(
+            detect_file_type_from_bytes(&fixture("sample.msg")).file_type,
+            FileType::OutlookMessage
+        );
+    }
+
+    #[test]
+    fn test_email_needs_header_block() {
+        for (text, expected) in [
+            (
+                &b"From: Anna <anna@example.com>\r\nSubject: Minutes\r\n\r\nSee attached.\r\n"[..],
+                FileType::Email,
+            ),
+            (
+                b"Received: from mx.example.com\n\tby mail.example.org\nDate: Tue, 6 Oct 2026\n\nHi\n",
+                FileType::Email,
+            ),
+            // One known field is a note, not a message
+            (b"Subject: groceries\n\nmilk, eggs\n", FileType::PlainText),
+            (b"Dear Anna,\nFrom: the office\nTo: you\n", FileType::PlainText),
+        ] {
+            assert_eq!(
+                detect_file_type_from_bytes(text).file_type,
+                expected,
+                "{}",
+                String::from_utf8_lossy(text)
+            );
+        }
+    }
+
+    #[test]
+    fn test_compound_file_directory_beyond_header() {
+        let xls = fixture("large.xls");
+        assert_eq!(
+            detect_file_type_with_tail(&xls[..8192], &[]).file_type,
+            FileType::CompoundFile
+        );
+        assert_eq!(
+            detect_compound_file_type(Cursor::new(&xls)).file_type,
+            FileType::LegacyExcel
+        );
+    }
+
+    #[test]
+    fn test_truncated_compound_file_is_unknown() {
+        let doc = fixture("legacy.doc");
+        for len in [8, 512, 1024, 1500] {
+            assert_eq!(
+                detect_file_type_from_bytes(&doc[..len]).file_type,
+                FileType::CompoundFile,
+                "{}",
+                len
+            );
+        }
     }
 
     #[test]
@@ -150,16 +1369,174 @@ mod tests {
         let text_content =
             b"This is a plain text file with normal content.\nIt has multiple lines.\n";
         assert_eq!(
-            detect_file_type_from_bytes(text_content),
+            detect_file_type_from_bytes(text_content).file_type,
+            FileType::PlainText
+        );
+    }
+
+    #[test]
+    fn test_csv_detection() {
+        assert_eq!(
+            detect_file_type_from_bytes(&fixture("sample.csv")).file_type,
+            FileType::Csv
+        );
+        assert_eq!(
+            detect_file_type_from_bytes(&fixture("semicolon.csv")).file_type,
+            FileType::Csv
+        );
+        assert_eq!(
+            detect_file_type_from_bytes(b"a|b|c\n1|2|3\n4|5").file_type,
+            FileType::Csv
+        );
+    }
+
+    #[test]
+    fn test_tsv_detection() {
+        assert_eq!(
+            detect_file_type_from_bytes(&fixture("export.tsv")).file_type,
+            FileType::Tsv
+        );
+    }
+
+    #[test]
+    fn test_semicolon_wins_over_decimal_commas() {
+        assert_eq!(column_count(&fixture("semicolon.csv"), b';'), Some(4));
+        assert_eq!(column_count(&fixture("semicolon.csv"), b','), None);
+    }
+
+    #[test]
+    fn test_quoted_delimiters_and_line_breaks() {
+        let csv = b"name,comment\r\n\"Smith, J\",\"said \"\"hi\"\",\r\nthen left\"\r\nDoe,none\r\n";
+        assert_eq!(column_count(csv, b','), Some(2));
+    }
+
+    #[test]
+    fn test_prose_is_not_csv() {
+        let text = b"Dear team, thanks for the update.\nThe report is attached.\nBest, Sam\n";
+        assert_eq!(
+            detect_file_type_from_bytes(text).file_type,
             FileType::PlainText
         );
     }
 
+    #[test]
+    fn test_html_detection() {
+        for html in [
+            &b"<!DOCTYPE html>\n<html><body>Report</body></html>"[..],
+            b"\xEF\xBB\xBF  <!doctype HTML><title>x</title>",
+            b"<!-- exported by ReportTool -->\n<HTML lang=\"en\">",
+            b"<?xml version=\"1.0\"?>\n<!DOCTYPE html PUBLIC \"-//W3C//DTD XHTML 1.0 Strict//EN\">",
+        ] {
+            assert_eq!(
+                detect_file_type_from_bytes(html).file_type,
+                FileType::Html,
+                "{}",
+                String::from_utf8_lossy(html)
+            );
+        }
+    }
+
+    #[test]
+    fn test_xml_detection() {
+        for xml in [
+            &b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<book><title>Guide</title></book>"[..],
+            b"<!-- docbook -->\n<!DOCTYPE article PUBLIC \"-//OASIS//DTD DocBook XML V4.5//EN\">",
+            b"<db:article xmlns:db=\"http://docbook.org/ns/docbook\"/>",
+        ] {
+            assert_eq!(
+                detect_file_type_from_bytes(xml).file_type,
+                FileType::Xml,
+                "{}",
+                String::from_utf8_lossy(xml)
+            );
+        }
+    }
+
+    #[test]
+    fn test_svg_is_not_convertible() {
+        let svg =
+            b"<?xml version=\"1.0\"?>\n<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"10\"/>";
+        assert_eq!(
+            detect_file_type_from_bytes(svg).file_type,
+            FileType::Unknown
+        );
+    }
+
+    #[test]
+    fn test_text_starting_with_angle_bracket() {
+        assert_eq!(
+            detect_file_type_from_bytes(b"<3 thanks for the files\n").file_type,
+            FileType::PlainText
+        );
+    }
+
+    #[test]
+    fn test_image_detection() {
+        for (name, expected) in [
+            ("pixel.png", FileType::Png),
+            ("pixel.jpg", FileType::Jpeg),
+            ("pixel.tiff", FileType::Tiff),
+            ("pixel.bmp", FileType::Bmp),
+            ("pixel.gif", FileType::Gif),
+            ("pixel.webp", FileType::Webp),
+        ] {
+            let detected = detect_file_type_from_bytes(&fixture(name)).file_type;
+            assert_eq!(detected, expected, "{}", name);
+            assert!(detected.is_image());
+        }
+    }
+
+    #[test]
+    fn test_text_starting_with_bm_is_not_bmp() {
+        assert_eq!(
+            detect_file_type_from_bytes(b"BMW service history\n").file_type,
+            FileType::PlainText
+        );
+    }
+
+    #[test]
+    fn test_encrypted_compound_files() {
+        for (name, encrypted) in [
+            ("encrypted.docx", true),
+            ("encrypted.xls", true),
+            ("encrypted.doc", true),
+            ("legacy.xls", false),
+            ("legacy.doc", false),
+            ("legacy.ppt", false),
+        ] {
+            let reader = std::io::Cursor::new(fixture(name));
+            assert_eq!(
+                is_encrypted_compound_file(reader).unwrap(),
+                encrypted,
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_truncated_compound_file_is_an_error() {
+        let doc = fixture("legacy.doc");
+        for len in [8, 512, 1024] {
+            let reader = std::io::Cursor::new(&doc[..len]);
+            assert!(is_encrypted_compound_file(reader).is_err(), "{}", len);
+        }
+    }
+
+    #[test]
+    fn test_cyclic_sector_chain_is_an_error() {
+        // Point the directory's FAT entry (sector 1) back at itself
+        let mut doc = fixture("legacy.doc");
+        doc[512 + 4..512 + 8].copy_from_slice(&1u32.to_le_bytes());
+        let reader = std::io::Cursor::new(doc);
+        assert!(is_encrypted_compound_file(reader).is_err());
+    }
+
     #[test]
     fn test_binary_rejection() {
         let binary_content = b"\x00\x01\x02\x03\xFF\xFE\xFD\xFC";
         assert_eq!(
-            detect_file_type_from_bytes(binary_content),
+            detect_file_type_from_bytes(binary_content).file_type,
             FileType::Unknown
         );
     }
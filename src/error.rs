@@ -1,35 +1,277 @@
+//! Errors of the conversion pipeline and their HTTP responses
+
+use std::sync::Arc;
+use std::time::Duration;
+
 use axum::body::Body;
-use hyper::{Response, StatusCode};
+use hyper::{Response, StatusCode, header, header::HeaderValue};
+use serde::Serialize;
+
+use crate::{
+    formats::IMAGE_OUTPUT_FORMATS,
+    headers::{QUEUE_WAIT_HEADER, RETRYABLE_HEADER},
+    page_count::Unit,
+    request_id,
+    workdir::NotWritable,
+};
 
+/// Result of the conversion pipeline
 pub type Result<T> = std::result::Result<T, LibreOfficeError>;
 
-#[derive(Debug, thiserror::Error)]
+/// Why a conversion or request failed. [`code`](Self::code) is the stable identifier
+/// clients match on, the variants may grow.
+#[derive(Debug, Clone, thiserror::Error)]
+#[non_exhaustive]
 pub enum LibreOfficeError {
+    /// Reading or writing a file failed
     #[error("IO error: {0}")]
-    Io(#[from] std::io::Error),
+    Io(Arc<std::io::Error>),
+    /// The conversion itself ran too long
     #[error("Conversion timeout")]
-    Timeout,
+    Timeout {
+        /// Time the conversion waited for the LibreOffice slot before it started
+        queue_wait: Option<Duration>,
+    },
+    /// No LibreOffice slot freed up within `MAX_QUEUE_WAIT_SECS`
+    #[error("Conversion not started after waiting {0:?} in the queue")]
+    QueueTimeout(Duration),
+    /// LibreOffice failed for a reason not told apart, with what it reported
     #[error("Conversion failed: {0}")]
     ConversionFailed(String),
+    /// LibreOffice exited without writing the output
     #[error("Output file not found after conversion")]
     OutputNotFound,
+    /// The output is empty or lacks the signature of the target format
+    #[error("Converted output is invalid ({size} bytes, starting with \"{head}\")")]
+    InvalidOutput {
+        /// Size of the output in bytes
+        size: usize,
+        /// First bytes of the output, lossily decoded
+        head: String,
+    },
+    /// LibreOffice could not read the upload
     #[error("Corrupted or invalid input file: {0}")]
     CorruptedInput(String),
+    /// LibreOffice has no filter between the two formats
     #[error("Unsupported format conversion from {from} to {to}")]
-    UnsupportedConversion { from: String, to: String },
+    UnsupportedConversion {
+        /// Input format
+        from: String,
+        /// Requested output format
+        to: String,
+    },
+    /// `ALLOWED_INPUT_TYPES` or `ALLOWED_OUTPUT_FORMATS` rule the conversion out
+    #[error("Conversion from {from} to {to} is not allowed")]
+    ConversionNotAllowed {
+        /// Input format
+        from: String,
+        /// Requested output format
+        to: String,
+        /// Input formats allowed, comma-separated, or `any`
+        allowed_inputs: String,
+        /// Output formats allowed, comma-separated, or `any`
+        allowed_outputs: String,
+    },
+    /// An image was asked for a target other than a drawing or image format
+    #[error("Images can only be converted to {}, not {to}", IMAGE_OUTPUT_FORMATS.join(", "))]
+    UnsupportedImageConversion {
+        /// Requested output format
+        to: String,
+    },
+    /// The upload is encrypted
     #[error("File is password protected")]
     PasswordProtected,
+    /// The upload is empty or holds nothing LibreOffice can open
     #[error("Input file is empty or invalid")]
     EmptyOrInvalidInput,
+    /// Neither `LIBREOFFICE_BIN` nor PATH has a LibreOffice executable
+    #[error("LibreOffice executable not found")]
+    BinaryNotFound,
+    /// The conversion backend can't run, with the reason
+    #[error("Conversion backend unavailable: {0}")]
+    BackendUnavailable(String),
+    /// The work dir is full or over its quota
+    #[error("Not enough disk space for the conversion")]
+    InsufficientStorage,
+    /// The service may not write to the work dir, with the IO error
+    #[error("Work directory not writable: {0}")]
+    WorkDirPermission(String),
+    /// The requested format is not known
+    #[error("Invalid or unsupported format: {0}")]
+    InvalidFormat(String),
+    /// A conversion option has an invalid value, the message says which
+    #[error("{0}")]
+    InvalidOption(String),
+    /// A form field was repeated
+    #[error("Field {0} was sent more than once")]
+    DuplicateField(String),
+    /// A form field is not known and `REJECT_UNKNOWN_FIELDS` is set
+    #[error("Unknown field {0:?}")]
+    UnknownField(String),
+    /// A text form field is too long
+    #[error("Field {name} is longer than {max_len} bytes")]
+    FieldTooLong {
+        /// Name of the field
+        name: String,
+        /// Longest value accepted, in bytes
+        max_len: usize,
+    },
+    /// The upload is an Outlook message, which LibreOffice can't open
+    #[error("Outlook .msg files can't be converted, save the message as .eml first")]
+    OutlookMessage,
+    /// The upload holds macros and `REJECT_MACRO_DOCUMENTS` is set
+    #[error("Macro-enabled documents are not accepted")]
+    MacroDocumentRejected,
+    /// The document is longer than `MAX_PAGES`
+    #[error("Document has {count} {unit}, more than the {max} allowed")]
+    TooManyPages {
+        /// Pages, slides or sheets of the document
+        count: u64,
+        /// What was counted
+        unit: Unit,
+        /// Most allowed
+        max: u64,
+    },
+    /// LibreOffice was killed for exceeding the CPU or memory limit
+    #[error("Document exceeded the conversion resource limits")]
+    ResourceLimitExceeded,
+    /// Resetting the LibreOffice profile did not help, with the number of resets
+    #[error("LibreOffice profile still corrupted after {0} resets")]
+    ProfileCorrupted(u32),
+    /// The audit record could not be written, so the conversion is refused
+    #[error("Audit record could not be written: {0}")]
+    AuditFailed(String),
+    /// The virus scanner found the named signature
+    #[error("Upload is infected with {0}")]
+    Infected(String),
+    /// The virus scanner could not be reached or failed
+    #[error("Upload could not be scanned: {0}")]
+    ScannerUnavailable(String),
+    /// The request did not complete within `REQUEST_TIMEOUT_SECS`
+    #[error("Request not completed within {0:?}")]
+    RequestTimeout(Duration),
+    /// No result is kept under the id
+    #[error("No result {0}")]
+    ResultNotFound(String),
+    /// The result with the id was kept but has expired
+    #[error("Result {0} is no longer kept")]
+    ResultExpired(String),
+}
+
+impl LibreOfficeError {
+    /// Wraps an IO error, singling out a full disk and a work directory the
+    /// service may not write to, see `workdir::not_writable`
+    pub fn from_io(error: std::io::Error) -> Self {
+        match error.kind() {
+            std::io::ErrorKind::StorageFull | std::io::ErrorKind::QuotaExceeded => {
+                LibreOfficeError::InsufficientStorage
+            }
+            _ if error.get_ref().is_some_and(|e| e.is::<NotWritable>()) => {
+                LibreOfficeError::WorkDirPermission(error.to_string())
+            }
+            _ => LibreOfficeError::Io(Arc::new(error)),
+        }
+    }
+
+    /// Stable machine-readable identifier of the error, the `code` of the error body
+    pub fn code(&self) -> &'static str {
+        match self {
+            LibreOfficeError::Io(_) => "io_error",
+            LibreOfficeError::Timeout { .. } => "timeout",
+            LibreOfficeError::QueueTimeout(_) => "queue_timeout",
+            LibreOfficeError::ConversionFailed(_) => "conversion_failed",
+            LibreOfficeError::OutputNotFound => "output_not_found",
+            LibreOfficeError::InvalidOutput { .. } => "invalid_output",
+            LibreOfficeError::CorruptedInput(_) => "corrupted_input",
+            LibreOfficeError::UnsupportedConversion { .. }
+            | LibreOfficeError::UnsupportedImageConversion { .. } => "unsupported_conversion",
+            LibreOfficeError::ConversionNotAllowed { .. } => "conversion_not_allowed",
+            LibreOfficeError::PasswordProtected => "password_protected",
+            LibreOfficeError::EmptyOrInvalidInput => "empty_input",
+            LibreOfficeError::BinaryNotFound => "binary_not_found",
+            LibreOfficeError::BackendUnavailable(_) => "backend_unavailable",
+            LibreOfficeError::InsufficientStorage => "insufficient_storage",
+            LibreOfficeError::WorkDirPermission(_) => "workdir_permission",
+            LibreOfficeError::InvalidFormat(_) => "invalid_format",
+            LibreOfficeError::InvalidOption(_) => "invalid_option",
+            LibreOfficeError::DuplicateField(_) => "duplicate_field",
+            LibreOfficeError::UnknownField(_) => "unknown_field",
+            LibreOfficeError::FieldTooLong { .. } => "field_too_long",
+            LibreOfficeError::OutlookMessage => "outlook_message_unsupported",
+            LibreOfficeError::MacroDocumentRejected => "macro_document_rejected",
+            LibreOfficeError::TooManyPages { .. } => "too_many_pages",
+            LibreOfficeError::ResourceLimitExceeded => "resource_limit_exceeded",
+            LibreOfficeError::ProfileCorrupted(_) => "profile_corrupted",
+            LibreOfficeError::AuditFailed(_) => "audit_failed",
+            LibreOfficeError::Infected(_) => "infected_upload",
+            LibreOfficeError::ScannerUnavailable(_) => "scanner_unavailable",
+            LibreOfficeError::RequestTimeout(_) => "request_timeout",
+            LibreOfficeError::ResultNotFound(_) => "result_not_found",
+            LibreOfficeError::ResultExpired(_) => "result_expired",
+        }
+    }
+
+    /// Counts the error in `conversion_errors_total` under its code and format pair
+    pub(crate) fn record(&self, input_format: &str, output_format: &str) {
+        metrics::counter!(
+            "conversion_errors_total",
+            "code" => self.code(),
+            "input_format" => input_format.to_string(),
+            "output_format" => output_format.to_string(),
+        )
+        .increment(1);
+    }
+
+    /// Whether the same request may succeed when sent again later, as opposed to
+    /// errors caused by the document or the request itself
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            LibreOfficeError::QueueTimeout(_)
+                | LibreOfficeError::BackendUnavailable(_)
+                | LibreOfficeError::ProfileCorrupted(_)
+                | LibreOfficeError::ScannerUnavailable(_)
+                | LibreOfficeError::AuditFailed(_)
+                | LibreOfficeError::InsufficientStorage
+        )
+    }
+
+    /// Errors meaning the conversion engine can't run at all, as opposed to document errors
+    pub fn is_backend_unavailable(&self) -> bool {
+        matches!(
+            self,
+            LibreOfficeError::BinaryNotFound | LibreOfficeError::BackendUnavailable(_)
+        )
+    }
+}
+
+impl From<std::io::Error> for LibreOfficeError {
+    fn from(error: std::io::Error) -> Self {
+        LibreOfficeError::from_io(error)
+    }
 }
 
 impl From<LibreOfficeError> for Response<Body> {
     fn from(error: LibreOfficeError) -> Self {
+        let code = error.code();
+        let retryable = error.is_retryable();
+        let queue_wait = match &error {
+            LibreOfficeError::Timeout { queue_wait } => *queue_wait,
+            LibreOfficeError::QueueTimeout(waited) => Some(*waited),
+            _ => None,
+        };
         let (status, message) = match error {
-            LibreOfficeError::Timeout => (
+            LibreOfficeError::Timeout { .. } => (
                 StatusCode::REQUEST_TIMEOUT,
                 "Conversion timed out".to_string(),
             ),
+            LibreOfficeError::QueueTimeout(waited) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!(
+                    "Conversion not started, LibreOffice was busy for {} seconds",
+                    waited.as_secs()
+                ),
+            ),
             LibreOfficeError::CorruptedInput(_) => (
                 StatusCode::BAD_REQUEST,
                 format!("Invalid or corrupted input file: {}", error),
@@ -38,10 +280,78 @@ impl From<LibreOfficeError> for Response<Body> {
                 StatusCode::BAD_REQUEST,
                 format!("Unsupported conversion from {} to {}", from, to),
             ),
+            LibreOfficeError::ConversionNotAllowed {
+                from,
+                to,
+                allowed_inputs,
+                allowed_outputs,
+            } => (
+                StatusCode::FORBIDDEN,
+                format!(
+                    "Conversion from {} to {} is not allowed, allowed inputs: {}; allowed outputs: {}",
+                    from, to, allowed_inputs, allowed_outputs
+                ),
+            ),
+            LibreOfficeError::UnsupportedImageConversion { .. }
+            | LibreOfficeError::OutlookMessage
+            | LibreOfficeError::InvalidOption(_)
+            | LibreOfficeError::DuplicateField(_)
+            | LibreOfficeError::UnknownField(_)
+            | LibreOfficeError::FieldTooLong { .. } => (StatusCode::BAD_REQUEST, error.to_string()),
             LibreOfficeError::PasswordProtected => (
                 StatusCode::BAD_REQUEST,
                 "File is password protected".to_string(),
             ),
+            LibreOfficeError::BinaryNotFound => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "LibreOffice executable not found, set LIBREOFFICE_BIN".to_string(),
+            ),
+            LibreOfficeError::BackendUnavailable(reason) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!("Conversion backend unavailable: {}", reason),
+            ),
+            LibreOfficeError::InsufficientStorage => (
+                StatusCode::INSUFFICIENT_STORAGE,
+                "Not enough disk space for the conversion".to_string(),
+            ),
+            LibreOfficeError::WorkDirPermission(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Work directory is not writable, check the permissions of WORK_DIR".to_string(),
+            ),
+            LibreOfficeError::InvalidFormat(format) => (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid or unsupported format: {}", format),
+            ),
+            LibreOfficeError::MacroDocumentRejected => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Macro-enabled documents are not accepted".to_string(),
+            ),
+            LibreOfficeError::TooManyPages { .. } => {
+                (StatusCode::UNPROCESSABLE_ENTITY, error.to_string())
+            }
+            LibreOfficeError::ResourceLimitExceeded => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Document is too complex to convert within the configured memory and CPU limits"
+                    .to_string(),
+            ),
+            LibreOfficeError::Infected(signature) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("Upload rejected, malware found: {}", signature),
+            ),
+            LibreOfficeError::ScannerUnavailable(_) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Upload could not be scanned for malware".to_string(),
+            ),
+            LibreOfficeError::RequestTimeout(timeout) => (
+                StatusCode::REQUEST_TIMEOUT,
+                format!("Request not completed within {} seconds", timeout.as_secs()),
+            ),
+            LibreOfficeError::ResultNotFound(_) => (StatusCode::NOT_FOUND, error.to_string()),
+            LibreOfficeError::ResultExpired(_) => (StatusCode::GONE, error.to_string()),
+            LibreOfficeError::AuditFailed(_) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Conversion could not be audited".to_string(),
+            ),
             LibreOfficeError::EmptyOrInvalidInput => (
                 StatusCode::BAD_REQUEST,
                 "Input file is empty or invalid".to_string(),
@@ -52,15 +362,96 @@ impl From<LibreOfficeError> for Response<Body> {
             ),
         };
 
-        create_error_response(status, &message)
+        let mut response = error_response(status, code, &message, retryable);
+        // Lets clients tell time spent waiting from time spent converting
+        if let Some(queue_wait) = queue_wait {
+            response.headers_mut().insert(
+                QUEUE_WAIT_HEADER,
+                HeaderValue::from(queue_wait.as_millis() as u64),
+            );
+        }
+        response
+    }
+}
+
+/// JSON body of every error response
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    code: &'a str,
+    message: &'a str,
+    retryable: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+/// Largest error body read back by [`read_error`]
+const MAX_ERROR_BODY_BYTES: usize = 64 * 1024;
+
+/// Status and JSON body of an error response, for protocols relaying the errors
+/// of the HTTP handlers
+pub(crate) async fn read_error(
+    response: Response<Body>,
+) -> (StatusCode, serde_json::Map<String, serde_json::Value>) {
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), MAX_ERROR_BODY_BYTES)
+        .await
+        .unwrap_or_default();
+    match serde_json::from_slice(&body) {
+        Ok(serde_json::Value::Object(body)) => (status, body),
+        _ => (status, serde_json::Map::new()),
+    }
+}
+
+/// Generic error code for responses not caused by a [`LibreOfficeError`]
+fn code_for_status(status: StatusCode) -> &'static str {
+    match status {
+        StatusCode::BAD_REQUEST => "bad_request",
+        StatusCode::NOT_FOUND => "not_found",
+        StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+        StatusCode::SERVICE_UNAVAILABLE => "service_unavailable",
+        _ if status.is_server_error() => "internal_error",
+        _ => "error",
     }
 }
 
 // Helper function to create error responses safely
-pub fn create_error_response(status: StatusCode, message: &str) -> Response<Body> {
+pub(crate) fn create_error_response(status: StatusCode, message: &str) -> Response<Body> {
+    create_error_response_with_code(status, code_for_status(status), message)
+}
+
+/// Error response with an explicit `code`, tagged with the current request ID.
+/// Only unavailability is worth retrying for errors that aren't a [`LibreOfficeError`].
+pub(crate) fn create_error_response_with_code(
+    status: StatusCode,
+    code: &str,
+    message: &str,
+) -> Response<Body> {
+    let retryable = matches!(
+        status,
+        StatusCode::SERVICE_UNAVAILABLE | StatusCode::TOO_MANY_REQUESTS
+    );
+    error_response(status, code, message, retryable)
+}
+
+fn error_response(
+    status: StatusCode,
+    code: &str,
+    message: &str,
+    retryable: bool,
+) -> Response<Body> {
+    let body = ErrorBody {
+        code,
+        message,
+        retryable,
+        request_id: request_id::current().filter(|id| !id.is_empty()),
+    };
+    let json = serde_json::to_vec(&body).unwrap_or_default();
+
     Response::builder()
         .status(status)
-        .body(Body::from(message.to_string()))
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(RETRYABLE_HEADER, if retryable { "true" } else { "false" })
+        .body(Body::from(json))
         .unwrap_or_else(|e| {
             tracing::error!("Failed to build error response: {}", e);
             Response::new(Body::from("Internal server error"))
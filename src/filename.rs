@@ -0,0 +1,164 @@
+/// Fallback used when nothing usable is left of the uploaded filename
+pub const DEFAULT_FILENAME: &str = "unknown_file";
+
+/// Maximum length of a sanitized filename in characters
+const MAX_FILENAME_CHARS: usize = 128;
+
+/// Invisible formatting characters that can disguise a filename, e.g. right-to-left
+/// overrides turning `report\u{202E}fdp.exe` into `reportexe.pdf` on screen
+fn is_format_char(c: char) -> bool {
+    matches!(
+        c,
+        '\u{200B}'..='\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}' | '\u{FEFF}'
+    )
+}
+
+/// Reduces an uploaded filename to a safe base name: no directories or drive
+/// prefixes, no control or formatting characters, and a restricted charset.
+pub fn sanitize_filename(name: &str) -> String {
+    // Only keep the last path component, for both unix and windows separators
+    let base = name.rsplit(['/', '\\']).next().unwrap_or("");
+
+    // Strip windows drive prefixes such as `C:file.docx`
+    let base = match base.as_bytes() {
+        [drive, b':', ..] if drive.is_ascii_alphabetic() => &base[2..],
+        _ => base,
+    };
+
+    let cleaned: String = base
+        .chars()
+        .filter(|c| !c.is_control() && !is_format_char(*c))
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, '.' | '-' | '_' | ' ' | '(' | ')') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    // No hidden files, `..` or surrounding whitespace
+    let cleaned = cleaned.trim_matches(|c: char| c == '.' || c.is_whitespace());
+
+    let truncated = truncate_keeping_extension(cleaned, MAX_FILENAME_CHARS);
+    if truncated.is_empty() {
+        DEFAULT_FILENAME.to_string()
+    } else {
+        truncated
+    }
+}
+
+fn truncate_keeping_extension(name: &str, max_chars: usize) -> String {
+    if name.chars().count() <= max_chars {
+        return name.to_string();
+    }
+
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if ext.chars().count() < max_chars / 2 => {
+            let stem_chars = max_chars - ext.chars().count() - 1;
+            format!(
+                "{}.{}",
+                stem.chars().take(stem_chars).collect::<String>(),
+                ext
+            )
+        }
+        _ => name.chars().take(max_chars).collect(),
+    }
+}
+
+/// Splits a sanitized filename into its stem and extension
+pub fn split_extension(name: &str) -> (&str, &str) {
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, ext),
+        _ => (name, ""),
+    }
+}
+
+/// Builds a Content-Disposition value with an ASCII fallback and the RFC 5987 UTF-8 form
+pub fn content_disposition(filename: &str) -> String {
+    let ascii_fallback: String = filename
+        .chars()
+        .map(|c| if c.is_ascii() && c != '"' { c } else { '_' })
+        .collect();
+
+    if ascii_fallback == filename {
+        return format!("attachment; filename=\"{}\"", filename);
+    }
+
+    let encoded: String = filename
+        .bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || b"-_.".contains(&b) {
+                (b as char).to_string()
+            } else {
+                format!("%{:02X}", b)
+            }
+        })
+        .collect();
+
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        ascii_fallback, encoded
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strips_path_components() {
+        assert_eq!(sanitize_filename("../../etc/passwd.docx"), "passwd.docx");
+        assert_eq!(
+            sanitize_filename("C:\\Users\\me\\report.docx"),
+            "report.docx"
+        );
+        assert_eq!(sanitize_filename("C:report.docx"), "report.docx");
+        assert_eq!(sanitize_filename(".."), DEFAULT_FILENAME);
+    }
+
+    #[test]
+    fn test_strips_control_and_format_characters() {
+        assert_eq!(sanitize_filename("report\n\0.docx"), "report.docx");
+        assert_eq!(
+            sanitize_filename("report\u{202E}xcod.exe"),
+            "reportxcod.exe"
+        );
+        assert_eq!(sanitize_filename("\u{FEFF}.hidden.txt"), "hidden.txt");
+    }
+
+    #[test]
+    fn test_normalizes_charset() {
+        assert_eq!(sanitize_filename("a;b|c\"d.docx"), "a_b_c_d.docx");
+        assert_eq!(
+            sanitize_filename("Übersicht 2024.xlsx"),
+            "Übersicht 2024.xlsx"
+        );
+    }
+
+    #[test]
+    fn test_truncates_overlong_names() {
+        let name = format!("{}.docx", "a".repeat(1000));
+        let sanitized = sanitize_filename(&name);
+        assert_eq!(sanitized.chars().count(), MAX_FILENAME_CHARS);
+        assert!(sanitized.ends_with(".docx"));
+    }
+
+    #[test]
+    fn test_empty_name() {
+        assert_eq!(sanitize_filename(""), DEFAULT_FILENAME);
+        assert_eq!(sanitize_filename("/"), DEFAULT_FILENAME);
+    }
+
+    #[test]
+    fn test_content_disposition() {
+        assert_eq!(
+            content_disposition("report.pdf"),
+            "attachment; filename=\"report.pdf\""
+        );
+        assert_eq!(
+            content_disposition("Übersicht.pdf"),
+            "attachment; filename=\"_bersicht.pdf\"; filename*=UTF-8''%C3%9Cbersicht.pdf"
+        );
+    }
+}
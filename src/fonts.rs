@@ -0,0 +1,134 @@
+//! Fonts a document asks for that are not installed. LibreOffice substitutes them
+//! silently and headless soffice doesn't report it, so the fonts declared by docx
+//! and ODF documents are compared against what fontconfig lists.
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::process::Command;
+use std::sync::OnceLock;
+
+use zip::ZipArchive;
+
+use crate::office_xml::{attribute_value, start_tags, unescape};
+
+/// Package parts declaring fonts, with the declaring element and its name attribute
+const DECLARATIONS: &[(&str, &str, &str)] = &[
+    ("word/fontTable.xml", "w:font", "w:name"),
+    ("content.xml", "style:font-face", "svg:font-family"),
+    ("styles.xml", "style:font-face", "svg:font-family"),
+];
+
+/// Fonts declared by the document at `path` that fontconfig doesn't know, empty
+/// when either can't be read
+pub fn missing_fonts(path: &Path) -> Vec<String> {
+    let Some(installed) = installed_families() else {
+        return Vec::new();
+    };
+
+    match declared_fonts(path) {
+        Ok(fonts) => fonts
+            .into_iter()
+            .filter(|font| !installed.contains(&font.to_lowercase()))
+            .collect(),
+        Err(e) => {
+            tracing::debug!("Could not read the fonts of {:?}: {}", path, e);
+            Vec::new()
+        }
+    }
+}
+
+/// Font families of the document's declarations, sorted and without duplicates
+fn declared_fonts(path: &Path) -> zip::result::ZipResult<Vec<String>> {
+    let mut archive = ZipArchive::new(File::open(path)?)?;
+    let mut fonts = Vec::new();
+
+    for (part, element, attribute) in DECLARATIONS {
+        let Ok(mut entry) = archive.by_name(part) else {
+            continue;
+        };
+        let mut xml = String::new();
+        entry.read_to_string(&mut xml)?;
+        fonts.extend(
+            start_tags(&xml, element)
+                .filter_map(|tag| attribute_value(tag, attribute))
+                .map(font_family)
+                .filter(|family| !family.is_empty()),
+        );
+    }
+
+    fonts.sort();
+    fonts.dedup();
+    Ok(fonts)
+}
+
+/// ODF quotes family names containing spaces, e.g. `'Liberation Serif'`
+fn font_family(value: &str) -> String {
+    unescape(value).trim().trim_matches('\'').trim().to_string()
+}
+
+/// Lowercased families fontconfig knows, unset when `fc-list` can't be run
+fn installed_families() -> Option<&'static HashSet<String>> {
+    static INSTALLED: OnceLock<Option<HashSet<String>>> = OnceLock::new();
+    INSTALLED
+        .get_or_init(|| {
+            let output = Command::new("fc-list")
+                .args([":", "family"])
+                .output()
+                .inspect_err(|e| {
+                    tracing::warn!("fc-list unavailable, missing fonts are not reported: {}", e)
+                })
+                .ok()
+                .filter(|output| output.status.success())?;
+            Some(parse_fc_list(&String::from_utf8_lossy(&output.stdout)))
+        })
+        .as_ref()
+}
+
+/// One line per font, listing the family under each of its localized names with
+/// `-` and `:` escaped
+fn parse_fc_list(output: &str) -> HashSet<String> {
+    output
+        .lines()
+        .flat_map(|line| line.split(','))
+        .map(|family| family.trim().replace('\\', "").to_lowercase())
+        .filter(|family| !family.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name)
+    }
+
+    #[test]
+    fn test_declared_fonts() {
+        assert_eq!(
+            declared_fonts(&fixture("fonts.docx")).unwrap(),
+            ["Calibri", "Corporate Sans", "Times New Roman"]
+        );
+        assert_eq!(
+            declared_fonts(&fixture("fonts.odt")).unwrap(),
+            ["Corporate Sans", "Liberation Serif"]
+        );
+        assert!(declared_fonts(&fixture("sample.docx")).unwrap().is_empty());
+        assert!(declared_fonts(&fixture("sample.txt")).is_err());
+    }
+
+    #[test]
+    fn test_parse_fc_list() {
+        let installed = parse_fc_list(
+            "DejaVu Sans\nLiberation Serif\nURW Gothic\\-Book\nNoto Sans CJK JP,Noto Sans CJK JP Regular\n",
+        );
+        assert!(installed.contains("liberation serif"));
+        assert!(installed.contains("urw gothic-book"));
+        assert!(installed.contains("noto sans cjk jp regular"));
+        assert!(!installed.contains("calibri"));
+    }
+}
@@ -0,0 +1,287 @@
+//! Input and output formats the service converts between
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::{config::Config, error::LibreOfficeError};
+
+/// Extensions LibreOffice is able to import
+pub const SUPPORTED_INPUT_FORMATS: &[&str] = &[
+    "doc", "docx", "docm", "dot", "dotx", "dotm", "odt", "ott", "fodt", "rtf", "txt", "html",
+    "htm", "xml", "wpd", "wps", "sdw", "sdc", "sdd", "sda", "xls", "xlsx", "xlsm", "xlt", "xltx",
+    "ods", "ots", "fods", "csv", "tsv", "dif", "ppt", "pptx", "pptm", "pps", "ppsx", "pot", "potx",
+    "odp", "otp", "fodp", "odg", "fodg", "pdf", "eml", "msg", "png", "jpg", "jpeg", "tif", "tiff",
+    "bmp", "gif", "webp",
+];
+
+/// Extensions LibreOffice is able to export
+pub const SUPPORTED_OUTPUT_FORMATS: &[&str] = &[
+    "pdf", "doc", "docx", "odt", "rtf", "txt", "html", "xhtml", "epub", "xls", "xlsx", "ods",
+    "csv", "ppt", "pptx", "odp", "odg", "svg", "png", "jpg", "gif", "bmp", "tiff", "webp",
+];
+
+/// Formats `sanitize=true` re-saves in place, office documents whose metadata can
+/// be stripped
+pub const SANITIZE_FORMATS: &[&str] = &["docx", "xlsx", "pptx", "odt", "ods", "odp", "odg"];
+
+/// Targets of image inputs, which LibreOffice can only place on a drawing page
+pub const IMAGE_OUTPUT_FORMATS: &[&str] = &["pdf", "odg", "png", "jpg"];
+
+/// Maximum length of a format extension
+const MAX_FORMAT_LEN: usize = 8;
+
+/// Normalizes a format extension, only `[a-z0-9]{1,8}` from the supported set is accepted
+fn parse_format(value: &str, supported: &[&str]) -> Result<String, LibreOfficeError> {
+    let format = value.trim().to_ascii_lowercase();
+
+    let well_formed = !format.is_empty()
+        && format.len() <= MAX_FORMAT_LEN
+        && format
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit());
+
+    if !well_formed || !supported.contains(&format.as_str()) {
+        return Err(LibreOfficeError::InvalidFormat(
+            value.escape_debug().to_string(),
+        ));
+    }
+
+    Ok(format)
+}
+
+/// Validated input file extension
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputFormat(String);
+
+impl InputFormat {
+    /// The extension, e.g. `docx`
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// PDF export filter of the LibreOffice application that opens this format
+    pub fn pdf_export_filter(&self) -> &'static str {
+        match self.as_str() {
+            "xls" | "xlsx" | "xlsm" | "xlt" | "xltx" | "ods" | "ots" | "fods" | "csv" | "tsv"
+            | "dif" | "sdc" => "calc_pdf_Export",
+            "ppt" | "pptx" | "pptm" | "pps" | "ppsx" | "pot" | "potx" | "odp" | "otp" | "fodp"
+            | "sdd" => "impress_pdf_Export",
+            "odg" | "fodg" | "sda" | "pdf" | "png" | "jpg" | "jpeg" | "tif" | "tiff" | "bmp"
+            | "gif" | "webp" => "draw_pdf_Export",
+            _ => "writer_pdf_Export",
+        }
+    }
+
+    /// Import filter LibreOffice would pick for this format, for formats whose
+    /// damaged files type detection may not recognize
+    pub fn import_filter(&self) -> Option<&'static str> {
+        match self.as_str() {
+            "docx" => Some("MS Word 2007 XML"),
+            "xlsx" => Some("Calc MS Excel 2007 XML"),
+            "pptx" => Some("Impress MS PowerPoint 2007 XML"),
+            "odt" => Some("writer8"),
+            "ods" => Some("calc8"),
+            "odp" => Some("impress8"),
+            "odg" => Some("draw8"),
+            "doc" => Some("MS Word 97"),
+            "xls" => Some("MS Excel 97"),
+            "ppt" => Some("MS PowerPoint 97"),
+            "rtf" => Some("Rich Text Format"),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = LibreOfficeError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        parse_format(value, SUPPORTED_INPUT_FORMATS).map(Self)
+    }
+}
+
+impl fmt::Display for InputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Validated target format, safe to pass as `--convert-to` argument
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputFormat(String);
+
+impl OutputFormat {
+    /// The format name, e.g. `pdf`
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = LibreOfficeError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        parse_format(value, SUPPORTED_OUTPUT_FORMATS).map(Self)
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Conversions a deployment accepts, from `ALLOWED_INPUT_TYPES` and
+/// `ALLOWED_OUTPUT_FORMATS`; every supported format when a list is unset
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ConversionPolicy {
+    inputs: Option<Vec<&'static str>>,
+    outputs: Option<Vec<&'static str>>,
+}
+
+/// Formats a deployment converts between, as listed by `/formats`
+#[derive(Debug, Serialize)]
+pub(crate) struct EffectiveFormats {
+    pub input_formats: Vec<&'static str>,
+    pub output_formats: Vec<&'static str>,
+}
+
+impl ConversionPolicy {
+    /// Keeps the supported formats of the configured lists, warning about the others
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            inputs: config
+                .allowed_input_types
+                .as_deref()
+                .map(|allowed| restrict(allowed, SUPPORTED_INPUT_FORMATS, "ALLOWED_INPUT_TYPES")),
+            outputs: config.allowed_output_formats.as_deref().map(|allowed| {
+                restrict(allowed, SUPPORTED_OUTPUT_FORMATS, "ALLOWED_OUTPUT_FORMATS")
+            }),
+        }
+    }
+
+    pub fn formats(&self) -> EffectiveFormats {
+        EffectiveFormats {
+            input_formats: self
+                .inputs
+                .clone()
+                .unwrap_or_else(|| SUPPORTED_INPUT_FORMATS.to_vec()),
+            output_formats: self
+                .outputs
+                .clone()
+                .unwrap_or_else(|| SUPPORTED_OUTPUT_FORMATS.to_vec()),
+        }
+    }
+
+    /// Fails unless every one of `inputs`, the declared and the detected format
+    /// of the upload, may be converted to `output`
+    pub fn check(&self, inputs: &[&str], output: &OutputFormat) -> Result<(), LibreOfficeError> {
+        let input_allowed = |input: &&str| allows(&self.inputs, input);
+        if inputs.iter().all(input_allowed) && allows(&self.outputs, output.as_str()) {
+            return Ok(());
+        }
+
+        let listed = |allowed: &Option<Vec<&str>>| match allowed {
+            Some(allowed) => allowed.join(", "),
+            None => "any".to_string(),
+        };
+        Err(LibreOfficeError::ConversionNotAllowed {
+            from: inputs.first().copied().unwrap_or_default().to_string(),
+            to: output.to_string(),
+            allowed_inputs: listed(&self.inputs),
+            allowed_outputs: listed(&self.outputs),
+        })
+    }
+}
+
+fn allows(allowed: &Option<Vec<&str>>, format: &str) -> bool {
+    allowed
+        .as_ref()
+        .is_none_or(|allowed| allowed.contains(&format))
+}
+
+fn restrict(allowed: &[String], supported: &[&'static str], variable: &str) -> Vec<&'static str> {
+    for format in allowed {
+        if !supported.contains(&format.as_str()) {
+            tracing::warn!("Ignoring unsupported format {:?} in {}", format, variable);
+        }
+    }
+    supported
+        .iter()
+        .copied()
+        .filter(|format| allowed.iter().any(|allowed| allowed == format))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversion_policy() {
+        let unrestricted = ConversionPolicy::default();
+        let pdf = "pdf".parse::<OutputFormat>().unwrap();
+        let png = "png".parse::<OutputFormat>().unwrap();
+        assert!(unrestricted.check(&["docx"], &png).is_ok());
+        assert_eq!(
+            unrestricted.formats().output_formats,
+            SUPPORTED_OUTPUT_FORMATS
+        );
+
+        let policy = ConversionPolicy::from_config(&Config {
+            allowed_input_types: Some(vec!["docx".to_string(), "odt".to_string()]),
+            allowed_output_formats: Some(vec!["pdf".to_string(), "exe".to_string()]),
+            ..Config::from_env()
+        });
+        assert_eq!(policy.formats().input_formats, ["docx", "odt"]);
+        assert_eq!(policy.formats().output_formats, ["pdf"]);
+        assert!(policy.check(&["docx"], &pdf).is_ok());
+        assert!(policy.check(&["docx", "odt"], &pdf).is_ok());
+
+        let Err(error) = policy.check(&["docx"], &png) else {
+            panic!("docx to png allowed");
+        };
+        assert_eq!(error.code(), "conversion_not_allowed");
+        // Declared docx, detected xlsx
+        assert!(policy.check(&["docx", "xlsx"], &pdf).is_err());
+    }
+
+    #[test]
+    fn test_output_format_accepts_supported() {
+        assert_eq!("pdf".parse::<OutputFormat>().unwrap().as_str(), "pdf");
+        assert_eq!(" DOCX ".parse::<OutputFormat>().unwrap().as_str(), "docx");
+    }
+
+    #[test]
+    fn test_output_format_rejects_malformed() {
+        for value in [
+            "",
+            "pdf --something",
+            "../x",
+            "pdf;rm",
+            "verylongformat",
+            "p\0df",
+        ] {
+            assert!(
+                value.parse::<OutputFormat>().is_err(),
+                "{:?} should be rejected",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn test_output_format_rejects_unsupported() {
+        assert!("exe".parse::<OutputFormat>().is_err());
+        // Input-only formats can't be exported
+        assert!("wpd".parse::<OutputFormat>().is_err());
+    }
+
+    #[test]
+    fn test_input_format() {
+        assert_eq!("PPTX".parse::<InputFormat>().unwrap().as_str(), "pptx");
+        assert!("docx/../../etc".parse::<InputFormat>().is_err());
+    }
+}
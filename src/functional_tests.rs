@@ -0,0 +1,282 @@
+//! End-to-end conversions through the full router, run with
+//! `cargo test --features functional-tests` on a machine with LibreOffice installed
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use axum::{
+    body::{Body, to_bytes},
+    http::{Request, StatusCode, header},
+};
+use tower::ServiceExt;
+
+use crate::routes;
+
+const BOUNDARY: &str = "functional-test-boundary";
+
+/// Text contained in every fixture
+const SAMPLE_TEXT: &str = "Hello from libreoffice-rest";
+
+fn fixture(name: &str) -> Vec<u8> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name);
+    std::fs::read(&path).unwrap_or_else(|e| panic!("reading fixture {:?}: {}", path, e))
+}
+
+fn multipart_body(
+    filename: &str,
+    content: &[u8],
+    output_format: &str,
+    fields: &[(&str, &str)],
+) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(
+        format!(
+            "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{filename}\"\r\n\
+             Content-Type: application/octet-stream\r\n\r\n"
+        )
+        .as_bytes(),
+    );
+    body.extend_from_slice(content);
+    for (name, value) in fields {
+        body.extend_from_slice(
+            format!(
+                "\r\n--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}"
+            )
+            .as_bytes(),
+        );
+    }
+    body.extend_from_slice(
+        format!(
+            "\r\n--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"output_format\"\r\n\r\n\
+             {output_format}\r\n--{BOUNDARY}--\r\n"
+        )
+        .as_bytes(),
+    );
+    body
+}
+
+/// Converts a fixture through `POST /convert`, returning status, content type and body
+async fn convert(fixture_name: &str, output_format: &str) -> (StatusCode, String, Vec<u8>) {
+    convert_with_fields(fixture_name, output_format, &[]).await
+}
+
+/// [`convert`] with additional form fields
+async fn convert_with_fields(
+    fixture_name: &str,
+    output_format: &str,
+    fields: &[(&str, &str)],
+) -> (StatusCode, String, Vec<u8>) {
+    let request = Request::builder()
+        .method("POST")
+        .uri("/convert")
+        .header(
+            header::CONTENT_TYPE,
+            format!("multipart/form-data; boundary={}", BOUNDARY),
+        )
+        .body(Body::from(multipart_body(
+            fixture_name,
+            &fixture(fixture_name),
+            output_format,
+            fields,
+        )))
+        .unwrap();
+
+    let response = routes::router(Arc::default())
+        .oneshot(request)
+        .await
+        .unwrap();
+    let status = response.status();
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+    (status, content_type, body.to_vec())
+}
+
+async fn assert_converts_to_pdf(fixture_name: &str) {
+    let (status, content_type, body) = convert(fixture_name, "pdf").await;
+    assert_eq!(
+        status,
+        StatusCode::OK,
+        "{}: {}",
+        fixture_name,
+        String::from_utf8_lossy(&body)
+    );
+    assert_eq!(content_type, "application/pdf");
+    assert!(body.starts_with(b"%PDF-"), "{}: not a pdf", fixture_name);
+}
+
+async fn assert_converts_to_txt(fixture_name: &str) {
+    let (status, content_type, body) = convert(fixture_name, "txt").await;
+    assert_eq!(
+        status,
+        StatusCode::OK,
+        "{}: {}",
+        fixture_name,
+        String::from_utf8_lossy(&body)
+    );
+    assert_eq!(content_type, "text/plain");
+    assert!(
+        String::from_utf8_lossy(&body).contains(SAMPLE_TEXT),
+        "{}: text missing from output",
+        fixture_name
+    );
+}
+
+#[tokio::test]
+async fn test_docx() {
+    assert_converts_to_pdf("sample.docx").await;
+    assert_converts_to_txt("sample.docx").await;
+}
+
+#[tokio::test]
+async fn test_odt() {
+    assert_converts_to_pdf("sample.odt").await;
+    assert_converts_to_txt("sample.odt").await;
+}
+
+#[tokio::test]
+async fn test_rtf() {
+    assert_converts_to_pdf("sample.rtf").await;
+    assert_converts_to_txt("sample.rtf").await;
+}
+
+#[tokio::test]
+async fn test_txt() {
+    assert_converts_to_pdf("sample.txt").await;
+    assert_converts_to_txt("sample.txt").await;
+}
+
+// Calc and Impress have no plain text export, spreadsheets go to csv instead
+#[tokio::test]
+async fn test_xlsx() {
+    assert_converts_to_pdf("sample.xlsx").await;
+
+    let (status, _, body) = convert("sample.xlsx", "csv").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(String::from_utf8_lossy(&body).contains(SAMPLE_TEXT));
+}
+
+/// Page objects of a PDF, LibreOffice doesn't compress its object dictionaries
+fn pdf_page_count(pdf: &[u8]) -> usize {
+    [b"/Type/Page".as_slice(), b"/Type /Page"]
+        .iter()
+        .map(|marker| {
+            pdf.windows(marker.len() + 1)
+                .filter(|window| window.starts_with(marker) && window[marker.len()] != b's')
+                .count()
+        })
+        .sum()
+}
+
+#[tokio::test]
+async fn test_fit_to_width() {
+    let (status, _, default) = convert("wide.xlsx", "pdf").await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _, fitted) =
+        convert_with_fields("wide.xlsx", "pdf", &[("fit_to_width", "1")]).await;
+    assert_eq!(status, StatusCode::OK);
+
+    assert!(
+        pdf_page_count(&fitted) < pdf_page_count(&default),
+        "{} pages fitted, {} by default",
+        pdf_page_count(&fitted),
+        pdf_page_count(&default)
+    );
+    assert_eq!(pdf_page_count(&fitted), 1);
+}
+
+#[tokio::test]
+async fn test_page_setup_is_rejected_for_documents() {
+    let (status, _, body) =
+        convert_with_fields("sample.docx", "pdf", &[("landscape", "true")]).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["code"], "invalid_option");
+}
+
+#[tokio::test]
+async fn test_embed_fonts() {
+    let (status, _, body) =
+        convert_with_fields("sample.txt", "pdf", &[("embed_fonts", "true")]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.windows(9).any(|window| window == b"/FontFile"));
+}
+
+#[tokio::test]
+async fn test_output_password() {
+    let (status, _, body) =
+        convert_with_fields("sample.txt", "pdf", &[("output_password", "s3cret")]).await;
+    assert_eq!(status, StatusCode::OK);
+    let document = lopdf::Document::load_mem(&body).unwrap();
+    assert!(document.is_encrypted());
+    assert!(document.authenticate_password("s3cret").is_ok());
+    assert!(document.authenticate_password("").is_err());
+}
+
+#[tokio::test]
+async fn test_strip_metadata() {
+    let (status, _, body) =
+        convert_with_fields("metadata.docx", "pdf", &[("strip_metadata", "true")]).await;
+    assert_eq!(status, StatusCode::OK);
+    let document = lopdf::Document::load_mem(&body).unwrap();
+    assert!(!document.trailer.has(b"Info"));
+    assert!(!document.catalog().unwrap().has(b"Metadata"));
+
+    let (status, _, body) =
+        convert_with_fields("metadata.odt", "docx", &[("strip_metadata", "true")]).await;
+    assert_eq!(status, StatusCode::OK);
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(body)).unwrap();
+    let mut core = String::new();
+    std::io::Read::read_to_string(
+        &mut archive.by_name("docProps/core.xml").unwrap(),
+        &mut core,
+    )
+    .unwrap();
+    assert!(!core.contains("Alice Example"));
+    assert!(!core.contains("Bob Example"));
+}
+
+#[tokio::test]
+async fn test_repair() {
+    let (status, content_type, body) =
+        convert_with_fields("truncated.docx", "pdf", &[("repair", "true")]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(content_type, "application/pdf");
+    assert!(body.starts_with(b"%PDF"));
+}
+
+#[tokio::test]
+async fn test_csv() {
+    assert_converts_to_pdf("sample.csv").await;
+}
+
+#[tokio::test]
+async fn test_pptx() {
+    assert_converts_to_pdf("sample.pptx").await;
+}
+
+#[tokio::test]
+async fn test_eml() {
+    assert_converts_to_pdf("sample.eml").await;
+}
+
+#[tokio::test]
+async fn test_msg_is_rejected() {
+    let (status, _, body) = convert("sample.msg", "pdf").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["code"], "outlook_message_unsupported");
+}
+
+#[tokio::test]
+async fn test_unsupported_output_format_is_rejected() {
+    let (status, _, _) = convert("sample.txt", "exe").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
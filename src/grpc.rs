@@ -0,0 +1,421 @@
+//! gRPC service of `proto/libreoffice_rest.proto`, served on `GRPC_PORT`. The RPCs
+//! go through the handlers of the HTTP routes, so conversions share their
+//! scheduler, limits, format policy and audit trail.
+
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::body::{Body, Bytes};
+use futures_util::{Stream, StreamExt, TryStreamExt};
+use hyper::{StatusCode, header};
+use serde::Serialize;
+use tokio_util::io::StreamReader;
+use tonic::{Code, Request, Response, Status, Streaming, metadata::MetadataValue};
+
+use crate::{
+    converter::ConversionOptions,
+    error::{LibreOfficeError, read_error},
+    filename,
+    headers::{CONTENT_SHA256_HEADER, CONVERSION_ID_HEADER, RETRYABLE_HEADER},
+    libreoffice::InputFile,
+    routes::{
+        convert::{FormFields, handle_conversion},
+        detect,
+        ws::UploadError,
+    },
+    state::AppState,
+};
+
+pub mod proto {
+    tonic::include_proto!("libreoffice_rest.v1");
+}
+
+use proto::{
+    ConvertHeader, ConvertRequest, ConvertResponse, ConvertResult, DetectRequest, Detection,
+    Formats, GetFormatsRequest, conversion_server::Conversion, convert_request, convert_response,
+};
+
+pub use proto::conversion_server::ConversionServer;
+
+/// Metadata holding the `code` of the HTTP error body
+pub const ERROR_CODE_METADATA: &str = "x-error-code";
+
+type ConvertStream = Pin<Box<dyn Stream<Item = Result<ConvertResponse, Status>> + Send>>;
+
+pub struct Service {
+    state: Arc<AppState>,
+}
+
+impl Service {
+    pub fn new(state: Arc<AppState>) -> Self {
+        Self { state }
+    }
+}
+
+#[tonic::async_trait]
+impl Conversion for Service {
+    type ConvertStream = ConvertStream;
+
+    async fn convert(
+        &self,
+        request: Request<Streaming<ConvertRequest>>,
+    ) -> Result<Response<ConvertStream>, Status> {
+        let client_ip = request.remote_addr().map(|addr| addr.ip());
+        let mut requests = request.into_inner();
+        let config = self.state.config();
+
+        let header = match requests.message().await? {
+            Some(ConvertRequest {
+                payload: Some(convert_request::Payload::Header(header)),
+            }) => header,
+            _ => {
+                let error = UploadError::Protocol("the first request must hold the header");
+                return Err(status(error.into_response()).await);
+            }
+        };
+        let ConvertHeader {
+            filename,
+            output_format,
+            options: fields,
+        } = header;
+        let options = match parse_options(fields, config.reject_unknown_fields) {
+            Ok(options) => options,
+            Err(e) => return Err(status(e.into()).await),
+        };
+
+        let chunks = upload(
+            requests,
+            config.max_upload_size,
+            config.server_limits.body_read_timeout,
+        );
+        let input_file =
+            match InputFile::from_reader(&mut StreamReader::new(chunks), &config.work_dir).await {
+                Ok(file) => file,
+                Err(e) => {
+                    let response = match e.downcast::<UploadError>() {
+                        Ok(e) => e.into_response(),
+                        Err(e) => LibreOfficeError::from_io(e).into(),
+                    };
+                    return Err(status(response).await);
+                }
+            };
+
+        let input_filename = filename::sanitize_filename(&filename);
+        let (stem, _) = filename::split_extension(&input_filename);
+        let output_filename = format!("{}.{}", stem, output_format);
+        let response = handle_conversion(
+            &self.state,
+            client_ip,
+            input_file,
+            input_filename.clone(),
+            Some(output_format),
+            options,
+        )
+        .await;
+        if response.status() != StatusCode::OK {
+            return Err(status(response).await);
+        }
+
+        let (parts, body) = response.into_parts();
+        let header = |name: &str| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default()
+        };
+        let result = ConvertResult {
+            filename: output_filename,
+            content_type: header(header::CONTENT_TYPE.as_str()).to_string(),
+            size: header(header::CONTENT_LENGTH.as_str())
+                .parse()
+                .unwrap_or_default(),
+            conversion_id: header(CONVERSION_ID_HEADER).to_string(),
+            sha256: header(CONTENT_SHA256_HEADER).to_string(),
+        };
+        let result = ConvertResponse {
+            payload: Some(convert_response::Payload::Result(result)),
+        };
+        let chunks = body
+            .into_data_stream()
+            .map_ok(|chunk| ConvertResponse {
+                payload: Some(convert_response::Payload::Chunk(chunk.to_vec())),
+            })
+            .map_err(|e| Status::internal(format!("Cannot read the output: {}", e)));
+        let responses = futures_util::stream::once(async { Ok(result) }).chain(chunks);
+        Ok(Response::new(Box::pin(responses)))
+    }
+
+    async fn detect(&self, request: Request<DetectRequest>) -> Result<Response<Detection>, Status> {
+        let content = request.into_inner().content;
+        let detection =
+            match InputFile::from_reader(&mut content.as_slice(), &self.state.config().work_dir)
+                .await
+            {
+                Ok(file) => detect::detect(&file).await,
+                Err(e) => Err(e),
+            };
+        let detection = match detection {
+            Ok(detection) => detection,
+            Err(e) => return Err(status(LibreOfficeError::from_io(e).into()).await),
+        };
+
+        Ok(Response::new(Detection {
+            r#type: name(detection.file_type),
+            mime: detection.mime.to_string(),
+            extension: detection.extension.to_string(),
+            confidence: name(detection.confidence),
+            encrypted: detection.encrypted,
+            macros: detection.macros,
+            encoding: detection.encoding.map(name).unwrap_or_default(),
+        }))
+    }
+
+    async fn get_formats(
+        &self,
+        _request: Request<GetFormatsRequest>,
+    ) -> Result<Response<Formats>, Status> {
+        let formats = self.state.policy().formats();
+        let names = |formats: Vec<&str>| formats.into_iter().map(String::from).collect();
+        Ok(Response::new(Formats {
+            input_formats: names(formats.input_formats),
+            output_formats: names(formats.output_formats),
+        }))
+    }
+}
+
+/// Options of the header, applied like the form fields of `/convert`
+fn parse_options(
+    fields: std::collections::HashMap<String, String>,
+    reject_unknown_fields: bool,
+) -> Result<ConversionOptions, LibreOfficeError> {
+    let form_fields = FormFields::new(reject_unknown_fields);
+    let mut options = ConversionOptions::default();
+    for (name, value) in fields {
+        if ConversionOptions::is_field(&name) {
+            options.set_field(&name, &value)?;
+        } else {
+            form_fields.unknown(&name)?;
+        }
+    }
+    Ok(options)
+}
+
+/// Chunks of the requests following the header, at most `max_bytes` of them
+fn upload(
+    requests: Streaming<ConvertRequest>,
+    max_bytes: usize,
+    stall_timeout: Duration,
+) -> impl Stream<Item = io::Result<Bytes>> + Unpin {
+    Box::pin(futures_util::stream::try_unfold(
+        (requests, 0),
+        move |(mut requests, received)| async move {
+            let request = match tokio::time::timeout(stall_timeout, requests.message()).await {
+                Ok(Ok(Some(request))) => request,
+                Ok(Ok(None)) => return Ok(None),
+                Ok(Err(_)) => return Err(io::Error::other(UploadError::Closed)),
+                Err(_) => return Err(io::Error::other(UploadError::Stalled)),
+            };
+            let error = match request.payload {
+                Some(convert_request::Payload::Chunk(chunk)) => {
+                    let received = received + chunk.len();
+                    if received <= max_bytes {
+                        return Ok(Some((Bytes::from(chunk), (requests, received))));
+                    }
+                    UploadError::TooLarge(max_bytes)
+                }
+                _ => UploadError::Protocol("only the first request may hold the header"),
+            };
+            Err(io::Error::other(error))
+        },
+    ))
+}
+
+/// Name of a detection result as in the JSON of `/detect`
+fn name(value: impl Serialize) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => String::new(),
+    }
+}
+
+/// gRPC status of an HTTP error response, carrying its code and retryability
+async fn status(response: axum::response::Response<Body>) -> Status {
+    let (http_status, body) = read_error(response).await;
+    let text = |key| body.get(key).and_then(|value| value.as_str());
+    let retryable = body
+        .get("retryable")
+        .and_then(|value| value.as_bool())
+        .unwrap_or_default();
+
+    let mut status = Status::new(
+        code_for_status(http_status),
+        text("message").unwrap_or(http_status.as_str()),
+    );
+    if let Some(Ok(code)) = text("code").map(MetadataValue::try_from) {
+        status.metadata_mut().insert(ERROR_CODE_METADATA, code);
+    }
+    status.metadata_mut().insert(
+        RETRYABLE_HEADER,
+        MetadataValue::from_static(if retryable { "true" } else { "false" }),
+    );
+    status
+}
+
+/// gRPC code matching the HTTP status of an error
+fn code_for_status(status: StatusCode) -> Code {
+    match status {
+        StatusCode::BAD_REQUEST | StatusCode::UNSUPPORTED_MEDIA_TYPE => Code::InvalidArgument,
+        StatusCode::FORBIDDEN => Code::PermissionDenied,
+        StatusCode::NOT_FOUND | StatusCode::GONE => Code::NotFound,
+        StatusCode::REQUEST_TIMEOUT => Code::DeadlineExceeded,
+        StatusCode::PAYLOAD_TOO_LARGE | StatusCode::INSUFFICIENT_STORAGE => Code::ResourceExhausted,
+        StatusCode::UNPROCESSABLE_ENTITY => Code::FailedPrecondition,
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => Code::Unavailable,
+        _ => Code::Internal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, converter::fake::FakeConverter};
+    use proto::conversion_client::ConversionClient;
+    use std::collections::HashMap;
+    use tonic::transport::{Channel, Server};
+
+    /// Serves the service on a free port, returning a client of it
+    async fn start(converter: Arc<FakeConverter>, config: Config) -> ConversionClient<Channel> {
+        let state = AppState::builder()
+            .config(config)
+            .converter(converter)
+            .build();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(ConversionServer::new(Service::new(Arc::new(state))))
+                .serve_with_incoming(tonic::transport::server::TcpIncoming::from(listener)),
+        );
+        ConversionClient::connect(format!("http://{}", addr))
+            .await
+            .unwrap()
+    }
+
+    fn requests(output_format: &str, chunks: &[&[u8]]) -> Vec<ConvertRequest> {
+        let header = ConvertHeader {
+            filename: "report.docx".to_string(),
+            output_format: output_format.to_string(),
+            options: HashMap::from([("strip_metadata".to_string(), "true".to_string())]),
+        };
+        let mut requests = vec![ConvertRequest {
+            payload: Some(convert_request::Payload::Header(header)),
+        }];
+        requests.extend(chunks.iter().map(|chunk| ConvertRequest {
+            payload: Some(convert_request::Payload::Chunk(chunk.to_vec())),
+        }));
+        requests
+    }
+
+    #[tokio::test]
+    async fn test_convert() {
+        let converter = Arc::new(FakeConverter::returning_bytes("pdf", b"%PDF-1.7"));
+        let mut client = start(converter.clone(), crate::config::get().clone()).await;
+
+        let requests = requests("pdf", &[b"PK\x03", b"\x04"]);
+        let mut responses = client
+            .convert(futures_util::stream::iter(requests))
+            .await
+            .unwrap()
+            .into_inner();
+        let Some(convert_response::Payload::Result(result)) =
+            responses.message().await.unwrap().unwrap().payload
+        else {
+            panic!("first response should hold the result");
+        };
+        assert_eq!(result.filename, "report.pdf");
+        assert_eq!(result.content_type, "application/pdf");
+        assert_eq!(result.sha256.len(), 64);
+
+        let mut data = Vec::new();
+        while let Some(response) = responses.message().await.unwrap() {
+            let Some(convert_response::Payload::Chunk(chunk)) = response.payload else {
+                panic!("only the first response should hold the result");
+            };
+            data.extend_from_slice(&chunk);
+        }
+        assert_eq!(data, b"%PDF-1.7");
+        assert!(converter.last_options().unwrap().strip_metadata);
+    }
+
+    #[tokio::test]
+    async fn test_errors_match_the_http_errors() {
+        let converter = Arc::new(FakeConverter::returning_bytes("pdf", b"%PDF-1.7"));
+        let config = Config {
+            max_upload_size: 4,
+            ..crate::config::get().clone()
+        };
+        let mut client = start(converter.clone(), config).await;
+
+        let cases = [
+            (
+                requests("pdf", &[b"PK\x03\x04 and more"]),
+                Code::ResourceExhausted,
+                "payload_too_large",
+            ),
+            (
+                requests("not a format", &[b"PK\x03\x04"]),
+                Code::InvalidArgument,
+                "invalid_format",
+            ),
+            (
+                requests("pdf", &[b"PK\x03\x04"])[1..].to_vec(),
+                Code::InvalidArgument,
+                "protocol_error",
+            ),
+        ];
+        for (requests, code, error_code) in cases {
+            let error = client
+                .convert(futures_util::stream::iter(requests))
+                .await
+                .unwrap_err();
+            assert_eq!(error.code(), code, "{}", error.message());
+            assert_eq!(
+                error.metadata().get(ERROR_CODE_METADATA).unwrap(),
+                error_code
+            );
+            assert_eq!(error.metadata().get(RETRYABLE_HEADER).unwrap(), "false");
+        }
+        assert_eq!(converter.calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_detect_and_formats() {
+        let converter = Arc::new(FakeConverter::returning_bytes("pdf", b"%PDF-1.7"));
+        let mut client = start(converter, crate::config::get().clone()).await;
+
+        let content = std::fs::read(
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/sample.docx"),
+        )
+        .unwrap();
+        let detection = client
+            .detect(DetectRequest { content })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(detection.r#type, "word");
+        assert_eq!(detection.extension, "docx");
+        assert_eq!(detection.confidence, "certain");
+        assert!(detection.encoding.is_empty());
+
+        let formats = client
+            .get_formats(GetFormatsRequest {})
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(formats.input_formats.contains(&"docx".to_string()));
+        assert!(formats.output_formats.contains(&"pdf".to_string()));
+    }
+}
@@ -0,0 +1,98 @@
+//! Names of the response headers the service sets beyond the standard ones, kept
+//! apart from the routes so the error responses can set them too
+
+use axum::http::{HeaderName, header};
+
+/// Header carrying the request ID, taken from the client or generated
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Lane the conversion was scheduled in
+pub const QUEUE_LANE_HEADER: &str = "x-queue-lane";
+
+/// Milliseconds the conversion waited for LibreOffice
+pub const QUEUE_WAIT_HEADER: &str = "x-queue-wait-ms";
+
+/// Milliseconds LibreOffice took once the conversion left the queue
+pub const CONVERSION_DURATION_HEADER: &str = "x-conversion-ms";
+
+/// Media type sniffed from the uploaded content
+pub const DETECTED_INPUT_TYPE_HEADER: &str = "x-detected-input-type";
+
+/// Set when the content was not recognized and converted by its extension alone
+pub const DETECTION_WARNING_HEADER: &str = "x-detection-warning";
+
+/// How tracked changes were handled, set when the request chose it
+pub const TRACKED_CHANGES_HEADER: &str = "x-tracked-changes";
+
+/// Comma separated fonts the input uses that were substituted, non-ASCII bytes,
+/// commas and `%` percent-encoded
+pub const MISSING_FONTS_HEADER: &str = "x-missing-fonts";
+
+/// Milliseconds the malware scan of the upload took
+pub const SCAN_DURATION_HEADER: &str = "x-scan-ms";
+
+/// Set to `true` when the input only converted after `repair`, parts of it may be lost
+pub const REPAIRED_HEADER: &str = "x-repaired";
+
+/// `true` when the same request may succeed later, `false` otherwise
+pub const RETRYABLE_HEADER: &str = "x-retryable";
+
+/// Id a kept output can be downloaded again with from `/results/{id}`
+pub const CONVERSION_ID_HEADER: &str = "x-conversion-id";
+
+/// Hex SHA-256 of the response body
+pub const CONTENT_SHA256_HEADER: &str = "x-content-sha256";
+
+/// RFC 3230 digest of the response body, `sha-256=` and the base64 SHA-256
+pub const DIGEST_HEADER: &str = "digest";
+
+/// Comma separated placeholders of the template no value was given for
+pub const UNMATCHED_PLACEHOLDERS_HEADER: &str = "x-unmatched-placeholders";
+
+/// Response headers browsers may read in CORS requests, besides the safelisted ones
+pub fn exposed() -> Vec<HeaderName> {
+    let mut exposed = vec![
+        header::CONTENT_DISPOSITION,
+        header::RETRY_AFTER,
+        REQUEST_ID_HEADER,
+    ];
+    exposed.extend(
+        [
+            QUEUE_LANE_HEADER,
+            QUEUE_WAIT_HEADER,
+            CONVERSION_DURATION_HEADER,
+            DETECTED_INPUT_TYPE_HEADER,
+            DETECTION_WARNING_HEADER,
+            TRACKED_CHANGES_HEADER,
+            MISSING_FONTS_HEADER,
+            SCAN_DURATION_HEADER,
+            REPAIRED_HEADER,
+            RETRYABLE_HEADER,
+            CONVERSION_ID_HEADER,
+            CONTENT_SHA256_HEADER,
+            DIGEST_HEADER,
+            UNMATCHED_PLACEHOLDERS_HEADER,
+        ]
+        .map(HeaderName::from_static),
+    );
+    exposed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_header_is_exposed() {
+        let exposed = exposed();
+        let names: Vec<&str> = include_str!("headers.rs")
+            .lines()
+            .filter(|line| line.starts_with("pub const ") && line.contains("_HEADER"))
+            .map(|line| line.split('"').nth(1).expect("header names are literals"))
+            .collect();
+        assert_eq!(names.len(), 15);
+        for name in names {
+            assert!(exposed.iter().any(|exposed| exposed == name), "{}", name);
+        }
+    }
+}
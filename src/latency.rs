@@ -0,0 +1,269 @@
+//! Rolling latency profile of conversions per format pair and input size, what
+//! `/estimate` predicts durations from
+
+use std::collections::{BTreeMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Config, state::AppState};
+
+/// Durations kept per format pair and size bucket, the oldest are dropped beyond it
+const MAX_BUCKET_SAMPLES: usize = 100;
+
+/// Durations kept across all buckets for the fallback estimate
+const MAX_GLOBAL_SAMPLES: usize = 1000;
+
+/// Upper bounds in bytes of the size buckets, larger inputs share the last bucket
+const SIZE_BUCKETS: &[u64] = &[64 * 1024, 1024 * 1024, 10 * 1024 * 1024, 50 * 1024 * 1024];
+
+/// File in the work dir the profile is kept in with `LATENCY_PROFILE_PERSIST`
+const PROFILE_FILE: &str = "latency-profile.json";
+
+/// Interval the profile is written to disk at when it changed
+const PERSIST_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What an estimate was computed from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Basis {
+    /// Conversions between the same formats of a similar size
+    Bucket,
+    /// All conversions, the bucket had no samples
+    Global,
+    /// Nothing converted yet
+    None,
+}
+
+/// Expected duration of a conversion once it got the LibreOffice slot
+#[derive(Debug, Clone, Copy)]
+pub struct Estimate {
+    pub basis: Basis,
+    pub samples: usize,
+    pub p50: Option<Duration>,
+    pub p95: Option<Duration>,
+}
+
+/// Durations in milliseconds, oldest first
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Samples {
+    /// Keyed by [`bucket_key`]
+    buckets: BTreeMap<String, VecDeque<u64>>,
+    global: VecDeque<u64>,
+}
+
+/// Recent conversion durations, optionally persisted across restarts
+pub struct LatencyProfile {
+    samples: Mutex<Samples>,
+    /// Where the profile is persisted, unset keeps it in memory only
+    path: Option<PathBuf>,
+    /// Set when samples were recorded since the last save
+    dirty: AtomicBool,
+}
+
+impl LatencyProfile {
+    /// Profile persisted at `path` and loaded from it, if given
+    pub fn new(path: Option<PathBuf>) -> Self {
+        let samples = path
+            .as_deref()
+            .and_then(|path| match load(path) {
+                Ok(samples) => samples,
+                Err(e) => {
+                    tracing::warn!("Ignoring latency profile {:?}: {}", path, e);
+                    None
+                }
+            })
+            .unwrap_or_default();
+        Self {
+            samples: Mutex::new(samples),
+            path,
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    /// Profile in the work dir with `LATENCY_PROFILE_PERSIST`, in memory otherwise
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(
+            config
+                .latency_profile_persist
+                .then(|| config.work_dir.join(PROFILE_FILE)),
+        )
+    }
+
+    /// Adds the duration of a successful conversion of `input_bytes` from `from` to `to`
+    pub fn record(&self, from: &str, to: &str, input_bytes: u64, duration: Duration) {
+        let millis = duration.as_millis() as u64;
+        let mut samples = self.samples.lock().unwrap();
+        let bucket = samples
+            .buckets
+            .entry(bucket_key(from, to, input_bytes))
+            .or_default();
+        push_bounded(bucket, millis, MAX_BUCKET_SAMPLES);
+        push_bounded(&mut samples.global, millis, MAX_GLOBAL_SAMPLES);
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// p50 and p95 of the conversions like the given one, falling back to all
+    /// conversions when there were none like it
+    pub fn estimate(&self, from: &str, to: &str, input_bytes: u64) -> Estimate {
+        let samples = self.samples.lock().unwrap();
+        let (basis, durations) = match samples.buckets.get(&bucket_key(from, to, input_bytes)) {
+            Some(bucket) if !bucket.is_empty() => (Basis::Bucket, bucket),
+            _ if !samples.global.is_empty() => (Basis::Global, &samples.global),
+            _ => (Basis::None, &samples.global),
+        };
+        Estimate {
+            basis,
+            samples: durations.len(),
+            p50: percentile(durations, 50),
+            p95: percentile(durations, 95),
+        }
+    }
+
+    /// Median of all recent conversions
+    pub fn global_median(&self) -> Option<Duration> {
+        percentile(&self.samples.lock().unwrap().global, 50)
+    }
+
+    /// Writes the profile to its file if it changed since the last save
+    pub fn save(&self) -> std::io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let json = serde_json::to_vec(&*self.samples.lock().unwrap())?;
+        // Renamed into place so a crash mid-write leaves the previous profile
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, json)?;
+        std::fs::rename(&tmp, path)
+    }
+}
+
+/// Upper bound in bytes of the size bucket of `input_bytes`, none for the last
+pub fn size_bucket_max(input_bytes: u64) -> Option<u64> {
+    SIZE_BUCKETS.iter().copied().find(|max| input_bytes < *max)
+}
+
+/// Saves the profile every [`PERSIST_INTERVAL`]
+pub fn spawn_persister(state: Arc<AppState>) {
+    if !state.config().latency_profile_persist {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PERSIST_INTERVAL);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            save(&state);
+        }
+    });
+}
+
+/// Saves the profile, logging failures
+pub fn save(state: &AppState) {
+    if let Err(e) = state.latency().save() {
+        tracing::warn!("Could not save the latency profile: {}", e);
+    }
+}
+
+fn load(path: &Path) -> std::io::Result<Option<Samples>> {
+    match std::fs::read(path) {
+        Ok(json) => Ok(Some(serde_json::from_slice(&json)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn bucket_key(from: &str, to: &str, input_bytes: u64) -> String {
+    let bucket = SIZE_BUCKETS
+        .iter()
+        .position(|max| input_bytes < *max)
+        .unwrap_or(SIZE_BUCKETS.len());
+    format!("{}:{}:{}", from, to, bucket)
+}
+
+fn push_bounded(samples: &mut VecDeque<u64>, millis: u64, max: usize) {
+    if samples.len() == max {
+        samples.pop_front();
+    }
+    samples.push_back(millis);
+}
+
+/// Nearest-rank percentile, none without samples
+fn percentile(samples: &VecDeque<u64>, percent: usize) -> Option<Duration> {
+    if samples.is_empty() {
+        return None;
+    }
+    let mut sorted: Vec<u64> = samples.iter().copied().collect();
+    sorted.sort_unstable();
+    let rank = (sorted.len() * percent).div_ceil(100).max(1);
+    Some(Duration::from_millis(sorted[rank - 1]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: u64 = 1024 * 1024;
+
+    #[test]
+    fn test_estimate_per_bucket() {
+        let profile = LatencyProfile::new(None);
+        for millis in 1..=20 {
+            profile.record("docx", "pdf", MB, Duration::from_millis(millis * 100));
+        }
+        profile.record("docx", "pdf", 100 * MB, Duration::from_secs(60));
+
+        let estimate = profile.estimate("docx", "pdf", 2 * MB);
+        assert_eq!(estimate.basis, Basis::Bucket);
+        assert_eq!(estimate.samples, 20);
+        assert_eq!(estimate.p50, Some(Duration::from_millis(1000)));
+        assert_eq!(estimate.p95, Some(Duration::from_millis(1900)));
+
+        let large = profile.estimate("docx", "pdf", 60 * MB);
+        assert_eq!(large.basis, Basis::Bucket);
+        assert_eq!(large.p95, Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_estimate_falls_back_to_all_conversions() {
+        let profile = LatencyProfile::new(None);
+        let none = profile.estimate("xlsx", "pdf", MB);
+        assert_eq!(none.basis, Basis::None);
+        assert_eq!(none.p50, None);
+
+        profile.record("docx", "pdf", MB, Duration::from_millis(400));
+        let global = profile.estimate("xlsx", "pdf", MB);
+        assert_eq!(global.basis, Basis::Global);
+        assert_eq!(global.samples, 1);
+        assert_eq!(global.p50, Some(Duration::from_millis(400)));
+        assert_eq!(profile.global_median(), Some(Duration::from_millis(400)));
+    }
+
+    #[test]
+    fn test_profile_persists() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(PROFILE_FILE);
+        let profile = LatencyProfile::new(Some(path.clone()));
+        profile.save().unwrap();
+        assert!(!path.exists(), "nothing recorded, nothing to save");
+
+        profile.record("docx", "pdf", MB, Duration::from_millis(700));
+        profile.save().unwrap();
+
+        let restored = LatencyProfile::new(Some(path.clone()));
+        let estimate = restored.estimate("docx", "pdf", MB);
+        assert_eq!(estimate.basis, Basis::Bucket);
+        assert_eq!(estimate.p50, Some(Duration::from_millis(700)));
+
+        std::fs::write(&path, b"not json").unwrap();
+        let corrupt = LatencyProfile::new(Some(path));
+        assert_eq!(corrupt.estimate("docx", "pdf", MB).basis, Basis::None);
+    }
+}
@@ -0,0 +1,130 @@
+//! Document conversion with LibreOffice, served over HTTP or embedded.
+//!
+//! [`Converter`] is the conversion pipeline without HTTP. [`LibreOfficeConverter`]
+//! implements it with the configured [`backend`]s. [`build_router`] serves the
+//! API of an [`AppState`] holding a converter.
+
+#![warn(missing_docs)]
+
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use axum::Router;
+
+mod audit;
+pub mod backend;
+mod build_info;
+mod cfb;
+mod checksum;
+pub mod cli;
+mod coalesce;
+pub mod config;
+mod connections;
+pub mod converter;
+mod cors;
+mod deadline;
+pub mod detect_filetype;
+pub mod error;
+mod filename;
+mod fonts;
+pub mod formats;
+#[cfg(all(test, feature = "functional-tests"))]
+mod functional_tests;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod headers;
+mod latency;
+mod libreoffice;
+mod logging;
+mod metadata;
+mod office_xml;
+mod page_count;
+mod page_setup;
+mod panic;
+mod pdf;
+mod pdf_encryption;
+mod pipeline;
+mod queue;
+mod reaper;
+mod repair;
+mod request_id;
+mod results;
+mod retry;
+mod routes;
+mod scanner;
+mod selftest;
+mod server;
+mod state;
+mod template;
+mod tracked_changes;
+mod usage;
+mod warmup;
+mod workdir;
+
+pub use converter::{ConversionOptions, Converter};
+pub use libreoffice::{
+    ConversionOutput, ConvertedOutput, InputFile, LibreOfficeConverter, OutputFile,
+};
+pub use state::{AppState, AppStateBuilder};
+
+/// Router of the public API, `/convert`, `/detect`, `/health` and the others
+pub fn build_router(state: AppState) -> Router {
+    routes::router(Arc::new(state))
+}
+
+/// Runs the server configured by the environment until it is shut down. Fails
+/// without serving when the startup self-test does, unless `SELFTEST_SOFT` is set.
+pub async fn serve() -> ExitCode {
+    logging::init();
+    panic::install_hook();
+
+    let metrics = routes::metrics::install_recorder();
+    let state = Arc::new(AppState::builder().metrics(metrics).build());
+    let config = state.config();
+    build_info::log_startup(config);
+
+    for backend in state.backends().backends() {
+        tracing::info!(
+            "Conversion backend {} available: {}",
+            backend.name(),
+            backend.is_available()
+        );
+    }
+    match state.libreoffice_binary() {
+        Some(path) => tracing::info!("Using LibreOffice executable {:?}", path),
+        None => tracing::error!(
+            "LibreOffice executable not found, set LIBREOFFICE_BIN or add libreoffice/soffice to PATH"
+        ),
+    }
+
+    let work_dir = &config.work_dir;
+    tracing::info!("Using work directory {:?}", work_dir);
+    reaper::remove_stale_lock_files(work_dir);
+    workdir::sweep_stale_dirs(work_dir, config.temp_dir_max_age);
+    workdir::spawn_janitor(work_dir.clone(), config.temp_dir_max_age);
+    reaper::spawn_reaper(config.conversion_timeout);
+
+    if config.selftest
+        && let Err(e) = selftest::run(&state).await
+    {
+        if !config.selftest_soft {
+            tracing::error!("Self-test failed, not starting: {}", e);
+            return ExitCode::FAILURE;
+        }
+        tracing::error!("Self-test failed, /ready stays 503: {}", e);
+        selftest::fail(&e);
+    }
+    warmup::spawn_warmup(state.clone());
+    latency::spawn_persister(state.clone());
+    usage::spawn_exporter(state.clone());
+
+    server::serve(
+        routes::router(state.clone()),
+        routes::admin_router(state.clone()),
+        state.clone(),
+    )
+    .await;
+    latency::save(&state);
+    usage::export(&state).await;
+    ExitCode::SUCCESS
+}
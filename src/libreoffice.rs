@@ -1,68 +1,236 @@
-use std::path::PathBuf;
-use std::sync::OnceLock;
-use tempfile::{TempDir, tempdir};
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{Output, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
 use tokio::process::Command as TokioCommand;
-use tokio::sync::Mutex;
 
 use crate::{
-    detect_filetype::{FileType, detect_file_type_from_bytes},
+    backend::{self, BackendChain},
+    cfb, checksum,
+    coalesce::{Coalescer, ConversionKey},
+    config::{self, Config, ResourceLimits},
+    converter::{self, ConversionOptions, Converter},
+    detect_filetype::{
+        DetectedType, FileType, detect_file_type_from_reader, is_encrypted_compound_file,
+    },
     error::{LibreOfficeError, Result},
+    fonts,
+    formats::{IMAGE_OUTPUT_FORMATS, InputFormat, OutputFormat},
+    metadata,
+    page_count::{self, PageCount},
+    page_setup, pdf_encryption,
+    queue::{self, Lane, QueueStats, Scheduler},
+    reaper, repair,
+    scanner::{self, Scanner},
+    tracked_changes::{self, TrackedChanges},
+    workdir::{self, WorkDir},
 };
 
-// Global mutex to ensure only one LibreOffice conversion runs at a time
-static LIBREOFFICE_LOCK: OnceLock<Mutex<()>> = OnceLock::new();
+// Profile resets since the last successful conversion
+static PROFILE_RESETS: AtomicU32 = AtomicU32::new(0);
 
-fn get_libreoffice_lock() -> &'static Mutex<()> {
-    LIBREOFFICE_LOCK.get_or_init(|| Mutex::new(()))
+// Failed runs in a row that exited right after launch
+static IMMEDIATE_EXITS: AtomicU32 = AtomicU32::new(0);
+
+/// Runs exiting faster than this are treated as LibreOffice failing to start
+const IMMEDIATE_EXIT_THRESHOLD: Duration = Duration::from_secs(1);
+
+/// Consecutive immediate exits that are blamed on the user profile
+const IMMEDIATE_EXITS_BEFORE_RESET: u32 = 2;
+
+/// Attempts per conversion, the first run plus one retry for transient failures
+const MAX_ATTEMPTS: u32 = 2;
+
+/// Pause before retrying a transient failure
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Exit codes LibreOffice returns when it fails to start rather than to convert
+const RETRYABLE_EXIT_CODES: &[i32] = &[81];
+
+/// Name of the spilled upload before it is renamed for conversion
+const UPLOAD_FILENAME: &str = "upload";
+
+/// Maximum runtime of `libreoffice --version`
+const VERSION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// CPU seconds past `LIBREOFFICE_CPU_LIMIT_SECS` before a run ignoring SIGXCPU is killed
+const CPU_LIMIT_GRACE_SECS: u64 = 5;
+
+/// Directory inside the conversion's temp dir used as HOME by LibreOffice
+const SCRATCH_HOME_DIR: &str = "home";
+
+/// Directory inside the conversion's temp dir LibreOffice writes the output to,
+/// apart from the input so a re-save in the same format can't be mistaken for it
+const OUTPUT_DIR: &str = "out";
+
+struct RunOutput {
+    output: Output,
+    elapsed: Duration,
+}
+
+/// Binary names tried on PATH when `LIBREOFFICE_BIN` is not set
+const DEFAULT_BINARIES: &[&str] = &["libreoffice", "soffice"];
+
+/// Resolves the LibreOffice executable of `config`, `libreoffice_bin` or the first
+/// of [`DEFAULT_BINARIES`] on PATH
+pub fn find_libreoffice(config: &Config) -> Option<PathBuf> {
+    match &config.libreoffice_bin {
+        Some(bin) => resolve_executable(bin),
+        None => DEFAULT_BINARIES
+            .iter()
+            .find_map(|name| resolve_executable(name)),
+    }
+}
+
+/// First line of `program --version`, none when it fails or prints nothing
+pub async fn libreoffice_version(program: &Path) -> Option<String> {
+    let output = tokio::time::timeout(
+        VERSION_TIMEOUT,
+        TokioCommand::new(program)
+            .arg("--version")
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output(),
+    )
+    .await
+    .ok()?
+    .ok()?;
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+}
+
+/// Resolves a program given either as path or as name to look up on PATH
+pub fn resolve_executable(program: &str) -> Option<PathBuf> {
+    if program.contains('/') {
+        return Some(PathBuf::from(program)).filter(|path| is_executable(path));
+    }
+
+    find_executable(program, &std::env::var_os("PATH").unwrap_or_default())
+}
+
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+/// Searches the directories of a PATH-style variable for an executable
+fn find_executable(name: &str, path_var: &std::ffi::OsStr) -> Option<PathBuf> {
+    std::env::split_paths(path_var)
+        .map(|dir| dir.join(name))
+        .find(|candidate| is_executable(candidate))
 }
 
-fn temp_dir_with_files(input_name: &str) -> std::io::Result<(PathBuf, PathBuf, TempDir)> {
-    let temp_dir = tempdir()?;
+fn temp_dir_with_files(
+    work_dir: &Path,
+    input_name: &str,
+) -> std::io::Result<(PathBuf, PathBuf, WorkDir)> {
+    let temp_dir = workdir::create_temp_dir(work_dir)?;
     let input_path = temp_dir.path().join(input_name);
     let output_dir = temp_dir.path().to_path_buf();
 
     Ok((input_path, output_dir, temp_dir))
 }
 
-/// Analyzes LibreOffice error output to provide more specific error messages
-fn analyze_libreoffice_error(stderr: &str, stdout: &str, from: &str, to: &str) -> LibreOfficeError {
-    let combined_output = format!("{} {}", stderr, stdout).to_lowercase();
-
-    // Check for specific error patterns
-    if combined_output.contains("password") || combined_output.contains("encrypted") {
-        return LibreOfficeError::PasswordProtected;
-    }
+/// Error classes recognized in LibreOffice output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ErrorClass {
+    PasswordProtected,
+    UnsupportedConversion,
+    CorruptedInput,
+    EmptyInput,
+}
 
-    if combined_output.contains("format") && combined_output.contains("not supported") {
-        return LibreOfficeError::UnsupportedConversion {
-            from: from.to_string(),
-            to: to.to_string(),
-        };
+impl ErrorClass {
+    /// Label in `libreoffice_error_heuristics_total`
+    fn branch(self) -> &'static str {
+        match self {
+            ErrorClass::PasswordProtected => "password",
+            ErrorClass::UnsupportedConversion => "filter_not_found",
+            ErrorClass::CorruptedInput => "corrupt",
+            ErrorClass::EmptyInput => "empty",
+        }
     }
+}
 
-    if combined_output.contains("corrupt")
-        || combined_output.contains("damaged")
-        || combined_output.contains("invalid")
-        || combined_output.contains("parse error")
-        || combined_output.contains("bad file")
-    {
-        return LibreOfficeError::CorruptedInput(format!(
-            "File appears to be corrupted or in an invalid format"
-        ));
-    }
+/// Lowercase patterns mapped to the error they indicate, first match wins.
+/// Every substring of an entry has to be present.
+const ERROR_PATTERNS: &[(&[&str], ErrorClass)] = &[
+    (&["password"], ErrorClass::PasswordProtected),
+    (&["encrypted"], ErrorClass::PasswordProtected),
+    (&["no export filter"], ErrorClass::UnsupportedConversion),
+    (
+        &["format", "not supported"],
+        ErrorClass::UnsupportedConversion,
+    ),
+    (
+        &["source file could not be loaded"],
+        ErrorClass::CorruptedInput,
+    ),
+    (&["loadcomponentfromurl"], ErrorClass::CorruptedInput),
+    (&["corrupt"], ErrorClass::CorruptedInput),
+    (&["damaged"], ErrorClass::CorruptedInput),
+    (&["invalid"], ErrorClass::CorruptedInput),
+    (&["parse error"], ErrorClass::CorruptedInput),
+    (&["bad file"], ErrorClass::CorruptedInput),
+    (&["empty"], ErrorClass::EmptyInput),
+    (&["no content"], ErrorClass::EmptyInput),
+    (&["zero bytes"], ErrorClass::EmptyInput),
+    (&["filter", "not found"], ErrorClass::UnsupportedConversion),
+];
+
+fn classify_output(output: &str) -> Option<ErrorClass> {
+    let output = output.to_lowercase();
+
+    ERROR_PATTERNS
+        .iter()
+        .find(|(patterns, _)| patterns.iter().all(|pattern| output.contains(pattern)))
+        .map(|(_, class)| *class)
+}
 
-    if combined_output.contains("empty")
-        || combined_output.contains("no content")
-        || combined_output.contains("zero bytes")
-    {
-        return LibreOfficeError::EmptyOrInvalidInput;
-    }
+/// Counts which pattern explained a failure, `generic` when none did
+fn record_heuristic(class: Option<ErrorClass>) {
+    let branch = class.map_or("generic", ErrorClass::branch);
+    metrics::counter!("libreoffice_error_heuristics_total", "branch" => branch).increment(1);
+}
 
-    if combined_output.contains("filter") && combined_output.contains("not found") {
-        return LibreOfficeError::UnsupportedConversion {
+fn error_for_class(class: ErrorClass, from: &str, to: &str) -> LibreOfficeError {
+    match class {
+        ErrorClass::PasswordProtected => LibreOfficeError::PasswordProtected,
+        ErrorClass::UnsupportedConversion => LibreOfficeError::UnsupportedConversion {
             from: from.to_string(),
             to: to.to_string(),
-        };
+        },
+        ErrorClass::CorruptedInput => LibreOfficeError::CorruptedInput(
+            "File appears to be corrupted or in an invalid format".to_string(),
+        ),
+        ErrorClass::EmptyInput => LibreOfficeError::EmptyOrInvalidInput,
+    }
+}
+
+/// Analyzes LibreOffice error output to provide more specific error messages
+pub fn analyze_libreoffice_error(
+    stderr: &str,
+    stdout: &str,
+    from: &str,
+    to: &str,
+) -> LibreOfficeError {
+    let class = classify_output(&format!("{} {}", stderr, stdout));
+    record_heuristic(class);
+    if let Some(class) = class {
+        return error_for_class(class, from, to);
     }
 
     // Default to generic conversion failed with full output
@@ -72,8 +240,144 @@ fn analyze_libreoffice_error(stderr: &str, stdout: &str, from: &str, to: &str) -
     ))
 }
 
+/// Looks for `Error:` lines, which LibreOffice prints even when it exits with status 0
+fn find_reported_error(
+    stderr: &str,
+    stdout: &str,
+    from: &str,
+    to: &str,
+) -> Option<LibreOfficeError> {
+    let error_lines: Vec<&str> = stdout
+        .lines()
+        .chain(stderr.lines())
+        .map(str::trim)
+        .filter(|line| line.starts_with("Error:"))
+        .collect();
+
+    if error_lines.is_empty() {
+        return None;
+    }
+
+    let reported = error_lines.join("\n");
+    let class = classify_output(&reported);
+    record_heuristic(class);
+    Some(match class {
+        Some(class) => error_for_class(class, from, to),
+        None => LibreOfficeError::ConversionFailed(reported),
+    })
+}
+
+/// What LibreOffice says when a lock file of a concurrent or crashed run is in the way,
+/// lowercase
+const LOCK_MESSAGES: &[&str] = &[".~lock.", "profile is locked", "is locked by another"];
+
+/// Checks LibreOffice output for symptoms of a corrupted user profile
+fn is_profile_corruption(stderr: &str) -> bool {
+    let stderr = stderr.to_lowercase();
+    stderr.contains("javaldx") || stderr.contains("user installation could not be completed")
+}
+
+/// Whether LibreOffice blames the input document itself, which no retry or reset can fix
+fn is_document_error(stderr: &str, stdout: &str) -> bool {
+    classify_output(&format!("{} {}", stderr, stdout)).is_some()
+}
+
+/// Decides whether a failed run should be retried with a fresh user profile
+fn needs_profile_reset(run: &RunOutput) -> bool {
+    if run.output.status.success() {
+        IMMEDIATE_EXITS.store(0, Ordering::SeqCst);
+        return false;
+    }
+
+    let stderr = String::from_utf8_lossy(&run.output.stderr);
+    if is_profile_corruption(&stderr) {
+        return true;
+    }
+
+    // Quick exits explained by the document don't point at the profile
+    let stdout = String::from_utf8_lossy(&run.output.stdout);
+    if is_document_error(&stderr, &stdout) {
+        IMMEDIATE_EXITS.store(0, Ordering::SeqCst);
+        return false;
+    }
+
+    if run.elapsed < IMMEDIATE_EXIT_THRESHOLD {
+        let exits = IMMEDIATE_EXITS.fetch_add(1, Ordering::SeqCst) + 1;
+        if exits >= IMMEDIATE_EXITS_BEFORE_RESET {
+            IMMEDIATE_EXITS.store(0, Ordering::SeqCst);
+            return true;
+        }
+    } else {
+        IMMEDIATE_EXITS.store(0, Ordering::SeqCst);
+    }
+
+    false
+}
+
+/// Location of the LibreOffice user profile, either configured or the default one
+fn profile_dir(config: &Config) -> Option<PathBuf> {
+    if config.profile_dir.is_none() && config.scratch_home {
+        // Every run starts from a fresh profile in its scratch HOME
+        return None;
+    }
+
+    config.profile_dir.clone().or_else(|| {
+        std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config/libreoffice"))
+    })
+}
+
+/// Creates the scratch HOME for a run, unless disabled with `SCRATCH_HOME=false`
+async fn scratch_home(config: &Config, output_dir: &Path) -> Result<Option<PathBuf>> {
+    if !config.scratch_home {
+        return Ok(None);
+    }
+
+    let home = output_dir.join(SCRATCH_HOME_DIR);
+    tokio::fs::create_dir_all(&home)
+        .await
+        .map_err(LibreOfficeError::from_io)?;
+    Ok(Some(home))
+}
+
+/// Deletes and recreates the user profile, giving up after too many consecutive resets
+async fn reset_profile(config: &Config) -> Result<()> {
+    let resets = PROFILE_RESETS.load(Ordering::SeqCst);
+    if resets >= config.max_profile_resets {
+        tracing::error!(
+            "LibreOffice profile reset {} times in a row without a successful conversion, giving up",
+            resets
+        );
+        return Err(LibreOfficeError::ProfileCorrupted(resets));
+    }
+
+    let resets = PROFILE_RESETS.fetch_add(1, Ordering::SeqCst) + 1;
+    metrics::counter!("libreoffice_profile_resets_total").increment(1);
+    metrics::gauge!("libreoffice_profile_consecutive_resets").set(resets as f64);
+
+    let Some(dir) = profile_dir(config) else {
+        tracing::warn!("Cannot locate LibreOffice profile directory, retrying without reset");
+        return Ok(());
+    };
+
+    tracing::warn!(
+        "LibreOffice profile looks corrupted, resetting {:?} (attempt {})",
+        dir,
+        resets
+    );
+    match tokio::fs::remove_dir_all(&dir).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(LibreOfficeError::from_io(e)),
+    }
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .map_err(LibreOfficeError::from_io)?;
+
+    Ok(())
+}
+
 /// Analyzes why the output file is missing to provide more specific error messages
-fn analyze_missing_output_error(output_dir: &PathBuf) -> LibreOfficeError {
+fn analyze_missing_output_error(output_dir: &Path) -> LibreOfficeError {
     // Check what files actually exist in the output directory
     if let Ok(entries) = std::fs::read_dir(output_dir) {
         let files: Vec<String> = entries
@@ -93,250 +397,2393 @@ fn analyze_missing_output_error(output_dir: &PathBuf) -> LibreOfficeError {
     LibreOfficeError::OutputNotFound
 }
 
-/// Async version using tokio::process::Command with timeout
-pub async fn convert_libreoffice_async(
-    input_buf: Vec<u8>,
-    from: &str,
-    to: &str,
-) -> Result<Vec<u8>> {
-    tracing::debug!("Starting async CLI conversion: {} -> {}", from, to);
+/// Runs a single LibreOffice CLI conversion with timeout
+async fn run_libreoffice(
+    config: &Config,
+    program: &Path,
+    input_path: &Path,
+    output_dir: &Path,
+    convert_to: &str,
+    input_filter: Option<&str>,
+    home: Option<&Path>,
+) -> Result<RunOutput> {
+    let mut args = vec![
+        "--headless".to_string(),
+        "--convert-to".to_string(),
+        convert_to.to_string(),
+        "--outdir".to_string(),
+        output_dir.to_string_lossy().to_string(),
+    ];
+    if let Some(input_filter) = input_filter {
+        args.push(format!("--infilter={}", input_filter));
+    }
+    if let Some(profile_dir) = &config.profile_dir {
+        args.push(format!(
+            "-env:UserInstallation=file://{}",
+            profile_dir.display()
+        ));
+    }
+    args.extend(config.libreoffice_extra_args.iter().cloned());
+    args.push(input_path.to_string_lossy().to_string());
+
+    run_program(
+        program,
+        &args,
+        home,
+        config.resource_limits,
+        config.conversion_timeout,
+    )
+    .await
+}
 
-    // Acquire the lock to ensure only one LibreOffice process runs at a time
-    tracing::debug!("Waiting for LibreOffice lock...");
-    let _lock = get_libreoffice_lock().lock().await;
-    tracing::debug!("LibreOffice lock acquired, proceeding with conversion");
+/// Sets the configured rlimits, runs in the child between fork and exec
+fn apply_resource_limits(limits: ResourceLimits) -> std::io::Result<()> {
+    let settings = [
+        (libc::RLIMIT_AS, limits.memory_bytes),
+        (libc::RLIMIT_CPU, limits.cpu_secs),
+    ];
 
-    let input_filename = format!("document.{}", from);
-    let (input_path, output_dir, _temp_dir) =
-        temp_dir_with_files(&input_filename).map_err(LibreOfficeError::Io)?;
+    for (resource, limit) in settings {
+        let Some(limit) = limit else {
+            continue;
+        };
+        // At the hard limit the kernel sends SIGKILL, which can't be told apart
+        // from other kills, so only the soft limit's SIGXCPU is reported
+        let grace = if resource == libc::RLIMIT_CPU {
+            CPU_LIMIT_GRACE_SECS
+        } else {
+            0
+        };
+        let rlimit = libc::rlimit {
+            rlim_cur: limit as libc::rlim_t,
+            rlim_max: limit.saturating_add(grace) as libc::rlim_t,
+        };
+        if unsafe { libc::setrlimit(resource, &rlimit) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
 
-    // Write input file asynchronously
-    tokio::fs::write(&input_path, input_buf)
-        .await
-        .map_err(LibreOfficeError::Io)?;
-    tracing::debug!("Input file written: {:?}", input_path);
+    Ok(())
+}
 
-    // Run LibreOffice conversion with timeout
-    tracing::debug!("Running LibreOffice conversion...");
-    let output = tokio::time::timeout(
-        std::time::Duration::from_secs(60), // 60 second timeout
-        TokioCommand::new("libreoffice")
-            .args(&[
-                "--headless",
-                "--convert-to",
-                &to,
-                "--outdir",
-                output_dir.to_str().unwrap(),
-                input_path.to_str().unwrap(),
-            ])
-            .output(),
-    )
-    .await;
+/// A program that can't be started leaves the backend unusable, unlike IO
+/// errors of the conversion itself
+fn spawn_error(program: &Path, error: std::io::Error) -> LibreOfficeError {
+    match error.kind() {
+        std::io::ErrorKind::NotFound | std::io::ErrorKind::PermissionDenied => {
+            tracing::error!("Cannot run {:?}: {}", program, error);
+            LibreOfficeError::BackendUnavailable(format!(
+                "cannot run {}: {}",
+                program.display(),
+                error
+            ))
+        }
+        _ => LibreOfficeError::from_io(error),
+    }
+}
 
-    let output = match output {
-        Ok(Ok(output)) => output,
-        Ok(Err(e)) => return Err(LibreOfficeError::Io(e)),
-        Err(_) => return Err(LibreOfficeError::Timeout),
-    };
+async fn run_program(
+    program: &Path,
+    args: &[String],
+    home: Option<&Path>,
+    limits: ResourceLimits,
+    timeout: Duration,
+) -> Result<RunOutput> {
+    // Own process group so soffice.bin and its helpers can be reaped together
+    let mut command = TokioCommand::new(program);
+    command
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .kill_on_drop(true);
+    if let Some(home) = home {
+        // LibreOffice writes its profile and caches below these, keep them off the real HOME
+        command
+            .env("HOME", home)
+            .env("XDG_CONFIG_HOME", home.join(".config"))
+            .env("XDG_CACHE_HOME", home.join(".cache"));
+    }
+    if limits.is_enabled() {
+        // Only calls setrlimit, which is async-signal-safe
+        unsafe {
+            command.pre_exec(move || apply_resource_limits(limits));
+        }
+    }
 
-    let stderr = String::from_utf8_lossy(&output.stderr);
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    tracing::debug!("LibreOffice stderr: {}", stderr);
-    tracing::debug!("LibreOffice stdout: {}", stdout);
+    let child = command.spawn().map_err(|e| spawn_error(program, e))?;
+    // Kills the whole group if this future is dropped, e.g. when the client disconnects
+    let guard = child.id().map(reaper::ProcessGroupGuard::new);
 
-    // Check if conversion succeeded and analyze the error
-    if !output.status.success() {
-        // Analyze the error output for specific issues
-        let error = analyze_libreoffice_error(&stderr, &stdout, &from, &to);
-        return Err(error);
+    let started = Instant::now();
+    let output = tokio::time::timeout(timeout, child.wait_with_output()).await;
+
+    if let (Some(guard), Ok(Ok(_))) = (guard, &output) {
+        guard.disarm();
     }
 
-    tracing::debug!("LibreOffice conversion completed successfully");
+    match output {
+        Ok(Ok(output)) => Ok(RunOutput {
+            output,
+            elapsed: started.elapsed(),
+        }),
+        Ok(Err(e)) => Err(LibreOfficeError::from_io(e)),
+        Err(_) => Err(LibreOfficeError::Timeout { queue_wait: None }),
+    }
+}
+
+/// Whether a run was stopped by one of the configured rlimits rather than failing
+/// on its own: exceeding RLIMIT_CPU sends SIGXCPU, failed allocations under
+/// RLIMIT_AS end in SIGSEGV, SIGABRT or an out of memory message. Other crashes
+/// stay conversion failures.
+fn hit_resource_limit(output: &Output, limits: ResourceLimits) -> bool {
+    let signal = output.status.signal();
+
+    let cpu = limits.cpu_secs.is_some() && signal == Some(libc::SIGXCPU);
+    let memory = limits.memory_bytes.is_some() && {
+        let stderr = String::from_utf8_lossy(&output.stderr).to_lowercase();
+        signal.is_some_and(|signal| [libc::SIGSEGV, libc::SIGABRT].contains(&signal))
+            || ["bad_alloc", "cannot allocate memory", "out of memory"]
+                .iter()
+                .any(|pattern| stderr.contains(pattern))
+    };
 
-    // Find and read the output file
-    let expected_output = output_dir.join(format!("document.{}", to));
+    cpu || memory
+}
 
-    println!("Looking for output file at {:?}", expected_output);
+/// Content of a converted file, in memory or left on disk by the backend
+#[derive(Debug, Clone)]
+pub enum ConvertedOutput {
+    /// Content held in memory
+    Bytes(Vec<u8>),
+    /// A file in the conversion's temp directory, see [`ConversionOutput::work_dir`]
+    File {
+        /// Where the file is
+        path: PathBuf,
+        /// Its size in bytes
+        len: u64,
+    },
+}
 
-    if !expected_output.exists() {
-        // Try to find any file with the target extension
-        let mut entries = tokio::fs::read_dir(&output_dir)
-            .await
-            .map_err(LibreOfficeError::Io)?;
-        let mut found_file = None;
+impl ConvertedOutput {
+    /// Size of the content in bytes
+    pub fn len(&self) -> u64 {
+        match self {
+            Self::Bytes(data) => data.len() as u64,
+            Self::File { len, .. } => *len,
+        }
+    }
 
-        while let Some(entry) = entries.next_entry().await.map_err(LibreOfficeError::Io)? {
-            let path = entry.path();
+    /// Whether the content has no bytes
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 
-            if let Some(ext) = path.extension() {
-                if ext == to {
-                    found_file = Some(path);
-                    break;
-                }
+    /// Up to `max_len` bytes from the start of the content
+    pub async fn head(&self, max_len: usize) -> std::io::Result<Vec<u8>> {
+        match self {
+            Self::Bytes(data) => Ok(data[..data.len().min(max_len)].to_vec()),
+            Self::File { path, .. } => {
+                let mut head = Vec::with_capacity(max_len);
+                tokio::fs::File::open(path)
+                    .await?
+                    .take(max_len as u64)
+                    .read_to_end(&mut head)
+                    .await?;
+                Ok(head)
             }
         }
+    }
 
-        match found_file {
-            Some(path) => {
-                let output_data = tokio::fs::read(path).await.map_err(LibreOfficeError::Io)?;
-                tracing::debug!(
-                    "Conversion completed, output size: {} bytes",
-                    output_data.len()
-                );
-                return Ok(output_data);
-            }
-            None => {
-                // No output file found - this could indicate various issues
-                return Err(analyze_missing_output_error(&output_dir));
-            }
+    /// The whole content, reading the file back if needed
+    #[cfg(test)]
+    pub async fn into_bytes(self) -> std::io::Result<Vec<u8>> {
+        match self {
+            Self::Bytes(data) => Ok(data),
+            Self::File { path, .. } => tokio::fs::read(path).await,
         }
     }
+}
 
-    // Read the converted file
-    let output_data = tokio::fs::read(expected_output)
-        .await
-        .map_err(LibreOfficeError::Io)?;
-    tracing::debug!(
-        "Conversion completed, output size: {} bytes",
-        output_data.len()
-    );
+/// A file produced by a conversion
+#[derive(Debug, Clone)]
+pub struct OutputFile {
+    /// Filename the backend gave the file
+    pub name: String,
+    /// Content of the file
+    pub data: ConvertedOutput,
+}
 
-    Ok(output_data)
+/// Everything a conversion produced: the requested document plus auxiliary files
+/// some filters emit next to it (images of an html export, one csv per sheet, ...)
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ConversionOutput {
+    /// The document in the requested format
+    pub primary: OutputFile,
+    /// Files the filter wrote next to the document
+    pub auxiliary: Vec<OutputFile>,
+    /// Scheduling of the conversion, set once it went through the queue
+    pub queue: Option<QueueStats>,
+    /// Temp directory holding the file-backed outputs, removed once the last
+    /// clone is dropped
+    pub work_dir: Option<Arc<WorkDir>>,
+    /// Fonts the input declares that aren't installed and were substituted
+    pub missing_fonts: Vec<String>,
+    /// Whether the input only converted after a repair, parts of it may be lost
+    pub repaired: bool,
+    /// Time the malware scan of the input took, unset when scanning is off
+    pub scan_duration: Option<Duration>,
+    /// Time LibreOffice took once the conversion left the queue
+    pub conversion_duration: Option<Duration>,
+    /// SHA-256 of the primary output, computed once the converter finished it.
+    /// Unset when the converter leaves it to the response.
+    pub sha256: Option<[u8; 32]>,
 }
 
-// Convenience function - use the async version by default
-pub async fn convert_libreoffice(input_buf: Vec<u8>, from: &str, to: &str) -> Result<Vec<u8>> {
-    let detected_mimetype = detect_file_type_from_bytes(&input_buf);
+impl ConversionOutput {
+    /// Output of a conversion producing `primary` only, with nothing else to report
+    pub fn new(primary: OutputFile) -> Self {
+        Self {
+            primary,
+            auxiliary: Vec::new(),
+            queue: None,
+            work_dir: None,
+            missing_fonts: Vec::new(),
+            repaired: false,
+            scan_duration: None,
+            conversion_duration: None,
+            sha256: None,
+        }
+    }
 
-    if detected_mimetype == FileType::Unknown {
-        return Err(LibreOfficeError::UnsupportedConversion {
-            from: from.to_string(),
-            to: to.to_string(),
-        });
+    /// Adds the files the filter wrote next to the document
+    pub fn with_auxiliary(mut self, auxiliary: Vec<OutputFile>) -> Self {
+        self.auxiliary = auxiliary;
+        self
     }
 
-    convert_libreoffice_async(input_buf, from, to).await
+    /// Keeps the temp directory of file-backed outputs until the output is dropped
+    pub fn with_work_dir(mut self, work_dir: Arc<WorkDir>) -> Self {
+        self.work_dir = Some(work_dir);
+        self
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::sync::Arc;
-    use std::sync::atomic::{AtomicUsize, Ordering};
-    use std::time::Instant;
-    use tokio::time::{Duration, sleep};
+/// Names of the files currently in `dir`
+async fn snapshot_dir(dir: &Path) -> Result<HashSet<OsString>> {
+    let mut entries = tokio::fs::read_dir(dir)
+        .await
+        .map_err(LibreOfficeError::from_io)?;
+    let mut names = HashSet::new();
 
-    #[tokio::test]
-    async fn test_libreoffice_lock_initialization() {
-        // Test that the lock can be initialized and acquired
-        let lock = get_libreoffice_lock();
-        let _guard = lock.lock().await;
-        // If we get here, the lock works
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .map_err(LibreOfficeError::from_io)?
+    {
+        names.insert(entry.file_name());
     }
 
-    #[tokio::test]
-    async fn test_concurrent_lock_access() {
-        // Test that only one task can hold the lock at a time
-        let counter = Arc::new(AtomicUsize::new(0));
-        let mut handles = vec![];
-
-        for _ in 0..5 {
-            let counter_clone = counter.clone();
-            let handle = tokio::spawn(async move {
-                let _lock = get_libreoffice_lock().lock().await;
+    Ok(names)
+}
 
-                // Increment counter and sleep to simulate work
-                let current = counter_clone.fetch_add(1, Ordering::SeqCst);
-                sleep(Duration::from_millis(10)).await;
-                let after_sleep = counter_clone.load(Ordering::SeqCst);
+/// Files in `dir` that were not part of the `before` snapshot, with their mtime
+async fn new_files(dir: &Path, before: &HashSet<OsString>) -> Result<Vec<(PathBuf, SystemTime)>> {
+    let mut entries = tokio::fs::read_dir(dir)
+        .await
+        .map_err(LibreOfficeError::from_io)?;
+    let mut files = Vec::new();
 
-                // If locking works correctly, no other task should have incremented
-                // the counter while we were sleeping
-                assert_eq!(current + 1, after_sleep);
-            });
-            handles.push(handle);
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .map_err(LibreOfficeError::from_io)?
+    {
+        if before.contains(&entry.file_name()) {
+            continue;
         }
 
-        // Wait for all tasks to complete
-        for handle in handles {
-            handle.await.expect("Task should complete successfully");
+        let metadata = entry.metadata().await.map_err(LibreOfficeError::from_io)?;
+        if metadata.is_file() {
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            files.push((entry.path(), modified));
         }
+    }
+
+    Ok(files)
+}
 
-        // All tasks should have completed
-        assert_eq!(counter.load(Ordering::SeqCst), 5);
+/// Picks the requested document among the new files: `document.{to}` first, then the
+/// newest file with the target extension, then the newest file overall
+fn pick_primary(files: &[(PathBuf, SystemTime)], to: &str) -> Option<usize> {
+    let expected_name = format!("document.{}", to);
+    if let Some(index) = files
+        .iter()
+        .position(|(path, _)| path.file_name().is_some_and(|name| *name == *expected_name))
+    {
+        return Some(index);
     }
 
-    #[tokio::test]
-    async fn test_lock_released_on_drop() {
-        // Test that the lock is properly released when the guard is dropped
-        {
-            let _guard = get_libreoffice_lock().lock().await;
-            // Lock is held here
-        }
-        // Lock should be released here
+    let newest = |matching: &dyn Fn(&Path) -> bool| {
+        files
+            .iter()
+            .enumerate()
+            .filter(|(_, (path, _))| matching(path))
+            .max_by_key(|(_, (_, modified))| *modified)
+            .map(|(index, _)| index)
+    };
+
+    newest(&|path| path.extension().is_some_and(|ext| ext == to)).or_else(|| newest(&|_| true))
+}
+
+/// Lists the files created by the conversion, `None` when nothing was produced
+async fn collect_output(
+    output_dir: &Path,
+    before: &HashSet<OsString>,
+    to: &str,
+) -> Result<Option<ConversionOutput>> {
+    let mut files = new_files(output_dir, before).await?;
+    let Some(primary_index) = pick_primary(&files, to) else {
+        return Ok(None);
+    };
+
+    let (primary_path, _) = files.remove(primary_index);
+    tracing::debug!("Output file {:?}", primary_path);
+
+    let read = |path: PathBuf| async move {
+        let len = tokio::fs::metadata(&path)
+            .await
+            .map_err(LibreOfficeError::from_io)?
+            .len();
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        Ok::<_, LibreOfficeError>(OutputFile {
+            name,
+            data: ConvertedOutput::File { path, len },
+        })
+    };
 
-        // We should be able to acquire it again immediately
-        let _guard2 = get_libreoffice_lock().lock().await;
+    let primary = read(primary_path).await?;
+    let mut auxiliary = Vec::with_capacity(files.len());
+    for (path, _) in files {
+        auxiliary.push(read(path).await?);
     }
 
-    #[tokio::test]
-    async fn test_serial_execution_timing() {
-        // Test that tasks execute serially, not concurrently
-        use std::time::Instant;
+    Ok(Some(
+        ConversionOutput::new(primary).with_auxiliary(auxiliary),
+    ))
+}
 
-        let start_time = Arc::new(std::sync::Mutex::new(Vec::new()));
-        let end_time = Arc::new(std::sync::Mutex::new(Vec::new()));
-        let mut handles = vec![];
+/// Decides whether a failed run is worth retrying. Startup races (dbus, profile locks,
+/// exit code 81 on first launch) are transient, document problems are permanent.
+fn is_transient_failure(output: &Output, output_missing: bool) -> bool {
+    if output.status.success() && !output_missing {
+        return false;
+    }
 
-        for i in 0..3 {
-            let start_time_clone = start_time.clone();
-            let end_time_clone = end_time.clone();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stdout = String::from_utf8_lossy(&output.stdout);
 
-            let handle = tokio::spawn(async move {
-                let _lock = get_libreoffice_lock().lock().await;
+    if is_document_error(&stderr, &stdout) {
+        return false;
+    }
 
-                // Record start time
-                {
-                    let mut times = start_time_clone.lock().unwrap();
-                    times.push((i, Instant::now()));
-                }
+    if output
+        .status
+        .code()
+        .is_some_and(|code| RETRYABLE_EXIT_CODES.contains(&code))
+    {
+        return true;
+    }
 
-                // Simulate work
-                sleep(Duration::from_millis(50)).await;
+    let stderr = stderr.to_lowercase();
+    let locked = LOCK_MESSAGES.iter().any(|message| stderr.contains(message));
+    if stderr.contains("dbus") || locked || is_profile_corruption(&stderr) {
+        return true;
+    }
 
-                // Record end time
-                {
-                    let mut times = end_time_clone.lock().unwrap();
-                    times.push((i, Instant::now()));
-                }
-            });
-            handles.push(handle);
+    stderr.trim().is_empty() && output_missing
+}
+
+/// `--convert-to` argument, naming the export filter when one was asked for and the
+/// PDF export filter along with its FilterData as JSON (LibreOffice 7.4 and later)
+/// when options need it
+fn convert_to_argument(
+    from: &InputFormat,
+    to: &OutputFormat,
+    options: &ConversionOptions,
+) -> String {
+    let filter_data = options.pdf_filter_data();
+    if to.as_str() != "pdf" || filter_data.is_empty() {
+        return match &options.output_filter {
+            Some(filter) => format!("{}:{}", to, filter),
+            None => to.to_string(),
+        };
+    }
+
+    let properties: serde_json::Map<_, _> = filter_data
+        .into_iter()
+        .map(|(name, value)| {
+            let property = serde_json::json!({ "type": "boolean", "value": value.to_string() });
+            (name.to_string(), property)
+        })
+        .collect();
+    format!(
+        "pdf:{}:{}",
+        options
+            .output_filter
+            .as_deref()
+            .unwrap_or(from.pdf_export_filter()),
+        serde_json::Value::Object(properties)
+    )
+}
+
+/// Converts the input file in place, retrying transient LibreOffice failures once
+pub async fn convert_file(
+    config: &Config,
+    program: &Path,
+    input_path: &Path,
+    output_dir: &Path,
+    from: &InputFormat,
+    to: &OutputFormat,
+    options: &ConversionOptions,
+) -> Result<ConversionOutput> {
+    let convert_to = convert_to_argument(from, to, options);
+    let (from, to) = (from.as_str(), to.as_str());
+    let home = scratch_home(config, output_dir).await?;
+    let before = snapshot_dir(output_dir).await?;
+    let mut attempt = 0;
+    let output = loop {
+        attempt += 1;
+        let run = run_libreoffice(
+            config,
+            program,
+            input_path,
+            output_dir,
+            &convert_to,
+            options.input_filter,
+            home.as_deref(),
+        )
+        .await?;
+
+        // Neither a retry nor a fresh profile helps a document that is too big
+        if hit_resource_limit(&run.output, config.resource_limits) {
+            tracing::warn!(
+                "LibreOffice run ended with {} under resource limits",
+                run.output.status
+            );
+            metrics::counter!("libreoffice_resource_limit_exceeded_total").increment(1);
+            return Err(LibreOfficeError::ResourceLimitExceeded);
         }
 
-        // Wait for all tasks to complete
-        for handle in handles {
-            handle.await.expect("Task should complete successfully");
+        // A broken user profile makes every conversion fail, reset it before retrying
+        let retry = if needs_profile_reset(&run) {
+            reset_profile(config).await?;
+            true
+        } else {
+            let output_missing = new_files(output_dir, &before).await?.is_empty();
+            is_transient_failure(&run.output, output_missing)
+        };
+
+        if retry && attempt < MAX_ATTEMPTS {
+            tracing::warn!(
+                "LibreOffice run failed with {}, retrying (attempt {} of {})",
+                run.output.status,
+                attempt + 1,
+                MAX_ATTEMPTS
+            );
+            tokio::time::sleep(RETRY_DELAY).await;
+            continue;
         }
 
-        let start_times = start_time.lock().unwrap();
-        let end_times = end_time.lock().unwrap();
+        break run.output;
+    };
 
-        // Verify that tasks executed serially (no overlap)
-        assert_eq!(start_times.len(), 3);
-        assert_eq!(end_times.len(), 3);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    tracing::debug!("LibreOffice stderr: {}", stderr);
+    tracing::debug!("LibreOffice stdout: {}", stdout);
 
-        // Check that each task's start time is after the previous task's end time
-        // (with some tolerance for timing variations)
-        let mut sorted_starts: Vec<_> = start_times.iter().collect();
-        let mut sorted_ends: Vec<_> = end_times.iter().collect();
+    // Check if conversion succeeded and analyze the error
+    if !output.status.success() {
+        // Analyze the error output for specific issues
+        let error = analyze_libreoffice_error(&stderr, &stdout, from, to);
+        return Err(error);
+    }
 
-        sorted_starts.sort_by_key(|(_, time)| *time);
-        sorted_ends.sort_by_key(|(_, time)| *time);
+    PROFILE_RESETS.store(0, Ordering::SeqCst);
+    metrics::gauge!("libreoffice_profile_consecutive_resets").set(0.0);
+    tracing::debug!("LibreOffice conversion completed successfully");
 
-        // The end of each task should be before the start of the next task
-        for i in 0..sorted_ends.len() - 1 {
-            assert!(sorted_ends[i].1 <= sorted_starts[i + 1].1);
+    // Find and read the output files
+    let reported_error = find_reported_error(&stderr, &stdout, from, to);
+    let Some(output) = collect_output(output_dir, &before, to).await? else {
+        // LibreOffice may have said what went wrong despite exiting successfully
+        if let Some(error) = reported_error {
+            return Err(error);
         }
+
+        // No output file found - this could indicate various issues
+        return Err(analyze_missing_output_error(output_dir));
+    };
+
+    if let Some(error) = reported_error {
+        tracing::warn!(
+            "LibreOffice reported an error but produced output: {}",
+            error
+        );
+    }
+
+    tracing::debug!(
+        "Conversion completed after {} attempt(s), output size: {} bytes, {} auxiliary file(s)",
+        attempt,
+        output.primary.data.len(),
+        output.auxiliary.len()
+    );
+
+    Ok(output)
+}
+
+/// Targets that are zip containers (OOXML, ODF, EPUB)
+const ZIP_BASED_FORMATS: &[&str] = &["docx", "xlsx", "pptx", "odt", "ods", "odp", "odg", "epub"];
+
+/// Bytes of an invalid output quoted in the error
+const INVALID_OUTPUT_PREVIEW_LEN: usize = 16;
+
+/// Rejects empty outputs and outputs lacking the signature of well-known targets,
+/// LibreOffice sometimes exits 0 after writing garbage for corrupt inputs
+async fn validate_output(output: &ConvertedOutput, to: &str) -> Result<()> {
+    let head = output
+        .head(INVALID_OUTPUT_PREVIEW_LEN)
+        .await
+        .map_err(LibreOfficeError::from_io)?;
+    let valid = match to {
+        _ if output.is_empty() => false,
+        "pdf" => head.starts_with(b"%PDF"),
+        _ if ZIP_BASED_FORMATS.contains(&to) => head.starts_with(b"PK"),
+        _ => true,
+    };
+
+    if valid {
+        return Ok(());
+    }
+
+    Err(LibreOfficeError::InvalidOutput {
+        size: output.len() as usize,
+        head: head.escape_ascii().to_string(),
+    })
+}
+
+/// Length and SHA-256 of the file at `path`
+fn hash_file(path: &Path) -> std::io::Result<(u64, [u8; 32])> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    let mut len = 0;
+    loop {
+        let read = std::io::Read::read(&mut file, &mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+        len += read as u64;
+    }
+    Ok((len, hasher.finalize().into()))
+}
+
+/// Uploaded input spilled to disk inside its own per-conversion temp directory
+pub struct InputFile {
+    path: PathBuf,
+    len: u64,
+    /// SHA-256 of the content, used to coalesce identical conversions
+    hash: [u8; 32],
+    temp_dir: WorkDir,
+}
+
+impl InputFile {
+    /// Streams `reader` into a fresh temp directory under `work_dir`
+    pub async fn from_reader<R>(reader: &mut R, work_dir: &Path) -> std::io::Result<Self>
+    where
+        R: AsyncRead + Unpin + ?Sized,
+    {
+        let (path, _, temp_dir) = temp_dir_with_files(work_dir, UPLOAD_FILENAME)?;
+
+        let mut file = tokio::fs::File::create(&path)
+            .await
+            .map_err(workdir::not_writable)?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0; 64 * 1024];
+        let mut len = 0;
+        loop {
+            let read = reader.read(&mut buf).await?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buf[..read]);
+            file.write_all(&buf[..read]).await?;
+            len += read as u64;
+        }
+        file.flush().await?;
+        tracing::debug!("Input file written: {:?} ({} bytes)", path, len);
+
+        Ok(Self {
+            path,
+            len,
+            hash: hasher.finalize().into(),
+            temp_dir,
+        })
+    }
+
+    /// Where the upload is on disk, removed once the [`InputFile`] is dropped
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Size of the upload in bytes
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Whether the upload has no bytes
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Edits the upload in place off the async runtime, its length and hash follow
+    pub(crate) async fn edit<T>(
+        &mut self,
+        edit: impl FnOnce(&Path) -> Result<T> + Send + 'static,
+    ) -> Result<T>
+    where
+        T: Send + 'static,
+    {
+        let path = self.path.clone();
+        let (edited, len, hash) = tokio::task::spawn_blocking(move || {
+            let edited = edit(&path)?;
+            let (len, hash) = hash_file(&path).map_err(LibreOfficeError::from_io)?;
+            Ok::<_, LibreOfficeError>((edited, len, hash))
+        })
+        .await
+        .map_err(|e| LibreOfficeError::from_io(std::io::Error::other(e)))??;
+
+        self.len = len;
+        self.hash = hash;
+        Ok(edited)
+    }
+
+    /// The upload itself as the output of a request that needs no conversion
+    pub(crate) fn into_output(self, name: String) -> ConversionOutput {
+        ConversionOutput::new(OutputFile {
+            name,
+            data: ConvertedOutput::File {
+                path: self.path,
+                len: self.len,
+            },
+        })
+        .with_work_dir(Arc::new(self.temp_dir))
+    }
+
+    /// Sniffs the content type, see [`detect_file_type_from_reader`]
+    pub async fn detect_file_type(&self) -> std::io::Result<DetectedType> {
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || {
+            let file = std::fs::File::open(path)?;
+            detect_file_type_from_reader(&mut std::io::BufReader::new(file))
+        })
+        .await
+        .map_err(std::io::Error::other)?
+    }
+
+    /// Page, slide or sheet count stored in the upload, see [`page_count::estimate`]
+    async fn page_count(&self, file_type: FileType) -> Option<PageCount> {
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || page_count::estimate(&path, file_type))
+            .await
+            .ok()
+            .flatten()
+    }
+
+    /// Whether the upload is a compound file holding a password protected document.
+    /// Files that can't be parsed are left for LibreOffice to judge.
+    pub async fn is_password_protected(&self) -> bool {
+        let path = self.path.clone();
+        let encrypted = tokio::task::spawn_blocking(move || {
+            let mut file = std::fs::File::open(path)?;
+            let mut signature = [0; 8];
+            if std::io::Read::read_exact(&mut file, &mut signature).is_err()
+                || signature != cfb::SIGNATURE
+            {
+                return Ok(false);
+            }
+            is_encrypted_compound_file(std::io::BufReader::new(file))
+        })
+        .await;
+
+        match encrypted {
+            Ok(Ok(encrypted)) => encrypted,
+            Ok(Err(e)) => {
+                tracing::debug!("Could not check compound file for encryption: {}", e);
+                false
+            }
+            Err(e) => {
+                tracing::warn!("Encryption check failed: {}", e);
+                false
+            }
+        }
+    }
+}
+
+/// Applies the options that are carried out by editing the document before the export
+fn prepare_document(path: &Path, options: &ConversionOptions) -> Result<()> {
+    if !options.page_setup.is_empty() {
+        page_setup::apply(path, &options.page_setup)?;
+    }
+    if let Some(changes) = options.changes {
+        tracked_changes::apply(path, changes)?;
+    }
+    Ok(())
+}
+
+/// Applies the options that are carried out by editing the primary output, off the
+/// async runtime like the preparation
+async fn finish_output(
+    mut output: ConversionOutput,
+    to: &OutputFormat,
+    options: &ConversionOptions,
+) -> Result<ConversionOutput> {
+    let (to, options) = (to.clone(), options.clone());
+    tokio::task::spawn_blocking(move || {
+        if options.strips_metadata() {
+            metadata::strip(&mut output.primary.data, &to)?;
+        }
+        // Last, nothing can be edited once encrypted
+        if !options.encryption.is_empty() {
+            pdf_encryption::encrypt(&mut output.primary.data, &options.encryption)?;
+        }
+        Ok(output)
+    })
+    .await
+    .map_err(|e| LibreOfficeError::from_io(std::io::Error::other(e)))?
+}
+
+/// Runs uploads through content detection, coalescing, the queue and the backend chain
+#[derive(Clone)]
+pub struct LibreOfficeConverter {
+    config: Arc<Config>,
+    backends: Arc<BackendChain>,
+    scheduler: Arc<Scheduler>,
+    scanner: Option<Arc<dyn Scanner>>,
+    coalescer: Arc<Coalescer>,
+}
+
+impl LibreOfficeConverter {
+    pub(crate) fn new(
+        config: Arc<Config>,
+        backends: Arc<BackendChain>,
+        scheduler: Arc<Scheduler>,
+    ) -> Self {
+        let scanner = scanner::from_config(&config);
+        Self {
+            config,
+            backends,
+            scheduler,
+            scanner,
+            coalescer: Arc::default(),
+        }
+    }
+
+    /// Replaces the scanner configured by `SCANNER_ADDR`
+    #[cfg(test)]
+    pub fn with_scanner(mut self, scanner: Arc<dyn Scanner>) -> Self {
+        self.scanner = Some(scanner);
+        self
+    }
+
+    /// Converter backed by the global configuration, backend chain and scheduler
+    pub fn global() -> Self {
+        Self::new(
+            Arc::new(config::get().clone()),
+            backend::chain().clone(),
+            queue::scheduler().clone(),
+        )
+    }
+
+    /// Waits for the LibreOffice slot and converts with the first available backend
+    pub(crate) async fn convert_async(
+        &self,
+        input: InputFile,
+        from: &InputFormat,
+        to: &OutputFormat,
+        options: &ConversionOptions,
+    ) -> Result<ConversionOutput> {
+        tracing::debug!(
+            "Starting async CLI conversion: {} -> {} ({} bytes)",
+            from,
+            to,
+            input.len()
+        );
+
+        // Only one LibreOffice process runs at a time, small inputs get to go first
+        let lane = Lane::for_input_len(input.len(), &self.config);
+        tracing::debug!("Waiting for LibreOffice in the {} lane...", lane.as_str());
+        let max_wait = self.config.max_queue_wait;
+        let Ok(permit) = tokio::time::timeout(max_wait, self.scheduler.acquire(lane)).await else {
+            tracing::warn!(
+                "LibreOffice still busy after {:?} in the {} lane, giving up",
+                max_wait,
+                lane.as_str()
+            );
+            metrics::counter!("conversion_queue_timeouts_total", "lane" => lane.as_str())
+                .increment(1);
+            return Err(LibreOfficeError::QueueTimeout(max_wait));
+        };
+        tracing::debug!(
+            "LibreOffice acquired after {:?}, proceeding with conversion",
+            permit.stats.wait
+        );
+        let started = Instant::now();
+
+        workdir::ensure_free_space(&self.config, input.len())?;
+
+        // LibreOffice picks the import filter from the extension
+        let input_path = input.temp_dir.path().join(format!("document.{}", from));
+        tokio::fs::rename(&input.path, &input_path)
+            .await
+            .map_err(|e| LibreOfficeError::from_io(workdir::not_writable(e)))?;
+        let output_dir = input.temp_dir.path().join(OUTPUT_DIR);
+        tokio::fs::create_dir(&output_dir)
+            .await
+            .map_err(|e| LibreOfficeError::from_io(workdir::not_writable(e)))?;
+
+        let (path, prepared) = (input_path.clone(), options.clone());
+        let missing_fonts = tokio::task::spawn_blocking(move || {
+            prepare_document(&path, &prepared).map(|()| fonts::missing_fonts(&path))
+        })
+        .await
+        .map_err(|e| LibreOfficeError::from_io(std::io::Error::other(e)))??;
+
+        // Run LibreOffice conversion with timeout
+        tracing::debug!("Running LibreOffice conversion...");
+        let (mut result, mut backend) = self
+            .backends
+            .convert(&input_path, &output_dir, from, to, options)
+            .await;
+        let mut repaired = false;
+        if options.repair && matches!(result, Err(LibreOfficeError::CorruptedInput(_))) {
+            (result, backend) = self
+                .convert_repaired(&input_path, &output_dir, from, to, options)
+                .await;
+            repaired = result.is_ok();
+            metrics::counter!(
+                "libreoffice_repairs_total",
+                "outcome" => if repaired { "success" } else { "failure" }
+            )
+            .increment(1);
+        }
+        let result = match result {
+            Ok(output) => validate_output(&output.primary.data, to.as_str())
+                .await
+                .inspect_err(|_| {
+                    metrics::counter!(
+                        "libreoffice_invalid_outputs_total",
+                        "backend" => backend,
+                        "from" => from.to_string(),
+                        "to" => to.to_string()
+                    )
+                    .increment(1);
+                })
+                .map(|()| ConversionOutput {
+                    queue: Some(permit.stats),
+                    // File-backed outputs live in the upload's temp directory
+                    work_dir: Some(Arc::new(input.temp_dir)),
+                    missing_fonts,
+                    repaired,
+                    conversion_duration: Some(started.elapsed()),
+                    ..output
+                }),
+            Err(LibreOfficeError::Timeout { .. }) => {
+                tracing::warn!(
+                    "Conversion timed out after {:?}, having waited {:?} in the queue",
+                    started.elapsed(),
+                    permit.stats.wait
+                );
+                Err(LibreOfficeError::Timeout {
+                    queue_wait: Some(permit.stats.wait),
+                })
+            }
+            Err(e) => Err(e),
+        };
+        let result = match result {
+            Ok(output) if options.strips_metadata() || !options.encryption.is_empty() => {
+                finish_output(output, to, options).await
+            }
+            result => result,
+        };
+        // Hashed here rather than per response, so coalesced requests and kept
+        // results share the digest
+        let result = match result {
+            Ok(output) => checksum::sha256(&output.primary.data)
+                .await
+                .map(|digest| ConversionOutput {
+                    sha256: Some(digest),
+                    ..output
+                })
+                .map_err(LibreOfficeError::from_io),
+            Err(e) => Err(e),
+        };
+
+        metrics::counter!(
+            "libreoffice_conversions_total",
+            "backend" => backend,
+            "outcome" => if result.is_ok() { "success" } else { "failure" }
+        )
+        .increment(1);
+        if let Ok(output) = &result {
+            tracing::debug!(
+                "Converted in {:?} after {:?} in the queue",
+                started.elapsed(),
+                permit.stats.wait
+            );
+            if !output.missing_fonts.is_empty() {
+                tracing::info!(
+                    "Substituted missing fonts: {}",
+                    output.missing_fonts.join(", ")
+                );
+            }
+            // Counted per font to tell which ones are worth installing
+            for font in &output.missing_fonts {
+                metrics::counter!("libreoffice_missing_fonts_total", "font" => font.clone())
+                    .increment(1);
+            }
+        }
+
+        result
+    }
+
+    /// Retries an input that failed as corrupted, with its zip package rebuilt
+    /// when that is what's damaged and the import filter of its format forced
+    async fn convert_repaired(
+        &self,
+        input_path: &Path,
+        output_dir: &Path,
+        from: &InputFormat,
+        to: &OutputFormat,
+        options: &ConversionOptions,
+    ) -> (Result<ConversionOutput>, &'static str) {
+        let path = input_path.to_path_buf();
+        let rebuilt = tokio::task::spawn_blocking(move || repair::rebuild_package(&path))
+            .await
+            .map_err(|e| LibreOfficeError::from_io(std::io::Error::other(e)));
+        match rebuilt {
+            Ok(Ok(rebuilt)) => tracing::info!(
+                "Retrying corrupted input to repair it, package rebuilt: {}",
+                rebuilt
+            ),
+            Ok(Err(e)) | Err(e) => {
+                tracing::warn!("Could not rebuild corrupted input: {}", e);
+            }
+        }
+
+        let options = ConversionOptions {
+            input_filter: from.import_filter(),
+            ..options.clone()
+        };
+        self.backends
+            .convert(input_path, output_dir, from, to, &options)
+            .await
+    }
+}
+
+#[async_trait]
+impl Converter for LibreOfficeConverter {
+    /// Converts an upload, sharing the result with identical conversions in flight
+    async fn convert(
+        &self,
+        input: InputFile,
+        from: &InputFormat,
+        to: &OutputFormat,
+        options: &ConversionOptions,
+    ) -> Result<ConversionOutput> {
+        let detected = input
+            .detect_file_type()
+            .await
+            .map_err(LibreOfficeError::from_io)?;
+        tracing::debug!("Detected {} content in .{} upload", detected.mime, from);
+
+        // Without a password LibreOffice would only fail after a full launch. Checked
+        // first as encrypted OOXML is otherwise an unknown compound file.
+        if input.is_password_protected().await {
+            return Err(LibreOfficeError::PasswordProtected);
+        }
+
+        let detected_mimetype = detected.file_type;
+        if detected_mimetype == FileType::CompoundFile {
+            tracing::warn!(
+                "Unrecognized compound file, leaving it to LibreOffice as .{}",
+                from
+            );
+        }
+        if detected_mimetype == FileType::Unknown {
+            if !self
+                .config
+                .unrecognized_content_extensions
+                .iter()
+                .any(|extension| extension == from.as_str())
+            {
+                return Err(LibreOfficeError::UnsupportedConversion {
+                    from: from.to_string(),
+                    to: to.to_string(),
+                });
+            }
+            tracing::warn!("Content not recognized, converting it as .{} anyway", from);
+        }
+
+        if detected_mimetype.is_image() && !IMAGE_OUTPUT_FORMATS.contains(&to.as_str()) {
+            return Err(LibreOfficeError::UnsupportedImageConversion { to: to.to_string() });
+        }
+
+        if detected_mimetype.has_macros() && self.config.reject_macro_documents {
+            return Err(LibreOfficeError::MacroDocumentRejected);
+        }
+
+        if detected_mimetype == FileType::OutlookMessage {
+            return Err(LibreOfficeError::OutlookMessage);
+        }
+
+        if !options.page_setup.is_empty() && !page_setup::applies_to(detected_mimetype) {
+            return Err(LibreOfficeError::InvalidOption(format!(
+                "{} only apply to xlsx and ods spreadsheets",
+                page_setup::FIELDS.join(", ")
+            )));
+        }
+        if options
+            .changes
+            .is_some_and(|changes| changes != TrackedChanges::Show)
+            && !tracked_changes::applies_to(detected_mimetype)
+        {
+            return Err(LibreOfficeError::InvalidOption(
+                "changes=accept and changes=reject only apply to docx documents".to_string(),
+            ));
+        }
+        if !options.pdf_filter_data().is_empty() && to.as_str() != "pdf" {
+            return Err(LibreOfficeError::InvalidOption(format!(
+                "{} only apply to pdf output",
+                converter::PDF_FIELDS.join(", ")
+            )));
+        }
+        if !options.encryption.is_empty() && to.as_str() != "pdf" {
+            return Err(LibreOfficeError::InvalidOption(format!(
+                "{} only apply to pdf output",
+                pdf_encryption::FIELDS.join(", ")
+            )));
+        }
+        if options.strips_metadata() && !metadata::applies_to(to) {
+            return Err(LibreOfficeError::InvalidOption(format!(
+                "strip_metadata only applies to {} output",
+                metadata::FORMATS.join(", ")
+            )));
+        }
+
+        // Huge documents would hold the LibreOffice slot until the timeout
+        if let Some(max) = self.config.max_pages
+            && let Some(PageCount { count, unit }) = input.page_count(detected_mimetype).await
+            && count > max
+        {
+            return Err(LibreOfficeError::TooManyPages { count, unit, max });
+        }
+
+        // Last check before the upload reaches LibreOffice, after the cheap rejections
+        let scan_duration = match &self.scanner {
+            Some(scanner) => Some(
+                scanner::check(
+                    scanner.as_ref(),
+                    &input.path,
+                    self.config.scanner_timeout,
+                    self.config.scanner_fail_closed,
+                )
+                .await?,
+            ),
+            None => None,
+        };
+
+        // LibreOffice has no mail import filter, Writer lays the message out as text
+        let from = &if detected_mimetype == FileType::Email {
+            "txt".parse()?
+        } else {
+            from.clone()
+        };
+
+        let key = ConversionKey {
+            content_hash: input.hash,
+            from: from.to_string(),
+            to: to.to_string(),
+            options: options.clone(),
+        };
+        self.coalescer
+            .run(key, || self.convert_async(input, from, to, options))
+            .await
+            .map(|output| ConversionOutput {
+                scan_duration,
+                ..output
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{ConversionBackend, fake::CannedBackend};
+    use crate::scanner::{ScanOutcome, fake::FakeScanner};
+    use std::sync::Arc;
+    use std::time::Instant;
+    use tokio::time::{Duration, sleep};
+
+    /// Timeout of the stub programs run directly
+    const TEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn test_profile_corruption_detection() {
+        assert!(is_profile_corruption(
+            "javaldx: Could not find a Java Runtime Environment!"
+        ));
+        assert!(is_profile_corruption(
+            "User installation could not be completed."
+        ));
+        assert!(!is_profile_corruption(
+            "Error: source file could not be loaded"
+        ));
+    }
+
+    /// Writes an executable shell script standing in for the libreoffice binary
+    fn write_stub_program(dir: &Path, script: &str) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = dir.join("libreoffice-stub");
+        std::fs::write(&path, format!("#!/bin/sh\n{}", script)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    /// Script body writing `output` into the --outdir directory
+    const STUB_WRITE_OUTPUT: &str = r#"
+while [ $# -gt 0 ]; do
+    if [ "$1" = "--outdir" ]; then outdir="$2"; fi
+    shift
+done
+printf 'converted' > "$outdir/document.pdf"
+"#;
+
+    #[test]
+    fn test_find_executable_on_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let program = write_stub_program(dir.path(), "exit 0");
+        std::fs::write(dir.path().join("not-executable"), b"").unwrap();
+
+        let path_var =
+            std::env::join_paths(["/nonexistent", dir.path().to_str().unwrap()]).unwrap();
+        assert_eq!(
+            find_executable("libreoffice-stub", &path_var),
+            Some(program)
+        );
+        assert_eq!(find_executable("not-executable", &path_var), None);
+        assert_eq!(find_executable("missing", &path_var), None);
+    }
+
+    #[test]
+    fn test_lock_files_are_transient() {
+        let failed = |stderr: &str| Output {
+            status: std::process::ExitStatus::from_raw(1 << 8),
+            stdout: Vec::new(),
+            stderr: stderr.as_bytes().to_vec(),
+        };
+        for stderr in [
+            "Error: /tmp/work/.~lock.document.docx# exists",
+            "The user profile is locked",
+            "document.docx is locked by another user",
+        ] {
+            assert!(is_transient_failure(&failed(stderr), true), "{}", stderr);
+        }
+        for stderr in [
+            "Blocked by policy",
+            "clock skew detected",
+            "Error: file was unlocked and rewritten",
+            "invalid block size",
+        ] {
+            assert!(!is_transient_failure(&failed(stderr), true), "{}", stderr);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transient_failure_is_retried_once() {
+        let stub_dir = tempfile::tempdir().unwrap();
+        let marker = stub_dir.path().join("called");
+        let program = write_stub_program(
+            stub_dir.path(),
+            &format!(
+                "if [ ! -f {0:?} ]; then touch {0:?}; exit 81; fi\n{1}",
+                marker, STUB_WRITE_OUTPUT
+            ),
+        );
+
+        let (input_path, output_dir, _temp_dir) =
+            temp_dir_with_files(&config::get().work_dir, "document.txt").unwrap();
+        std::fs::write(&input_path, b"hello").unwrap();
+
+        let result = convert_file(
+            config::get(),
+            &program,
+            &input_path,
+            &output_dir,
+            &"txt".parse().unwrap(),
+            &"pdf".parse().unwrap(),
+            &ConversionOptions::default(),
+        )
+        .await;
+
+        assert_eq!(
+            result.unwrap().primary.data.into_bytes().await.unwrap(),
+            b"converted"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_permanent_failure_is_not_retried() {
+        let stub_dir = tempfile::tempdir().unwrap();
+        let counter = stub_dir.path().join("calls");
+        let program = write_stub_program(
+            stub_dir.path(),
+            &format!(
+                "echo x >> {:?}\necho 'Error: document is password protected' >&2\nexit 1",
+                counter
+            ),
+        );
+
+        let (input_path, output_dir, _temp_dir) =
+            temp_dir_with_files(&config::get().work_dir, "document.docx").unwrap();
+        std::fs::write(&input_path, b"hello").unwrap();
+
+        let result = convert_file(
+            config::get(),
+            &program,
+            &input_path,
+            &output_dir,
+            &"docx".parse().unwrap(),
+            &"pdf".parse().unwrap(),
+            &ConversionOptions::default(),
+        )
+        .await;
+
+        assert!(matches!(result, Err(LibreOfficeError::PasswordProtected)));
+        let calls = std::fs::read_to_string(&counter).unwrap();
+        assert_eq!(calls.lines().count(), 1);
+    }
+
+    #[test]
+    fn test_reported_errors_from_captured_output() {
+        type ErrorCheck = fn(&LibreOfficeError) -> bool;
+        let cases: &[(&str, &str, ErrorCheck)] = &[
+            ("Error: source file could not be loaded\n", "", |e| {
+                matches!(e, LibreOfficeError::CorruptedInput(_))
+            }),
+            (
+                "convert /tmp/.tmpAbCdEf/document.docx -> /tmp/.tmpAbCdEf/document.xyz using filter : \n\
+                 Error: no export filter for /tmp/.tmpAbCdEf/document.xyz found, aborting.\n",
+                "",
+                |e| matches!(e, LibreOfficeError::UnsupportedConversion { .. }),
+            ),
+            (
+                "",
+                "Error: loadComponentFromURL returned an empty reference\n",
+                |e| matches!(e, LibreOfficeError::CorruptedInput(_)),
+            ),
+            (
+                "Error: Please verify input parameters... (SfxBaseModel::impl_store \
+                 <file:///tmp/out/document.pdf> failed: 0x507(Error Area:Io Class:Access Code:7))\n",
+                "",
+                |e| matches!(e, LibreOfficeError::ConversionFailed(_)),
+            ),
+        ];
+
+        for (stdout, stderr, expected) in cases {
+            let error = find_reported_error(stderr, stdout, "docx", "pdf")
+                .unwrap_or_else(|| panic!("no error found in {:?}", stdout));
+            assert!(expected(&error), "unexpected {:?} for {:?}", error, stdout);
+        }
+    }
+
+    #[test]
+    fn test_successful_output_has_no_reported_error() {
+        let stdout = "convert /tmp/.tmpAbCdEf/document.docx -> /tmp/.tmpAbCdEf/document.pdf \
+                      using filter : writer_pdf_Export\n";
+        assert!(find_reported_error("", stdout, "docx", "pdf").is_none());
+    }
+
+    #[test]
+    fn test_analyze_libreoffice_error_patterns() {
+        assert!(matches!(
+            analyze_libreoffice_error("Error: document is password protected", "", "docx", "pdf"),
+            LibreOfficeError::PasswordProtected
+        ));
+        assert!(matches!(
+            analyze_libreoffice_error("", "filter for xyz not found", "docx", "xyz"),
+            LibreOfficeError::UnsupportedConversion { .. }
+        ));
+        assert!(matches!(
+            analyze_libreoffice_error("segfault", "", "docx", "pdf"),
+            LibreOfficeError::ConversionFailed(_)
+        ));
+    }
+
+    #[test]
+    fn test_error_heuristics_are_counted() {
+        let ((), recorded) = crate::routes::metrics::fake::record(|| {
+            for stderr in [
+                "Error: document is password protected",
+                "filter for xyz not found",
+                "source file could not be loaded",
+                "segfault",
+                "killed",
+            ] {
+                analyze_libreoffice_error(stderr, "", "docx", "pdf");
+            }
+        });
+
+        for (branch, expected) in [
+            ("password", 1),
+            ("filter_not_found", 1),
+            ("corrupt", 1),
+            ("empty", 0),
+            ("generic", 2),
+        ] {
+            let count =
+                recorded.counter("libreoffice_error_heuristics_total", &[("branch", branch)]);
+            assert_eq!(count, expected, "{}", branch);
+        }
+    }
+
+    async fn validate(data: &[u8], to: &str) -> Result<()> {
+        validate_output(&ConvertedOutput::Bytes(data.to_vec()), to).await
+    }
+
+    #[tokio::test]
+    async fn test_validate_output() {
+        assert!(validate(b"%PDF-1.7\n", "pdf").await.is_ok());
+        assert!(validate(b"PK\x03\x04", "docx").await.is_ok());
+        assert!(validate(b"plain text", "txt").await.is_ok());
+
+        assert!(matches!(
+            validate(b"", "txt").await,
+            Err(LibreOfficeError::InvalidOutput { size: 0, .. })
+        ));
+        match validate(b"<html>not a pdf</html>", "pdf").await {
+            Err(LibreOfficeError::InvalidOutput { size, head }) => {
+                assert_eq!(size, 22);
+                assert_eq!(head, "<html>not a pdf<");
+            }
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        assert!(validate(b"<xml/>", "odt").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_output_manifest_lists_new_files() {
+        let stub_dir = tempfile::tempdir().unwrap();
+        let program = write_stub_program(
+            stub_dir.path(),
+            r#"
+while [ $# -gt 0 ]; do
+    if [ "$1" = "--outdir" ]; then outdir="$2"; fi
+    shift
+done
+printf 'page' > "$outdir/report.html"
+printf 'image' > "$outdir/report_html_1.png"
+"#,
+        );
+
+        let (input_path, output_dir, _temp_dir) =
+            temp_dir_with_files(&config::get().work_dir, "document.docx").unwrap();
+        std::fs::write(&input_path, b"hello").unwrap();
+        std::fs::write(output_dir.join("unrelated.html"), b"old").unwrap();
+
+        let output = convert_file(
+            config::get(),
+            &program,
+            &input_path,
+            &output_dir,
+            &"docx".parse().unwrap(),
+            &"html".parse().unwrap(),
+            &ConversionOptions::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(output.primary.name, "report.html");
+        assert_eq!(output.primary.data.into_bytes().await.unwrap(), b"page");
+        assert_eq!(output.auxiliary.len(), 1);
+        assert_eq!(output.auxiliary[0].name, "report_html_1.png");
+    }
+
+    #[tokio::test]
+    async fn test_injected_config_reaches_the_backend() {
+        let stub_dir = tempfile::tempdir().unwrap();
+        let args_file = stub_dir.path().join("args");
+        let program = write_stub_program(
+            stub_dir.path(),
+            &format!("echo \"$@\" > {:?}\nsleep 30", args_file),
+        );
+        let state = crate::state::AppState::builder()
+            .config(config::Config {
+                backends: vec![config::BackendKind::Cli],
+                libreoffice_bin: Some(program.to_string_lossy().to_string()),
+                libreoffice_extra_args: vec!["--injected-arg".to_string()],
+                conversion_timeout: Duration::from_millis(500),
+                ..config::get().clone()
+            })
+            .build();
+
+        let input = InputFile::from_reader(&mut b"hello".as_slice(), &config::get().work_dir)
+            .await
+            .unwrap();
+        let started = Instant::now();
+        let result = state
+            .converter()
+            .convert(
+                input,
+                &"txt".parse().unwrap(),
+                &"pdf".parse().unwrap(),
+                &ConversionOptions::default(),
+            )
+            .await;
+
+        assert!(matches!(result, Err(LibreOfficeError::Timeout { .. })));
+        assert!(started.elapsed() < Duration::from_secs(10));
+        let args = std::fs::read_to_string(&args_file).unwrap();
+        assert!(args.contains("--injected-arg"), "{}", args);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_conversion_kills_process() {
+        let stub_dir = tempfile::tempdir().unwrap();
+        let pid_file = stub_dir.path().join("pid");
+        let program = write_stub_program(
+            stub_dir.path(),
+            &format!("echo $$ > {:?}\nsleep 30", pid_file),
+        );
+
+        let (input_path, output_dir, _temp_dir) =
+            temp_dir_with_files(&config::get().work_dir, "document.txt").unwrap();
+        std::fs::write(&input_path, b"hello").unwrap();
+
+        let handle = tokio::spawn(async move {
+            convert_file(
+                config::get(),
+                &program,
+                &input_path,
+                &output_dir,
+                &"txt".parse().unwrap(),
+                &"pdf".parse().unwrap(),
+                &ConversionOptions::default(),
+            )
+            .await
+        });
+
+        // Wait for the stub to start
+        let mut pid = None;
+        for _ in 0..100 {
+            if let Some(value) = std::fs::read_to_string(&pid_file)
+                .ok()
+                .and_then(|s| s.trim().parse::<u32>().ok())
+            {
+                pid = Some(value);
+                break;
+            }
+            sleep(Duration::from_millis(20)).await;
+        }
+        let pid = pid.expect("stub should have started");
+        assert!(reaper::group_alive(pid));
+
+        // Dropping the future is what axum does when the client goes away
+        handle.abort();
+        let _ = handle.await;
+
+        let mut alive = true;
+        for _ in 0..100 {
+            alive = reaper::group_alive(pid);
+            if !alive {
+                break;
+            }
+            sleep(Duration::from_millis(20)).await;
+        }
+        assert!(!alive, "process group should be killed");
+    }
+
+    #[tokio::test]
+    async fn test_scratch_home_is_passed_to_process() {
+        let stub_dir = tempfile::tempdir().unwrap();
+        let program = write_stub_program(
+            stub_dir.path(),
+            "printf '%s|%s|%s' \"$HOME\" \"$XDG_CONFIG_HOME\" \"$XDG_CACHE_HOME\"",
+        );
+        let home = stub_dir.path().join(SCRATCH_HOME_DIR);
+
+        let run = run_program(
+            &program,
+            &[],
+            Some(&home),
+            ResourceLimits::default(),
+            TEST_TIMEOUT,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&run.output.stdout),
+            format!(
+                "{}|{}/.config|{}/.cache",
+                home.display(),
+                home.display(),
+                home.display()
+            )
+        );
+    }
+
+    #[test]
+    fn test_resource_limit_matches_the_signal_of_the_limit() {
+        let ended = |signal: i32, stderr: &str| Output {
+            status: std::process::ExitStatus::from_raw(signal),
+            stdout: Vec::new(),
+            stderr: stderr.as_bytes().to_vec(),
+        };
+        let cpu = ResourceLimits {
+            memory_bytes: None,
+            cpu_secs: Some(60),
+        };
+        let memory = ResourceLimits {
+            memory_bytes: Some(1 << 30),
+            cpu_secs: None,
+        };
+
+        assert!(hit_resource_limit(&ended(libc::SIGXCPU, ""), cpu));
+        assert!(hit_resource_limit(&ended(libc::SIGSEGV, ""), memory));
+        assert!(hit_resource_limit(&ended(libc::SIGABRT, ""), memory));
+        assert!(hit_resource_limit(&ended(0, "std::bad_alloc"), memory));
+
+        // A crash unrelated to the limit that is set stays a conversion failure
+        assert!(!hit_resource_limit(&ended(libc::SIGSEGV, ""), cpu));
+        assert!(!hit_resource_limit(&ended(0, "out of memory"), cpu));
+        assert!(!hit_resource_limit(&ended(libc::SIGXCPU, ""), memory));
+        assert!(!hit_resource_limit(&ended(libc::SIGKILL, ""), cpu));
+        assert!(!hit_resource_limit(&ended(libc::SIGBUS, ""), memory));
+        assert!(!hit_resource_limit(
+            &ended(libc::SIGSEGV, ""),
+            ResourceLimits::default()
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_memory_limit_stops_huge_document() {
+        let stub_dir = tempfile::tempdir().unwrap();
+        // Loads the whole document into memory like a filter would
+        let program = write_stub_program(
+            stub_dir.path(),
+            "exec awk '{ s = s $0 } END { print length(s) }' \"$1\"",
+        );
+        let input_path = stub_dir.path().join("huge.txt");
+        std::fs::write(&input_path, vec![b'a'; 64 * 1024 * 1024]).unwrap();
+        let args = vec![input_path.to_string_lossy().to_string()];
+
+        let limits = ResourceLimits {
+            memory_bytes: Some(32 * 1024 * 1024),
+            cpu_secs: None,
+        };
+        let run = run_program(&program, &args, None, limits, TEST_TIMEOUT)
+            .await
+            .unwrap();
+        assert!(!run.output.status.success());
+        assert!(hit_resource_limit(&run.output, limits));
+
+        std::fs::write(&input_path, b"small").unwrap();
+        let run = run_program(
+            &program,
+            &args,
+            None,
+            ResourceLimits::default(),
+            TEST_TIMEOUT,
+        )
+        .await
+        .unwrap();
+        assert!(run.output.status.success());
+        assert!(!hit_resource_limit(&run.output, ResourceLimits::default()));
+    }
+
+    #[tokio::test]
+    async fn test_images_only_convert_to_drawing_targets() {
+        let png =
+            std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/pixel.png"))
+                .unwrap();
+        let input = InputFile::from_reader(&mut png.as_slice(), &config::get().work_dir)
+            .await
+            .unwrap();
+
+        let result = LibreOfficeConverter::global()
+            .convert(
+                input,
+                &"png".parse().unwrap(),
+                &"docx".parse().unwrap(),
+                &ConversionOptions::default(),
+            )
+            .await;
+        let error = result.unwrap_err();
+        assert!(matches!(
+            error,
+            LibreOfficeError::UnsupportedImageConversion { .. }
+        ));
+        assert_eq!(
+            error.to_string(),
+            "Images can only be converted to pdf, odg, png, jpg, not docx"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_documents_are_rejected_before_launch() {
+        let docx = std::fs::read(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/encrypted.docx"),
+        )
+        .unwrap();
+        let input = InputFile::from_reader(&mut docx.as_slice(), &config::get().work_dir)
+            .await
+            .unwrap();
+
+        let result = LibreOfficeConverter::global()
+            .convert(
+                input,
+                &"docx".parse().unwrap(),
+                &"pdf".parse().unwrap(),
+                &ConversionOptions::default(),
+            )
+            .await;
+        assert!(matches!(result, Err(LibreOfficeError::PasswordProtected)));
+    }
+
+    #[tokio::test]
+    async fn test_unrecognized_compound_file_is_left_to_libreoffice() {
+        let ole = std::fs::read(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/unknown-streams.ole"),
+        )
+        .unwrap();
+        let input = InputFile::from_reader(&mut ole.as_slice(), &config::get().work_dir)
+            .await
+            .unwrap();
+        let converter = LibreOfficeConverter::new(
+            Arc::new(config::get().clone()),
+            Arc::new(BackendChain::new(vec![Box::new(CannedBackend)])),
+            Arc::new(Scheduler::new(1)),
+        );
+
+        let output = converter
+            .convert(
+                input,
+                &"wps".parse().unwrap(),
+                &"pdf".parse().unwrap(),
+                &ConversionOptions::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            output.primary.data.into_bytes().await.unwrap(),
+            b"%PDF-1.7 canned"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_documents_over_max_pages_are_rejected() {
+        let docx =
+            std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/pages.docx"))
+                .unwrap();
+        let convert = |max_pages| {
+            let docx = docx.clone();
+            async move {
+                let config = Config {
+                    max_pages,
+                    ..config::get().clone()
+                };
+                let converter = LibreOfficeConverter::new(
+                    Arc::new(config),
+                    Arc::new(BackendChain::new(vec![Box::new(CannedBackend)])),
+                    Arc::new(Scheduler::new(1)),
+                );
+                let input = InputFile::from_reader(&mut docx.as_slice(), &config::get().work_dir)
+                    .await
+                    .unwrap();
+                converter
+                    .convert(
+                        input,
+                        &"docx".parse().unwrap(),
+                        &"pdf".parse().unwrap(),
+                        &ConversionOptions::default(),
+                    )
+                    .await
+            }
+        };
+
+        let Err(error) = convert(Some(500)).await else {
+            panic!("12000 pages converted with MAX_PAGES=500");
+        };
+        assert!(matches!(
+            error,
+            LibreOfficeError::TooManyPages {
+                count: 12000,
+                unit: page_count::Unit::Pages,
+                max: 500
+            }
+        ));
+        assert_eq!(
+            error.to_string(),
+            "Document has 12000 pages, more than the 500 allowed"
+        );
+        assert!(convert(Some(12000)).await.is_ok());
+        assert!(convert(None).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_uploads_are_scanned() {
+        let convert = |outcome: ScanOutcome| async move {
+            let converter = LibreOfficeConverter::new(
+                Arc::new(config::get().clone()),
+                Arc::new(BackendChain::new(vec![Box::new(CannedBackend)])),
+                Arc::new(Scheduler::new(1)),
+            )
+            .with_scanner(Arc::new(FakeScanner(outcome)));
+            let input =
+                InputFile::from_reader(&mut b"some notes".as_slice(), &config::get().work_dir)
+                    .await
+                    .unwrap();
+            converter
+                .convert(
+                    input,
+                    &"txt".parse().unwrap(),
+                    &"pdf".parse().unwrap(),
+                    &ConversionOptions::default(),
+                )
+                .await
+        };
+
+        let output = convert(ScanOutcome::Clean).await.unwrap();
+        assert!(output.scan_duration.is_some());
+
+        let error = convert(ScanOutcome::Infected("Eicar-Signature".to_string()))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(error, LibreOfficeError::Infected(signature) if signature == "Eicar-Signature")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unrunnable_binary_is_backend_unavailable() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let program = dir.path().join("soffice");
+        let run = || run_program(&program, &[], None, ResourceLimits::default(), TEST_TIMEOUT);
+
+        let Err(missing) = run().await else {
+            panic!("missing binary ran");
+        };
+        std::fs::write(&program, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o644)).unwrap();
+        let Err(not_executable) = run().await else {
+            panic!("non-executable binary ran");
+        };
+
+        for error in [missing, not_executable] {
+            assert!(error.is_backend_unavailable(), "{:?}", error);
+            assert_eq!(error.code(), "backend_unavailable");
+            let response: axum::response::Response = error.into();
+            assert_eq!(
+                response.status(),
+                axum::http::StatusCode::SERVICE_UNAVAILABLE
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_work_dir_io_errors_are_told_apart() {
+        use std::os::unix::fs::PermissionsExt;
+        let error = |code| LibreOfficeError::from_io(std::io::Error::from_raw_os_error(code));
+        for code in [libc::ENOSPC, libc::EDQUOT] {
+            assert!(matches!(error(code), LibreOfficeError::InsufficientStorage));
+        }
+        for code in [libc::EACCES, libc::EROFS] {
+            // Only work dir operations blame WORK_DIR
+            assert_eq!(error(code).code(), "io_error");
+            let os_error = std::io::Error::from_raw_os_error(code);
+            let error = LibreOfficeError::from_io(workdir::not_writable(os_error));
+            assert_eq!(error.code(), "workdir_permission");
+            let response: axum::response::Response = error.into();
+            assert_eq!(
+                response.status(),
+                axum::http::StatusCode::INTERNAL_SERVER_ERROR
+            );
+        }
+
+        // A read-only directory outside the work dir is a plain IO error
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::set_permissions(dir.path(), std::fs::Permissions::from_mode(0o555)).unwrap();
+        match tokio::fs::File::create(dir.path().join(UPLOAD_FILENAME)).await {
+            Err(e) => assert_eq!(LibreOfficeError::from_io(e).code(), "io_error"),
+            // Root ignores the mode bits
+            Ok(_) => assert_eq!(unsafe { libc::geteuid() }, 0),
+        }
+    }
+
+    /// Backend whose conversions always run out of time
+    struct HangingBackend;
+
+    #[async_trait]
+    impl ConversionBackend for HangingBackend {
+        fn name(&self) -> &'static str {
+            "hanging"
+        }
+
+        fn is_available(&self) -> bool {
+            true
+        }
+
+        async fn convert(
+            &self,
+            _input_path: &Path,
+            _output_dir: &Path,
+            _from: &InputFormat,
+            _to: &OutputFormat,
+            _options: &ConversionOptions,
+        ) -> Result<ConversionOutput> {
+            Err(LibreOfficeError::Timeout { queue_wait: None })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_queue_timeout_is_told_from_conversion_timeout() {
+        let config = Arc::new(Config {
+            max_queue_wait: Duration::from_millis(50),
+            ..config::get().clone()
+        });
+        let scheduler = Arc::new(Scheduler::new(1));
+        let convert = |backend: Box<dyn ConversionBackend>| {
+            let converter = LibreOfficeConverter::new(
+                config.clone(),
+                Arc::new(BackendChain::new(vec![backend])),
+                scheduler.clone(),
+            );
+            async move {
+                let input =
+                    InputFile::from_reader(&mut b"some notes".as_slice(), &config::get().work_dir)
+                        .await
+                        .unwrap();
+                converter
+                    .convert_async(
+                        input,
+                        &"txt".parse().unwrap(),
+                        &"pdf".parse().unwrap(),
+                        &ConversionOptions::default(),
+                    )
+                    .await
+            }
+        };
+
+        let busy = scheduler.acquire(Lane::Interactive).await;
+        assert!(matches!(
+            convert(Box::new(CannedBackend)).await,
+            Err(LibreOfficeError::QueueTimeout(waited)) if waited == Duration::from_millis(50)
+        ));
+        drop(busy);
+
+        let output = convert(Box::new(CannedBackend)).await.unwrap();
+        assert!(output.queue.is_some());
+        assert!(output.conversion_duration.is_some());
+        assert!(matches!(
+            convert(Box::new(HangingBackend)).await,
+            Err(LibreOfficeError::Timeout {
+                queue_wait: Some(_)
+            })
+        ));
+    }
+
+    /// Backend re-saving the input under its own name into the output dir, as
+    /// LibreOffice does for a conversion to the same format
+    struct ResavingBackend;
+
+    #[async_trait]
+    impl ConversionBackend for ResavingBackend {
+        fn name(&self) -> &'static str {
+            "resaving"
+        }
+
+        fn is_available(&self) -> bool {
+            true
+        }
+
+        async fn convert(
+            &self,
+            input_path: &Path,
+            output_dir: &Path,
+            _from: &InputFormat,
+            to: &OutputFormat,
+            _options: &ConversionOptions,
+        ) -> Result<ConversionOutput> {
+            let before = snapshot_dir(output_dir).await?;
+            let resaved = output_dir.join(input_path.file_name().unwrap());
+            std::fs::write(resaved, b"PK\x03\x04 resaved").unwrap();
+            Ok(collect_output(output_dir, &before, to.as_str())
+                .await?
+                .expect("the re-saved document is new in the output dir"))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_same_format_output_is_kept_apart_from_the_input() {
+        let converter = LibreOfficeConverter::new(
+            Arc::new(config::get().clone()),
+            Arc::new(BackendChain::new(vec![Box::new(ResavingBackend)])),
+            Arc::new(Scheduler::new(1)),
+        );
+        let input = InputFile::from_reader(
+            &mut b"PK\x03\x04 original".as_slice(),
+            &config::get().work_dir,
+        )
+        .await
+        .unwrap();
+        let output = converter
+            .convert_async(
+                input,
+                &"docx".parse().unwrap(),
+                &"docx".parse().unwrap(),
+                &ConversionOptions::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(output.primary.name, "document.docx");
+        let digest = checksum::sha256(&output.primary.data).await.unwrap();
+        assert_eq!(output.sha256, Some(digest));
+        assert_eq!(
+            output.primary.data.into_bytes().await.unwrap(),
+            b"PK\x03\x04 resaved"
+        );
+    }
+
+    /// Backend failing unless handed a plain text document
+    struct TextOnlyBackend;
+
+    #[async_trait]
+    impl ConversionBackend for TextOnlyBackend {
+        fn name(&self) -> &'static str {
+            "text-only"
+        }
+
+        fn is_available(&self) -> bool {
+            true
+        }
+
+        async fn convert(
+            &self,
+            input_path: &Path,
+            output_dir: &Path,
+            from: &InputFormat,
+            to: &OutputFormat,
+            options: &ConversionOptions,
+        ) -> Result<ConversionOutput> {
+            assert!(input_path.ends_with("document.txt"), "{:?}", input_path);
+            assert_eq!(from.as_str(), "txt");
+            CannedBackend
+                .convert(input_path, output_dir, from, to, options)
+                .await
+        }
+    }
+
+    /// Backend failing as LibreOffice does on packages without a central directory
+    struct ZipOnlyBackend;
+
+    #[async_trait]
+    impl ConversionBackend for ZipOnlyBackend {
+        fn name(&self) -> &'static str {
+            "zip-only"
+        }
+
+        fn is_available(&self) -> bool {
+            true
+        }
+
+        async fn convert(
+            &self,
+            input_path: &Path,
+            output_dir: &Path,
+            from: &InputFormat,
+            to: &OutputFormat,
+            options: &ConversionOptions,
+        ) -> Result<ConversionOutput> {
+            let file = std::fs::File::open(input_path).unwrap();
+            if zip::ZipArchive::new(file).is_err() {
+                return Err(LibreOfficeError::CorruptedInput(
+                    "source file could not be loaded".to_string(),
+                ));
+            }
+            assert_eq!(options.input_filter, Some("MS Word 2007 XML"));
+            CannedBackend
+                .convert(input_path, output_dir, from, to, options)
+                .await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_repair_rebuilds_truncated_package() {
+        let converter = LibreOfficeConverter::new(
+            Arc::new(config::get().clone()),
+            Arc::new(BackendChain::new(vec![Box::new(ZipOnlyBackend)])),
+            Arc::new(Scheduler::new(1)),
+        );
+        let convert = |repair: bool| {
+            let converter = converter.clone();
+            async move {
+                let truncated = std::fs::read(
+                    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/truncated.docx"),
+                )
+                .unwrap();
+                let input =
+                    InputFile::from_reader(&mut truncated.as_slice(), &config::get().work_dir)
+                        .await
+                        .unwrap();
+                let options = ConversionOptions {
+                    repair,
+                    ..ConversionOptions::default()
+                };
+                converter
+                    .convert(
+                        input,
+                        &"docx".parse().unwrap(),
+                        &"pdf".parse().unwrap(),
+                        &options,
+                    )
+                    .await
+            }
+        };
+
+        assert!(matches!(
+            convert(false).await,
+            Err(LibreOfficeError::CorruptedInput(_))
+        ));
+        let output = convert(true).await.unwrap();
+        assert!(output.repaired);
+        assert_eq!(
+            output.primary.data.into_bytes().await.unwrap(),
+            b"%PDF-1.7 canned"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_emails_convert_as_text_and_outlook_messages_are_rejected() {
+        let fixture = |name: &str| {
+            std::fs::read(
+                Path::new(env!("CARGO_MANIFEST_DIR"))
+                    .join("tests/fixtures")
+                    .join(name),
+            )
+            .unwrap()
+        };
+        let converter = LibreOfficeConverter::new(
+            Arc::new(config::get().clone()),
+            Arc::new(BackendChain::new(vec![Box::new(TextOnlyBackend)])),
+            Arc::new(Scheduler::new(1)),
+        );
+        let convert = |name: &'static str, from: &'static str| {
+            let converter = converter.clone();
+            async move {
+                let input =
+                    InputFile::from_reader(&mut fixture(name).as_slice(), &config::get().work_dir)
+                        .await
+                        .unwrap();
+                converter
+                    .convert(
+                        input,
+                        &from.parse().unwrap(),
+                        &"pdf".parse().unwrap(),
+                        &ConversionOptions::default(),
+                    )
+                    .await
+            }
+        };
+
+        let output = convert("sample.eml", "eml").await.unwrap();
+        assert_eq!(
+            output.primary.data.into_bytes().await.unwrap(),
+            b"%PDF-1.7 canned"
+        );
+
+        let error = convert("sample.msg", "msg").await.unwrap_err();
+        assert!(matches!(error, LibreOfficeError::OutlookMessage));
+        assert!(error.to_string().contains(".eml"));
+    }
+
+    /// Backend checking that the page setup reached the worksheet before the export
+    struct FitToPageBackend;
+
+    #[async_trait]
+    impl ConversionBackend for FitToPageBackend {
+        fn name(&self) -> &'static str {
+            "fit-to-page"
+        }
+
+        fn is_available(&self) -> bool {
+            true
+        }
+
+        async fn convert(
+            &self,
+            input_path: &Path,
+            output_dir: &Path,
+            from: &InputFormat,
+            to: &OutputFormat,
+            options: &ConversionOptions,
+        ) -> Result<ConversionOutput> {
+            let mut archive =
+                zip::ZipArchive::new(std::fs::File::open(input_path).unwrap()).unwrap();
+            let mut sheet = String::new();
+            std::io::Read::read_to_string(
+                &mut archive.by_name("xl/worksheets/sheet1.xml").unwrap(),
+                &mut sheet,
+            )
+            .unwrap();
+            assert!(sheet.contains(r#"fitToWidth="1""#), "{}", sheet);
+            CannedBackend
+                .convert(input_path, output_dir, from, to, options)
+                .await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_page_setup_only_applies_to_spreadsheets() {
+        let converter = LibreOfficeConverter::new(
+            Arc::new(config::get().clone()),
+            Arc::new(BackendChain::new(vec![Box::new(FitToPageBackend)])),
+            Arc::new(Scheduler::new(1)),
+        );
+        let options = ConversionOptions {
+            page_setup: page_setup::PageSetup {
+                fit_to_width: Some(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let convert = |name: &'static str, from: &'static str| {
+            let (converter, options) = (converter.clone(), options.clone());
+            async move {
+                let content = std::fs::read(
+                    Path::new(env!("CARGO_MANIFEST_DIR"))
+                        .join("tests/fixtures")
+                        .join(name),
+                )
+                .unwrap();
+                let input =
+                    InputFile::from_reader(&mut content.as_slice(), &config::get().work_dir)
+                        .await
+                        .unwrap();
+                converter
+                    .convert(
+                        input,
+                        &from.parse().unwrap(),
+                        &"pdf".parse().unwrap(),
+                        &options,
+                    )
+                    .await
+            }
+        };
+
+        assert!(convert("wide.xlsx", "xlsx").await.is_ok());
+
+        let error = convert("sample.docx", "docx").await.unwrap_err();
+        assert!(matches!(error, LibreOfficeError::InvalidOption(_)));
+        assert!(error.to_string().contains("only apply to xlsx and ods"));
+    }
+
+    #[tokio::test]
+    async fn test_document_options_are_validated() {
+        let converter = LibreOfficeConverter::new(
+            Arc::new(config::get().clone()),
+            Arc::new(BackendChain::new(vec![Box::new(CannedBackend)])),
+            Arc::new(Scheduler::new(1)),
+        );
+        let convert = |name: &'static str, to: &'static str, options: ConversionOptions| {
+            let converter = converter.clone();
+            async move {
+                let content = std::fs::read(
+                    Path::new(env!("CARGO_MANIFEST_DIR"))
+                        .join("tests/fixtures")
+                        .join(name),
+                )
+                .unwrap();
+                let (_, from) = name.rsplit_once('.').unwrap();
+                let input =
+                    InputFile::from_reader(&mut content.as_slice(), &config::get().work_dir)
+                        .await
+                        .unwrap();
+                converter
+                    .convert(
+                        input,
+                        &from.parse().unwrap(),
+                        &to.parse().unwrap(),
+                        &options,
+                    )
+                    .await
+            }
+        };
+        let accept = ConversionOptions {
+            changes: Some(TrackedChanges::Accept),
+            ..Default::default()
+        };
+        let comments = ConversionOptions {
+            include_comments: Some(true),
+            ..Default::default()
+        };
+
+        assert!(
+            convert("tracked-changes.docx", "pdf", accept.clone())
+                .await
+                .is_ok()
+        );
+        assert!(matches!(
+            convert("sample.odt", "pdf", accept).await,
+            Err(LibreOfficeError::InvalidOption(_))
+        ));
+        let show = ConversionOptions {
+            changes: Some(TrackedChanges::Show),
+            ..Default::default()
+        };
+        assert!(convert("sample.odt", "pdf", show).await.is_ok());
+
+        assert!(convert("sample.odt", "pdf", comments.clone()).await.is_ok());
+        assert!(matches!(
+            convert("sample.odt", "docx", comments).await,
+            Err(LibreOfficeError::InvalidOption(_))
+        ));
+
+        let tagged = ConversionOptions {
+            tagged_pdf: Some(true),
+            ..Default::default()
+        };
+        assert!(matches!(
+            convert("sample.odt", "odt", tagged).await,
+            Err(LibreOfficeError::InvalidOption(_))
+        ));
+
+        let mut encrypted = ConversionOptions::default();
+        encrypted.set_field("output_password", "s3cret").unwrap();
+        assert!(matches!(
+            convert("sample.odt", "docx", encrypted).await,
+            Err(LibreOfficeError::InvalidOption(_))
+        ));
+
+        let strip = ConversionOptions {
+            strip_metadata: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            convert("sample.odt", "txt", strip).await,
+            Err(LibreOfficeError::InvalidOption(_))
+        ));
+    }
+
+    #[test]
+    fn test_convert_to_argument() {
+        let comments = ConversionOptions {
+            include_comments: Some(true),
+            ..Default::default()
+        };
+        let (docx, pptx) = ("docx".parse().unwrap(), "pptx".parse().unwrap());
+        let pdf = "pdf".parse().unwrap();
+
+        assert_eq!(
+            convert_to_argument(&docx, &pdf, &ConversionOptions::default()),
+            "pdf"
+        );
+        assert_eq!(
+            convert_to_argument(&docx, &pdf, &comments),
+            r#"pdf:writer_pdf_Export:{"ExportNotes":{"type":"boolean","value":"true"}}"#
+        );
+        assert!(convert_to_argument(&pptx, &pdf, &comments).starts_with("pdf:impress_pdf_Export:"));
+        let archival = ConversionOptions {
+            embed_fonts: Some(true),
+            tagged_pdf: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(
+            convert_to_argument(&docx, &pdf, &archival),
+            r#"pdf:writer_pdf_Export:{"EmbedStandardFonts":{"type":"boolean","value":"true"},"UseTaggedPDF":{"type":"boolean","value":"false"}}"#
+        );
+        assert_eq!(
+            convert_to_argument(&docx, &"odt".parse().unwrap(), &comments),
+            "odt"
+        );
+
+        let resave = ConversionOptions {
+            output_filter: Some("MS Word 2007 XML".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            convert_to_argument(&docx, &"docx".parse().unwrap(), &resave),
+            "docx:MS Word 2007 XML"
+        );
+        let pdf_filter = ConversionOptions {
+            output_filter: Some("draw_pdf_Export".to_string()),
+            ..comments
+        };
+        assert!(convert_to_argument(&pptx, &pdf, &pdf_filter).starts_with("pdf:draw_pdf_Export:"));
+    }
+
+    #[tokio::test]
+    async fn test_detects_compound_file_with_directory_beyond_header() {
+        let xls =
+            std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/large.xls"))
+                .unwrap();
+        assert!(xls.len() > crate::detect_filetype::DETECTION_HEADER_LEN);
+
+        let input = InputFile::from_reader(&mut xls.as_slice(), &config::get().work_dir)
+            .await
+            .unwrap();
+        assert_eq!(
+            input.detect_file_type().await.unwrap().file_type,
+            FileType::LegacyExcel
+        );
+    }
+
+    #[tokio::test]
+    async fn test_macro_documents_rejected_when_configured() {
+        let docm =
+            std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/macros.docm"))
+                .unwrap();
+        let input = InputFile::from_reader(&mut docm.as_slice(), &config::get().work_dir)
+            .await
+            .unwrap();
+        let config = Config {
+            reject_macro_documents: true,
+            ..config::get().clone()
+        };
+
+        let result = LibreOfficeConverter::new(
+            Arc::new(config),
+            backend::chain().clone(),
+            queue::scheduler().clone(),
+        )
+        .convert(
+            input,
+            &"docm".parse().unwrap(),
+            &"pdf".parse().unwrap(),
+            &ConversionOptions::default(),
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(LibreOfficeError::MacroDocumentRejected)
+        ));
     }
 
     #[tokio::test]
@@ -363,7 +2810,20 @@ mod tests {
 
                 // This will fail because LibreOffice isn't installed, but that's expected
                 // The important thing is that the locking mechanism is exercised
-                let result = convert_libreoffice_async(input_data_clone, "txt", "pdf").await;
+                let input = InputFile::from_reader(
+                    &mut input_data_clone.as_slice(),
+                    &config::get().work_dir,
+                )
+                .await
+                .unwrap();
+                let result = LibreOfficeConverter::global()
+                    .convert_async(
+                        input,
+                        &"txt".parse().unwrap(),
+                        &"pdf".parse().unwrap(),
+                        &ConversionOptions::default(),
+                    )
+                    .await;
 
                 // We expect this to fail due to LibreOffice not being available
                 assert!(result.is_err());
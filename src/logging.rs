@@ -0,0 +1,173 @@
+use std::fmt;
+
+use serde_json::{Map, Value};
+use tracing::{Event, Subscriber, field::Field};
+use tracing_subscriber::{
+    EnvFilter,
+    fmt::{
+        FmtContext, FormatEvent, FormattedFields, MakeWriter,
+        format::{JsonFields, Writer},
+        time::{FormatTime, SystemTime},
+    },
+    registry::LookupSpan,
+};
+
+/// Level used when `LOG_LEVEL` is unset
+const DEFAULT_LOG_LEVEL: &str = "debug";
+
+/// Installs the global subscriber logging to stdout. `LOG_FORMAT` and `LOG_LEVEL`
+/// are read directly rather than through [`crate::config`], whose parse warnings
+/// need a subscriber.
+pub fn init() {
+    init_with(std::io::stdout);
+}
+
+/// Installs the global subscriber logging to stderr, leaving stdout to the output
+/// of a CLI command
+pub fn init_stderr() {
+    init_with(std::io::stderr);
+}
+
+fn init_with<W>(writer: W)
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let filter = EnvFilter::try_new(
+        std::env::var("LOG_LEVEL").unwrap_or_else(|_| DEFAULT_LOG_LEVEL.to_string()),
+    )
+    .unwrap_or_else(|e| {
+        eprintln!("Invalid LOG_LEVEL ({}), using {}", e, DEFAULT_LOG_LEVEL);
+        EnvFilter::new(DEFAULT_LOG_LEVEL)
+    });
+
+    match std::env::var("LOG_FORMAT").as_deref() {
+        Ok("json") => tracing_subscriber::fmt()
+            .with_env_filter(filter)
+            .with_writer(writer)
+            .fmt_fields(JsonFields::new())
+            .event_format(FlatJson)
+            .init(),
+        _ => tracing_subscriber::fmt()
+            .with_env_filter(filter)
+            .with_writer(writer)
+            .init(),
+    }
+}
+
+/// One JSON object per event, with the fields of all enclosing spans (request_id,
+/// formats, duration, ...) merged in at the top level
+struct FlatJson;
+
+impl<S> FormatEvent<S, JsonFields> for FlatJson
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, JsonFields>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let mut fields = Map::new();
+
+        let mut timestamp = String::new();
+        SystemTime.format_time(&mut Writer::new(&mut timestamp))?;
+        fields.insert("timestamp".into(), timestamp.into());
+        fields.insert("level".into(), event.metadata().level().as_str().into());
+        fields.insert("target".into(), event.metadata().target().into());
+
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                let extensions = span.extensions();
+                let Some(formatted) = extensions.get::<FormattedFields<JsonFields>>() else {
+                    continue;
+                };
+                if let Ok(Value::Object(span_fields)) = serde_json::from_str(&formatted.fields) {
+                    fields.extend(span_fields);
+                }
+            }
+        }
+
+        event.record(&mut JsonVisitor(&mut fields));
+        writeln!(writer, "{}", Value::Object(fields))
+    }
+}
+
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl tracing::field::Visit for JsonVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().into(), format!("{:?}", value).into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_span_fields_are_flattened() {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .fmt_fields(JsonFields::new())
+            .event_format(FlatJson)
+            .with_writer(move || writer.clone())
+            .finish();
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!(
+                "request",
+                request_id = "abc",
+                output_format = tracing::field::Empty
+            );
+            let _entered = span.enter();
+            span.record("output_format", "pdf");
+            tracing::info!(size = 42, "Conversion done");
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(output.lines().count(), 1);
+        let line: Value = serde_json::from_str(output.trim()).unwrap();
+        assert_eq!(line["message"], "Conversion done");
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["request_id"], "abc");
+        assert_eq!(line["output_format"], "pdf");
+        assert_eq!(line["size"], 42);
+    }
+}
@@ -1,41 +1,12 @@
-use std::env;
+use std::process::ExitCode;
 
-use axum::{
-    Router,
-    extract::DefaultBodyLimit,
-    routing::{get, post},
-};
-use tower_http::trace::TraceLayer;
-
-mod detect_filetype;
-mod error;
-mod libreoffice;
-mod routes;
-
-const DEFAULT_PORT: u16 = 1234;
+use clap::Parser;
+use libreoffice_rest::cli::{self, Cli, Command};
 
 #[tokio::main]
-async fn main() {
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::DEBUG)
-        .init();
-
-    let port = env::var("PORT")
-        .ok()
-        .and_then(|p| p.parse::<u16>().ok())
-        .unwrap_or(DEFAULT_PORT);
-
-    let app = Router::new()
-        .route("/health", get(routes::health::handler))
-        .route("/ready", get(routes::ready::handler))
-        .route(
-            "/convert",
-            post(routes::convert::handler).layer(DefaultBodyLimit::max(250 * 1024 * 1024)),
-        )
-        .layer(TraceLayer::new_for_http());
-
-    let addr: String = format!("0.0.0.0:{}", port);
-    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-    tracing::info!("Starting server on {}", &addr);
-    axum::serve(listener, app).await.unwrap();
+async fn main() -> ExitCode {
+    match Cli::parse().command.unwrap_or(Command::Serve) {
+        Command::Serve => libreoffice_rest::serve().await,
+        Command::Convert(args) => cli::run_convert(args).await,
+    }
 }
@@ -0,0 +1,201 @@
+//! Removal of the authoring metadata Office and LibreOffice write into documents:
+//! author and editor names, company, printing and revision history. Titles, dates
+//! and the content stay as they are.
+
+use lopdf::Document;
+
+use crate::{
+    error::{LibreOfficeError, Result},
+    formats::OutputFormat,
+    libreoffice::ConvertedOutput,
+    office_xml::{self, remove_elements},
+    pdf,
+};
+
+/// Output formats whose metadata can be stripped
+pub const FORMATS: &[&str] = &["pdf", "docx", "xlsx", "pptx", "odt", "ods", "odp", "odg"];
+
+/// Elements of docProps/core.xml naming people or counting revisions
+const CORE_PROPERTIES: &[&str] = &[
+    "dc:creator",
+    "cp:lastModifiedBy",
+    "cp:revision",
+    "cp:lastPrinted",
+];
+
+/// Elements of docProps/app.xml about the organization and the editing
+const APP_PROPERTIES: &[&str] = &["Company", "Manager", "TotalTime", "Template"];
+
+/// Elements of the ODF meta.xml, user defined fields are where Company ends up
+const ODF_META: &[&str] = &[
+    "meta:initial-creator",
+    "dc:creator",
+    "meta:printed-by",
+    "meta:print-date",
+    "meta:editing-cycles",
+    "meta:editing-duration",
+    "meta:template",
+    "meta:user-defined",
+];
+
+pub fn applies_to(to: &OutputFormat) -> bool {
+    FORMATS.contains(&to.as_str())
+}
+
+/// Strips the metadata of converted output in `to` format in place
+pub fn strip(data: &mut ConvertedOutput, to: &OutputFormat) -> Result<()> {
+    if to.as_str() == "pdf" {
+        return pdf::edit(data, |document| {
+            strip_pdf(document);
+            Ok(())
+        });
+    }
+
+    match data {
+        ConvertedOutput::Bytes(bytes) => {
+            *bytes = office_xml::rewrite_entries_in_memory(bytes, is_metadata_part, strip_part)?;
+        }
+        ConvertedOutput::File { path, len } => {
+            office_xml::rewrite_entries(path, is_metadata_part, strip_part)?;
+            *len = std::fs::metadata(path)
+                .map_err(LibreOfficeError::from_io)?
+                .len();
+        }
+    }
+    Ok(())
+}
+
+/// Drops the Info dictionary and the XMP packet, which repeats it
+fn strip_pdf(document: &mut Document) {
+    document.trailer.remove(b"Info");
+    if let Ok(catalog) = document.catalog_mut() {
+        catalog.remove(b"Metadata");
+    }
+    document.prune_objects();
+}
+
+fn is_metadata_part(name: &str) -> bool {
+    matches!(
+        name,
+        "docProps/core.xml" | "docProps/app.xml" | "docProps/custom.xml" | "meta.xml"
+    )
+}
+
+fn strip_part(name: &str, xml: &str) -> String {
+    let elements = match name {
+        "docProps/core.xml" => CORE_PROPERTIES,
+        "docProps/app.xml" => APP_PROPERTIES,
+        // Custom properties are free form, none of them is kept
+        "docProps/custom.xml" => &["property"],
+        _ => ODF_META,
+    };
+    elements
+        .iter()
+        .fold(xml.to_string(), |xml, name| remove_elements(&xml, name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::Read;
+    use std::path::Path;
+
+    use zip::ZipArchive;
+
+    fn fixture(name: &str) -> Vec<u8> {
+        std::fs::read(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/fixtures")
+                .join(name),
+        )
+        .unwrap()
+    }
+
+    fn read_entry(archive: &mut ZipArchive<impl Read + std::io::Seek>, name: &str) -> String {
+        let mut content = String::new();
+        archive
+            .by_name(name)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    }
+
+    #[test]
+    fn test_strip_docx() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("document.docx");
+        std::fs::write(&path, fixture("metadata.docx")).unwrap();
+        let mut data = ConvertedOutput::File {
+            path: path.clone(),
+            len: 0,
+        };
+
+        strip(&mut data, &"docx".parse().unwrap()).unwrap();
+
+        assert_eq!(data.len(), std::fs::metadata(&path).unwrap().len());
+        let mut archive = ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        let core = read_entry(&mut archive, "docProps/core.xml");
+        assert!(!core.contains("Alice Example"));
+        assert!(!core.contains("Bob Example"));
+        assert!(!core.contains("cp:revision"));
+        assert!(core.contains("<dc:title>Quarterly report</dc:title>"));
+        let app = read_entry(&mut archive, "docProps/app.xml");
+        assert!(!app.contains("Example Corp"));
+        assert!(!app.contains("Carol Example"));
+        assert!(app.contains("<Application>"));
+        assert!(read_entry(&mut archive, "word/document.xml").contains("<w:body>"));
+    }
+
+    #[test]
+    fn test_strip_odt() {
+        let mut data = ConvertedOutput::Bytes(fixture("metadata.odt"));
+
+        strip(&mut data, &"odt".parse().unwrap()).unwrap();
+
+        let ConvertedOutput::Bytes(bytes) = data else {
+            unreachable!()
+        };
+        let mut archive = ZipArchive::new(std::io::Cursor::new(bytes)).unwrap();
+        // The mimetype has to stay the first entry for the package to open
+        assert_eq!(archive.by_index(0).unwrap().name(), "mimetype");
+        let meta = read_entry(&mut archive, "meta.xml");
+        for name in [
+            "Alice Example",
+            "Bob Example",
+            "Carol Example",
+            "Example Corp",
+        ] {
+            assert!(!meta.contains(name), "{} left in {}", name, meta);
+        }
+        assert!(!meta.contains("meta:editing-cycles"));
+        assert!(meta.contains("<dc:title>Quarterly report</dc:title>"));
+        assert!(read_entry(&mut archive, "content.xml").contains("office:text"));
+    }
+
+    #[test]
+    fn test_strip_pdf() {
+        let mut data = ConvertedOutput::Bytes(fixture("metadata.pdf"));
+
+        strip(&mut data, &"pdf".parse().unwrap()).unwrap();
+
+        let ConvertedOutput::Bytes(bytes) = data else {
+            unreachable!()
+        };
+        assert!(!bytes.windows(13).any(|window| window == b"Alice Example"));
+        assert!(!bytes.windows(12).any(|window| window == b"Example Corp"));
+        let document = Document::load_mem(&bytes).unwrap();
+        assert!(!document.trailer.has(b"Info"));
+        assert!(!document.catalog().unwrap().has(b"Metadata"));
+        assert_eq!(document.get_pages().len(), 1);
+    }
+
+    #[test]
+    fn test_applies_to() {
+        assert!(applies_to(&"pdf".parse().unwrap()));
+        assert!(applies_to(&"ods".parse().unwrap()));
+        assert!(!applies_to(&"txt".parse().unwrap()));
+        assert!(!applies_to(&"doc".parse().unwrap()));
+    }
+}
@@ -0,0 +1,360 @@
+//! Edits of the XML parts inside OOXML and ODF packages. Parts are handled as text
+//! with a few tag level helpers instead of being parsed, leaving everything that is
+//! not touched byte for byte as the producer wrote it.
+
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use zip::{ZipArchive, ZipWriter, write::SimpleFileOptions};
+
+use crate::error::{LibreOfficeError, Result};
+
+/// Rewrites the package at `path` in place: `rewrite` gets the name and content of
+/// every entry `matches` selects, the other entries are copied unchanged
+pub fn rewrite_entries(
+    path: &Path,
+    matches: impl Fn(&str) -> bool,
+    rewrite: impl Fn(&str, &str) -> String,
+) -> Result<()> {
+    let rewritten_path = path.with_extension("rewritten");
+    let input = File::open(path).map_err(LibreOfficeError::from_io)?;
+    let output = File::create(&rewritten_path).map_err(LibreOfficeError::from_io)?;
+    rewrite_archive(input, output, matches, rewrite).map_err(zip_error)?;
+    std::fs::rename(&rewritten_path, path).map_err(LibreOfficeError::from_io)
+}
+
+/// [`rewrite_entries`] for a package held in memory
+pub fn rewrite_entries_in_memory(
+    data: &[u8],
+    matches: impl Fn(&str) -> bool,
+    rewrite: impl Fn(&str, &str) -> String,
+) -> Result<Vec<u8>> {
+    let mut output = Cursor::new(Vec::new());
+    rewrite_archive(Cursor::new(data), &mut output, matches, rewrite).map_err(zip_error)?;
+    Ok(output.into_inner())
+}
+
+fn zip_error(error: zip::result::ZipError) -> LibreOfficeError {
+    match error {
+        zip::result::ZipError::Io(e) => LibreOfficeError::from_io(e),
+        e => LibreOfficeError::CorruptedInput(e.to_string()),
+    }
+}
+
+fn rewrite_archive(
+    input: impl Read + Seek,
+    output: impl Write + Seek,
+    matches: impl Fn(&str) -> bool,
+    rewrite: impl Fn(&str, &str) -> String,
+) -> zip::result::ZipResult<()> {
+    let mut archive = ZipArchive::new(input)?;
+    let mut writer = ZipWriter::new(output);
+
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        let name = entry.name().to_string();
+        if !matches(&name) {
+            writer.raw_copy_file(entry)?;
+            continue;
+        }
+
+        let mut xml = String::new();
+        entry.read_to_string(&mut xml)?;
+        let options = SimpleFileOptions::default().compression_method(entry.compression());
+        writer.start_file(name.as_str(), options)?;
+        writer.write_all(rewrite(&name, &xml).as_bytes())?;
+    }
+
+    writer.finish()?;
+    Ok(())
+}
+
+/// Name of a tag, prefixed with `/` for end tags
+pub fn tag_name(tag: &str) -> &str {
+    let rest = &tag[1..];
+    let len = rest
+        .char_indices()
+        .find(|&(i, c)| c.is_whitespace() || c == '>' || (c == '/' && i > 0))
+        .map_or(rest.len(), |(i, _)| i);
+    &rest[..len]
+}
+
+/// Replaces every tag for which `replace` returns a replacement
+fn map_tags(xml: &str, mut replace: impl FnMut(&str) -> Option<String>) -> String {
+    let mut output = String::with_capacity(xml.len());
+    let mut copied = 0;
+    let mut from = 0;
+
+    while let Some(offset) = xml[from..].find('<') {
+        let start = from + offset;
+        let Some(len) = xml[start..].find('>') else {
+            break;
+        };
+        let end = start + len + 1;
+        if let Some(replacement) = replace(&xml[start..end]) {
+            output.push_str(&xml[copied..start]);
+            output.push_str(&replacement);
+            copied = end;
+        }
+        from = end;
+    }
+
+    output.push_str(&xml[copied..]);
+    output
+}
+
+/// End of the element whose start tag is `start_tag`, after its end tag
+fn element_end(xml: &str, start_tag: Range<usize>, name: &str) -> Option<usize> {
+    if xml[start_tag.clone()].ends_with("/>") {
+        return Some(start_tag.end);
+    }
+
+    let end_name = format!("/{}", name);
+    let mut depth = 1;
+    let mut from = start_tag.end;
+    while let Some(offset) = xml[from..].find('<') {
+        let start = from + offset;
+        let end = start + xml[start..].find('>')? + 1;
+        let tag = &xml[start..end];
+        if tag_name(tag) == name && !tag.ends_with("/>") {
+            depth += 1;
+        } else if tag_name(tag) == end_name {
+            depth -= 1;
+            if depth == 0 {
+                return Some(end);
+            }
+        }
+        from = end;
+    }
+    None
+}
+
+/// Drops every `name` element along with its content
+pub fn remove_elements(xml: &str, name: &str) -> String {
+    let mut output = String::with_capacity(xml.len());
+    let mut copied = 0;
+
+    while let Some(tag) =
+        find_start_tag(&xml[copied..], name, false).map(|tag| tag.start + copied..tag.end + copied)
+    {
+        let Some(end) = element_end(xml, tag.clone(), name) else {
+            break;
+        };
+        output.push_str(&xml[copied..tag.start]);
+        copied = end;
+    }
+
+    output.push_str(&xml[copied..]);
+    output
+}
+
+/// Drops the tags of every `name` element, keeping their content in place
+pub fn unwrap_elements(xml: &str, name: &str) -> String {
+    let end_name = format!("/{}", name);
+    map_tags(xml, |tag| {
+        let tag_name = tag_name(tag);
+        (tag_name == name || tag_name == end_name).then(String::new)
+    })
+}
+
+/// Renames every `from` element to `to`, attributes and content stay
+pub fn rename_elements(xml: &str, from: &str, to: &str) -> String {
+    let end_from = format!("/{}", from);
+    map_tags(xml, |tag| {
+        let tag_name = tag_name(tag);
+        if tag_name == from {
+            Some(format!("<{}{}", to, &tag[1 + from.len()..]))
+        } else if tag_name == end_from {
+            Some(format!("</{}{}", to, &tag[2 + from.len()..]))
+        } else {
+            None
+        }
+    })
+}
+
+/// Range of the first `<name ...>` start tag, or of the root element's when
+/// `any_prefix` allows `<prefix:name ...>`
+pub fn find_start_tag(xml: &str, name: &str, any_prefix: bool) -> Option<Range<usize>> {
+    let mut from = 0;
+    while let Some(offset) = xml[from..].find('<') {
+        let start = from + offset;
+        let rest = &xml[start + 1..];
+        let tag_name_len = rest
+            .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .unwrap_or(rest.len());
+        let tag_name = &rest[..tag_name_len];
+
+        let matches = tag_name == name
+            || (any_prefix
+                && tag_name
+                    .split_once(':')
+                    .is_some_and(|(_, local)| local == name));
+        if matches {
+            let end = start + xml[start..].find('>')? + 1;
+            return Some(start..end);
+        }
+        from = start + 1;
+    }
+    None
+}
+
+/// Every `<name ...>` start tag, in document order
+pub fn start_tags<'a>(xml: &'a str, name: &'a str) -> impl Iterator<Item = &'a str> {
+    let mut from = 0;
+    std::iter::from_fn(move || {
+        let tag = find_start_tag(&xml[from..], name, false)?;
+        let start = from + tag.start;
+        from += tag.end;
+        Some(&xml[start..from])
+    })
+}
+
+/// Escapes text for character data or attribute values
+pub fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Resolves the predefined entities of an attribute value
+pub fn unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Value of `name` in a start tag
+pub fn attribute_value<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let range = attribute_range(tag, name)?;
+    let quoted = &tag[range];
+    let value_start = quoted.find(['"', '\''])? + 1;
+    Some(&quoted[value_start..quoted.len() - 1])
+}
+
+/// Range of ` name="value"` in a start tag, including the leading whitespace
+fn attribute_range(tag: &str, name: &str) -> Option<Range<usize>> {
+    let mut from = 0;
+    while let Some(offset) = tag[from..].find(name) {
+        let start = from + offset;
+        from = start + name.len();
+
+        let preceded_by_space = tag[..start].ends_with(|c: char| c.is_whitespace());
+        let after = tag[from..].trim_start();
+        if !preceded_by_space || !after.starts_with('=') {
+            continue;
+        }
+
+        let value = after[1..].trim_start();
+        let quote = value.chars().next().filter(|&c| c == '"' || c == '\'')?;
+        let value_start = tag.len() - value.len() + 1;
+        let value_end = value_start + tag[value_start..].find(quote)? + 1;
+        let space_start = tag[..start].trim_end().len();
+        return Some(space_start..value_end);
+    }
+    None
+}
+
+/// Sets `name` on a start tag, replacing its previous value
+pub fn set_attribute(tag: &str, name: &str, value: &str) -> String {
+    let attribute = format!(" {}=\"{}\"", name, value);
+    match attribute_range(tag, name) {
+        Some(range) => {
+            let mut tag = tag.to_string();
+            tag.replace_range(range, &attribute);
+            tag
+        }
+        None => {
+            let end = if tag.ends_with("/>") {
+                tag.len() - 2
+            } else {
+                tag.len() - 1
+            };
+            let (open, close) = tag.split_at(end);
+            format!("{}{}{}", open.trim_end(), attribute, close)
+        }
+    }
+}
+
+pub fn remove_attribute(tag: &str, name: &str) -> String {
+    let mut tag = tag.to_string();
+    if let Some(range) = attribute_range(&tag, name) {
+        tag.replace_range(range, "");
+    }
+    tag
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attributes() {
+        let tag = r#"<pageSetup paperSize = '9' xpaperSize="1"/>"#;
+        assert_eq!(attribute_value(tag, "paperSize"), Some("9"));
+        assert_eq!(
+            set_attribute(tag, "paperSize", "8"),
+            r#"<pageSetup paperSize="8" xpaperSize="1"/>"#
+        );
+        assert_eq!(
+            remove_attribute(tag, "xpaperSize"),
+            r#"<pageSetup paperSize = '9'/>"#
+        );
+        assert_eq!(set_attribute("<a>", "b", "c"), r#"<a b="c">"#);
+    }
+
+    #[test]
+    fn test_escape() {
+        let value = r#"Smith & Sons <"Ltd's">"#;
+        assert_eq!(
+            escape(value),
+            "Smith &amp; Sons &lt;&quot;Ltd&apos;s&quot;&gt;"
+        );
+        assert_eq!(unescape(&escape(value)), value);
+    }
+
+    #[test]
+    fn test_elements() {
+        let xml = r#"<w:p><w:del w:id="1"><w:r><w:delText>old</w:delText></w:r></w:del><w:ins w:id="2"><w:r><w:t>new</w:t></w:r></w:ins><w:rPr><w:del w:id="3"/></w:rPr></w:p>"#;
+
+        assert_eq!(
+            remove_elements(xml, "w:del"),
+            r#"<w:p><w:ins w:id="2"><w:r><w:t>new</w:t></w:r></w:ins><w:rPr></w:rPr></w:p>"#
+        );
+        assert_eq!(
+            unwrap_elements(xml, "w:ins"),
+            r#"<w:p><w:del w:id="1"><w:r><w:delText>old</w:delText></w:r></w:del><w:r><w:t>new</w:t></w:r><w:rPr><w:del w:id="3"/></w:rPr></w:p>"#
+        );
+        assert_eq!(
+            rename_elements(
+                "<w:delText xml:space=\"preserve\"> a</w:delText>",
+                "w:delText",
+                "w:t"
+            ),
+            "<w:t xml:space=\"preserve\"> a</w:t>"
+        );
+        assert_eq!(
+            start_tags(xml, "w:del").collect::<Vec<_>>(),
+            [r#"<w:del w:id="1">"#, r#"<w:del w:id="3"/>"#]
+        );
+    }
+
+    #[test]
+    fn test_nested_elements_are_removed_whole() {
+        assert_eq!(
+            remove_elements("<a><b><b>x</b><b/></b>y</a>", "b"),
+            "<a>y</a>"
+        );
+        assert_eq!(
+            remove_elements("<a><b>unterminated</a>", "b"),
+            "<a><b>unterminated</a>"
+        );
+    }
+}
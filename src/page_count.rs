@@ -0,0 +1,169 @@
+//! Page, slide and sheet counts of OOXML and ODF documents, read from the
+//! statistics stored in the package instead of laying the document out with
+//! LibreOffice. Counts are as current as the application that last saved the file.
+
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use zip::ZipArchive;
+
+use crate::{
+    detect_filetype::FileType,
+    office_xml::{attribute_value, find_start_tag, start_tags},
+};
+
+/// Largest package part read for a count, statistics parts are a few kilobytes
+const MAX_PART_BYTES: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Pages,
+    Slides,
+    Sheets,
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Unit::Pages => "pages",
+            Unit::Slides => "slides",
+            Unit::Sheets => "sheets",
+        })
+    }
+}
+
+/// Reads the count out of the part holding it
+type CountReader = fn(&str) -> Option<u64>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageCount {
+    pub count: u64,
+    pub unit: Unit,
+}
+
+/// Count stored in the document at `path` of type `file_type`, none for other
+/// formats and for documents without the statistics
+pub fn estimate(path: &Path, file_type: FileType) -> Option<PageCount> {
+    let (part, unit, count): (&str, Unit, CountReader) = match file_type {
+        FileType::Word | FileType::WordMacro => ("docProps/app.xml", Unit::Pages, |xml| {
+            element_value(xml, "Pages")
+        }),
+        FileType::PowerPoint | FileType::PowerPointMacro => {
+            ("docProps/app.xml", Unit::Slides, |xml| {
+                element_value(xml, "Slides")
+            })
+        }
+        FileType::Excel | FileType::ExcelMacro => ("xl/workbook.xml", Unit::Sheets, |xml| {
+            Some(start_tags(xml, "sheet").count() as u64)
+        }),
+        FileType::OpenDocumentText => ("meta.xml", Unit::Pages, |xml| {
+            statistic(xml, "meta:page-count")
+        }),
+        FileType::OpenDocumentSpreadsheet => ("meta.xml", Unit::Sheets, |xml| {
+            statistic(xml, "meta:table-count")
+        }),
+        _ => return None,
+    };
+
+    let xml = match read_part(path, part) {
+        Ok(xml) => xml?,
+        Err(e) => {
+            tracing::debug!("Could not read {} of {:?}: {}", part, path, e);
+            return None;
+        }
+    };
+    Some(PageCount {
+        count: count(&xml)?,
+        unit,
+    })
+}
+
+/// Text of the package part `name`, none when the package doesn't have it
+fn read_part(path: &Path, name: &str) -> zip::result::ZipResult<Option<String>> {
+    let mut archive = ZipArchive::new(File::open(path)?)?;
+    let Ok(entry) = archive.by_name(name) else {
+        return Ok(None);
+    };
+    let mut xml = String::new();
+    entry.take(MAX_PART_BYTES).read_to_string(&mut xml)?;
+    Ok(Some(xml))
+}
+
+/// Number in `<name>12</name>` of docProps/app.xml
+fn element_value(xml: &str, name: &str) -> Option<u64> {
+    let tag = find_start_tag(xml, name, false)?;
+    let text = &xml[tag.end..];
+    text[..text.find('<')?].trim().parse().ok()
+}
+
+/// Attribute of the ODF `meta:document-statistic` element
+fn statistic(xml: &str, attribute: &str) -> Option<u64> {
+    let tag = start_tags(xml, "meta:document-statistic").next()?;
+    attribute_value(tag, attribute)?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name)
+    }
+
+    #[test]
+    fn test_estimate() {
+        let cases = [
+            ("pages.docx", FileType::Word, 12000, Unit::Pages),
+            ("slides.pptx", FileType::PowerPoint, 3, Unit::Slides),
+            ("sample.xlsx", FileType::Excel, 1, Unit::Sheets),
+            ("pages.odt", FileType::OpenDocumentText, 7, Unit::Pages),
+            (
+                "sheets.ods",
+                FileType::OpenDocumentSpreadsheet,
+                2,
+                Unit::Sheets,
+            ),
+        ];
+        for (name, file_type, count, unit) in cases {
+            assert_eq!(
+                estimate(&fixture(name), file_type),
+                Some(PageCount { count, unit }),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_documents_without_statistics_pass() {
+        for (name, file_type) in [
+            ("sample.docx", FileType::Word),
+            ("sample.odt", FileType::OpenDocumentText),
+            ("truncated.docx", FileType::Word),
+            ("sample.rtf", FileType::RichText),
+            ("legacy.doc", FileType::LegacyWord),
+        ] {
+            assert_eq!(estimate(&fixture(name), file_type), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_element_value() {
+        assert_eq!(
+            element_value("<Properties><Pages> 4 </Pages></Properties>", "Pages"),
+            Some(4)
+        );
+        assert_eq!(
+            element_value("<Properties><Pages/></Properties>", "Pages"),
+            None
+        );
+        assert_eq!(
+            element_value("<Properties><Slides>x</Slides></Properties>", "Slides"),
+            None
+        );
+    }
+}
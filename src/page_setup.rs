@@ -0,0 +1,498 @@
+//! Page setup of spreadsheets, applied before the export by rewriting the page
+//! styles inside the document: the worksheet `pageSetup` of xlsx files and the
+//! page layouts in the `styles.xml` of ods files. LibreOffice has no filter option
+//! for them, Calc only exports what the document's page style says.
+
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::{
+    converter::parse_bool,
+    detect_filetype::FileType,
+    error::{LibreOfficeError, Result},
+    office_xml::{self, attribute_value, find_start_tag, remove_attribute, set_attribute},
+};
+
+/// Form fields of the page setup options
+pub const FIELDS: &[&str] = &["fit_to_width", "fit_to_height", "landscape", "paper_size"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PaperSize {
+    A4,
+    Letter,
+    A3,
+}
+
+impl PaperSize {
+    /// `paperSize` code of SpreadsheetML
+    fn ooxml_code(self) -> &'static str {
+        match self {
+            PaperSize::Letter => "1",
+            PaperSize::A3 => "8",
+            PaperSize::A4 => "9",
+        }
+    }
+
+    /// Portrait width and height
+    fn dimensions(self) -> (&'static str, &'static str) {
+        match self {
+            PaperSize::A4 => ("210mm", "297mm"),
+            PaperSize::Letter => ("8.5in", "11in"),
+            PaperSize::A3 => ("297mm", "420mm"),
+        }
+    }
+}
+
+impl FromStr for PaperSize {
+    type Err = LibreOfficeError;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "a4" => Ok(PaperSize::A4),
+            "letter" => Ok(PaperSize::Letter),
+            "a3" => Ok(PaperSize::A3),
+            _ => Err(LibreOfficeError::InvalidOption(
+                "paper_size must be one of A4, Letter, A3".to_string(),
+            )),
+        }
+    }
+}
+
+/// Print settings requested for a spreadsheet, everything unset keeps the document's own
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct PageSetup {
+    /// Pages the sheets are scaled to fit horizontally
+    pub fit_to_width: Option<u32>,
+    /// Pages the sheets are scaled to fit vertically
+    pub fit_to_height: Option<u32>,
+    pub landscape: bool,
+    pub paper_size: Option<PaperSize>,
+}
+
+impl PageSetup {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Applies the form field `name`, one of [`FIELDS`]
+    pub fn set_field(&mut self, name: &str, value: &str) -> Result<()> {
+        let pages = |value: &str| {
+            value
+                .trim()
+                .parse::<u32>()
+                .ok()
+                .filter(|&pages| pages > 0)
+                .ok_or_else(|| {
+                    LibreOfficeError::InvalidOption(format!(
+                        "{} must be a positive number of pages",
+                        name
+                    ))
+                })
+        };
+
+        match name {
+            "fit_to_width" => self.fit_to_width = Some(pages(value)?),
+            "fit_to_height" => self.fit_to_height = Some(pages(value)?),
+            "landscape" => self.landscape = parse_bool(name, value)?,
+            "paper_size" => self.paper_size = Some(value.parse()?),
+            _ => unreachable!("{} is not a page setup field", name),
+        }
+        Ok(())
+    }
+
+    fn fits_to_pages(&self) -> bool {
+        self.fit_to_width.is_some() || self.fit_to_height.is_some()
+    }
+}
+
+/// Whether the page setup of documents of this type can be rewritten
+pub fn applies_to(file_type: FileType) -> bool {
+    matches!(
+        file_type,
+        FileType::Excel | FileType::ExcelMacro | FileType::OpenDocumentSpreadsheet
+    )
+}
+
+/// Rewrites the page setup of the xlsx or ods document at `path` in place
+pub fn apply(path: &Path, setup: &PageSetup) -> Result<()> {
+    office_xml::rewrite_entries(
+        path,
+        |name| is_worksheet(name) || name == "styles.xml",
+        |name, xml| {
+            if name == "styles.xml" {
+                rewrite_ods_styles(xml, setup)
+            } else {
+                rewrite_worksheet(xml, setup)
+            }
+        },
+    )
+}
+
+fn is_worksheet(name: &str) -> bool {
+    name.strip_prefix("xl/worksheets/")
+        .is_some_and(|rest| rest.ends_with(".xml") && !rest.contains('/'))
+}
+
+/// Elements of a worksheet that come after `pageSetup`, in schema order
+const AFTER_PAGE_SETUP: &[&str] = &[
+    "headerFooter",
+    "rowBreaks",
+    "colBreaks",
+    "customProperties",
+    "cellWatches",
+    "ignoredErrors",
+    "smartTags",
+    "drawing",
+    "legacyDrawing",
+    "legacyDrawingHF",
+    "drawingHF",
+    "picture",
+    "oleObjects",
+    "controls",
+    "webPublishItems",
+    "tableParts",
+    "extLst",
+];
+
+/// Sets the `pageSetup` of an xlsx worksheet, creating the elements it needs
+fn rewrite_worksheet(xml: &str, setup: &PageSetup) -> String {
+    let Some(root) = find_start_tag(xml, "worksheet", true) else {
+        return xml.to_string();
+    };
+    // Some producers write prefixed elements (`<x:worksheet>`)
+    let root_name = xml[root.start + 1..root.end]
+        .split(|c: char| c.is_whitespace() || c == '>' || c == '/')
+        .next()
+        .unwrap_or_default();
+    let prefix = root_name.strip_suffix("worksheet").unwrap_or_default();
+    let element = |name: &str| format!("{}{}", prefix, name);
+
+    let mut attributes = Vec::new();
+    if setup.fits_to_pages() {
+        // Zero leaves that direction unconstrained
+        attributes.push(("fitToWidth", setup.fit_to_width.unwrap_or(0).to_string()));
+        attributes.push(("fitToHeight", setup.fit_to_height.unwrap_or(0).to_string()));
+    }
+    if setup.landscape {
+        attributes.push(("orientation", "landscape".to_string()));
+    }
+    if let Some(paper_size) = setup.paper_size {
+        attributes.push(("paperSize", paper_size.ooxml_code().to_string()));
+    }
+    if attributes.is_empty() {
+        return xml.to_string();
+    }
+
+    let mut xml = xml.to_string();
+    match find_start_tag(&xml, &element("pageSetup"), false) {
+        Some(range) => {
+            let mut tag = xml[range.clone()].to_string();
+            for (name, value) in &attributes {
+                tag = set_attribute(&tag, name, value);
+            }
+            xml.replace_range(range, &tag);
+        }
+        None => {
+            let tag = attributes.iter().fold(
+                format!("<{}/>", element("pageSetup")),
+                |tag, (name, value)| set_attribute(&tag, name, value),
+            );
+            let position = AFTER_PAGE_SETUP
+                .iter()
+                .filter_map(|name| find_start_tag(&xml, &element(name), false))
+                .map(|range| range.start)
+                .min()
+                .or_else(|| xml.rfind(&format!("</{}>", element("worksheet"))))
+                .unwrap_or(xml.len());
+            xml.insert_str(position, &tag);
+        }
+    }
+
+    // Excel only honors fitToWidth and fitToHeight with fitToPage set
+    if setup.fits_to_pages() {
+        set_fit_to_page(&mut xml, &element);
+    }
+    xml
+}
+
+/// Sets `fitToPage` in the `sheetPr` properties, the first child of the worksheet
+fn set_fit_to_page(xml: &mut String, element: &dyn Fn(&str) -> String) {
+    let properties = format!("<{} fitToPage=\"1\"/>", element("pageSetUpPr"));
+
+    if let Some(range) = find_start_tag(xml, &element("pageSetUpPr"), false) {
+        let tag = set_attribute(&xml[range.clone()], "fitToPage", "1");
+        xml.replace_range(range, &tag);
+    } else if let Some(range) = find_start_tag(xml, &element("sheetPr"), false) {
+        let tag = &xml[range.clone()];
+        if let Some(open) = tag.strip_suffix("/>") {
+            let replacement = format!(
+                "{}>{}</{}>",
+                open.trim_end(),
+                properties,
+                element("sheetPr")
+            );
+            xml.replace_range(range, &replacement);
+        } else if let Some(end) = xml.find(&format!("</{}>", element("sheetPr"))) {
+            // pageSetUpPr is the last child of sheetPr
+            xml.insert_str(end, &properties);
+        }
+    } else if let Some(root) = find_start_tag(xml, &element("worksheet"), false) {
+        let sheet_properties = format!(
+            "<{}>{}</{}>",
+            element("sheetPr"),
+            properties,
+            element("sheetPr")
+        );
+        xml.insert_str(root.end, &sheet_properties);
+    }
+}
+
+/// Attributes of ods page layouts that scale the print out, replaced when fitting to pages
+const ODS_SCALE_ATTRIBUTES: &[&str] = &[
+    "style:scale-to",
+    "style:scale-to-pages",
+    "style:scale-to-X",
+    "style:scale-to-Y",
+    "loext:scale-to-X",
+    "loext:scale-to-Y",
+];
+
+/// Sets the properties of every page layout in an ods `styles.xml`
+fn rewrite_ods_styles(xml: &str, setup: &PageSetup) -> String {
+    let mut xml = xml.to_string();
+    let mut from = 0;
+
+    while let Some(range) = find_start_tag(&xml[from..], "style:page-layout-properties", false)
+        .map(|range| range.start + from..range.end + from)
+    {
+        let mut tag = xml[range.clone()].to_string();
+
+        if setup.fits_to_pages() {
+            for attribute in ODS_SCALE_ATTRIBUTES {
+                tag = remove_attribute(&tag, attribute);
+            }
+            if let Some(width) = setup.fit_to_width {
+                tag = set_attribute(&tag, "style:scale-to-X", &width.to_string());
+            }
+            if let Some(height) = setup.fit_to_height {
+                tag = set_attribute(&tag, "style:scale-to-Y", &height.to_string());
+            }
+        }
+
+        let size = match setup.paper_size {
+            Some(paper_size) => {
+                let (width, height) = paper_size.dimensions();
+                Some((width.to_string(), height.to_string()))
+            }
+            None => attribute_value(&tag, "fo:page-width")
+                .zip(attribute_value(&tag, "fo:page-height"))
+                .map(|(width, height)| (width.to_string(), height.to_string())),
+        };
+        if let Some((mut width, mut height)) = size {
+            if setup.landscape && is_shorter(&width, &height) {
+                std::mem::swap(&mut width, &mut height);
+            }
+            tag = set_attribute(&tag, "fo:page-width", &width);
+            tag = set_attribute(&tag, "fo:page-height", &height);
+        }
+        if setup.landscape {
+            tag = set_attribute(&tag, "style:print-orientation", "landscape");
+        }
+
+        from = range.start + tag.len();
+        xml.replace_range(range, &tag);
+    }
+
+    xml
+}
+
+/// Number and unit of a length like `21.001cm`
+fn split_length(length: &str) -> Option<(f64, &str)> {
+    let unit_start = length
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(length.len());
+    let (number, unit) = length.split_at(unit_start);
+    number.parse().ok().map(|number| (number, unit))
+}
+
+/// Compares two lengths of the same unit, `false` when they can't be compared
+fn is_shorter(a: &str, b: &str) -> bool {
+    match (split_length(a), split_length(b)) {
+        (Some((a, unit_a)), Some((b, unit_b))) => unit_a == unit_b && a < b,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::Read;
+    use zip::ZipArchive;
+
+    const WORKSHEET: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetData/><pageMargins left="0.7" right="0.7" top="0.75" bottom="0.75" header="0.3" footer="0.3"/><drawing r:id="rId1"/></worksheet>"#;
+
+    fn fit_to_width() -> PageSetup {
+        PageSetup {
+            fit_to_width: Some(1),
+            ..PageSetup::default()
+        }
+    }
+
+    #[test]
+    fn test_set_field() {
+        let mut setup = PageSetup::default();
+        assert!(setup.is_empty());
+        setup.set_field("fit_to_width", "2").unwrap();
+        setup.set_field("landscape", "true").unwrap();
+        setup.set_field("paper_size", "letter").unwrap();
+        assert_eq!(
+            setup,
+            PageSetup {
+                fit_to_width: Some(2),
+                fit_to_height: None,
+                landscape: true,
+                paper_size: Some(PaperSize::Letter),
+            }
+        );
+
+        for (name, value) in [
+            ("fit_to_height", "0"),
+            ("fit_to_height", "wide"),
+            ("landscape", "yes"),
+            ("paper_size", "B5"),
+        ] {
+            assert!(
+                matches!(
+                    setup.set_field(name, value),
+                    Err(LibreOfficeError::InvalidOption(_))
+                ),
+                "{}={}",
+                name,
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn test_worksheet_page_setup_is_inserted() {
+        let setup = PageSetup {
+            landscape: true,
+            paper_size: Some(PaperSize::A3),
+            ..fit_to_width()
+        };
+        let xml = rewrite_worksheet(WORKSHEET, &setup);
+
+        assert!(xml.contains(r#"<sheetPr><pageSetUpPr fitToPage="1"/></sheetPr><sheetData/>"#));
+        assert!(xml.contains(
+            r#"footer="0.3"/><pageSetup fitToWidth="1" fitToHeight="0" orientation="landscape" paperSize="8"/><drawing"#
+        ));
+    }
+
+    #[test]
+    fn test_worksheet_page_setup_is_updated() {
+        let worksheet = r#"<x:worksheet xmlns:x="urn:x"><x:sheetPr codeName="Sheet1"/><x:sheetData/><x:pageSetup paperSize="9" orientation="portrait" fitToHeight="3"/></x:worksheet>"#;
+        let xml = rewrite_worksheet(
+            worksheet,
+            &PageSetup {
+                fit_to_height: Some(2),
+                landscape: true,
+                ..PageSetup::default()
+            },
+        );
+
+        assert_eq!(
+            xml,
+            r#"<x:worksheet xmlns:x="urn:x"><x:sheetPr codeName="Sheet1"><x:pageSetUpPr fitToPage="1"/></x:sheetPr><x:sheetData/><x:pageSetup paperSize="9" orientation="landscape" fitToHeight="2" fitToWidth="0"/></x:worksheet>"#
+        );
+    }
+
+    #[test]
+    fn test_existing_sheet_properties_are_kept() {
+        let worksheet = r#"<worksheet><sheetPr><tabColor rgb="FF0000"/><pageSetUpPr autoPageBreaks="0"/></sheetPr><sheetData/></worksheet>"#;
+        let xml = rewrite_worksheet(worksheet, &fit_to_width());
+
+        assert!(xml.contains(
+            r#"<sheetPr><tabColor rgb="FF0000"/><pageSetUpPr autoPageBreaks="0" fitToPage="1"/></sheetPr>"#
+        ));
+        assert!(xml.ends_with(r#"<pageSetup fitToWidth="1" fitToHeight="0"/></worksheet>"#));
+    }
+
+    #[test]
+    fn test_ods_page_layouts() {
+        let styles = r#"<office:document-styles><style:page-layout style:name="pm1"><style:page-layout-properties fo:page-width="21.001cm" fo:page-height="29.7cm" style:scale-to="50%"/></style:page-layout><style:page-layout style:name="pm2"><style:page-layout-properties fo:page-width="8.5in" fo:page-height="11in"/></style:page-layout></office:document-styles>"#;
+
+        let xml = rewrite_ods_styles(
+            styles,
+            &PageSetup {
+                landscape: true,
+                ..fit_to_width()
+            },
+        );
+        assert!(xml.contains(
+            r#"<style:page-layout-properties fo:page-width="29.7cm" fo:page-height="21.001cm" style:scale-to-X="1" style:print-orientation="landscape"/>"#
+        ));
+        assert!(xml.contains(
+            r#"<style:page-layout-properties fo:page-width="11in" fo:page-height="8.5in" style:scale-to-X="1" style:print-orientation="landscape"/>"#
+        ));
+
+        let xml = rewrite_ods_styles(
+            styles,
+            &PageSetup {
+                paper_size: Some(PaperSize::A3),
+                ..PageSetup::default()
+            },
+        );
+        assert!(
+            xml.contains(r#"fo:page-width="297mm" fo:page-height="420mm" style:scale-to="50%""#)
+        );
+    }
+
+    fn read_entry(path: &Path, name: &str) -> String {
+        let mut archive = ZipArchive::new(File::open(path).unwrap()).unwrap();
+        let mut xml = String::new();
+        archive
+            .by_name(name)
+            .unwrap()
+            .read_to_string(&mut xml)
+            .unwrap();
+        xml
+    }
+
+    #[test]
+    fn test_apply_to_fixtures() {
+        let dir = tempfile::tempdir().unwrap();
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+
+        let xlsx = dir.path().join("document.xlsx");
+        std::fs::copy(fixtures.join("wide.xlsx"), &xlsx).unwrap();
+        apply(&xlsx, &fit_to_width()).unwrap();
+        assert!(read_entry(&xlsx, "xl/worksheets/sheet1.xml").contains(r#"fitToPage="1""#));
+        assert!(read_entry(&xlsx, "xl/workbook.xml").contains("<sheet "));
+
+        let ods = dir.path().join("document.ods");
+        std::fs::copy(fixtures.join("sample.ods"), &ods).unwrap();
+        apply(&ods, &fit_to_width()).unwrap();
+        assert!(read_entry(&ods, "styles.xml").contains(r#"style:scale-to-X="1""#));
+        // The mimetype entry stays first and uncompressed
+        let content = std::fs::read(&ods).unwrap();
+        assert_eq!(&content[30..38], b"mimetype");
+        assert_eq!(
+            crate::detect_filetype::detect_file_type_from_bytes(&content).file_type,
+            FileType::OpenDocumentSpreadsheet
+        );
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_apply_rejects_garbage() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("document.xlsx");
+        std::fs::write(&path, b"not a zip").unwrap();
+        assert!(matches!(
+            apply(&path, &fit_to_width()),
+            Err(LibreOfficeError::CorruptedInput(_))
+        ));
+    }
+}
@@ -0,0 +1,48 @@
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::panic::AssertUnwindSafe;
+
+use axum::{extract::Request, http::StatusCode, middleware::Next, response::Response};
+use futures_util::FutureExt;
+
+use crate::error::create_error_response_with_code;
+
+/// Logs panics with payload, location and backtrace through tracing, so they end up
+/// in the request's span (and its request_id) like any other event
+pub fn install_hook() {
+    std::panic::set_hook(Box::new(|info| {
+        let location = info
+            .location()
+            .map(|location| location.to_string())
+            .unwrap_or_default();
+        tracing::error!(
+            panic = payload_message(info.payload()),
+            location,
+            backtrace = %Backtrace::force_capture(),
+            "Panic"
+        );
+    }));
+}
+
+fn payload_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("non-string panic payload")
+}
+
+/// Turns a panic in a handler into a JSON 500 instead of dropping the connection
+pub async fn catch_panic(request: Request, next: Next) -> Response {
+    match AssertUnwindSafe(next.run(request)).catch_unwind().await {
+        Ok(response) => response,
+        Err(_) => {
+            metrics::counter!("http_panics_total").increment(1);
+            create_error_response_with_code(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "internal_panic",
+                "Internal server error",
+            )
+        }
+    }
+}
@@ -0,0 +1,37 @@
+//! Edits of PDF output after the export, loading the whole document with lopdf
+
+use lopdf::Document;
+
+use crate::{
+    error::{LibreOfficeError, Result},
+    libreoffice::ConvertedOutput,
+};
+
+/// Loads the PDF in `data`, applies `edit` and writes it back in place
+pub fn edit(
+    data: &mut ConvertedOutput,
+    edit: impl FnOnce(&mut Document) -> lopdf::Result<()>,
+) -> Result<()> {
+    match data {
+        ConvertedOutput::Bytes(bytes) => {
+            let mut document = Document::load_mem(bytes).map_err(pdf_error)?;
+            edit(&mut document).map_err(pdf_error)?;
+            bytes.clear();
+            document.save_to(bytes).map_err(LibreOfficeError::from_io)?;
+        }
+        ConvertedOutput::File { path, len } => {
+            let mut document = Document::load(&path).map_err(pdf_error)?;
+            edit(&mut document).map_err(pdf_error)?;
+            *len = document
+                .save(&path)
+                .and_then(|file| file.metadata())
+                .map_err(LibreOfficeError::from_io)?
+                .len();
+        }
+    }
+    Ok(())
+}
+
+fn pdf_error(error: lopdf::Error) -> LibreOfficeError {
+    LibreOfficeError::ConversionFailed(format!("could not rewrite the PDF output: {}", error))
+}
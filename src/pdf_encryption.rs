@@ -0,0 +1,191 @@
+//! Password protection of PDF output, added with AES-256 after the export. The
+//! passwords never leave the process: the EncryptFile option of LibreOffice's PDF
+//! export would put them on the soffice or unoconvert command line, which other
+//! processes can read through /proc.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use lopdf::encryption::crypt_filters::{Aes256CryptFilter, CryptFilter};
+use lopdf::{EncryptionState, EncryptionVersion, Permissions};
+
+use crate::{
+    checksum,
+    converter::parse_bool,
+    error::{LibreOfficeError, Result},
+    libreoffice::ConvertedOutput,
+    pdf,
+};
+
+/// Form fields of the encryption options
+pub const FIELDS: &[&str] = &[
+    "output_password",
+    "output_owner_password",
+    "disallow_printing",
+    "disallow_copying",
+];
+
+/// A password, left out of `Debug` output so options can be logged
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Password(String);
+
+impl fmt::Debug for Password {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Password(***)")
+    }
+}
+
+/// Protection requested for a PDF, nothing set leaves it unencrypted
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct PdfEncryption {
+    /// Needed to open the document, unset lets anyone open it
+    pub user_password: Option<Password>,
+    /// Lifts the restrictions, a random one is used when unset
+    pub owner_password: Option<Password>,
+    pub disallow_printing: bool,
+    pub disallow_copying: bool,
+}
+
+impl PdfEncryption {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Applies the form field `name`, one of [`FIELDS`]
+    pub fn set_field(&mut self, name: &str, value: &str) -> Result<()> {
+        let password = |value: &str| {
+            if value.is_empty() {
+                return Err(LibreOfficeError::InvalidOption(format!(
+                    "{} must not be empty",
+                    name
+                )));
+            }
+            Ok(Some(Password(value.to_string())))
+        };
+
+        match name {
+            "output_password" => self.user_password = password(value)?,
+            "output_owner_password" => self.owner_password = password(value)?,
+            "disallow_printing" => self.disallow_printing = parse_bool(name, value)?,
+            "disallow_copying" => self.disallow_copying = parse_bool(name, value)?,
+            _ => unreachable!("{} is not an encryption field", name),
+        }
+        Ok(())
+    }
+
+    fn permissions(&self) -> Permissions {
+        let mut permissions = Permissions::all();
+        if self.disallow_printing {
+            permissions.remove(Permissions::PRINTABLE | Permissions::PRINTABLE_IN_HIGH_QUALITY);
+        }
+        if self.disallow_copying {
+            // Assistive technology may still extract the text
+            permissions.remove(Permissions::COPYABLE);
+        }
+        permissions
+    }
+}
+
+/// Encrypts the PDF in `data` in place
+pub fn encrypt(data: &mut ConvertedOutput, encryption: &PdfEncryption) -> Result<()> {
+    let file_encryption_key = random_bytes::<32>()?;
+    let owner_password = match &encryption.owner_password {
+        Some(password) => password.0.clone(),
+        // Without an owner password anyone could lift the restrictions
+        None => checksum::hex(&random_bytes::<16>()?),
+    };
+    let user_password = encryption
+        .user_password
+        .as_ref()
+        .map_or("", |password| password.0.as_str());
+
+    pdf::edit(data, |document| {
+        let crypt_filter: Arc<dyn CryptFilter> = Arc::new(Aes256CryptFilter);
+        let state = EncryptionState::try_from(EncryptionVersion::V5 {
+            encrypt_metadata: true,
+            crypt_filters: BTreeMap::from([(b"StdCF".to_vec(), crypt_filter)]),
+            file_encryption_key: &file_encryption_key,
+            stream_filter: b"StdCF".to_vec(),
+            string_filter: b"StdCF".to_vec(),
+            owner_password: &owner_password,
+            user_password,
+            permissions: encryption.permissions(),
+        })?;
+        document.encrypt(&state)
+    })
+}
+
+fn random_bytes<const N: usize>() -> Result<[u8; N]> {
+    let mut bytes = [0; N];
+    getrandom::fill(&mut bytes).map_err(|e| {
+        LibreOfficeError::ConversionFailed(format!("no randomness for the PDF encryption: {}", e))
+    })?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::Document;
+    use std::path::Path;
+
+    fn encrypted(encryption: &PdfEncryption) -> Document {
+        let pdf = std::fs::read(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/metadata.pdf"),
+        )
+        .unwrap();
+        let mut data = ConvertedOutput::Bytes(pdf);
+
+        encrypt(&mut data, encryption).unwrap();
+
+        let ConvertedOutput::Bytes(bytes) = data else {
+            unreachable!()
+        };
+        Document::load_mem(&bytes).unwrap()
+    }
+
+    #[test]
+    fn test_user_password() {
+        let mut encryption = PdfEncryption::default();
+        encryption.set_field("output_password", "s3cret").unwrap();
+
+        let document = encrypted(&encryption);
+        assert!(document.is_encrypted());
+        assert!(document.authenticate_user_password("s3cret").is_ok());
+        assert!(document.authenticate_password("").is_err());
+        assert!(document.authenticate_password("wrong").is_err());
+    }
+
+    #[test]
+    fn test_permissions() {
+        let mut encryption = PdfEncryption::default();
+        encryption
+            .set_field("output_owner_password", "owner")
+            .unwrap();
+        encryption.set_field("disallow_printing", "true").unwrap();
+
+        let document = encrypted(&encryption);
+        assert!(document.authenticate_user_password("").is_ok());
+        assert!(document.authenticate_owner_password("owner").is_ok());
+        let permissions = document.encryption_state.as_ref().unwrap().permissions();
+        assert!(!permissions.contains(Permissions::PRINTABLE));
+        assert!(permissions.contains(Permissions::COPYABLE));
+
+        // Opens without a password, lopdf decrypts it while loading
+        assert_eq!(document.get_pages().len(), 1);
+    }
+
+    #[test]
+    fn test_fields() {
+        let mut encryption = PdfEncryption::default();
+        assert!(encryption.is_empty());
+        assert!(matches!(
+            encryption.set_field("output_password", ""),
+            Err(LibreOfficeError::InvalidOption(_))
+        ));
+        encryption.set_field("output_password", "s3cret").unwrap();
+        assert!(!encryption.is_empty());
+        assert!(!format!("{:?}", encryption).contains("s3cret"));
+    }
+}
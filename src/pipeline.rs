@@ -0,0 +1,225 @@
+//! The conversion behind `/convert`: sniffing, policy, converter and audit trail,
+//! without HTTP so the CLI runs the very same steps
+
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use crate::{
+    audit::AuditRecord,
+    converter::ConversionOptions,
+    detect_filetype::{Confidence, DetectedType},
+    error::{LibreOfficeError, Result},
+    filename,
+    formats::{InputFormat, OutputFormat, SANITIZE_FORMATS},
+    libreoffice::{ConversionOutput, InputFile},
+    state::AppState,
+    usage,
+};
+
+/// Format label of errors raised before the format is known
+const UNKNOWN_FORMAT: &str = "unknown";
+
+/// A successful conversion
+pub struct Conversion {
+    pub output: ConversionOutput,
+    pub detected: DetectedType,
+    /// Filename of the input without its extension
+    pub input_stem: String,
+    pub input_format: InputFormat,
+    pub output_format: OutputFormat,
+    pub input_bytes: u64,
+    /// Time from handing the input to the converter to its output
+    pub duration: Duration,
+}
+
+/// Converts `input_file` to `output_format`, recording the outcome in the metrics,
+/// the recent conversions, the usage and the audit trail. With `sanitize` the
+/// output format may be left out, it is the input's own.
+pub async fn convert(
+    state: &AppState,
+    client_ip: Option<IpAddr>,
+    input_file: InputFile,
+    input_filename: &str,
+    output_format: Option<&str>,
+    options: &ConversionOptions,
+) -> Result<Conversion> {
+    let input_bytes = input_file.len();
+    let result = run(
+        state,
+        client_ip,
+        input_file,
+        input_filename,
+        output_format,
+        options,
+    )
+    .await;
+    match &result {
+        Ok(conversion) => state.usage().record_success(
+            usage::ANONYMOUS,
+            input_bytes,
+            conversion.output.primary.data.len(),
+            conversion
+                .output
+                .conversion_duration
+                .unwrap_or(conversion.duration),
+        ),
+        Err(_) => state.usage().record_failure(usage::ANONYMOUS, input_bytes),
+    }
+    result
+}
+
+async fn run(
+    state: &AppState,
+    client_ip: Option<IpAddr>,
+    input_file: InputFile,
+    input_filename: &str,
+    output_format: Option<&str>,
+    options: &ConversionOptions,
+) -> Result<Conversion> {
+    let requested = match output_format {
+        Some(output_format) => {
+            tracing::Span::current().record("output_format", output_format);
+            Some(output_format.parse::<OutputFormat>().inspect_err(|e| {
+                e.record(UNKNOWN_FORMAT, UNKNOWN_FORMAT);
+            })?)
+        }
+        None => None,
+    };
+    let output_label = requested
+        .as_ref()
+        .map_or(UNKNOWN_FORMAT, OutputFormat::as_str);
+
+    let detected = input_file.detect_file_type().await.map_err(|e| {
+        let e = LibreOfficeError::from_io(e);
+        e.record(UNKNOWN_FORMAT, output_label);
+        e
+    })?;
+
+    // Get file extension from input filename, falling back to the sniffed type when
+    // the filename has none or an unknown one
+    let (input_stem, input_extension) = filename::split_extension(input_filename);
+    let input_format = match input_extension.parse::<InputFormat>() {
+        Ok(format) => format,
+        Err(e) => match detected.extension.parse::<InputFormat>() {
+            Ok(format) if detected.confidence != Confidence::Unknown => {
+                tracing::debug!("Using detected format {} for {:?}", format, input_filename);
+                format
+            }
+            _ => {
+                e.record(UNKNOWN_FORMAT, output_label);
+                return Err(e);
+            }
+        },
+    };
+    tracing::Span::current().record("input_format", input_format.as_str());
+
+    let output_format = target_format(requested.as_ref(), &input_format, options)
+        .inspect_err(|e| e.record(input_format.as_str(), output_label))?;
+    tracing::Span::current().record("output_format", output_format.as_str());
+
+    // Renaming an upload mustn't get its content past the policy
+    let mut inputs = vec![input_format.as_str()];
+    if detected.confidence != Confidence::Unknown {
+        inputs.push(detected.extension);
+    }
+    if let Err(e) = state.policy().check(&inputs, &output_format) {
+        tracing::debug!("Refusing conversion: {}", e);
+        e.record(input_format.as_str(), output_format.as_str());
+        return Err(e);
+    }
+
+    let input_bytes = input_file.len();
+    let started = Instant::now();
+    let result = state
+        .converter()
+        .convert(input_file, &input_format, &output_format, options)
+        .await;
+    let duration = started.elapsed();
+    state.record_conversion(
+        input_filename,
+        input_format.as_str(),
+        output_format.as_str(),
+        duration,
+        result.as_ref().map_or_else(|e| e.code(), |_| "success"),
+    );
+
+    if let Some(auditor) = state.auditor() {
+        let record = AuditRecord {
+            input_bytes,
+            output_bytes: result.as_ref().ok().map(|output| output.primary.data.len()),
+            duration_ms: duration.as_millis() as u64,
+            outcome: result.as_ref().map_or_else(|e| e.code(), |_| "success"),
+            ..AuditRecord::new(
+                client_ip,
+                input_filename,
+                input_format.as_str(),
+                output_format.as_str(),
+            )
+        };
+        // Failing closed, no output is handed out without a record of it
+        if let Err(e) = auditor.record(record).await {
+            e.record(input_format.as_str(), output_format.as_str());
+            return Err(e);
+        }
+    }
+
+    match result {
+        Ok(output) => {
+            // Queue wait is estimated separately, from the queue at the time
+            state.latency().record(
+                input_format.as_str(),
+                output_format.as_str(),
+                input_bytes,
+                output.conversion_duration.unwrap_or(duration),
+            );
+            tracing::debug!(
+                "Conversion completed successfully, produced {} and {} auxiliary file(s)",
+                output.primary.name,
+                output.auxiliary.len()
+            );
+            Ok(Conversion {
+                output,
+                detected,
+                input_stem: input_stem.to_string(),
+                input_format,
+                output_format,
+                input_bytes,
+                duration,
+            })
+        }
+        Err(e) => {
+            tracing::error!("Conversion failed: {}", e);
+            e.record(input_format.as_str(), output_format.as_str());
+            Err(e)
+        }
+    }
+}
+
+/// Format to convert to: the requested one, or the input's own to `sanitize` it
+fn target_format(
+    requested: Option<&OutputFormat>,
+    input_format: &InputFormat,
+    options: &ConversionOptions,
+) -> Result<OutputFormat> {
+    if !options.sanitize {
+        return requested.cloned().ok_or_else(|| {
+            LibreOfficeError::InvalidOption(
+                "output_format is required unless sanitize=true".to_string(),
+            )
+        });
+    }
+    if !SANITIZE_FORMATS.contains(&input_format.as_str()) {
+        return Err(LibreOfficeError::InvalidOption(format!(
+            "sanitize only applies to {} inputs",
+            SANITIZE_FORMATS.join(", ")
+        )));
+    }
+    let own = input_format.as_str().parse::<OutputFormat>()?;
+    match requested {
+        Some(requested) if *requested != own => Err(LibreOfficeError::InvalidOption(format!(
+            "sanitize re-saves {} as {}, not {}",
+            input_format, own, requested
+        ))),
+        _ => Ok(own),
+    }
+}
@@ -0,0 +1,433 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tokio::sync::oneshot;
+
+use crate::config::{self, Config};
+
+/// LibreOffice runs one conversion at a time
+pub const MAX_PARALLEL_CONVERSIONS: usize = 1;
+
+/// Queue a conversion waits in for the single LibreOffice slot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lane {
+    /// Inputs below `INTERACTIVE_MAX_BYTES`
+    Interactive,
+    /// Everything else
+    Bulk,
+}
+
+impl Lane {
+    /// Lane for an input of `len` bytes
+    pub fn for_input_len(len: u64, config: &Config) -> Self {
+        if len < config.interactive_max_bytes {
+            Lane::Interactive
+        } else {
+            Lane::Bulk
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Lane::Interactive => "interactive",
+            Lane::Bulk => "bulk",
+        }
+    }
+}
+
+/// Current load of the scheduler
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct QueueSnapshot {
+    /// Conversions holding the LibreOffice slot
+    pub running: usize,
+    pub interactive_waiting: usize,
+    pub bulk_waiting: usize,
+}
+
+/// How a conversion was scheduled, reported in response headers
+#[derive(Debug, Clone, Copy)]
+pub struct QueueStats {
+    pub lane: Lane,
+    pub wait: Duration,
+}
+
+#[derive(Default)]
+struct State {
+    busy: bool,
+    interactive: VecDeque<oneshot::Sender<()>>,
+    bulk: VecDeque<oneshot::Sender<()>>,
+    // Interactive grants in a row while bulk conversions were waiting
+    interactive_streak: u32,
+}
+
+/// Hands out the LibreOffice slot, preferring interactive conversions without
+/// starving bulk ones: after `weight` interactive grants a waiting bulk
+/// conversion goes next
+pub struct Scheduler {
+    state: Mutex<State>,
+    weight: u32,
+}
+
+impl Scheduler {
+    pub fn new(weight: u32) -> Self {
+        Self {
+            state: Mutex::new(State::default()),
+            weight: weight.max(1),
+        }
+    }
+
+    /// Waits until `lane` gets the slot, which is held until the permit is dropped
+    pub async fn acquire(&self, lane: Lane) -> Permit<'_> {
+        let started = Instant::now();
+        let receiver = {
+            let mut state = self.state.lock().unwrap();
+            if !state.busy {
+                state.busy = true;
+                None
+            } else {
+                let (sender, receiver) = oneshot::channel();
+                match lane {
+                    Lane::Interactive => state.interactive.push_back(sender),
+                    Lane::Bulk => state.bulk.push_back(sender),
+                }
+                Some(receiver)
+            }
+        };
+
+        if let Some(receiver) = receiver {
+            let mut waiting = Waiting {
+                scheduler: self,
+                receiver,
+                granted: false,
+            };
+            // The sender is only dropped after a successful send
+            let _ = (&mut waiting.receiver).await;
+            waiting.granted = true;
+        }
+
+        let stats = QueueStats {
+            lane,
+            wait: started.elapsed(),
+        };
+        metrics::histogram!("conversion_queue_wait_seconds", "lane" => lane.as_str())
+            .record(stats.wait.as_secs_f64());
+
+        Permit {
+            scheduler: self,
+            stats,
+        }
+    }
+
+    /// Conversions running and waiting per lane, ignoring waiters that went away
+    pub fn snapshot(&self) -> QueueSnapshot {
+        let state = self.state.lock().unwrap();
+        let waiting = |queue: &VecDeque<oneshot::Sender<()>>| {
+            queue.iter().filter(|sender| !sender.is_closed()).count()
+        };
+
+        QueueSnapshot {
+            running: usize::from(state.busy),
+            interactive_waiting: waiting(&state.interactive),
+            bulk_waiting: waiting(&state.bulk),
+        }
+    }
+
+    /// Passes the slot to the next waiter, or marks it free
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let prefer_bulk =
+                state.interactive_streak >= self.weight || state.interactive.is_empty();
+            let next = if prefer_bulk {
+                state.bulk.pop_front().map(|sender| (sender, Lane::Bulk))
+            } else {
+                None
+            }
+            .or_else(|| {
+                state
+                    .interactive
+                    .pop_front()
+                    .map(|sender| (sender, Lane::Interactive))
+            });
+
+            let Some((sender, lane)) = next else {
+                state.busy = false;
+                state.interactive_streak = 0;
+                return;
+            };
+
+            // Waiters that went away in the meantime are skipped
+            if sender.send(()).is_ok() {
+                state.interactive_streak = match lane {
+                    Lane::Interactive if !state.bulk.is_empty() => state.interactive_streak + 1,
+                    _ => 0,
+                };
+                return;
+            }
+        }
+    }
+}
+
+/// Queued request, handing a grant that arrives after it was dropped back on
+struct Waiting<'a> {
+    scheduler: &'a Scheduler,
+    receiver: oneshot::Receiver<()>,
+    granted: bool,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        if !self.granted {
+            self.receiver.close();
+            if self.receiver.try_recv().is_ok() {
+                self.scheduler.release();
+            }
+        }
+    }
+}
+
+/// Exclusive use of LibreOffice, passed on when dropped
+pub struct Permit<'a> {
+    scheduler: &'a Scheduler,
+    pub stats: QueueStats,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.scheduler.release();
+    }
+}
+
+static SCHEDULER: OnceLock<Arc<Scheduler>> = OnceLock::new();
+
+/// Scheduler of [`LibreOfficeConverter::global`](crate::LibreOfficeConverter::global),
+/// an [`AppState`](crate::AppState) builds one of its own
+pub fn scheduler() -> &'static Arc<Scheduler> {
+    SCHEDULER.get_or_init(|| Arc::new(Scheduler::new(config::get().interactive_weight)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_interactive_jumps_ahead_of_bulk() {
+        let scheduler = Arc::new(Scheduler::new(4));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let running = scheduler.acquire(Lane::Bulk).await;
+
+        let mut handles = Vec::new();
+        for lane in [Lane::Bulk, Lane::Interactive, Lane::Interactive] {
+            let scheduler = scheduler.clone();
+            let order = order.clone();
+            handles.push(tokio::spawn(async move {
+                let _permit = scheduler.acquire(lane).await;
+                order.lock().unwrap().push(lane);
+            }));
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        drop(running);
+        for handle in handles {
+            handle.await.unwrap();
+        }
+        assert_eq!(
+            *order.lock().unwrap(),
+            vec![Lane::Interactive, Lane::Interactive, Lane::Bulk]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bulk_is_not_starved() {
+        let scheduler = Arc::new(Scheduler::new(2));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let running = scheduler.acquire(Lane::Interactive).await;
+
+        let mut handles = Vec::new();
+        for lane in [
+            Lane::Bulk,
+            Lane::Interactive,
+            Lane::Interactive,
+            Lane::Interactive,
+        ] {
+            let scheduler = scheduler.clone();
+            let order = order.clone();
+            handles.push(tokio::spawn(async move {
+                let _permit = scheduler.acquire(lane).await;
+                order.lock().unwrap().push(lane);
+            }));
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        drop(running);
+        for handle in handles {
+            handle.await.unwrap();
+        }
+        assert_eq!(
+            *order.lock().unwrap(),
+            vec![
+                Lane::Interactive,
+                Lane::Interactive,
+                Lane::Bulk,
+                Lane::Interactive
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_counts_waiters() {
+        let scheduler = Arc::new(Scheduler::new(4));
+        assert_eq!(scheduler.snapshot().running, 0);
+        let running = scheduler.acquire(Lane::Interactive).await;
+
+        let waiter = {
+            let scheduler = scheduler.clone();
+            tokio::spawn(async move {
+                let _permit = scheduler.acquire(Lane::Bulk).await;
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let snapshot = scheduler.snapshot();
+        assert_eq!(snapshot.running, 1);
+        assert_eq!(snapshot.interactive_waiting, 0);
+        assert_eq!(snapshot.bulk_waiting, 1);
+
+        drop(running);
+        waiter.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dropped_waiter_does_not_hold_slot() {
+        let scheduler = Arc::new(Scheduler::new(4));
+        let running = scheduler.acquire(Lane::Interactive).await;
+
+        let abandoned = {
+            let scheduler = scheduler.clone();
+            tokio::spawn(async move {
+                let _permit = scheduler.acquire(Lane::Interactive).await;
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        abandoned.abort();
+        drop(running);
+
+        tokio::time::timeout(Duration::from_secs(1), scheduler.acquire(Lane::Bulk))
+            .await
+            .expect("slot should be free");
+    }
+
+    #[tokio::test]
+    async fn test_libreoffice_lock_initialization() {
+        // Test that the lock can be initialized and acquired
+        let _permit = scheduler().acquire(Lane::Interactive).await;
+        // If we get here, the lock works
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_lock_access() {
+        // Test that only one task can hold the lock at a time
+        let counter = Arc::new(AtomicUsize::new(0));
+        let mut handles = vec![];
+
+        for _ in 0..5 {
+            let counter_clone = counter.clone();
+            let handle = tokio::spawn(async move {
+                let _lock = scheduler().acquire(Lane::Interactive).await;
+
+                // Increment counter and sleep to simulate work
+                let current = counter_clone.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                let after_sleep = counter_clone.load(Ordering::SeqCst);
+
+                // If locking works correctly, no other task should have incremented
+                // the counter while we were sleeping
+                assert_eq!(current + 1, after_sleep);
+            });
+            handles.push(handle);
+        }
+
+        // Wait for all tasks to complete
+        for handle in handles {
+            handle.await.expect("Task should complete successfully");
+        }
+
+        // All tasks should have completed
+        assert_eq!(counter.load(Ordering::SeqCst), 5);
+    }
+
+    #[tokio::test]
+    async fn test_lock_released_on_drop() {
+        // Test that the lock is properly released when the guard is dropped
+        {
+            let _guard = scheduler().acquire(Lane::Interactive).await;
+            // Lock is held here
+        }
+        // Lock should be released here
+
+        // We should be able to acquire it again immediately
+        let _guard2 = scheduler().acquire(Lane::Interactive).await;
+    }
+
+    #[tokio::test]
+    async fn test_serial_execution_timing() {
+        // Test that tasks execute serially, not concurrently
+        let start_time = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let end_time = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut handles = vec![];
+
+        for i in 0..3 {
+            let start_time_clone = start_time.clone();
+            let end_time_clone = end_time.clone();
+
+            let handle = tokio::spawn(async move {
+                let _lock = scheduler().acquire(Lane::Interactive).await;
+
+                // Record start time
+                {
+                    let mut times = start_time_clone.lock().unwrap();
+                    times.push((i, Instant::now()));
+                }
+
+                // Simulate work
+                tokio::time::sleep(Duration::from_millis(50)).await;
+
+                // Record end time
+                {
+                    let mut times = end_time_clone.lock().unwrap();
+                    times.push((i, Instant::now()));
+                }
+            });
+            handles.push(handle);
+        }
+
+        // Wait for all tasks to complete
+        for handle in handles {
+            handle.await.expect("Task should complete successfully");
+        }
+
+        let start_times = start_time.lock().unwrap();
+        let end_times = end_time.lock().unwrap();
+
+        // Verify that tasks executed serially (no overlap)
+        assert_eq!(start_times.len(), 3);
+        assert_eq!(end_times.len(), 3);
+
+        // Check that each task's start time is after the previous task's end time
+        // (with some tolerance for timing variations)
+        let mut sorted_starts: Vec<_> = start_times.iter().collect();
+        let mut sorted_ends: Vec<_> = end_times.iter().collect();
+
+        sorted_starts.sort_by_key(|(_, time)| *time);
+        sorted_ends.sort_by_key(|(_, time)| *time);
+
+        // The end of each task should be before the start of the next task
+        for i in 0..sorted_ends.len() - 1 {
+            assert!(sorted_ends[i].1 <= sorted_starts[i + 1].1);
+        }
+    }
+}
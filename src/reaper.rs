@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// How often the reaper looks for leftover LibreOffice processes
+const REAP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A LibreOffice process group launched for a conversion
+struct TrackedProcess {
+    started: Instant,
+    finished: bool,
+}
+
+// Process groups of launched conversions, keyed by the group leader PID
+static REGISTRY: OnceLock<Mutex<HashMap<u32, TrackedProcess>>> = OnceLock::new();
+
+fn registry() -> &'static Mutex<HashMap<u32, TrackedProcess>> {
+    REGISTRY.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Registers the process group led by `pid` as belonging to a running conversion
+pub fn register(pid: u32) {
+    registry().lock().unwrap().insert(
+        pid,
+        TrackedProcess {
+            started: Instant::now(),
+            finished: false,
+        },
+    );
+}
+
+/// Marks the conversion owning the process group as done, any survivors are orphans
+pub fn mark_finished(pid: u32) {
+    if let Some(process) = registry().lock().unwrap().get_mut(&pid) {
+        process.finished = true;
+    }
+}
+
+/// Returns true while any process of the group is still alive
+pub fn group_alive(pgid: u32) -> bool {
+    // Signal 0 only checks whether the group can be signalled
+    unsafe { libc::kill(-(pgid as libc::pid_t), 0) == 0 }
+}
+
+/// Sends SIGKILL to every process of the group
+pub fn kill_group(pgid: u32) {
+    unsafe {
+        libc::kill(-(pgid as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+/// Tracks a conversion's process group and kills it when dropped before completion,
+/// so abandoned conversions (client gone, timeout) don't leave LibreOffice running
+pub struct ProcessGroupGuard {
+    pgid: u32,
+    armed: bool,
+}
+
+impl ProcessGroupGuard {
+    pub fn new(pgid: u32) -> Self {
+        register(pgid);
+        Self { pgid, armed: true }
+    }
+
+    /// Called once the process exited on its own; leftovers are left to the reaper
+    pub fn disarm(mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for ProcessGroupGuard {
+    fn drop(&mut self) {
+        if self.armed && group_alive(self.pgid) {
+            tracing::warn!(
+                "Conversion abandoned, killing LibreOffice process group {}",
+                self.pgid
+            );
+            kill_group(self.pgid);
+            metrics::counter!("libreoffice_conversions_cancelled_total").increment(1);
+        }
+        mark_finished(self.pgid);
+    }
+}
+
+/// Kills process groups whose conversion finished or which outlived `max_age`.
+/// Returns the number of groups that were killed.
+fn reap_once(max_age: Duration) -> usize {
+    let mut registry = registry().lock().unwrap();
+    let mut reaped = 0;
+
+    registry.retain(|&pgid, process| {
+        if !group_alive(pgid) {
+            return !process.finished && process.started.elapsed() <= max_age;
+        }
+
+        let age = process.started.elapsed();
+        if process.finished || age > max_age {
+            tracing::warn!(
+                "Reaping orphaned LibreOffice process group {} (age {:?}, conversion finished: {})",
+                pgid,
+                age,
+                process.finished
+            );
+            kill_group(pgid);
+            metrics::counter!("libreoffice_orphans_reaped_total").increment(1);
+            reaped += 1;
+            return false;
+        }
+
+        true
+    });
+
+    reaped
+}
+
+/// Spawns the background task periodically reaping orphaned LibreOffice processes,
+/// those of conversions running longer than `conversion_timeout` included
+pub fn spawn_reaper(conversion_timeout: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(REAP_INTERVAL);
+        loop {
+            interval.tick().await;
+            // Give conversions a grace period on top of their own timeout
+            reap_once(conversion_timeout + REAP_INTERVAL);
+        }
+    });
+}
+
+/// Removes `.~lock.*` files LibreOffice left behind in the work directory
+pub fn remove_stale_lock_files(dir: &Path) -> usize {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+
+    let mut removed = 0;
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let is_lock_file = entry.file_name().to_string_lossy().starts_with(".~lock.");
+        if is_lock_file && std::fs::remove_file(entry.path()).is_ok() {
+            tracing::info!("Removed stale lock file {:?}", entry.path());
+            removed += 1;
+        }
+    }
+
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::CommandExt;
+    use std::process::Command;
+
+    #[test]
+    fn test_finished_conversion_group_is_reaped() {
+        let mut child = Command::new("sleep")
+            .arg("30")
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+
+        register(pid);
+        assert_eq!(reap_once(Duration::from_secs(600)), 0);
+        assert!(group_alive(pid));
+
+        mark_finished(pid);
+        assert_eq!(reap_once(Duration::from_secs(600)), 1);
+
+        child.wait().unwrap();
+        assert!(!group_alive(pid));
+        assert!(!registry().lock().unwrap().contains_key(&pid));
+    }
+
+    #[test]
+    fn test_remove_stale_lock_files() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(".~lock.document.docx#"), b"").unwrap();
+        std::fs::write(dir.path().join("document.docx"), b"").unwrap();
+
+        assert_eq!(remove_stale_lock_files(dir.path()), 1);
+        assert!(dir.path().join("document.docx").exists());
+    }
+}
@@ -0,0 +1,128 @@
+//! Salvaging of zip based documents (OOXML, ODF) whose central directory is
+//! missing or cut short, e.g. by an interrupted upload or download. Headless
+//! LibreOffice declines its repair prompt, so the package is rebuilt from the
+//! local header in front of each entry, dropping the entry the file ends in.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, Write};
+use std::path::Path;
+
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter, read::read_zipfile_from_stream};
+
+use crate::error::{LibreOfficeError, Result};
+
+/// Rebuilds the package at `path` in place when it doesn't open as a zip archive
+/// but starts with readable entries. Returns whether it was rebuilt.
+pub fn rebuild_package(path: &Path) -> Result<bool> {
+    let mut input = BufReader::new(File::open(path).map_err(LibreOfficeError::from_io)?);
+    if ZipArchive::new(&mut input).is_ok() {
+        return Ok(false);
+    }
+    input.rewind().map_err(LibreOfficeError::from_io)?;
+
+    let rebuilt = path.with_extension("rebuilt");
+    let salvaged = salvage_entries(
+        &mut input,
+        File::create(&rebuilt).map_err(LibreOfficeError::from_io)?,
+    );
+    match salvaged {
+        Ok(0) | Err(_) => {
+            let _ = std::fs::remove_file(&rebuilt);
+            salvaged.map(|_| false)
+        }
+        Ok(entries) => {
+            tracing::info!("Rebuilt damaged package from {} entries", entries);
+            std::fs::rename(&rebuilt, path).map_err(LibreOfficeError::from_io)?;
+            Ok(true)
+        }
+    }
+}
+
+/// Copies the entries readable in full from `input` into a new archive, in their
+/// order so ODF's `mimetype` stays first. Returns the number of entries copied.
+fn salvage_entries(input: &mut impl Read, output: impl Write + Seek) -> Result<usize> {
+    let mut writer = ZipWriter::new(BufWriter::new(output));
+    let mut entries = 0;
+
+    // Stops at the central directory, or at the first entry that is cut short or
+    // fails its checksum
+    while let Ok(Some(mut entry)) = read_zipfile_from_stream(input) {
+        let mut data = Vec::with_capacity(entry.size() as usize);
+        if entry.read_to_end(&mut data).is_err() {
+            break;
+        }
+        let method = match entry.compression() {
+            CompressionMethod::Stored => CompressionMethod::Stored,
+            _ => CompressionMethod::Deflated,
+        };
+        let name = entry.name().to_string();
+        if entry.is_dir() {
+            writer.add_directory(name, SimpleFileOptions::default())
+        } else {
+            writer.start_file(
+                name,
+                SimpleFileOptions::default().compression_method(method),
+            )
+        }
+        .map_err(zip_error)?;
+        writer.write_all(&data).map_err(LibreOfficeError::from_io)?;
+        entries += 1;
+    }
+
+    writer.finish().map_err(zip_error)?;
+    Ok(entries)
+}
+
+fn zip_error(error: zip::result::ZipError) -> LibreOfficeError {
+    LibreOfficeError::ConversionFailed(format!("could not rebuild the document: {}", error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name)
+    }
+
+    fn copy_fixture(dir: &Path, name: &str) -> std::path::PathBuf {
+        let path = dir.join(name);
+        std::fs::copy(fixture(name), &path).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_rebuilds_truncated_docx() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = copy_fixture(dir.path(), "truncated.docx");
+        assert!(ZipArchive::new(File::open(&path).unwrap()).is_err());
+
+        assert!(rebuild_package(&path).unwrap());
+
+        let mut archive = ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        let mut document = String::new();
+        archive
+            .by_name("word/document.xml")
+            .unwrap()
+            .read_to_string(&mut document)
+            .unwrap();
+        assert!(document.contains("Recovered paragraph"));
+        assert!(archive.by_name("[Content_Types].xml").is_ok());
+    }
+
+    #[test]
+    fn test_leaves_other_files_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["sample.docx", "sample.txt"] {
+            let path = copy_fixture(dir.path(), name);
+            assert!(!rebuild_package(&path).unwrap(), "{}", name);
+            assert_eq!(
+                std::fs::read(&path).unwrap(),
+                std::fs::read(fixture(name)).unwrap()
+            );
+        }
+    }
+}
@@ -0,0 +1,24 @@
+use axum::{extract::Request, middleware::Next, response::Response};
+
+use crate::headers::REQUEST_ID_HEADER;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// ID of the request being handled by the current task
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(String::clone).ok()
+}
+
+/// Makes the request ID available to [`current`] while the request is handled
+pub async fn scope(request: Request, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+
+    REQUEST_ID.scope(request_id, next.run(request)).await
+}
@@ -0,0 +1,256 @@
+//! Outputs of recent conversions, kept for `RESULT_RETENTION_SECS` so a client
+//! that lost the response can download it again from `/results/{id}`
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::body::{Body, Bytes, HttpBody};
+use hyper::{
+    Response, StatusCode, header,
+    header::{HeaderName, HeaderValue},
+};
+
+use crate::{
+    checksum,
+    config::Config,
+    error::LibreOfficeError,
+    headers::{CONTENT_SHA256_HEADER, CONVERSION_ID_HEADER, DIGEST_HEADER},
+};
+
+/// Ids of expired or evicted results remembered to answer 410 instead of 404
+const MAX_GONE_IDS: usize = 4096;
+
+#[derive(Debug, Clone)]
+pub struct StoredResult {
+    pub content_type: Option<HeaderValue>,
+    pub content_disposition: Option<HeaderValue>,
+    /// Checksum headers of the response, see [`checksum::headers`]
+    pub checksum: Vec<(HeaderName, HeaderValue)>,
+    pub data: Bytes,
+}
+
+/// Outcome of looking up a result
+#[derive(Debug)]
+pub enum Lookup {
+    Found(StoredResult),
+    /// Kept once, but expired or evicted since
+    Gone,
+    Missing,
+}
+
+/// Where kept outputs live
+pub trait ResultStore: Send + Sync {
+    /// Keeps `result` under `id`, evicting the oldest results to stay within the
+    /// store's size
+    fn put(&self, id: String, result: StoredResult);
+
+    fn get(&self, id: &str) -> Lookup;
+}
+
+/// Keeps results in memory for `retention`, `max_bytes` in total
+pub struct MemoryStore {
+    retention: Duration,
+    max_bytes: u64,
+    entries: Mutex<Entries>,
+}
+
+#[derive(Default)]
+struct Entries {
+    /// Oldest first
+    results: VecDeque<(String, Instant, StoredResult)>,
+    bytes: u64,
+    gone: VecDeque<String>,
+}
+
+impl Entries {
+    fn remove_oldest(&mut self) {
+        if let Some((id, _, result)) = self.results.pop_front() {
+            self.bytes -= result.data.len() as u64;
+            if self.gone.len() == MAX_GONE_IDS {
+                self.gone.pop_front();
+            }
+            self.gone.push_back(id);
+        }
+    }
+
+    fn expire(&mut self, retention: Duration) {
+        while self
+            .results
+            .front()
+            .is_some_and(|(_, kept, _)| kept.elapsed() >= retention)
+        {
+            self.remove_oldest();
+        }
+    }
+}
+
+impl MemoryStore {
+    pub fn new(retention: Duration, max_bytes: u64) -> Self {
+        Self {
+            retention,
+            max_bytes,
+            entries: Mutex::default(),
+        }
+    }
+}
+
+impl ResultStore for MemoryStore {
+    fn put(&self, id: String, result: StoredResult) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.expire(self.retention);
+        entries.bytes += result.data.len() as u64;
+        entries.results.push_back((id, Instant::now(), result));
+        while entries.bytes > self.max_bytes {
+            entries.remove_oldest();
+        }
+    }
+
+    fn get(&self, id: &str) -> Lookup {
+        let mut entries = self.entries.lock().unwrap();
+        entries.expire(self.retention);
+        if let Some((_, _, result)) = entries.results.iter().find(|(kept, _, _)| kept == id) {
+            return Lookup::Found(result.clone());
+        }
+        if entries.gone.iter().any(|gone| gone == id) {
+            return Lookup::Gone;
+        }
+        Lookup::Missing
+    }
+}
+
+/// Hands successful conversion responses to the store
+pub struct Results {
+    store: Arc<dyn ResultStore>,
+    max_bytes: u64,
+}
+
+impl Results {
+    /// `max_bytes` is the largest output kept
+    pub fn new(store: Arc<dyn ResultStore>, max_bytes: u64) -> Self {
+        Self { store, max_bytes }
+    }
+
+    /// Results kept in memory, none when `RESULT_RETENTION_SECS` is unset
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let retention = config.result_retention?;
+        let store = MemoryStore::new(retention, config.result_store_max_bytes);
+        Some(Self::new(Arc::new(store), config.result_store_max_bytes))
+    }
+
+    /// Keeps the output a successful response carries, tagging the response with
+    /// its [`CONVERSION_ID_HEADER`]. Outputs larger than the store go out untagged.
+    pub async fn keep(&self, response: Response<Body>) -> Response<Body> {
+        if response.status() != StatusCode::OK {
+            return response;
+        }
+        let len = response
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|len| len.to_str().ok()?.parse().ok())
+            .or_else(|| response.body().size_hint().exact());
+        let Some(len) = len.filter(|len| *len <= self.max_bytes) else {
+            tracing::debug!("Output too large to be kept for download");
+            return response;
+        };
+
+        let (mut parts, body) = response.into_parts();
+        let data = match axum::body::to_bytes(body, len as usize).await {
+            Ok(data) => data,
+            Err(e) => return LibreOfficeError::from_io(std::io::Error::other(e)).into(),
+        };
+        match new_id() {
+            Ok(id) => {
+                self.store.put(
+                    id.clone(),
+                    StoredResult {
+                        content_type: parts.headers.get(header::CONTENT_TYPE).cloned(),
+                        content_disposition: parts
+                            .headers
+                            .get(header::CONTENT_DISPOSITION)
+                            .cloned(),
+                        checksum: [CONTENT_SHA256_HEADER, DIGEST_HEADER]
+                            .into_iter()
+                            .filter_map(|name| {
+                                let value = parts.headers.get(name)?.clone();
+                                Some((HeaderName::from_static(name), value))
+                            })
+                            .collect(),
+                        data: data.clone(),
+                    },
+                );
+                if let Ok(id) = HeaderValue::from_str(&id) {
+                    parts.headers.insert(CONVERSION_ID_HEADER, id);
+                }
+            }
+            Err(e) => tracing::warn!("Could not generate a conversion id: {}", e),
+        }
+        Response::from_parts(parts, Body::from(data))
+    }
+
+    pub fn get(&self, id: &str) -> Lookup {
+        self.store.get(id)
+    }
+}
+
+/// 128 random bits as hex, unguessable as they are the only credential of a result
+fn new_id() -> Result<String, getrandom::Error> {
+    let mut bytes = [0; 16];
+    getrandom::fill(&mut bytes)?;
+    Ok(checksum::hex(&bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(data: &'static [u8]) -> StoredResult {
+        StoredResult {
+            content_type: None,
+            content_disposition: None,
+            checksum: Vec::new(),
+            data: Bytes::from_static(data),
+        }
+    }
+
+    #[test]
+    fn test_oldest_results_are_evicted_beyond_the_size() {
+        let store = MemoryStore::new(Duration::from_secs(60), 10);
+        store.put("a".to_string(), result(b"12345"));
+        store.put("b".to_string(), result(b"12345"));
+        assert!(matches!(store.get("a"), Lookup::Found(_)));
+
+        store.put("c".to_string(), result(b"1"));
+        assert!(matches!(store.get("a"), Lookup::Gone));
+        assert!(matches!(store.get("b"), Lookup::Found(_)));
+        assert!(matches!(store.get("c"), Lookup::Found(_)));
+        assert!(matches!(store.get("d"), Lookup::Missing));
+    }
+
+    #[test]
+    fn test_results_expire() {
+        let store = MemoryStore::new(Duration::from_millis(50), 1024);
+        store.put("a".to_string(), result(b"pdf"));
+        assert!(matches!(store.get("a"), Lookup::Found(result) if result.data == "pdf"));
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(matches!(store.get("a"), Lookup::Gone));
+    }
+
+    #[tokio::test]
+    async fn test_keep_tags_kept_responses() {
+        let results = Results::new(Arc::new(MemoryStore::new(Duration::from_secs(60), 4)), 4);
+
+        let response = results.keep(Response::new(Body::from("pdf"))).await;
+        let id = response.headers()[CONVERSION_ID_HEADER].to_str().unwrap();
+        assert_eq!(id.len(), 32);
+        assert!(matches!(results.get(id), Lookup::Found(result) if result.data == "pdf"));
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"pdf");
+
+        let response = results.keep(Response::new(Body::from("too large"))).await;
+        assert!(!response.headers().contains_key(CONVERSION_ID_HEADER));
+    }
+}
@@ -0,0 +1,85 @@
+//! Tells clients when a retryable error is worth retrying
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderValue, header},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::{headers::RETRYABLE_HEADER, queue::QueueSnapshot, state::AppState};
+
+/// Suggested wait before any conversion has completed
+const DEFAULT_RETRY_AFTER_SECS: u64 = 10;
+/// Longest suggested wait, however deep the queue
+const MAX_RETRY_AFTER_SECS: u64 = 300;
+
+/// Adds `Retry-After` to responses marked retryable that don't carry one yet
+pub async fn add_retry_after(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+    let headers = response.headers();
+    let retryable = headers
+        .get(RETRYABLE_HEADER)
+        .is_some_and(|value| value == "true");
+    if retryable && !headers.contains_key(header::RETRY_AFTER) {
+        let secs = retry_after_secs(
+            state.average_conversion_duration(),
+            state.scheduler().snapshot(),
+        );
+        response
+            .headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from(secs));
+    }
+    response
+}
+
+/// One average conversion for each conversion running or waiting, so a retry
+/// lands roughly when the queue has drained
+fn retry_after_secs(average: Option<Duration>, queue: QueueSnapshot) -> u64 {
+    let Some(average) = average else {
+        return DEFAULT_RETRY_AFTER_SECS;
+    };
+    let ahead = queue.running + queue.interactive_waiting + queue.bulk_waiting;
+    let estimate = average.as_secs_f64() * ahead.max(1) as f64;
+    (estimate.ceil() as u64).clamp(1, MAX_RETRY_AFTER_SECS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(running: usize, waiting: usize) -> QueueSnapshot {
+        QueueSnapshot {
+            running,
+            interactive_waiting: waiting,
+            bulk_waiting: 0,
+        }
+    }
+
+    #[test]
+    fn test_retry_after_follows_recent_durations() {
+        assert_eq!(
+            retry_after_secs(None, queue(1, 5)),
+            DEFAULT_RETRY_AFTER_SECS
+        );
+        assert_eq!(
+            retry_after_secs(Some(Duration::from_millis(200)), queue(0, 0)),
+            1
+        );
+        assert_eq!(
+            retry_after_secs(Some(Duration::from_secs(3)), queue(1, 3)),
+            12
+        );
+        assert_eq!(
+            retry_after_secs(Some(Duration::from_secs(60)), queue(1, 20)),
+            MAX_RETRY_AFTER_SECS
+        );
+    }
+}
@@ -1,63 +1,118 @@
-use axum::{body::Body, extract::Multipart, http::StatusCode, response::Response};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+
+use axum::{
+    Extension,
+    body::Body,
+    extract::{
+        ConnectInfo, FromRequest, Multipart, Request, State,
+        multipart::{Field, MultipartError},
+    },
+    http::StatusCode,
+    response::Response,
+};
+use futures_util::{StreamExt, TryStreamExt};
 use hyper::header;
+use tokio_util::io::{ReaderStream, StreamReader};
+
+use crate::{
+    checksum,
+    config::Config,
+    converter::ConversionOptions,
+    deadline,
+    detect_filetype::{Confidence, DetectedType},
+    error::{LibreOfficeError, create_error_response, create_error_response_with_code},
+    filename::{self, DEFAULT_FILENAME},
+    formats::{InputFormat, OutputFormat},
+    headers::{
+        CONVERSION_DURATION_HEADER, DETECTED_INPUT_TYPE_HEADER, DETECTION_WARNING_HEADER,
+        MISSING_FONTS_HEADER, QUEUE_LANE_HEADER, QUEUE_WAIT_HEADER, REPAIRED_HEADER,
+        SCAN_DURATION_HEADER, TRACKED_CHANGES_HEADER,
+    },
+    libreoffice::{ConversionOutput, ConvertedOutput, InputFile},
+    pipeline,
+    state::AppState,
+    tracked_changes::TrackedChanges,
+};
+
+/// Longest `output_format` value, format names are a few characters
+pub const MAX_FORMAT_FIELD_LEN: usize = 32;
+
+/// Longest value of a conversion option field
+const MAX_OPTION_FIELD_LEN: usize = 1024;
 
-use crate::{error::create_error_response, libreoffice};
+/// Connection info of the request, missing on unix sockets
+pub type Peer = Option<Extension<ConnectInfo<SocketAddr>>>;
 
-#[axum::debug_handler]
-pub async fn handler(mut multipart: Multipart) -> Response {
+/// IP address of the peer, recorded in the audit trail
+pub fn peer_ip(peer: Peer) -> Option<IpAddr> {
+    peer.map(|Extension(ConnectInfo(addr))| addr.ip())
+}
+
+#[axum::debug_handler(state = Arc<AppState>)]
+pub async fn handler(
+    State(state): State<Arc<AppState>>,
+    peer: Peer,
+    Form(mut multipart): Form,
+) -> Response {
     // Extract multipart data with proper error handling
-    let (file_bytes, input_format, output_format) =
-        match extract_multipart_data(&mut multipart).await {
+    let (input_file, input_format, output_format, options) =
+        match extract_multipart_data(&mut multipart, state.config()).await {
             Ok(data) => data,
             Err(response) => return response,
         };
 
-    handle_conversion(file_bytes, input_format, output_format).await
+    handle_conversion(
+        &state,
+        peer_ip(peer),
+        input_file,
+        input_format,
+        output_format,
+        options,
+    )
+    .await
 }
 
 async fn extract_multipart_data(
     multipart: &mut Multipart,
-) -> Result<(Vec<u8>, String, String), Response<Body>> {
-    let mut file_bytes: Option<Vec<u8>> = None;
+    config: &Config,
+) -> Result<(InputFile, String, Option<String>, ConversionOptions), Response<Body>> {
+    let mut input_file: Option<InputFile> = None;
     let mut input_filename: Option<String> = None;
     let mut output_format: Option<String> = None;
+    let mut options = ConversionOptions::default();
+    let mut fields = FormFields::new(config.reject_unknown_fields);
 
-    while let Ok(Some(field)) = multipart.next_field().await {
-        let name = field.name().unwrap_or("");
+    while let Some(field) = next_field(multipart).await? {
+        let name = field.name().unwrap_or("").to_string();
 
-        match name {
+        match name.as_str() {
             "file" => {
-                input_filename = Some(field.file_name().unwrap_or("unknown_file").to_string());
-
-                file_bytes = Some(
-                    field
-                        .bytes()
-                        .await
-                        .map_err(|e| {
-                            tracing::debug!("Error reading file field: {:?}", e);
-                            create_error_response(
-                                StatusCode::BAD_REQUEST,
-                                "Error reading uploaded file",
-                            )
-                        })?
-                        .to_vec(),
-                )
+                fields.check(&name)?;
+                let (file, filename) = read_upload(field, &config.work_dir).await?;
+                input_file = Some(file);
+                input_filename = Some(filename);
             }
             "output_format" => {
-                output_format = Some(field.text().await.map_err(|e| {
-                    tracing::debug!("Error reading output_format field: {}", e);
-                    create_error_response(StatusCode::BAD_REQUEST, "Error reading output_format")
-                })?)
+                fields.check(&name)?;
+                output_format = Some(read_text(field, MAX_FORMAT_FIELD_LEN).await?);
             }
-            _ => {
-                // Skip unknown fields
+            name if ConversionOptions::is_field(name) => {
+                fields.check(name)?;
+                let value = read_text(field, MAX_OPTION_FIELD_LEN).await?;
+                options.set_field(name, &value)?;
             }
+            name => fields.unknown(name)?,
         }
     }
 
-    match (file_bytes, input_filename, output_format) {
-        (Some(bytes), Some(input_filename), Some(output_format)) => {
-            Ok((bytes, input_filename, output_format))
+    // Sanitizing re-saves in the input's format, which is only known once sniffed
+    match (input_file, input_filename, output_format) {
+        (Some(input_file), Some(input_filename), output_format)
+            if output_format.is_some() || options.sanitize =>
+        {
+            Ok((input_file, input_filename, output_format, options))
         }
         _ => Err(create_error_response(
             StatusCode::BAD_REQUEST,
@@ -66,50 +121,283 @@ async fn extract_multipart_data(
     }
 }
 
-async fn handle_conversion(
-    bytes: Vec<u8>,
+/// Multipart form of the upload routes. Requests that aren't one are rejected with
+/// the JSON error body instead of axum's plain text.
+pub struct Form(pub Multipart);
+
+impl<S: Send + Sync> FromRequest<S> for Form {
+    type Rejection = Response<Body>;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let is_form = request
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("multipart/form-data"));
+
+        match Multipart::from_request(request, state).await {
+            Ok(multipart) => Ok(Form(multipart)),
+            Err(_) if !is_form => Err(create_error_response_with_code(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "unsupported_media_type",
+                "Expected a multipart/form-data body",
+            )),
+            Err(rejection) => Err(create_error_response_with_code(
+                StatusCode::BAD_REQUEST,
+                "malformed_multipart",
+                &rejection.body_text(),
+            )),
+        }
+    }
+}
+
+/// Next field of the form. A malformed body fails instead of ending the form early,
+/// which would report its fields as missing.
+pub async fn next_field(multipart: &mut Multipart) -> Result<Option<Field<'_>>, Response<Body>> {
+    multipart
+        .next_field()
+        .await
+        .map_err(|e| multipart_error(&e))
+}
+
+/// Error response of a form body that couldn't be read: too large, stalled or malformed
+fn multipart_error(error: &MultipartError) -> Response<Body> {
+    if deadline::is_body_timeout(error) {
+        tracing::debug!("Upload stalled: {:?}", error);
+        return upload_stalled();
+    }
+    tracing::debug!("Error reading multipart body: {}", error);
+    if error.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        return create_error_response_with_code(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "payload_too_large",
+            "Upload exceeds MAX_UPLOAD_SIZE_MB",
+        );
+    }
+    create_error_response_with_code(
+        StatusCode::BAD_REQUEST,
+        "malformed_multipart",
+        &format!("Malformed multipart body: {}", error.body_text()),
+    )
+}
+
+/// Fields of a form seen so far, each may be sent once
+pub struct FormFields {
+    seen: Vec<String>,
+    reject_unknown: bool,
+}
+
+impl FormFields {
+    pub fn new(reject_unknown: bool) -> Self {
+        Self {
+            seen: Vec::new(),
+            reject_unknown,
+        }
+    }
+
+    /// Fails when the field `name` was already sent
+    pub fn check(&mut self, name: &str) -> Result<(), LibreOfficeError> {
+        if self.seen.iter().any(|seen| seen == name) {
+            return Err(LibreOfficeError::DuplicateField(name.to_string()));
+        }
+        self.seen.push(name.to_string());
+        Ok(())
+    }
+
+    /// Refuses a field the route doesn't know with `REJECT_UNKNOWN_FIELDS`, logs it otherwise
+    pub fn unknown(&self, name: &str) -> Result<(), LibreOfficeError> {
+        if self.reject_unknown {
+            return Err(LibreOfficeError::UnknownField(name.to_string()));
+        }
+        tracing::warn!("Ignoring unknown field {:?}", name);
+        Ok(())
+    }
+}
+
+/// Reads a text field, refusing values longer than `max_len` bytes without
+/// buffering them
+pub async fn read_text(mut field: Field<'_>, max_len: usize) -> Result<String, Response<Body>> {
+    let name = field.name().unwrap_or("").to_string();
+    let mut value = Vec::new();
+    loop {
+        let chunk = field.chunk().await.map_err(|e| multipart_error(&e))?;
+        let Some(chunk) = chunk else { break };
+        if value.len() + chunk.len() > max_len {
+            return Err(LibreOfficeError::FieldTooLong { name, max_len }.into());
+        }
+        value.extend_from_slice(&chunk);
+    }
+    String::from_utf8(value).map_err(|_| {
+        create_error_response(
+            StatusCode::BAD_REQUEST,
+            &format!("{} is not valid UTF-8", name),
+        )
+    })
+}
+
+fn upload_stalled() -> Response<Body> {
+    metrics::counter!("http_request_timeouts_total", "reason" => "body").increment(1);
+    create_error_response_with_code(
+        StatusCode::REQUEST_TIMEOUT,
+        "request_timeout",
+        "Upload stalled, no data received in time",
+    )
+}
+
+/// Streams a file field to disk, along with its sanitized filename
+pub async fn read_upload(
+    field: Field<'_>,
+    work_dir: &Path,
+) -> Result<(InputFile, String), Response<Body>> {
+    let filename = filename::sanitize_filename(field.file_name().unwrap_or(DEFAULT_FILENAME));
+
+    // Stream the upload straight to disk instead of buffering it
+    let mut reader = StreamReader::new(
+        field.map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+    );
+
+    let file = InputFile::from_reader(&mut reader, work_dir)
+        .await
+        .map_err(|e| {
+            let read_error = e
+                .get_ref()
+                .and_then(|error| error.downcast_ref::<MultipartError>());
+            if let Some(error) = read_error {
+                multipart_error(error)
+            } else {
+                tracing::error!("Error writing uploaded file: {}", e);
+                LibreOfficeError::from_io(e).into()
+            }
+        })?;
+    Ok((file, filename))
+}
+
+pub async fn handle_conversion(
+    state: &AppState,
+    client_ip: Option<IpAddr>,
+    input_file: InputFile,
     input_filename: String,
-    output_format: String,
+    output_format: Option<String>,
+    options: ConversionOptions,
 ) -> Response<Body> {
     tracing::debug!(
         "Starting conversion request: {} -> {}",
         input_filename,
-        output_format
+        output_format.as_deref().unwrap_or("same format")
     );
 
-    // Get file extension from input filename
-    let input_format = match input_filename.rsplit('.').next() {
-        Some(ext) => ext.to_lowercase(),
-        None => String::from(""),
+    let conversion = match pipeline::convert(
+        state,
+        client_ip,
+        input_file,
+        &input_filename,
+        output_format.as_deref(),
+        &options,
+    )
+    .await
+    {
+        Ok(conversion) => conversion,
+        Err(e) => return e.into(),
     };
 
-    match libreoffice::convert_libreoffice(bytes, &input_format, &output_format).await {
-        Ok(converted_bytes) => {
-            tracing::debug!("Conversion completed successfully");
-            create_success_response(converted_bytes, &output_format)
-        }
-        Err(e) => {
-            tracing::error!("Conversion failed: {}", e);
-            e.into()
-        }
+    let response = create_success_response(
+        conversion.output,
+        &conversion.detected,
+        options.changes,
+        &conversion.input_stem,
+        &conversion.input_format,
+        &conversion.output_format,
+    )
+    .await;
+    match state.results() {
+        Some(results) => results.keep(response).await,
+        None => response,
     }
 }
 
-fn create_success_response(converted_bytes: Vec<u8>, output_format: &str) -> Response<Body> {
-    let filename = format!("converted.{}", output_format);
-    let content_type = mime_guess::from_ext(output_format)
+pub async fn create_success_response(
+    output: ConversionOutput,
+    detected: &DetectedType,
+    changes: Option<TrackedChanges>,
+    input_stem: &str,
+    input_format: &InputFormat,
+    output_format: &OutputFormat,
+) -> Response<Body> {
+    let filename = format!("{}.{}", input_stem, output_format);
+    let content_type = mime_guess::from_ext(output_format.as_str())
         .first_or_octet_stream()
         .to_string();
 
-    match Response::builder()
+    let mut builder = Response::builder()
         .status(StatusCode::OK)
         .header(header::CONTENT_TYPE, content_type)
         .header(
             header::CONTENT_DISPOSITION,
-            format!("attachment; filename=\"{}\"", filename),
+            filename::content_disposition(&filename),
         )
-        .body(Body::from(converted_bytes))
-    {
+        .header(DETECTED_INPUT_TYPE_HEADER, detected.mime);
+    if detected.confidence == Confidence::Unknown {
+        builder = builder.header(
+            DETECTION_WARNING_HEADER,
+            format!("content not recognized, converted as {}", input_format),
+        );
+    }
+    if let Some(changes) = changes {
+        builder = builder.header(TRACKED_CHANGES_HEADER, changes.as_str());
+    }
+    if !output.missing_fonts.is_empty() {
+        builder = builder.header(MISSING_FONTS_HEADER, missing_fonts(&output.missing_fonts));
+    }
+    if let Some(scan_duration) = output.scan_duration {
+        builder = builder.header(SCAN_DURATION_HEADER, scan_duration.as_millis().to_string());
+    }
+    if output.repaired {
+        builder = builder.header(REPAIRED_HEADER, "true");
+    }
+    if let Some(queue) = output.queue {
+        builder = builder
+            .header(QUEUE_LANE_HEADER, queue.lane.as_str())
+            .header(QUEUE_WAIT_HEADER, queue.wait.as_millis().to_string());
+    }
+    if let Some(conversion_duration) = output.conversion_duration {
+        builder = builder.header(
+            CONVERSION_DURATION_HEADER,
+            conversion_duration.as_millis().to_string(),
+        );
+    }
+    // The checksum goes out in headers, which precede the body, so it can't come
+    // from hashing the stream; trailers would carry it but most clients ignore
+    // them. The LibreOffice converter hashed the output already, others get
+    // their output hashed here.
+    let digest = match output.sha256 {
+        Some(digest) => digest,
+        None => match checksum::sha256(&output.primary.data).await {
+            Ok(digest) => digest,
+            Err(e) => return LibreOfficeError::from_io(e).into(),
+        },
+    };
+    for (name, value) in checksum::headers(&digest) {
+        builder = builder.header(name, value);
+    }
+
+    let body = match output.primary.data {
+        ConvertedOutput::Bytes(data) => Body::from(data),
+        ConvertedOutput::File { path, len } => {
+            let file = match tokio::fs::File::open(&path).await {
+                Ok(file) => file,
+                Err(e) => return LibreOfficeError::from_io(e).into(),
+            };
+            // The stream holds on to the temp directory until the file was sent
+            let work_dir = output.work_dir;
+            builder = builder.header(header::CONTENT_LENGTH, len);
+            Body::from_stream(ReaderStream::new(file).inspect(move |_| {
+                let _ = &work_dir;
+            }))
+        }
+    };
+
+    match builder.body(body) {
         Ok(response) => response,
         Err(e) => {
             tracing::error!("Error building success response: {}", e);
@@ -117,3 +405,991 @@ fn create_success_response(converted_bytes: Vec<u8>, output_format: &str) -> Res
         }
     }
 }
+
+fn missing_fonts(fonts: &[String]) -> String {
+    let encode = |font: &String| -> String {
+        font.bytes()
+            .map(|b| {
+                if (b' '..=b'~').contains(&b) && b != b',' && b != b'%' {
+                    (b as char).to_string()
+                } else {
+                    format!("%{:02X}", b)
+                }
+            })
+            .collect()
+    };
+    fonts.iter().map(encode).collect::<Vec<_>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        audit::{Auditor, FailureMode, fake::MemorySink},
+        backend::fake::CannedBackend,
+        config::{self, Config},
+        converter::fake::FakeConverter,
+        headers::{
+            CONTENT_SHA256_HEADER, CONVERSION_ID_HEADER, REQUEST_ID_HEADER, RETRYABLE_HEADER,
+        },
+        libreoffice::OutputFile,
+        page_setup::{PageSetup, PaperSize},
+        queue::{Lane, QueueStats, Scheduler},
+        routes, workdir,
+    };
+    use axum::{body::to_bytes, http::Request};
+    use sha2::{Digest, Sha256};
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    const BOUNDARY: &str = "handler-test-boundary";
+
+    fn file_field(filename: &str, content: &[u8]) -> Vec<u8> {
+        let mut field = format!(
+            "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{filename}\"\r\n\r\n"
+        )
+        .into_bytes();
+        field.extend_from_slice(content);
+        field.extend_from_slice(b"\r\n");
+        field
+    }
+
+    fn text_field(name: &str, value: &str) -> Vec<u8> {
+        format!(
+            "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n"
+        )
+        .into_bytes()
+    }
+
+    fn output_format_field(output_format: &str) -> Vec<u8> {
+        text_field("output_format", output_format)
+    }
+
+    /// Posts the given fields to /convert of a router backed by `converter`
+    async fn post(
+        converter: Arc<FakeConverter>,
+        config: Config,
+        fields: &[Vec<u8>],
+    ) -> (StatusCode, header::HeaderMap, Vec<u8>) {
+        let state = AppState::builder()
+            .config(config)
+            .converter(converter)
+            .build();
+        post_to(state, fields).await
+    }
+
+    async fn post_to(
+        state: AppState,
+        fields: &[Vec<u8>],
+    ) -> (StatusCode, header::HeaderMap, Vec<u8>) {
+        let mut body = fields.concat();
+        body.extend_from_slice(format!("--{BOUNDARY}--\r\n").as_bytes());
+        let content_type = format!("multipart/form-data; boundary={}", BOUNDARY);
+        post_body(state, &content_type, body).await
+    }
+
+    /// Posts `body` to /convert as is
+    async fn post_body(
+        state: AppState,
+        content_type: &str,
+        body: Vec<u8>,
+    ) -> (StatusCode, header::HeaderMap, Vec<u8>) {
+        let request = Request::builder()
+            .method("POST")
+            .uri("/convert")
+            .header(header::CONTENT_TYPE, content_type)
+            .body(Body::from(body))
+            .unwrap();
+
+        let response = routes::router(Arc::new(state))
+            .oneshot(request)
+            .await
+            .unwrap();
+        let (parts, body) = response.into_parts();
+        let body = to_bytes(body, usize::MAX).await.unwrap();
+        (parts.status, parts.headers, body.to_vec())
+    }
+
+    async fn convert(converter: Arc<FakeConverter>) -> (StatusCode, header::HeaderMap, Vec<u8>) {
+        post(
+            converter,
+            config::get().clone(),
+            &[
+                file_field("report.docx", b"PK\x03\x04"),
+                output_format_field("pdf"),
+            ],
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_success_response() {
+        let converter = Arc::new(FakeConverter::returning(ConversionOutput {
+            queue: Some(QueueStats {
+                lane: Lane::Interactive,
+                wait: Duration::from_millis(42),
+            }),
+            missing_fonts: vec!["Corporate Sans".to_string(), "Füße, Inc".to_string()],
+            repaired: true,
+            scan_duration: Some(Duration::from_millis(7)),
+            conversion_duration: Some(Duration::from_millis(1500)),
+            ..ConversionOutput::new(OutputFile {
+                name: "document.pdf".to_string(),
+                data: ConvertedOutput::Bytes(b"%PDF-1.7".to_vec()),
+            })
+        }));
+
+        let (status, headers, body) = convert(converter.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CONTENT_TYPE], "application/pdf");
+        assert!(
+            headers[header::CONTENT_DISPOSITION]
+                .to_str()
+                .unwrap()
+                .contains("report.pdf")
+        );
+        assert_eq!(headers[QUEUE_LANE_HEADER], "interactive");
+        assert!(headers.get(TRACKED_CHANGES_HEADER).is_none());
+        assert_eq!(
+            headers[MISSING_FONTS_HEADER],
+            "Corporate Sans, F%C3%BC%C3%9Fe%2C Inc"
+        );
+        assert_eq!(headers[QUEUE_WAIT_HEADER], "42");
+        assert_eq!(headers[CONVERSION_DURATION_HEADER], "1500");
+        assert_eq!(headers[REPAIRED_HEADER], "true");
+        assert_eq!(headers[SCAN_DURATION_HEADER], "7");
+        assert_eq!(
+            headers[DETECTED_INPUT_TYPE_HEADER],
+            "application/octet-stream"
+        );
+        assert_eq!(body, b"%PDF-1.7");
+        assert_eq!(converter.calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_upload_goes_to_the_work_dir_of_the_state() {
+        let converter = Arc::new(FakeConverter::returning_bytes("pdf", b"%PDF"));
+        let config = Config {
+            work_dir: "/nonexistent/work-dir".into(),
+            ..config::get().clone()
+        };
+        let fields = [
+            file_field("report.docx", b"PK\x03\x04"),
+            output_format_field("pdf"),
+        ];
+
+        let (status, _, body) = post(converter.clone(), config, &fields).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error["code"], "io_error");
+        assert_eq!(converter.calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_option_fields() {
+        let converter = Arc::new(FakeConverter::returning_bytes("pdf", b"%PDF-1.7"));
+        let (status, headers, _) = post(
+            converter.clone(),
+            config::get().clone(),
+            &[
+                file_field("report.xlsx", b"PK\x03\x04"),
+                output_format_field("pdf"),
+                text_field("fit_to_width", "1"),
+                text_field("landscape", "true"),
+                text_field("paper_size", "A3"),
+                text_field("changes", "accept"),
+                text_field("include_comments", "false"),
+                text_field("strip_metadata", "true"),
+                text_field("embed_fonts", "true"),
+                text_field("tagged_pdf", "false"),
+                text_field("output_password", "s3cret"),
+                text_field("disallow_copying", "true"),
+                text_field("repair", "true"),
+            ],
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[TRACKED_CHANGES_HEADER], "accept");
+        let options = converter.last_options().unwrap();
+        assert_eq!(options.changes, Some(TrackedChanges::Accept));
+        assert_eq!(options.include_comments, Some(false));
+        assert!(options.strip_metadata);
+        assert_eq!(options.embed_fonts, Some(true));
+        assert_eq!(options.tagged_pdf, Some(false));
+        assert!(options.encryption.user_password.is_some());
+        assert!(options.encryption.disallow_copying);
+        assert!(options.repair);
+        assert_eq!(
+            converter.last_options().unwrap().page_setup,
+            PageSetup {
+                fit_to_width: Some(1),
+                fit_to_height: None,
+                landscape: true,
+                paper_size: Some(PaperSize::A3),
+            }
+        );
+
+        let converter = Arc::new(FakeConverter::returning_bytes("pdf", b"%PDF-1.7"));
+        let (status, _, body) = post(
+            converter.clone(),
+            config::get().clone(),
+            &[
+                file_field("report.xlsx", b"PK\x03\x04"),
+                output_format_field("pdf"),
+                text_field("fit_to_width", "all"),
+            ],
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "invalid_option");
+        assert_eq!(
+            body["message"],
+            "fit_to_width must be a positive number of pages"
+        );
+        assert_eq!(converter.calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_sanitize() {
+        let converter = Arc::new(FakeConverter::returning_bytes("docx", b"PK\x03\x04"));
+        let (status, _, _) = post(
+            converter.clone(),
+            config::get().clone(),
+            &[
+                file_field("report.docx", b"PK\x03\x04"),
+                text_field("sanitize", "true"),
+                text_field("output_filter", "MS Word 2007 XML"),
+            ],
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            converter.requests(),
+            vec![("docx".to_string(), "docx".to_string())]
+        );
+        let options = converter.last_options().unwrap();
+        assert!(options.strips_metadata());
+        assert_eq!(options.output_filter.as_deref(), Some("MS Word 2007 XML"));
+
+        for (fields, message) in [
+            (
+                vec![
+                    file_field("report.docx", b"PK\x03\x04"),
+                    output_format_field("pdf"),
+                    text_field("sanitize", "true"),
+                ],
+                "sanitize re-saves docx as docx, not pdf",
+            ),
+            (
+                vec![
+                    file_field("notes.txt", b"notes"),
+                    text_field("sanitize", "true"),
+                ],
+                "sanitize only applies to docx, xlsx, pptx, odt, ods, odp, odg inputs",
+            ),
+            (
+                vec![
+                    file_field("report.docx", b"PK\x03\x04"),
+                    output_format_field("docx"),
+                    text_field("output_filter", "MS Word\"; rm"),
+                ],
+                "output_filter must be a LibreOffice filter name of at most 64 letters, digits, spaces and _-().",
+            ),
+        ] {
+            let (status, _, body) = post(converter.clone(), config::get().clone(), &fields).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["code"], "invalid_option");
+            assert_eq!(body["message"], message);
+        }
+        assert_eq!(converter.calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_conversions_are_audited() {
+        let sink = Arc::new(MemorySink::default());
+        let state = AppState::builder()
+            .converter(Arc::new(FakeConverter::returning_bytes("pdf", b"%PDF-1.7")))
+            .auditor(Auditor::new(sink.clone(), FailureMode::Closed))
+            .build();
+        let (status, _, _) = post_to(
+            state,
+            &[
+                file_field("report.docx", b"PK\x03\x04"),
+                output_format_field("pdf"),
+            ],
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let records = sink.records.lock().unwrap();
+        let [record] = records.as_slice() else {
+            panic!("expected one record, got {:?}", records)
+        };
+        assert_eq!(record.input_format, "docx");
+        assert_eq!(record.output_format, "pdf");
+        assert_eq!(record.input_bytes, 4);
+        assert_eq!(record.output_bytes, Some(8));
+        assert_eq!(record.outcome, "success");
+        assert!(!record.request_id.is_empty());
+        assert_ne!(record.filename_sha256, "report.docx");
+    }
+
+    #[tokio::test]
+    async fn test_audit_failure_fails_closed() {
+        let failing = || {
+            Arc::new(MemorySink {
+                failing: true,
+                ..MemorySink::default()
+            })
+        };
+        let convert = |failure_mode| async move {
+            let state = AppState::builder()
+                .converter(Arc::new(FakeConverter::returning_bytes("pdf", b"%PDF-1.7")))
+                .auditor(Auditor::new(failing(), failure_mode))
+                .build();
+            post_to(
+                state,
+                &[
+                    file_field("report.docx", b"PK\x03\x04"),
+                    output_format_field("pdf"),
+                ],
+            )
+            .await
+        };
+
+        let (status, _, body) = convert(FailureMode::Open).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, b"%PDF-1.7");
+
+        let (status, _, body) = convert(FailureMode::Closed).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "audit_failed");
+    }
+
+    #[tokio::test]
+    async fn test_file_output_is_streamed() {
+        let work_dir = workdir::create_temp_dir(&config::get().work_dir).unwrap();
+        let path = work_dir.path().join("document.pdf");
+        std::fs::write(&path, b"%PDF-1.7 on disk").unwrap();
+        let converter = Arc::new(FakeConverter::returning(
+            ConversionOutput::new(OutputFile {
+                name: "document.pdf".to_string(),
+                data: ConvertedOutput::File { path, len: 16 },
+            })
+            .with_work_dir(Arc::new(work_dir)),
+        ));
+
+        let (status, headers, body) = convert(converter).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CONTENT_LENGTH], "16");
+        assert_eq!(body, b"%PDF-1.7 on disk");
+        let sha256 = checksum::hex(&Sha256::digest(&body));
+        assert_eq!(headers[CONTENT_SHA256_HEADER], sha256.as_str());
+        assert!(headers["digest"].to_str().unwrap().starts_with("sha-256="));
+    }
+
+    #[tokio::test]
+    async fn test_digest_of_the_converter_is_not_recomputed() {
+        let converter = Arc::new(FakeConverter::returning(ConversionOutput {
+            sha256: Some([0xab; 32]),
+            ..ConversionOutput::new(OutputFile {
+                name: "document.pdf".to_string(),
+                data: ConvertedOutput::Bytes(b"%PDF-1.7".to_vec()),
+            })
+        }));
+
+        let (status, headers, _) = convert(converter).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[CONTENT_SHA256_HEADER], "ab".repeat(32));
+    }
+
+    #[tokio::test]
+    async fn test_input_format_detected_without_extension() {
+        let converter = Arc::new(FakeConverter::returning_bytes("pdf", b"%PDF-1.7"));
+        let docx = std::fs::read(
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/sample.docx"),
+        )
+        .unwrap();
+
+        let (status, headers, _) = post(
+            converter.clone(),
+            config::get().clone(),
+            &[file_field("scan", &docx), output_format_field("pdf")],
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            headers[DETECTED_INPUT_TYPE_HEADER],
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+        );
+        assert_eq!(
+            converter.requests(),
+            vec![("docx".to_string(), "pdf".to_string())]
+        );
+
+        // Content that isn't recognized still needs a known extension
+        let (status, _, body) = post(
+            converter.clone(),
+            config::get().clone(),
+            &[
+                file_field("scan", b"\x00\x01\x02\x03"),
+                output_format_field("pdf"),
+            ],
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "invalid_format");
+        assert_eq!(converter.calls(), 1);
+    }
+
+    fn error_cases() -> Vec<(LibreOfficeError, StatusCode)> {
+        vec![
+            (
+                LibreOfficeError::Timeout { queue_wait: None },
+                StatusCode::REQUEST_TIMEOUT,
+            ),
+            (
+                LibreOfficeError::QueueTimeout(Duration::from_secs(120)),
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            (
+                LibreOfficeError::RequestTimeout(Duration::from_secs(300)),
+                StatusCode::REQUEST_TIMEOUT,
+            ),
+            (
+                LibreOfficeError::CorruptedInput("bad zip".to_string()),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                LibreOfficeError::UnsupportedConversion {
+                    from: "docx".to_string(),
+                    to: "pdf".to_string(),
+                },
+                StatusCode::BAD_REQUEST,
+            ),
+            (LibreOfficeError::PasswordProtected, StatusCode::BAD_REQUEST),
+            (LibreOfficeError::OutlookMessage, StatusCode::BAD_REQUEST),
+            (
+                LibreOfficeError::EmptyOrInvalidInput,
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                LibreOfficeError::BinaryNotFound,
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            (
+                LibreOfficeError::BackendUnavailable("unoserver down".to_string()),
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            (
+                LibreOfficeError::InsufficientStorage,
+                StatusCode::INSUFFICIENT_STORAGE,
+            ),
+            (
+                LibreOfficeError::ResourceLimitExceeded,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                LibreOfficeError::ConversionFailed("crashed".to_string()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                LibreOfficeError::OutputNotFound,
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                LibreOfficeError::ProfileCorrupted(3),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_error_mapping() {
+        for (error, expected_status) in error_cases() {
+            let code = error.code();
+            let retryable = error.is_retryable();
+            let (status, headers, body) = convert(Arc::new(FakeConverter::failing(error))).await;
+            assert_eq!(status, expected_status, "{}", code);
+            assert_eq!(headers[header::CONTENT_TYPE], "application/json");
+            assert_eq!(headers[RETRYABLE_HEADER], retryable.to_string(), "{}", code);
+            assert_eq!(
+                headers.contains_key(header::RETRY_AFTER),
+                retryable,
+                "{}",
+                code
+            );
+
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["code"], code);
+            assert_eq!(body["retryable"], retryable, "{}", code);
+        }
+
+        // Time spent queueing is reported apart from the conversion
+        let (_, headers, _) = convert(Arc::new(FakeConverter::failing(
+            LibreOfficeError::QueueTimeout(Duration::from_secs(120)),
+        )))
+        .await;
+        assert_eq!(headers[header::RETRY_AFTER], "10");
+        assert_eq!(headers[QUEUE_WAIT_HEADER], "120000");
+        let (_, headers, _) = convert(Arc::new(FakeConverter::failing(
+            LibreOfficeError::Timeout {
+                queue_wait: Some(Duration::from_millis(250)),
+            },
+        )))
+        .await;
+        assert_eq!(headers[QUEUE_WAIT_HEADER], "250");
+        assert!(headers.get(header::RETRY_AFTER).is_none());
+    }
+
+    #[test]
+    fn test_errors_are_counted_by_code_and_formats() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let cases = error_cases();
+        let ((), recorded) = routes::metrics::fake::record(|| {
+            runtime.block_on(async {
+                for (error, _) in error_cases() {
+                    convert(Arc::new(FakeConverter::failing(error))).await;
+                }
+                // Rejected before any conversion, with an output format that isn't one
+                post(
+                    Arc::new(FakeConverter::returning_bytes("pdf", b"%PDF-1.7")),
+                    config::get().clone(),
+                    &[
+                        file_field("report.docx", b"PK\x03\x04"),
+                        output_format_field("xyz"),
+                    ],
+                )
+                .await;
+            })
+        });
+
+        for (error, _) in cases {
+            let labels = [
+                ("code", error.code()),
+                ("input_format", "docx"),
+                ("output_format", "pdf"),
+            ];
+            assert_eq!(
+                recorded.counter("conversion_errors_total", &labels),
+                1,
+                "{}",
+                error.code()
+            );
+        }
+        let labels = [("input_format", "unknown"), ("output_format", "unknown")];
+        assert_eq!(recorded.counter("conversion_errors_total", &labels), 1);
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_follows_recent_conversions() {
+        let state = AppState::builder()
+            .converter(Arc::new(FakeConverter::failing(
+                LibreOfficeError::BackendUnavailable("restarting".to_string()),
+            )))
+            .build();
+        for _ in 0..3 {
+            state.record_conversion("a.docx", "docx", "pdf", Duration::from_secs(4), "success");
+        }
+
+        let (status, headers, _) = post_to(
+            state,
+            &[
+                file_field("report.docx", b"PK\x03\x04"),
+                output_format_field("pdf"),
+            ],
+        )
+        .await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(headers[RETRYABLE_HEADER], "true");
+        assert_eq!(headers[header::RETRY_AFTER], "4");
+    }
+
+    #[tokio::test]
+    async fn test_missing_fields() {
+        let converter = Arc::new(FakeConverter::returning_bytes("pdf", b"%PDF-1.7"));
+
+        for fields in [
+            vec![output_format_field("pdf")],
+            vec![file_field("report.docx", b"PK\x03\x04")],
+            vec![],
+        ] {
+            let (status, _, body) = post(converter.clone(), config::get().clone(), &fields).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["code"], "bad_request");
+        }
+        assert_eq!(converter.calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_strict_form_fields() {
+        let converter = Arc::new(FakeConverter::returning_bytes("pdf", b"%PDF-1.7"));
+        let file = || file_field("report.docx", b"PK\x03\x04");
+        let rejected = |status: StatusCode, body: Vec<u8>| {
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            (body["code"].clone(), body["message"].to_string())
+        };
+
+        let cases = [
+            (
+                vec![file(), file(), output_format_field("pdf")],
+                "duplicate_field",
+                "file",
+            ),
+            (
+                vec![
+                    file(),
+                    output_format_field("pdf"),
+                    output_format_field("png"),
+                ],
+                "duplicate_field",
+                "output_format",
+            ),
+            (
+                vec![file(), output_format_field(&"pdf".repeat(11))],
+                "field_too_long",
+                "output_format",
+            ),
+            (
+                // Not a form field, the body is malformed
+                vec![format!("--{BOUNDARY}\r\nno header here\r\n\r\npdf\r\n").into_bytes()],
+                "malformed_multipart",
+                "",
+            ),
+        ];
+        for (fields, code, named) in cases {
+            let (status, _, body) = post(converter.clone(), config::get().clone(), &fields).await;
+            let (actual_code, message) = rejected(status, body);
+            assert_eq!(actual_code, code, "{}", message);
+            assert!(message.contains(named), "{}", message);
+            assert!(!message.contains("Missing required fields"), "{}", message);
+        }
+        assert_eq!(converter.calls(), 0);
+
+        // Unknown fields are only logged unless REJECT_UNKNOWN_FIELDS is set
+        let fields = [
+            file(),
+            output_format_field("pdf"),
+            text_field("colour", "red"),
+        ];
+        let (status, _, _) = post(converter.clone(), config::get().clone(), &fields).await;
+        assert_eq!(status, StatusCode::OK);
+        let config = Config {
+            reject_unknown_fields: true,
+            ..config::get().clone()
+        };
+        let (status, _, body) = post(converter.clone(), config, &fields).await;
+        let (code, message) = rejected(status, body);
+        assert_eq!(code, "unknown_field");
+        assert!(message.contains("colour"), "{}", message);
+        assert_eq!(converter.calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_bodies_get_error_bodies() {
+        let form = format!("multipart/form-data; boundary={}", BOUNDARY);
+        let state = || {
+            AppState::builder()
+                .config(Config {
+                    max_upload_size: 1024,
+                    ..config::get().clone()
+                })
+                .converter(Arc::new(FakeConverter::returning_bytes("pdf", b"%PDF-1.7")))
+                .build()
+        };
+        let mut over_limit = file_field("report.docx", &[b'x'; 4096]);
+        over_limit.extend_from_slice(format!("--{BOUNDARY}--\r\n").as_bytes());
+        // Cut off before the closing boundary
+        let truncated = [
+            output_format_field("pdf"),
+            file_field("report.docx", b"PK\x03\x04")[..60].to_vec(),
+        ]
+        .concat();
+
+        let cases = [
+            (
+                form.as_str(),
+                over_limit,
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "payload_too_large",
+            ),
+            (
+                form.as_str(),
+                truncated,
+                StatusCode::BAD_REQUEST,
+                "malformed_multipart",
+            ),
+            (
+                "multipart/form-data",
+                output_format_field("pdf"),
+                StatusCode::BAD_REQUEST,
+                "malformed_multipart",
+            ),
+            (
+                "application/json",
+                br#"{"output_format":"pdf"}"#.to_vec(),
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "unsupported_media_type",
+            ),
+        ];
+        for (content_type, body, expected_status, expected_code) in cases {
+            let (status, headers, body) = post_body(state(), content_type, body).await;
+            assert_eq!(status, expected_status, "{}", expected_code);
+            assert_eq!(headers[header::CONTENT_TYPE], "application/json");
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["code"], expected_code);
+            assert_eq!(
+                body["request_id"],
+                headers[REQUEST_ID_HEADER].to_str().unwrap()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_conversion_policy_is_enforced() {
+        let converter = Arc::new(FakeConverter::returning_bytes("pdf", b"%PDF-1.7"));
+        let docx = std::fs::read(
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/sample.docx"),
+        )
+        .unwrap();
+        let config = Config {
+            allowed_input_types: Some(vec!["docx".to_string()]),
+            allowed_output_formats: Some(vec!["pdf".to_string()]),
+            ..config::get().clone()
+        };
+
+        let (status, _, _) = post(
+            converter.clone(),
+            config.clone(),
+            &[file_field("report.docx", &docx), output_format_field("pdf")],
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        for fields in [
+            [file_field("report.docx", &docx), output_format_field("png")],
+            [file_field("report.odt", &docx), output_format_field("pdf")],
+            // A PDF renamed to docx is still a PDF
+            [
+                file_field("report.docx", b"%PDF-1.7\n%\xE2\xE3\xCF\xD3\n1 0 obj\n"),
+                output_format_field("pdf"),
+            ],
+        ] {
+            let (status, _, body) = post(converter.clone(), config.clone(), &fields).await;
+            assert_eq!(status, StatusCode::FORBIDDEN);
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["code"], "conversion_not_allowed");
+            let message = body["message"].as_str().unwrap();
+            assert!(
+                message.ends_with("allowed inputs: docx; allowed outputs: pdf"),
+                "{}",
+                message
+            );
+        }
+        assert_eq!(converter.calls(), 1);
+
+        let state = AppState::builder().config(config).build();
+        let request = Request::builder()
+            .uri("/formats")
+            .body(Body::empty())
+            .unwrap();
+        let response = routes::router(Arc::new(state))
+            .oneshot(request)
+            .await
+            .unwrap();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let formats: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(formats["input_formats"], serde_json::json!(["docx"]));
+        assert_eq!(formats["output_formats"], serde_json::json!(["pdf"]));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_output_format() {
+        let converter = Arc::new(FakeConverter::returning_bytes("pdf", b"%PDF-1.7"));
+
+        let (status, _, body) = post(
+            converter.clone(),
+            config::get().clone(),
+            &[
+                file_field("report.docx", b"PK\x03\x04"),
+                output_format_field("exe"),
+            ],
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "invalid_format");
+        assert_eq!(converter.calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_unrecognized_content_policy() {
+        let docx = std::fs::read(
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/sample.docx"),
+        )
+        .unwrap();
+        let garbage = b"\x00\x01\x02\x03\xFF\xFE\xFD\xFC";
+        let config = Config {
+            unrecognized_content_extensions: vec!["dif".to_string()],
+            ..config::get().clone()
+        };
+        // Real converter with a canned backend, as the policy is enforced by the converter
+        let convert = |filename: &str, content: &[u8]| {
+            let state = AppState::builder()
+                .config(config.clone())
+                .backend(CannedBackend)
+                .scheduler(Scheduler::new(1))
+                .build();
+            let fields = [file_field(filename, content), output_format_field("pdf")];
+            async move { post_to(state, &fields).await }
+        };
+        let code = |body: &[u8]| {
+            serde_json::from_slice::<serde_json::Value>(body).unwrap()["code"].clone()
+        };
+
+        // Recognized content, known and unknown extension
+        let (status, headers, _) = convert("report.docx", &docx).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!headers.contains_key(DETECTION_WARNING_HEADER));
+        let (status, headers, _) = convert("report.xyz", &docx).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!headers.contains_key(DETECTION_WARNING_HEADER));
+
+        // Unrecognized content, allowlisted and unknown extension
+        let (status, headers, _) = convert("table.dif", garbage).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            headers[DETECTION_WARNING_HEADER],
+            "content not recognized, converted as dif"
+        );
+        let (status, _, body) = convert("table.xyz", garbage).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(code(&body), "invalid_format");
+
+        // Known extension that isn't allowlisted
+        let (status, _, body) = convert("report.docx", garbage).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(code(&body), "unsupported_conversion");
+    }
+
+    #[tokio::test]
+    async fn test_oversized_upload_is_rejected() {
+        let converter = Arc::new(FakeConverter::returning_bytes("pdf", b"%PDF-1.7"));
+        let config = Config {
+            max_upload_size: 1024,
+            ..config::get().clone()
+        };
+
+        let (status, _, _) = post(
+            converter.clone(),
+            config,
+            &[
+                file_field("report.docx", &[0; 4096]),
+                output_format_field("pdf"),
+            ],
+        )
+        .await;
+        assert!(status.is_client_error(), "{}", status);
+        assert_eq!(converter.calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_stalled_upload_times_out() {
+        let converter = Arc::new(FakeConverter::returning_bytes("pdf", b"%PDF-1.7"));
+        let mut config = config::get().clone();
+        config.server_limits.body_read_timeout = Duration::from_millis(100);
+        let state = AppState::builder()
+            .config(config)
+            .converter(converter.clone())
+            .build();
+
+        // The client sends the start of the file, then nothing
+        let head = file_field("report.docx", b"PK\x03\x04")[..100].to_vec();
+        let body = futures_util::stream::iter([Ok::<_, std::io::Error>(head)])
+            .chain(futures_util::stream::pending());
+        let request = Request::builder()
+            .method("POST")
+            .uri("/convert")
+            .header(
+                header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={}", BOUNDARY),
+            )
+            .body(Body::from_stream(body))
+            .unwrap();
+
+        let response = routes::router(Arc::new(state))
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "request_timeout");
+        assert_eq!(converter.calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_results_can_be_downloaded_again() {
+        let config = Config {
+            result_retention: Some(Duration::from_secs(60)),
+            ..config::get().clone()
+        };
+        let converter = Arc::new(FakeConverter::returning_bytes("pdf", b"%PDF-1.7"));
+        let app = routes::router(Arc::new(
+            AppState::builder()
+                .config(config)
+                .converter(converter)
+                .build(),
+        ));
+
+        let mut body = [
+            file_field("report.docx", b"PK\x03\x04"),
+            output_format_field("pdf"),
+        ]
+        .concat();
+        body.extend_from_slice(format!("--{BOUNDARY}--\r\n").as_bytes());
+        let request = Request::builder()
+            .method("POST")
+            .uri("/convert")
+            .header(
+                header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={}", BOUNDARY),
+            )
+            .body(Body::from(body))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let id = response.headers()[CONVERSION_ID_HEADER].to_str().unwrap();
+
+        let get = |uri: String| Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let again = app
+            .clone()
+            .oneshot(get(format!("/results/{}", id)))
+            .await
+            .unwrap();
+        assert_eq!(again.status(), StatusCode::OK);
+        assert_eq!(
+            again.headers()[header::CONTENT_DISPOSITION],
+            response.headers()[header::CONTENT_DISPOSITION]
+        );
+        assert_eq!(
+            again.headers()[CONTENT_SHA256_HEADER],
+            response.headers()[CONTENT_SHA256_HEADER]
+        );
+        assert_eq!(again.headers()["digest"], response.headers()["digest"]);
+        let body = to_bytes(again.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"%PDF-1.7");
+
+        let unknown = app.oneshot(get("/results/0".to_string())).await.unwrap();
+        assert_eq!(unknown.status(), StatusCode::NOT_FOUND);
+    }
+}
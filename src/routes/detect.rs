@@ -0,0 +1,166 @@
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    body::Body,
+    extract::{Multipart, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+
+use crate::{
+    config::Config,
+    detect_filetype::{Confidence, FileType, TextEncoding},
+    error::{LibreOfficeError, create_error_response},
+    libreoffice::InputFile,
+    routes::convert::{Form, FormFields, next_field, read_upload},
+    state::AppState,
+};
+
+/// What `/detect` found out about an upload
+#[derive(Debug, Serialize)]
+pub struct Detection {
+    #[serde(rename = "type")]
+    pub file_type: FileType,
+    pub mime: &'static str,
+    pub extension: &'static str,
+    pub confidence: Confidence,
+    /// Password protected compound file, always false for other containers
+    pub encrypted: bool,
+    pub macros: bool,
+    pub encoding: Option<TextEncoding>,
+}
+
+/// Sniffs the type of the uploaded `file` without converting it. A prefix of the
+/// file will do unless the format is told apart by a late zip entry.
+#[axum::debug_handler(state = Arc<AppState>)]
+pub async fn handler(State(state): State<Arc<AppState>>, Form(mut multipart): Form) -> Response {
+    let file = match extract_multipart_data(&mut multipart, state.config()).await {
+        Ok(file) => file,
+        Err(response) => return response,
+    };
+
+    match detect(&file).await {
+        Ok(detection) => Json(detection).into_response(),
+        Err(e) => LibreOfficeError::from_io(e).into(),
+    }
+}
+
+/// Sniffs the type of an upload, shared with the gRPC service
+pub async fn detect(file: &InputFile) -> std::io::Result<Detection> {
+    let detected = file.detect_file_type().await?;
+    Ok(Detection {
+        file_type: detected.file_type,
+        mime: detected.mime,
+        extension: detected.extension,
+        confidence: detected.confidence,
+        encrypted: file.is_password_protected().await,
+        macros: detected.file_type.has_macros(),
+        encoding: detected.encoding,
+    })
+}
+
+async fn extract_multipart_data(
+    multipart: &mut Multipart,
+    config: &Config,
+) -> Result<InputFile, Response<Body>> {
+    let mut file = None;
+    let mut fields = FormFields::new(config.reject_unknown_fields);
+
+    while let Some(field) = next_field(multipart).await? {
+        let name = field.name().unwrap_or("").to_string();
+        match name.as_str() {
+            "file" => {
+                fields.check(&name)?;
+                file = Some(read_upload(field, &config.work_dir).await?.0);
+            }
+            name => fields.unknown(name)?,
+        }
+    }
+
+    file.ok_or_else(|| {
+        create_error_response(StatusCode::BAD_REQUEST, "Missing required field: file")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{converter::fake::FakeConverter, routes};
+    use axum::{body::to_bytes, http::Request};
+    use std::path::Path;
+    use tower::ServiceExt;
+
+    const BOUNDARY: &str = "detect-test-boundary";
+
+    /// Posts the first `len` bytes of a fixture as `file`
+    async fn detect(fixture: &str, len: usize) -> (StatusCode, serde_json::Value) {
+        let content = std::fs::read(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/fixtures")
+                .join(fixture),
+        )
+        .unwrap();
+        let mut body = format!(
+            "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"upload\"\r\n\r\n"
+        )
+        .into_bytes();
+        body.extend_from_slice(&content[..len.min(content.len())]);
+        body.extend_from_slice(format!("\r\n--{BOUNDARY}--\r\n").as_bytes());
+
+        let converter = Arc::new(FakeConverter::returning_bytes("pdf", b"%PDF"));
+        let state = AppState::builder().converter(converter.clone()).build();
+        let request = Request::builder()
+            .method("POST")
+            .uri("/detect")
+            .header(
+                "content-type",
+                format!("multipart/form-data; boundary={BOUNDARY}"),
+            )
+            .body(Body::from(body))
+            .unwrap();
+        let response = routes::router(Arc::new(state))
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(converter.calls(), 0);
+
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_detect() {
+        let (status, docx) = detect("sample.docx", usize::MAX).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(docx["type"], "word");
+        assert_eq!(docx["extension"], "docx");
+        assert_eq!(docx["confidence"], "certain");
+        assert_eq!(docx["encrypted"], false);
+        assert_eq!(docx["macros"], false);
+        assert!(docx["encoding"].is_null());
+
+        let (_, encrypted) = detect("encrypted.docx", usize::MAX).await;
+        assert_eq!(encrypted["encrypted"], true);
+
+        let (_, macros) = detect("macros.docm", usize::MAX).await;
+        assert_eq!(macros["type"], "word_macro");
+        assert_eq!(macros["macros"], true);
+
+        let (_, text) = detect("sample.txt", 64).await;
+        assert_eq!(text["type"], "plain_text");
+        assert_eq!(text["encoding"], "utf-8");
+        assert_eq!(text["confidence"], "likely");
+    }
+
+    #[tokio::test]
+    async fn test_detect_prefix() {
+        for (fixture, extension) in [("sample.pptx", "pptx"), ("legacy.doc", "doc")] {
+            let (status, detected) = detect(fixture, 2 * 1024).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(detected["extension"], extension, "{}", fixture);
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    Json,
+    extract::{Query, State, rejection::QueryRejection},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::create_error_response,
+    formats::{InputFormat, OutputFormat},
+    latency::{self, Basis},
+    queue::{Lane, QueueSnapshot},
+    state::AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct EstimateQuery {
+    from: String,
+    to: String,
+    /// Input size in bytes
+    size: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct EstimateResponse {
+    from: String,
+    to: String,
+    size: u64,
+    /// Upper bound of the size bucket the estimate is for, none for the largest
+    size_bucket_max_bytes: Option<u64>,
+    basis: Basis,
+    samples: usize,
+    p50_ms: Option<u64>,
+    p95_ms: Option<u64>,
+    /// Wait for the LibreOffice slot if the conversion was queued now
+    queue_wait_ms: Option<u64>,
+    queue: QueueSnapshot,
+}
+
+/// Predicts how long converting `size` bytes `from` one format `to` another takes,
+/// from the durations of recent conversions
+pub async fn handler(
+    State(state): State<Arc<AppState>>,
+    query: Result<Query<EstimateQuery>, QueryRejection>,
+) -> Response {
+    let Query(query) = match query {
+        Ok(query) => query,
+        Err(e) => return create_error_response(StatusCode::BAD_REQUEST, &e.body_text()),
+    };
+    let from = match query.from.parse::<InputFormat>() {
+        Ok(format) => format,
+        Err(e) => return e.into(),
+    };
+    let to = match query.to.parse::<OutputFormat>() {
+        Ok(format) => format,
+        Err(e) => return e.into(),
+    };
+
+    let estimate = state
+        .latency()
+        .estimate(from.as_str(), to.as_str(), query.size);
+    let lane = if query.size < state.config().interactive_max_bytes {
+        Lane::Interactive
+    } else {
+        Lane::Bulk
+    };
+    let queue = state.scheduler().snapshot();
+    let queue_wait = queue_wait(state.latency().global_median(), queue, lane);
+
+    Json(EstimateResponse {
+        from: from.to_string(),
+        to: to.to_string(),
+        size: query.size,
+        size_bucket_max_bytes: latency::size_bucket_max(query.size),
+        basis: estimate.basis,
+        samples: estimate.samples,
+        p50_ms: estimate.p50.map(|p50| p50.as_millis() as u64),
+        p95_ms: estimate.p95.map(|p95| p95.as_millis() as u64),
+        queue_wait_ms: queue_wait.map(|wait| wait.as_millis() as u64),
+        queue,
+    })
+    .into_response()
+}
+
+/// One typical conversion for each conversion running or ahead in the queue. Bulk
+/// conversions wait for the interactive ones as well.
+fn queue_wait(typical: Option<Duration>, queue: QueueSnapshot, lane: Lane) -> Option<Duration> {
+    let ahead = match lane {
+        Lane::Interactive => queue.running + queue.interactive_waiting,
+        Lane::Bulk => queue.running + queue.interactive_waiting + queue.bulk_waiting,
+    };
+    if ahead == 0 {
+        return Some(Duration::ZERO);
+    }
+    Some(typical? * ahead as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routes;
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+
+    async fn estimate(state: Arc<AppState>, query: &str) -> (StatusCode, serde_json::Value) {
+        let request = Request::builder()
+            .uri(format!("/estimate?{}", query))
+            .body(Body::empty())
+            .unwrap();
+        let response = routes::router(state).oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_estimate() {
+        let state = Arc::new(AppState::builder().build());
+        let (status, empty) = estimate(state.clone(), "from=docx&to=pdf&size=1048576").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(empty["basis"], "none");
+        assert!(empty["p50_ms"].is_null());
+
+        for millis in [800, 1000, 1200] {
+            state
+                .latency()
+                .record("docx", "pdf", 2_000_000, Duration::from_millis(millis));
+        }
+        let (_, docx) = estimate(state.clone(), "from=DOCX&to=pdf&size=1048576").await;
+        assert_eq!(docx["from"], "docx");
+        assert_eq!(docx["basis"], "bucket");
+        assert_eq!(docx["samples"], 3);
+        assert_eq!(docx["p50_ms"], 1000);
+        assert_eq!(docx["p95_ms"], 1200);
+        assert_eq!(docx["size_bucket_max_bytes"], 10 * 1024 * 1024);
+        assert_eq!(docx["queue_wait_ms"], 0);
+
+        let (_, xlsx) = estimate(state, "from=xlsx&to=pdf&size=1048576").await;
+        assert_eq!(xlsx["basis"], "global");
+        assert_eq!(xlsx["p50_ms"], 1000);
+    }
+
+    #[tokio::test]
+    async fn test_estimate_rejects_bad_queries() {
+        let state = Arc::new(AppState::builder().build());
+        let (status, missing) = estimate(state.clone(), "from=docx&to=pdf").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(missing["message"].as_str().unwrap().contains("size"));
+
+        let (status, unknown) = estimate(state, "from=exe&to=pdf&size=1").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(unknown["code"], "invalid_format");
+    }
+
+    #[test]
+    fn test_queue_wait() {
+        let second = Some(Duration::from_secs(1));
+        let queue = QueueSnapshot {
+            running: 1,
+            interactive_waiting: 2,
+            bulk_waiting: 3,
+        };
+        assert_eq!(
+            queue_wait(second, queue, Lane::Interactive),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(
+            queue_wait(second, queue, Lane::Bulk),
+            Some(Duration::from_secs(6))
+        );
+        assert_eq!(queue_wait(None, queue, Lane::Bulk), None);
+        let idle = QueueSnapshot {
+            running: 0,
+            interactive_waiting: 0,
+            bulk_waiting: 0,
+        };
+        assert_eq!(queue_wait(None, idle, Lane::Bulk), Some(Duration::ZERO));
+    }
+}
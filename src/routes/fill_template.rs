@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{Multipart, State},
+    http::{HeaderValue, StatusCode},
+    response::Response,
+};
+
+use crate::{
+    config::Config,
+    converter::ConversionOptions,
+    error::{LibreOfficeError, create_error_response},
+    filename,
+    formats::{InputFormat, OutputFormat},
+    headers::UNMATCHED_PLACEHOLDERS_HEADER,
+    libreoffice::InputFile,
+    routes::convert::{
+        Form, FormFields, MAX_FORMAT_FIELD_LEN, Peer, create_success_response, handle_conversion,
+        next_field, peer_ip, read_text, read_upload,
+    },
+    state::AppState,
+    template,
+};
+
+/// Longest JSON object of placeholder values
+const MAX_VALUES_FIELD_LEN: usize = 1024 * 1024;
+
+/// Fills the `{{name}}` placeholders of a docx or odt template with the JSON
+/// `values`, converting the result when an `output_format` is given
+#[axum::debug_handler(state = Arc<AppState>)]
+pub async fn handler(
+    State(state): State<Arc<AppState>>,
+    peer: Peer,
+    Form(mut multipart): Form,
+) -> Response {
+    let (mut template_file, template_filename, values, output_format) =
+        match extract_multipart_data(&mut multipart, state.config()).await {
+            Ok(data) => data,
+            Err(response) => return response,
+        };
+
+    let detected = match template_file.detect_file_type().await {
+        Ok(detected) => detected,
+        Err(e) => return LibreOfficeError::from_io(e).into(),
+    };
+    if !template::applies_to(detected.file_type) {
+        return LibreOfficeError::InvalidFormat(format!(
+            "templates must be docx or odt, got {}",
+            detected.mime
+        ))
+        .into();
+    }
+
+    let unmatched = match template_file
+        .edit(move |path| template::fill(path, &values))
+        .await
+    {
+        Ok(unmatched) => unmatched,
+        Err(e) => return e.into(),
+    };
+    if !unmatched.is_empty() {
+        tracing::debug!("Placeholders without a value: {:?}", unmatched);
+    }
+
+    // The filled template is returned as is unless another format was asked for
+    let (stem, _) = filename::split_extension(&template_filename);
+    let mut response = match output_format {
+        Some(output_format) if output_format != detected.extension => {
+            let filename = format!("{}.{}", stem, detected.extension);
+            handle_conversion(
+                &state,
+                peer_ip(peer),
+                template_file,
+                filename,
+                Some(output_format),
+                ConversionOptions::default(),
+            )
+            .await
+        }
+        _ => {
+            let input_format = match detected.extension.parse::<InputFormat>() {
+                Ok(format) => format,
+                Err(e) => return e.into(),
+            };
+            let output_format = match detected.extension.parse::<OutputFormat>() {
+                Ok(format) => format,
+                Err(e) => return e.into(),
+            };
+            // Outputs are restricted even when nothing is converted
+            if let Err(e) = state
+                .policy()
+                .check(&[input_format.as_str()], &output_format)
+            {
+                return e.into();
+            }
+            let output = template_file.into_output(format!("{}.{}", stem, output_format));
+            create_success_response(output, &detected, None, stem, &input_format, &output_format)
+                .await
+        }
+    };
+
+    // Placeholder names are ASCII, the header value always parses
+    if response.status() == StatusCode::OK
+        && !unmatched.is_empty()
+        && let Ok(value) = HeaderValue::from_str(&unmatched.join(", "))
+    {
+        response
+            .headers_mut()
+            .insert(UNMATCHED_PLACEHOLDERS_HEADER, value);
+    }
+    response
+}
+
+async fn extract_multipart_data(
+    multipart: &mut Multipart,
+    config: &Config,
+) -> Result<(InputFile, String, HashMap<String, String>, Option<String>), Response<Body>> {
+    let mut template_file: Option<(InputFile, String)> = None;
+    let mut values: Option<HashMap<String, String>> = None;
+    let mut output_format: Option<String> = None;
+    let mut fields = FormFields::new(config.reject_unknown_fields);
+
+    while let Some(field) = next_field(multipart).await? {
+        let name = field.name().unwrap_or("").to_string();
+
+        match name.as_str() {
+            "template" => {
+                fields.check(&name)?;
+                template_file = Some(read_upload(field, &config.work_dir).await?);
+            }
+            "values" => {
+                fields.check(&name)?;
+                let value = read_text(field, MAX_VALUES_FIELD_LEN).await?;
+                values = Some(template::parse_values(&value)?);
+            }
+            "output_format" => {
+                fields.check(&name)?;
+                output_format = Some(read_text(field, MAX_FORMAT_FIELD_LEN).await?);
+            }
+            name => fields.unknown(name)?,
+        }
+    }
+
+    match (template_file, values) {
+        (Some((template_file, template_filename)), Some(values)) => {
+            Ok((template_file, template_filename, values, output_format))
+        }
+        _ => Err(create_error_response(
+            StatusCode::BAD_REQUEST,
+            "Missing required fields: template, values",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{converter::fake::FakeConverter, routes};
+    use axum::{body::to_bytes, http::Request};
+    use hyper::header;
+    use std::io::{Cursor, Read};
+    use std::path::Path;
+    use tower::ServiceExt;
+
+    const BOUNDARY: &str = "fill-template-test-boundary";
+
+    fn template_field() -> Vec<u8> {
+        let content = std::fs::read(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/template.docx"),
+        )
+        .unwrap();
+        let mut field = format!(
+            "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"template\"; filename=\"offer.docx\"\r\n\r\n"
+        )
+        .into_bytes();
+        field.extend_from_slice(&content);
+        field.extend_from_slice(b"\r\n");
+        field
+    }
+
+    fn text_field(name: &str, value: &str) -> Vec<u8> {
+        format!(
+            "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n"
+        )
+        .into_bytes()
+    }
+
+    async fn post(
+        converter: Arc<FakeConverter>,
+        fields: &[Vec<u8>],
+    ) -> (StatusCode, header::HeaderMap, Vec<u8>) {
+        let mut body = fields.concat();
+        body.extend_from_slice(format!("--{BOUNDARY}--\r\n").as_bytes());
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/fill-template")
+            .header(
+                header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={}", BOUNDARY),
+            )
+            .body(Body::from(body))
+            .unwrap();
+
+        let state = AppState::builder().converter(converter).build();
+        let response = routes::router(Arc::new(state))
+            .oneshot(request)
+            .await
+            .unwrap();
+        let (parts, body) = response.into_parts();
+        let body = to_bytes(body, usize::MAX).await.unwrap();
+        (parts.status, parts.headers, body.to_vec())
+    }
+
+    #[tokio::test]
+    async fn test_returns_filled_template() {
+        let converter = Arc::new(FakeConverter::returning_bytes("pdf", b"%PDF-1.7"));
+        let (status, headers, body) = post(
+            converter.clone(),
+            &[
+                template_field(),
+                text_field("values", r#"{"name": "Ada Lovelace", "start": "1 May"}"#),
+            ],
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(
+            headers[header::CONTENT_DISPOSITION]
+                .to_str()
+                .unwrap()
+                .contains("offer.docx")
+        );
+        assert_eq!(headers[UNMATCHED_PLACEHOLDERS_HEADER], "salary");
+        let mut archive = zip::ZipArchive::new(Cursor::new(body)).unwrap();
+        let mut document = String::new();
+        archive
+            .by_name("word/document.xml")
+            .unwrap()
+            .read_to_string(&mut document)
+            .unwrap();
+        assert!(document.contains("Ada Lovelace"));
+        assert_eq!(converter.calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_converts_filled_template() {
+        let converter = Arc::new(FakeConverter::returning_bytes("pdf", b"%PDF-1.7"));
+        let (status, headers, body) = post(
+            converter.clone(),
+            &[
+                template_field(),
+                text_field(
+                    "values",
+                    r#"{"name": "Ada", "salary": 50000, "start": "1 May"}"#,
+                ),
+                text_field("output_format", "pdf"),
+            ],
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, b"%PDF-1.7");
+        assert!(headers.get(UNMATCHED_PLACEHOLDERS_HEADER).is_none());
+        assert_eq!(
+            converter.requests(),
+            [("docx".to_string(), "pdf".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalid_requests() {
+        let converter = Arc::new(FakeConverter::returning_bytes("pdf", b"%PDF-1.7"));
+
+        let (status, _, _) = post(converter.clone(), &[template_field()]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, _, body) = post(
+            converter.clone(),
+            &[template_field(), text_field("values", "[1, 2]")],
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "invalid_option");
+        assert_eq!(converter.calls(), 0);
+    }
+}
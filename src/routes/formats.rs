@@ -0,0 +1,11 @@
+use std::sync::Arc;
+
+use axum::{Json, extract::State, response::IntoResponse};
+
+use crate::state::AppState;
+
+/// Formats this deployment converts from and to, `ALLOWED_INPUT_TYPES` and
+/// `ALLOWED_OUTPUT_FORMATS` applied
+pub async fn handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(state.policy().formats())
+}
@@ -0,0 +1,23 @@
+use std::sync::Arc;
+
+use axum::{Json, extract::State, response::IntoResponse};
+use serde::Serialize;
+
+use crate::{
+    build_info::{self, BuildInfo},
+    config::ConfigSummary,
+    state::AppState,
+};
+
+#[derive(Serialize)]
+struct InfoResponse {
+    build: BuildInfo,
+    config: ConfigSummary,
+}
+
+pub async fn handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(InfoResponse {
+        build: build_info::get(),
+        config: state.config().summary(),
+    })
+}
@@ -0,0 +1,67 @@
+use std::sync::Arc;
+
+use axum::{extract::State, response::IntoResponse};
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+
+use crate::state::AppState;
+
+/// Installs the global Prometheus recorder, returning the handle backing the /metrics endpoint
+pub fn install_recorder() -> Option<PrometheusHandle> {
+    PrometheusBuilder::new()
+        .install_recorder()
+        .inspect_err(|e| tracing::error!("Failed to install metrics recorder: {}", e))
+        .ok()
+}
+
+pub async fn handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    state
+        .metrics()
+        .map(|handle| handle.render())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+pub mod fake {
+    use metrics_util::{
+        CompositeKey,
+        debugging::{DebugValue, DebuggingRecorder},
+    };
+
+    /// Metrics recorded by [`record`]
+    pub struct Recorded(Vec<(CompositeKey, DebugValue)>);
+
+    impl Recorded {
+        /// Total of the counters named `name` carrying all of `labels`
+        pub fn counter(&self, name: &str, labels: &[(&str, &str)]) -> u64 {
+            self.0
+                .iter()
+                .filter(|(key, _)| {
+                    let key = key.key();
+                    key.name() == name
+                        && labels.iter().all(|(label, value)| {
+                            key.labels()
+                                .any(|l| l.key() == *label && l.value() == *value)
+                        })
+                })
+                .map(|(_, value)| match value {
+                    DebugValue::Counter(count) => *count,
+                    _ => 0,
+                })
+                .sum()
+        }
+    }
+
+    /// Runs `f` with a recorder of its own on this thread, unaffected by other tests
+    pub fn record<T>(f: impl FnOnce() -> T) -> (T, Recorded) {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let result = metrics::with_local_recorder(&recorder, f);
+        let recorded = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .map(|(key, _, _, value)| (key, value))
+            .collect();
+        (result, Recorded(recorded))
+    }
+}
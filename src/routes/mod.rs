@@ -1,3 +1,257 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    Router,
+    body::Body,
+    extract::DefaultBodyLimit,
+    http::{Request, Response},
+    middleware,
+    routing::{get, post},
+};
+use tower_http::{
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    timeout::RequestBodyTimeoutLayer,
+    trace::TraceLayer,
+};
+use tracing::{Span, field::Empty};
+
+use crate::{
+    cors, deadline, headers::REQUEST_ID_HEADER, panic, request_id, retry, state::AppState,
+};
+
+pub mod convert;
+pub mod detect;
+pub mod estimate;
+pub mod fill_template;
+pub mod formats;
 pub mod health;
+pub mod info;
+pub mod metrics;
 pub mod ready;
-pub mod convert;
\ No newline at end of file
+pub mod results;
+pub mod status;
+pub mod usage;
+pub mod version;
+pub mod ws;
+
+/// Span every event of a request is logged in, conversion fields are recorded by the handler
+fn request_span(request: &Request<Body>) -> Span {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    tracing::info_span!(
+        "request",
+        request_id,
+        method = %request.method(),
+        uri = %request.uri(),
+        input_format = Empty,
+        output_format = Empty,
+        duration_ms = Empty,
+    )
+}
+
+fn on_response(response: &Response<Body>, latency: Duration, span: &Span) {
+    span.record("duration_ms", latency.as_millis() as u64);
+    tracing::info!(status = response.status().as_u16(), "Request finished");
+}
+
+/// Test route exercising [`panic::catch_panic`]
+#[cfg(test)]
+async fn panic_on_demand() -> &'static str {
+    panic!("panic on demand")
+}
+
+/// Routes for operators, served on `ADMIN_PORT` when set
+fn admin_routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/status", get(status::handler))
+        .route("/info", get(info::handler))
+        .route("/metrics", get(metrics::handler))
+        .route("/version", get(version::handler))
+        .route("/usage", get(usage::handler))
+}
+
+/// Router of the admin listener
+pub fn admin_router(state: Arc<AppState>) -> Router {
+    admin_routes()
+        .layer(TraceLayer::new_for_http().make_span_with(request_span))
+        .with_state(state)
+}
+
+/// Builds the service's router, shared by `main` and the functional tests.
+/// The admin routes are included unless they get their own port.
+pub fn router(state: Arc<AppState>) -> Router {
+    let upload_limit = DefaultBodyLimit::max(state.config().max_upload_size);
+    let mut convert_route = post(convert::handler).layer(upload_limit);
+    let mut fill_template_route = post(fill_template::handler).layer(upload_limit);
+    let mut detect_route = post(detect::handler).layer(upload_limit);
+    if let Some(cors) = cors::layer(state.config()) {
+        convert_route = convert_route.layer(cors.clone());
+        fill_template_route = fill_template_route.layer(cors.clone());
+        detect_route = detect_route.layer(cors);
+    }
+
+    let router = Router::new()
+        .route("/health", get(health::handler))
+        .route("/ready", get(ready::handler))
+        .route("/formats", get(formats::handler))
+        .route("/estimate", get(estimate::handler))
+        .route("/convert", convert_route)
+        .route("/fill-template", fill_template_route)
+        .route("/detect", detect_route)
+        .route("/results/{id}", get(results::handler))
+        .route("/ws", get(ws::handler));
+    let router = if state.config().admin_port.is_none() {
+        router.merge(admin_routes())
+    } else {
+        router
+    };
+    #[cfg(test)]
+    let router = router.route("/panic", get(panic_on_demand));
+
+    let limits = state.config().server_limits;
+    router
+        .layer(middleware::from_fn(panic::catch_panic))
+        .layer(middleware::from_fn_with_state(
+            limits.request_timeout,
+            deadline::enforce,
+        ))
+        .layer(RequestBodyTimeoutLayer::new(limits.body_read_timeout))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            retry::add_retry_after,
+        ))
+        .layer(middleware::from_fn(request_id::scope))
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(request_span)
+                .on_response(on_response),
+        )
+        .layer(PropagateRequestIdLayer::new(REQUEST_ID_HEADER))
+        .layer(SetRequestIdLayer::new(REQUEST_ID_HEADER, MakeRequestUuid))
+        .with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{backend::fake::CannedBackend, config::Config, queue::Scheduler};
+    use axum::{body::to_bytes, http::StatusCode};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_converts_with_injected_backend() {
+        // Own scheduler so the test doesn't queue behind other tests' conversions
+        let state = Arc::new(
+            AppState::builder()
+                .backend(CannedBackend)
+                .scheduler(Scheduler::new(1))
+                .build(),
+        );
+        let request = Request::builder()
+            .method("POST")
+            .uri("/convert")
+            .header("content-type", "multipart/form-data; boundary=x")
+            .body(Body::from(
+                "--x\r\nContent-Disposition: form-data; name=\"file\"; filename=\"notes.txt\"\r\n\r\n\
+                 some notes\r\n--x\r\nContent-Disposition: form-data; name=\"output_format\"\r\n\r\n\
+                 pdf\r\n--x--\r\n",
+            ))
+            .unwrap();
+
+        let response = router(state.clone()).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"%PDF-1.7 canned");
+
+        let usage = &state.usage().report().keys[crate::usage::ANONYMOUS];
+        assert_eq!((usage.requests, usage.successes), (1, 1));
+        assert_eq!(usage.input_bytes, 10);
+        assert_eq!(usage.output_bytes, body.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn test_admin_routes_move_to_admin_port() {
+        let config = Config {
+            admin_port: Some(4321),
+            ..Config::from_env()
+        };
+        let state = Arc::new(AppState::builder().config(config).build());
+
+        let request = |uri| Request::builder().uri(uri).body(Body::empty()).unwrap();
+        for uri in ["/status", "/info", "/metrics", "/usage"] {
+            let response = router(state.clone()).oneshot(request(uri)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", uri);
+            let response = admin_router(state.clone())
+                .oneshot(request(uri))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+        }
+        let response = router(state.clone())
+            .oneshot(request("/health"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = admin_router(state.clone())
+            .oneshot(request("/status"))
+            .await
+            .unwrap();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let status: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(status["limits"]["request_timeout_secs"], 300);
+
+        let response = admin_router(state).oneshot(request("/info")).await.unwrap();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let info: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(info["build"]["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(info["config"]["admin_port"], 4321);
+        assert_eq!(info["config"]["backends"], serde_json::json!(["cli"]));
+        assert!(info["config"].get("libreoffice_extra_args").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_panic_becomes_json_500() {
+        let request = Request::builder()
+            .uri("/panic")
+            .header(REQUEST_ID_HEADER, "panic-test")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = router(Arc::default()).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "panic-test");
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "internal_panic");
+        assert_eq!(body["request_id"], "panic-test");
+    }
+
+    #[tokio::test]
+    async fn test_errors_are_json_with_request_id() {
+        let request = Request::builder()
+            .method("POST")
+            .uri("/convert")
+            .header("content-type", "multipart/form-data; boundary=x")
+            .body(Body::from("--x--\r\n"))
+            .unwrap();
+
+        let response = router(Arc::default()).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let request_id = response.headers()[REQUEST_ID_HEADER]
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "bad_request");
+        assert_eq!(body["request_id"], request_id.as_str());
+    }
+}
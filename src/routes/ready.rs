@@ -1,5 +1,93 @@
-use axum::response::IntoResponse;
+use std::sync::Arc;
 
-pub async fn handler() -> impl IntoResponse {
-    "READY"
+use axum::{Json, extract::State, http::StatusCode, response::IntoResponse};
+use serde::Serialize;
+
+use crate::{selftest, state::AppState, warmup, workdir};
+
+#[derive(Serialize)]
+struct BackendStatus {
+    name: &'static str,
+    available: bool,
+}
+
+#[derive(Serialize)]
+struct ReadyResponse {
+    status: &'static str,
+    warmed_up: bool,
+    backends: Vec<BackendStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'static str>,
+}
+
+pub async fn handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let backends: Vec<BackendStatus> = state
+        .backends()
+        .backends()
+        .map(|backend| BackendStatus {
+            name: backend.name(),
+            available: backend.is_available(),
+        })
+        .collect();
+
+    if let Some(error) = selftest::failure() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ReadyResponse {
+                status: "SELF_TEST_FAILED",
+                warmed_up: warmup::is_warmed_up(),
+                backends,
+                error: Some(error),
+            }),
+        );
+    }
+
+    if !backends.iter().any(|backend| backend.available) {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ReadyResponse {
+                status: "NOT_READY",
+                warmed_up: false,
+                backends,
+                error: Some(
+                    "No conversion backend available: set LIBREOFFICE_BIN or add libreoffice/soffice to PATH",
+                ),
+            }),
+        );
+    }
+
+    // Per-conversion temp dirs and scratch HOMEs are created below the work dir
+    if !workdir::is_writable(&state.config().work_dir) {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ReadyResponse {
+                status: "NOT_READY",
+                warmed_up: warmup::is_warmed_up(),
+                backends,
+                error: Some("Work directory is not writable, check WORK_DIR"),
+            }),
+        );
+    }
+
+    if warmup::is_pending(state.config()) {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ReadyResponse {
+                status: "WARMING_UP",
+                warmed_up: false,
+                backends,
+                error: None,
+            }),
+        );
+    }
+
+    (
+        StatusCode::OK,
+        Json(ReadyResponse {
+            status: "READY",
+            warmed_up: warmup::is_warmed_up(),
+            backends,
+            error: None,
+        }),
+    )
 }
@@ -0,0 +1,40 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Response},
+};
+use hyper::header;
+
+use crate::{
+    error::LibreOfficeError, headers::CONVERSION_ID_HEADER, results::Lookup, state::AppState,
+};
+
+/// Downloads the output of a recent conversion again by its `X-Conversion-Id`,
+/// 410 once it is no longer kept
+pub async fn handler(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> Response {
+    let lookup = match state.results() {
+        Some(results) => results.get(&id),
+        None => Lookup::Missing,
+    };
+    let result = match lookup {
+        Lookup::Found(result) => result,
+        Lookup::Gone => return LibreOfficeError::ResultExpired(id).into(),
+        Lookup::Missing => return LibreOfficeError::ResultNotFound(id).into(),
+    };
+
+    let mut response = result.data.into_response();
+    let headers = response.headers_mut();
+    // Computed when the output was converted
+    headers.extend(result.checksum);
+    if let Some(content_type) = result.content_type {
+        headers.insert(header::CONTENT_TYPE, content_type);
+    }
+    if let Some(content_disposition) = result.content_disposition {
+        headers.insert(header::CONTENT_DISPOSITION, content_disposition);
+    }
+    if let Ok(id) = id.parse() {
+        headers.insert(CONVERSION_ID_HEADER, id);
+    }
+    response
+}
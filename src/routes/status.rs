@@ -0,0 +1,67 @@
+use std::sync::Arc;
+
+use axum::{Json, extract::State, response::IntoResponse};
+use serde::Serialize;
+
+use crate::{
+    config::LimitsSummary,
+    queue::{MAX_PARALLEL_CONVERSIONS, QueueSnapshot},
+    state::{AppState, CompletedConversion},
+    workdir,
+};
+
+#[derive(Serialize)]
+struct WorkDirStatus {
+    path: String,
+    temp_dirs: usize,
+    used_bytes: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    available_bytes: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct StatusResponse {
+    version: &'static str,
+    uptime_secs: u64,
+    backend: &'static str,
+    libreoffice_version: Option<String>,
+    max_parallel_conversions: usize,
+    queue: QueueSnapshot,
+    total_conversions: u64,
+    recent_conversions: Vec<CompletedConversion>,
+    work_dir: WorkDirStatus,
+    limits: LimitsSummary,
+}
+
+pub async fn handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let work_dir = state.config().work_dir.clone();
+    let work_dir_status = tokio::task::spawn_blocking(move || {
+        let (temp_dirs, used_bytes) = workdir::usage(&work_dir);
+        WorkDirStatus {
+            path: work_dir.display().to_string(),
+            temp_dirs,
+            used_bytes,
+            available_bytes: workdir::available_space(&work_dir).ok(),
+        }
+    })
+    .await
+    .ok();
+
+    Json(StatusResponse {
+        version: env!("CARGO_PKG_VERSION"),
+        uptime_secs: state.uptime().as_secs(),
+        backend: state.backends().primary().name(),
+        libreoffice_version: state.libreoffice_version().await.map(str::to_string),
+        max_parallel_conversions: MAX_PARALLEL_CONVERSIONS,
+        queue: state.scheduler().snapshot(),
+        total_conversions: state.total_conversions(),
+        recent_conversions: state.recent_conversions(),
+        work_dir: work_dir_status.unwrap_or(WorkDirStatus {
+            path: state.config().work_dir.display().to_string(),
+            temp_dirs: 0,
+            used_bytes: 0,
+            available_bytes: None,
+        }),
+        limits: state.config().server_limits.into(),
+    })
+}
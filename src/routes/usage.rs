@@ -0,0 +1,10 @@
+use std::sync::Arc;
+
+use axum::{Json, extract::State, response::IntoResponse};
+
+use crate::state::AppState;
+
+/// Conversion volume per API key since the last usage export
+pub async fn handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(state.usage().report())
+}
@@ -0,0 +1,27 @@
+use std::sync::Arc;
+
+use axum::{Json, extract::State, response::IntoResponse};
+use serde::Serialize;
+
+use crate::state::AppState;
+
+#[derive(Serialize)]
+struct VersionResponse {
+    version: &'static str,
+    backend: &'static str,
+    backends: Vec<&'static str>,
+    libreoffice_binary: Option<String>,
+}
+
+pub async fn handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let chain = state.backends();
+
+    Json(VersionResponse {
+        version: env!("CARGO_PKG_VERSION"),
+        backend: chain.primary().name(),
+        backends: chain.backends().map(|backend| backend.name()).collect(),
+        libreoffice_binary: state
+            .libreoffice_binary()
+            .map(|path| path.display().to_string()),
+    })
+}
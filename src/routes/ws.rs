@@ -0,0 +1,436 @@
+//! Conversions over one WebSocket connection instead of a multipart request.
+//!
+//! The client sends a `start` frame (`{"type":"start","filename":"report.docx",
+//! "output_format":"pdf","options":{...}}`), the document as binary frames and a
+//! `finish` frame. The server answers with `progress` frames, a `result` frame,
+//! the output as binary frames and a `done` frame, or with an `error` frame
+//! carrying the same `code`, `message` and `retryable` as the HTTP error body.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{
+        State,
+        ws::{Message, WebSocket, WebSocketUpgrade, rejection::WebSocketUpgradeRejection},
+    },
+    http::StatusCode,
+    response::Response,
+};
+use futures_util::{Stream, StreamExt};
+use hyper::header;
+use serde::{Deserialize, Serialize};
+use tokio_util::io::StreamReader;
+
+use crate::{
+    converter::ConversionOptions,
+    error::{LibreOfficeError, create_error_response, create_error_response_with_code, read_error},
+    filename,
+    headers::{CONTENT_SHA256_HEADER, CONVERSION_ID_HEADER},
+    libreoffice::InputFile,
+    routes::convert::{FormFields, Peer, handle_conversion, peer_ip},
+    state::AppState,
+};
+
+/// Largest message a client may send, uploads are split into chunks below it
+const MAX_MESSAGE_BYTES: usize = 1024 * 1024;
+
+/// Output buffered for a slow client before sending waits for it
+const MAX_WRITE_BUFFER_BYTES: usize = 4 * 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientFrame {
+    Start {
+        filename: String,
+        output_format: String,
+        /// Conversion options by form field name, strings or JSON scalars
+        #[serde(default)]
+        options: BTreeMap<String, serde_json::Value>,
+    },
+    Finish,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerFrame<'a> {
+    Progress {
+        stage: &'static str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        received_bytes: Option<u64>,
+    },
+    Result {
+        filename: &'a str,
+        content_type: Option<&'a str>,
+        size: Option<u64>,
+        /// Hex SHA-256 of the output
+        sha256: Option<&'a str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        conversion_id: Option<&'a str>,
+    },
+    Done,
+}
+
+/// Ways an upload streamed in messages ends early
+#[derive(Debug, thiserror::Error)]
+pub enum UploadError {
+    #[error("Upload larger than {0} bytes")]
+    TooLarge(usize),
+    #[error("Upload stalled, no data received in time")]
+    Stalled,
+    #[error("{0}")]
+    Protocol(&'static str),
+    #[error("Connection closed during the upload")]
+    Closed,
+}
+
+impl UploadError {
+    pub fn into_response(self) -> Response<Body> {
+        let message = self.to_string();
+        match self {
+            UploadError::TooLarge(_) => create_error_response_with_code(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "payload_too_large",
+                &message,
+            ),
+            UploadError::Stalled => create_error_response_with_code(
+                StatusCode::REQUEST_TIMEOUT,
+                "request_timeout",
+                &message,
+            ),
+            UploadError::Protocol(_) | UploadError::Closed => {
+                create_error_response_with_code(StatusCode::BAD_REQUEST, "protocol_error", &message)
+            }
+        }
+    }
+}
+
+/// Upgrades to the conversion protocol described in the module docs
+pub async fn handler(
+    State(state): State<Arc<AppState>>,
+    peer: Peer,
+    upgrade: Result<WebSocketUpgrade, WebSocketUpgradeRejection>,
+) -> Response {
+    let upgrade = match upgrade {
+        Ok(upgrade) => upgrade,
+        Err(rejection) => return create_error_response(rejection.status(), &rejection.body_text()),
+    };
+    let client_ip = peer_ip(peer);
+    upgrade
+        .max_message_size(MAX_MESSAGE_BYTES)
+        .max_frame_size(MAX_MESSAGE_BYTES)
+        .max_write_buffer_size(MAX_WRITE_BUFFER_BYTES)
+        .on_upgrade(move |socket| session(state, client_ip, socket))
+}
+
+/// Runs one conversion, bounded by `REQUEST_TIMEOUT_SECS` like an HTTP request
+async fn session(state: Arc<AppState>, client_ip: Option<IpAddr>, mut socket: WebSocket) {
+    let timeout = state.config().server_limits.request_timeout;
+    let converted = tokio::time::timeout(timeout, convert(&state, client_ip, &mut socket)).await;
+    let response = match converted {
+        Ok(Ok(())) => None,
+        Ok(Err(response)) => Some(response),
+        Err(_) => {
+            metrics::counter!("http_request_timeouts_total", "reason" => "deadline").increment(1);
+            Some(LibreOfficeError::RequestTimeout(timeout).into())
+        }
+    };
+    if let Some(response) = response {
+        let _ = socket.send(error_frame(response).await).await;
+    }
+    let _ = socket.send(Message::Close(None)).await;
+}
+
+/// Receives the upload, converts it and streams the output back. Errors are
+/// returned as the HTTP error response they correspond to.
+async fn convert(
+    state: &AppState,
+    client_ip: Option<IpAddr>,
+    socket: &mut WebSocket,
+) -> Result<(), Response<Body>> {
+    let body_read_timeout = state.config().server_limits.body_read_timeout;
+    let start = match tokio::time::timeout(body_read_timeout, socket.recv()).await {
+        Ok(Some(Ok(Message::Text(text)))) => serde_json::from_str(&text).ok(),
+        Ok(None | Some(Err(_))) => return Ok(()),
+        Ok(Some(Ok(_))) => None,
+        Err(_) => return Err(UploadError::Stalled.into_response()),
+    };
+    let Some(ClientFrame::Start {
+        filename,
+        output_format,
+        options: fields,
+    }) = start
+    else {
+        return Err(UploadError::Protocol("expected a start frame").into_response());
+    };
+
+    let mut options = ConversionOptions::default();
+    let form_fields = FormFields::new(state.config().reject_unknown_fields);
+    for (name, value) in fields {
+        if !ConversionOptions::is_field(&name) {
+            form_fields.unknown(&name)?;
+            continue;
+        }
+        let value = match value {
+            serde_json::Value::String(value) => value,
+            value => value.to_string(),
+        };
+        options.set_field(&name, &value)?;
+    }
+
+    let max_bytes = state.config().max_upload_size;
+    let mut reader = StreamReader::new(Box::pin(upload(socket, max_bytes, body_read_timeout)));
+    let uploaded = InputFile::from_reader(&mut reader, &state.config().work_dir).await;
+    drop(reader);
+    let input_file = match uploaded {
+        Ok(file) => file,
+        Err(e) => match e.downcast::<UploadError>() {
+            Ok(UploadError::Closed) => return Ok(()),
+            Ok(e) => return Err(e.into_response()),
+            Err(e) => return Err(LibreOfficeError::from_io(e).into()),
+        },
+    };
+
+    send(
+        socket,
+        &ServerFrame::Progress {
+            stage: "uploaded",
+            received_bytes: Some(input_file.len()),
+        },
+    )
+    .await;
+    send(
+        socket,
+        &ServerFrame::Progress {
+            stage: "converting",
+            received_bytes: None,
+        },
+    )
+    .await;
+
+    let input_filename = filename::sanitize_filename(&filename);
+    let (stem, _) = filename::split_extension(&input_filename);
+    let output_filename = format!("{}.{}", stem, output_format);
+    let response = handle_conversion(
+        state,
+        client_ip,
+        input_file,
+        input_filename.clone(),
+        Some(output_format),
+        options,
+    )
+    .await;
+    if response.status() != StatusCode::OK {
+        return Err(response);
+    }
+
+    let (parts, body) = response.into_parts();
+    let header = |name: &str| {
+        parts
+            .headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    send(
+        socket,
+        &ServerFrame::Result {
+            filename: &output_filename,
+            content_type: header(header::CONTENT_TYPE.as_str()),
+            size: header(header::CONTENT_LENGTH.as_str()).and_then(|len| len.parse().ok()),
+            sha256: header(CONTENT_SHA256_HEADER),
+            conversion_id: header(CONVERSION_ID_HEADER),
+        },
+    )
+    .await;
+
+    // Sending waits while the write buffer is full, a slow client slows the read
+    let mut data = body.into_data_stream();
+    while let Some(chunk) = data.next().await {
+        let chunk = chunk.map_err(|e| LibreOfficeError::from_io(io::Error::other(e)))?;
+        if socket.send(Message::Binary(chunk)).await.is_err() {
+            return Ok(());
+        }
+    }
+    send(socket, &ServerFrame::Done).await;
+    Ok(())
+}
+
+/// Binary frames up to the `finish` frame, at most `max_bytes` of them
+fn upload(
+    socket: &mut WebSocket,
+    max_bytes: usize,
+    stall_timeout: Duration,
+) -> impl Stream<Item = io::Result<Bytes>> + '_ {
+    futures_util::stream::try_unfold((socket, 0), move |(socket, received)| async move {
+        loop {
+            let message = match tokio::time::timeout(stall_timeout, socket.recv()).await {
+                Ok(Some(Ok(message))) => message,
+                Ok(None | Some(Err(_))) => return Err(io::Error::other(UploadError::Closed)),
+                Err(_) => return Err(io::Error::other(UploadError::Stalled)),
+            };
+            let error = match message {
+                Message::Binary(chunk) => {
+                    let received = received + chunk.len();
+                    if received > max_bytes {
+                        UploadError::TooLarge(max_bytes)
+                    } else {
+                        return Ok(Some((chunk, (socket, received))));
+                    }
+                }
+                Message::Text(text) => match serde_json::from_str(&text) {
+                    Ok(ClientFrame::Finish) => return Ok(None),
+                    _ => UploadError::Protocol("expected binary chunks or a finish frame"),
+                },
+                Message::Close(_) => UploadError::Closed,
+                // Pings are answered by the socket itself
+                Message::Ping(_) | Message::Pong(_) => continue,
+            };
+            return Err(io::Error::other(error));
+        }
+    })
+}
+
+async fn send(socket: &mut WebSocket, frame: &ServerFrame<'_>) {
+    if let Ok(frame) = serde_json::to_string(frame) {
+        let _ = socket.send(Message::Text(frame.into())).await;
+    }
+}
+
+/// The JSON body of an error response as an `error` frame
+async fn error_frame(response: Response<Body>) -> Message {
+    let (status, mut frame) = read_error(response).await;
+    frame.insert("type".to_string(), "error".into());
+    frame.insert("status".to_string(), status.as_u16().into());
+    Message::Text(serde_json::Value::Object(frame).to_string().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, converter::fake::FakeConverter, routes};
+    use futures_util::SinkExt;
+    use tokio_tungstenite::tungstenite::Message as ClientMessage;
+
+    /// Serves the router, returning the URL of `/ws`
+    async fn start(converter: Arc<FakeConverter>, config: Config) -> String {
+        let state = AppState::builder()
+            .config(config)
+            .converter(converter)
+            .build();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(axum::serve(listener, routes::router(Arc::new(state))).into_future());
+        format!("ws://{}/ws", addr)
+    }
+
+    /// Sends `messages`, returning the JSON frames and binary data received until
+    /// the server closes the connection
+    async fn exchange(
+        url: &str,
+        messages: Vec<ClientMessage>,
+    ) -> (Vec<serde_json::Value>, Vec<u8>) {
+        let (mut socket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        for message in messages {
+            // The server may close the connection before everything was sent
+            if socket.send(message).await.is_err() {
+                break;
+            }
+        }
+
+        let (mut frames, mut data) = (Vec::new(), Vec::new());
+        while let Some(Ok(message)) = socket.next().await {
+            match message {
+                ClientMessage::Text(text) => frames.push(serde_json::from_str(&text).unwrap()),
+                ClientMessage::Binary(chunk) => data.extend_from_slice(&chunk),
+                ClientMessage::Close(_) => break,
+                _ => {}
+            }
+        }
+        (frames, data)
+    }
+
+    fn start_frame(output_format: &str) -> ClientMessage {
+        let frame = serde_json::json!({
+            "type": "start",
+            "filename": "report.docx",
+            "output_format": output_format,
+            "options": {"strip_metadata": true},
+        });
+        ClientMessage::Text(frame.to_string().into())
+    }
+
+    fn finish_frame() -> ClientMessage {
+        ClientMessage::Text(r#"{"type":"finish"}"#.into())
+    }
+
+    #[tokio::test]
+    async fn test_converts_over_websocket() {
+        let converter = Arc::new(FakeConverter::returning_bytes("pdf", b"%PDF-1.7"));
+        let url = start(converter.clone(), crate::config::get().clone()).await;
+
+        let (frames, data) = exchange(
+            &url,
+            vec![
+                start_frame("pdf"),
+                ClientMessage::Binary(b"PK\x03"[..].into()),
+                ClientMessage::Binary(b"\x04"[..].into()),
+                finish_frame(),
+            ],
+        )
+        .await;
+        let types: Vec<_> = frames.iter().map(|frame| &frame["type"]).collect();
+        assert_eq!(types, ["progress", "progress", "result", "done"]);
+        assert_eq!(frames[0]["received_bytes"], 4);
+        assert_eq!(frames[2]["filename"], "report.pdf");
+        assert_eq!(frames[2]["content_type"], "application/pdf");
+        assert_eq!(frames[2]["sha256"].as_str().unwrap().len(), 64);
+        assert_eq!(data, b"%PDF-1.7");
+        assert!(converter.last_options().unwrap().strip_metadata);
+    }
+
+    #[tokio::test]
+    async fn test_errors_arrive_as_error_frames() {
+        let converter = Arc::new(FakeConverter::returning_bytes("pdf", b"%PDF-1.7"));
+        let config = Config {
+            max_upload_size: 4,
+            ..crate::config::get().clone()
+        };
+        let url = start(converter.clone(), config).await;
+
+        let cases = [
+            (
+                vec![
+                    start_frame("pdf"),
+                    ClientMessage::Binary(b"PK\x03\x04 and more"[..].into()),
+                    finish_frame(),
+                ],
+                "payload_too_large",
+                413,
+            ),
+            (
+                vec![start_frame("not a format"), finish_frame()],
+                "invalid_format",
+                400,
+            ),
+            (
+                vec![ClientMessage::Binary(b"PK\x03\x04"[..].into())],
+                "protocol_error",
+                400,
+            ),
+        ];
+        for (messages, code, status) in cases {
+            let (frames, data) = exchange(&url, messages).await;
+            let error = frames.last().unwrap();
+            assert_eq!(error["type"], "error");
+            assert_eq!(error["code"], code);
+            assert_eq!(error["status"], status);
+            assert_eq!(error["retryable"], false);
+            assert!(data.is_empty());
+        }
+        assert_eq!(converter.calls(), 0);
+    }
+}
@@ -0,0 +1,211 @@
+//! Client of ClamAV's clamd, streaming uploads with the INSTREAM command over TCP
+//! or a unix socket
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UnixStream};
+
+use super::{ScanOutcome, Scanner};
+
+/// Bytes sent per INSTREAM chunk, well below clamd's default StreamMaxLength
+const CHUNK_LEN: usize = 64 * 1024;
+
+/// Where clamd listens: `host:port`, or a socket path given as `unix:/path` or `/path`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClamdAddr {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl FromStr for ClamdAddr {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        if let Some(path) = value.strip_prefix("unix:") {
+            return Ok(Self::Unix(PathBuf::from(path)));
+        }
+        if value.starts_with('/') {
+            return Ok(Self::Unix(PathBuf::from(value)));
+        }
+        match value.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+                Ok(Self::Tcp(value.to_string()))
+            }
+            _ => Err("expected host:port or a unix socket path".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for ClamdAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => f.write_str(addr),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Scans with a clamd instance, one connection per scan
+pub struct ClamdScanner {
+    addr: ClamdAddr,
+}
+
+impl ClamdScanner {
+    pub fn new(addr: ClamdAddr) -> Self {
+        Self { addr }
+    }
+
+    async fn scan_file(&self, path: &Path) -> std::io::Result<String> {
+        let file = tokio::fs::File::open(path).await?;
+        match &self.addr {
+            ClamdAddr::Tcp(addr) => instream(TcpStream::connect(addr).await?, file).await,
+            ClamdAddr::Unix(path) => instream(UnixStream::connect(path).await?, file).await,
+        }
+    }
+}
+
+#[async_trait]
+impl Scanner for ClamdScanner {
+    fn name(&self) -> &'static str {
+        "clamd"
+    }
+
+    async fn scan(&self, path: &Path) -> ScanOutcome {
+        match self.scan_file(path).await {
+            Ok(reply) => parse_reply(&reply),
+            Err(e) => ScanOutcome::Error(format!("clamd at {}: {}", self.addr, e)),
+        }
+    }
+}
+
+/// Streams `file` as length-prefixed chunks ended by an empty one, and reads the
+/// NUL-terminated reply
+async fn instream(
+    mut stream: impl AsyncRead + AsyncWrite + Unpin,
+    mut file: impl AsyncRead + Unpin,
+) -> std::io::Result<String> {
+    stream.write_all(b"zINSTREAM\0").await?;
+    let mut chunk = vec![0; CHUNK_LEN];
+    loop {
+        let read = file.read(&mut chunk).await?;
+        stream.write_all(&(read as u32).to_be_bytes()).await?;
+        if read == 0 {
+            break;
+        }
+        stream.write_all(&chunk[..read]).await?;
+    }
+    stream.flush().await?;
+
+    let mut reply = Vec::new();
+    stream.read_to_end(&mut reply).await?;
+    let reply = reply.strip_suffix(b"\0").unwrap_or(&reply);
+    Ok(String::from_utf8_lossy(reply).trim().to_string())
+}
+
+/// Reads `stream: OK`, `stream: <signature> FOUND` or `... ERROR` replies
+fn parse_reply(reply: &str) -> ScanOutcome {
+    let verdict = reply.split_once(": ").map_or(reply, |(_, verdict)| verdict);
+    if verdict == "OK" {
+        ScanOutcome::Clean
+    } else if let Some(signature) = verdict.strip_suffix(" FOUND") {
+        ScanOutcome::Infected(signature.to_string())
+    } else {
+        ScanOutcome::Error(format!("clamd replied {:?}", reply))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::UnixListener;
+
+    #[test]
+    fn test_addr() {
+        assert_eq!(
+            "clamav:3310".parse(),
+            Ok(ClamdAddr::Tcp("clamav:3310".to_string()))
+        );
+        assert_eq!(
+            "unix:/run/clamd.sock".parse(),
+            Ok(ClamdAddr::Unix(PathBuf::from("/run/clamd.sock")))
+        );
+        assert_eq!(
+            "/run/clamd.sock".parse(),
+            Ok(ClamdAddr::Unix(PathBuf::from("/run/clamd.sock")))
+        );
+        assert!("clamav".parse::<ClamdAddr>().is_err());
+        assert!("clamav:port".parse::<ClamdAddr>().is_err());
+    }
+
+    #[test]
+    fn test_parse_reply() {
+        assert_eq!(parse_reply("stream: OK"), ScanOutcome::Clean);
+        assert_eq!(
+            parse_reply("stream: Win.Test.EICAR_HDB-1 FOUND"),
+            ScanOutcome::Infected("Win.Test.EICAR_HDB-1".to_string())
+        );
+        assert!(matches!(
+            parse_reply("INSTREAM size limit exceeded. ERROR"),
+            ScanOutcome::Error(_)
+        ));
+    }
+
+    /// Fake clamd answering one INSTREAM, flagging content containing `EICAR`
+    async fn serve_once(listener: UnixListener) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut command = [0; 10];
+        stream.read_exact(&mut command).await.unwrap();
+        assert_eq!(&command, b"zINSTREAM\0");
+
+        let mut content = Vec::new();
+        loop {
+            let len = stream.read_u32().await.unwrap() as usize;
+            if len == 0 {
+                break;
+            }
+            let mut chunk = vec![0; len];
+            stream.read_exact(&mut chunk).await.unwrap();
+            content.extend(chunk);
+        }
+        let reply: &[u8] = if content.windows(5).any(|window| window == b"EICAR") {
+            b"stream: Eicar-Signature FOUND\0"
+        } else {
+            b"stream: OK\0"
+        };
+        stream.write_all(reply).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_scan_over_unix_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("clamd.sock");
+        let scanner = ClamdScanner::new(ClamdAddr::Unix(socket.clone()));
+
+        for (content, expected) in [
+            (b"plain document".as_slice(), ScanOutcome::Clean),
+            (
+                b"X5O!P%@AP[4\\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*",
+                ScanOutcome::Infected("Eicar-Signature".to_string()),
+            ),
+        ] {
+            let _ = std::fs::remove_file(&socket);
+            let server = tokio::spawn(serve_once(UnixListener::bind(&socket).unwrap()));
+            let path = dir.path().join("upload");
+            std::fs::write(&path, content).unwrap();
+
+            assert_eq!(scanner.scan(&path).await, expected);
+            server.await.unwrap();
+        }
+
+        std::fs::remove_file(&socket).unwrap();
+        assert!(matches!(
+            scanner.scan(&dir.path().join("upload")).await,
+            ScanOutcome::Error(_)
+        ));
+    }
+}
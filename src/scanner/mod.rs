@@ -0,0 +1,174 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+
+use crate::{
+    config::Config,
+    error::{LibreOfficeError, Result},
+};
+
+#[cfg(feature = "clamav")]
+pub mod clamd;
+
+/// Verdict of a scanner on an upload. Only clamd gives verdicts, without it every
+/// scan is an error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(not(any(feature = "clamav", test)), allow(dead_code))]
+pub enum ScanOutcome {
+    Clean,
+    /// Name of the signature that matched
+    Infected(String),
+    /// The scanner could not be reached or gave no verdict
+    Error(String),
+}
+
+/// Malware scanner uploads go through before LibreOffice opens them
+#[async_trait]
+pub trait Scanner: Send + Sync {
+    /// Identifier used in metric labels
+    fn name(&self) -> &'static str;
+
+    async fn scan(&self, path: &Path) -> ScanOutcome;
+}
+
+/// Stand-in for a configured scanner that can't be used, every scan fails
+struct UnavailableScanner(String);
+
+#[async_trait]
+impl Scanner for UnavailableScanner {
+    fn name(&self) -> &'static str {
+        "unavailable"
+    }
+
+    async fn scan(&self, _path: &Path) -> ScanOutcome {
+        ScanOutcome::Error(self.0.clone())
+    }
+}
+
+/// Scanner configured by `SCANNER_ADDR`, none when scanning is off
+pub fn from_config(config: &Config) -> Option<Arc<dyn Scanner>> {
+    let addr = config.scanner_addr.as_ref()?;
+
+    #[cfg(feature = "clamav")]
+    let scanner: std::result::Result<Arc<dyn Scanner>, String> = addr
+        .parse()
+        .map(|addr| -> Arc<dyn Scanner> { Arc::new(clamd::ClamdScanner::new(addr)) })
+        .map_err(|e| format!("invalid SCANNER_ADDR {:?}: {}", addr, e));
+    #[cfg(not(feature = "clamav"))]
+    let scanner: std::result::Result<Arc<dyn Scanner>, String> = Err(format!(
+        "SCANNER_ADDR is {:?} but the service was built without the clamav feature",
+        addr
+    ));
+
+    match scanner {
+        Ok(scanner) => Some(scanner),
+        Err(reason) => {
+            tracing::error!("Uploads can't be scanned: {}", reason);
+            Some(Arc::new(UnavailableScanner(reason)))
+        }
+    }
+}
+
+/// Scans the upload at `path`, rejecting infected files and, when `fail_closed`,
+/// files the scanner gave no verdict on within `timeout`. Returns how long the
+/// scan took.
+pub async fn check(
+    scanner: &dyn Scanner,
+    path: &Path,
+    timeout: Duration,
+    fail_closed: bool,
+) -> Result<Duration> {
+    let started = Instant::now();
+    let outcome = tokio::time::timeout(timeout, scanner.scan(path))
+        .await
+        .unwrap_or_else(|_| {
+            ScanOutcome::Error(format!(
+                "{} gave no verdict within {:?}",
+                scanner.name(),
+                timeout
+            ))
+        });
+    let elapsed = started.elapsed();
+
+    let label = match &outcome {
+        ScanOutcome::Clean => "clean",
+        ScanOutcome::Infected(_) => "infected",
+        ScanOutcome::Error(_) => "error",
+    };
+    metrics::histogram!("upload_scan_duration_seconds", "scanner" => scanner.name())
+        .record(elapsed.as_secs_f64());
+    metrics::counter!("upload_scans_total", "scanner" => scanner.name(), "outcome" => label)
+        .increment(1);
+
+    match outcome {
+        ScanOutcome::Clean => Ok(elapsed),
+        ScanOutcome::Infected(signature) => {
+            tracing::warn!("Upload rejected, {} found by {}", signature, scanner.name());
+            Err(LibreOfficeError::Infected(signature))
+        }
+        ScanOutcome::Error(reason) if fail_closed => {
+            tracing::error!("Upload not scanned, rejecting it: {}", reason);
+            Err(LibreOfficeError::ScannerUnavailable(reason))
+        }
+        ScanOutcome::Error(reason) => {
+            tracing::warn!("Upload not scanned, converting it anyway: {}", reason);
+            Ok(elapsed)
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod fake {
+    use super::*;
+
+    /// Scanner answering every scan with the same outcome
+    pub struct FakeScanner(pub ScanOutcome);
+
+    #[async_trait]
+    impl Scanner for FakeScanner {
+        fn name(&self) -> &'static str {
+            "fake"
+        }
+
+        async fn scan(&self, _path: &Path) -> ScanOutcome {
+            self.0.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fake::FakeScanner;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_check() {
+        let path = Path::new("document.docx");
+        let check =
+            |scanner, path, fail_closed| check(scanner, path, Duration::from_secs(5), fail_closed);
+        assert!(
+            check(&FakeScanner(ScanOutcome::Clean), path, true)
+                .await
+                .is_ok()
+        );
+
+        let infected = FakeScanner(ScanOutcome::Infected("Eicar-Signature".to_string()));
+        assert!(matches!(
+            check(&infected, path, false).await,
+            Err(LibreOfficeError::Infected(signature)) if signature == "Eicar-Signature"
+        ));
+
+        let down = FakeScanner(ScanOutcome::Error("connection refused".to_string()));
+        assert!(check(&down, path, false).await.is_ok());
+        assert!(matches!(
+            check(&down, path, true).await,
+            Err(LibreOfficeError::ScannerUnavailable(_))
+        ));
+        assert!(matches!(
+            check(&UnavailableScanner("no clamd".to_string()), path, true).await,
+            Err(LibreOfficeError::ScannerUnavailable(_))
+        ));
+    }
+}
@@ -0,0 +1,222 @@
+//! Checks at startup that the container can convert at all. A misconfigured
+//! deployment otherwise boots, passes /health and then fails every conversion.
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::{
+    converter::{ConversionOptions, Converter},
+    error::LibreOfficeError,
+    libreoffice::InputFile,
+    state::AppState,
+    workdir,
+};
+
+/// Oldest LibreOffice accepted
+const MIN_VERSION: (u32, u32) = (7, 0);
+
+/// Directories fontconfig reads the system fonts from
+const FONT_DIRS: &[&str] = &["/usr/share/fonts", "/usr/local/share/fonts"];
+
+/// Directory levels searched for a font file
+const MAX_FONT_DIR_DEPTH: usize = 4;
+
+/// Why the self-test failed, phrased as what to fix
+static FAILURE: OnceLock<String> = OnceLock::new();
+
+/// The prerequisite of conversions that is missing
+#[derive(Debug, thiserror::Error)]
+pub enum SelfTestError {
+    #[error(
+        "LibreOffice executable not found, set LIBREOFFICE_BIN or add libreoffice/soffice to PATH"
+    )]
+    BinaryNotFound,
+    #[error(
+        "{0:?} did not report a LibreOffice version, check that it is a LibreOffice executable"
+    )]
+    UnknownVersion(PathBuf),
+    #[error("{found} is too old, LibreOffice {}.{} or newer is required", MIN_VERSION.0, MIN_VERSION.1)]
+    VersionTooOld { found: String },
+    #[error("Work directory {0:?} is not writable, check WORK_DIR and its permissions")]
+    WorkDirNotWritable(PathBuf),
+    #[error("Trial txt -> pdf conversion failed: {0}")]
+    TrialConversion(LibreOfficeError),
+}
+
+/// Runs every check against the configured installation, stopping at the first
+/// failing one
+pub async fn run(state: &AppState) -> Result<(), SelfTestError> {
+    let binary = check_binary(state.libreoffice_binary())?;
+    check_version(binary, state.libreoffice_version().await)?;
+    check_work_dir(&state.config().work_dir)?;
+    let font_dirs: Vec<&Path> = FONT_DIRS.iter().map(Path::new).collect();
+    if !has_fonts(&font_dirs) {
+        tracing::warn!(
+            "No fonts found in {}, documents will render with substituted fonts",
+            FONT_DIRS.join(", ")
+        );
+    }
+    check_conversion(state.converter(), &state.config().work_dir).await?;
+    tracing::info!("Self-test passed");
+    Ok(())
+}
+
+/// Keeps `/ready` at 503 with `error` for the rest of the process
+pub fn fail(error: &SelfTestError) {
+    let _ = FAILURE.set(error.to_string());
+}
+
+/// What the failed self-test found missing, none unless it failed
+pub fn failure() -> Option<&'static str> {
+    FAILURE.get().map(String::as_str)
+}
+
+/// The LibreOffice executable, resolved from `LIBREOFFICE_BIN` or PATH
+pub fn check_binary(binary: Option<&Path>) -> Result<&Path, SelfTestError> {
+    binary.ok_or(SelfTestError::BinaryNotFound)
+}
+
+/// Checks the first line of `--version` output of `binary` against [`MIN_VERSION`]
+pub fn check_version(binary: &Path, version: Option<&str>) -> Result<(), SelfTestError> {
+    let version = version.ok_or_else(|| SelfTestError::UnknownVersion(binary.to_path_buf()))?;
+    match parse_version(version) {
+        Some(found) if found >= MIN_VERSION => Ok(()),
+        Some(_) => Err(SelfTestError::VersionTooOld {
+            found: version.to_string(),
+        }),
+        None => Err(SelfTestError::UnknownVersion(binary.to_path_buf())),
+    }
+}
+
+/// Whether the per-conversion temp dirs can be created in the work dir
+pub fn check_work_dir(dir: &Path) -> Result<(), SelfTestError> {
+    if workdir::is_writable(dir) {
+        Ok(())
+    } else {
+        Err(SelfTestError::WorkDirNotWritable(dir.to_path_buf()))
+    }
+}
+
+/// Whether any of `dirs` holds a file, searched [`MAX_FONT_DIR_DEPTH`] levels deep
+pub fn has_fonts(dirs: &[&Path]) -> bool {
+    dirs.iter().any(|dir| holds_file(dir, MAX_FONT_DIR_DEPTH))
+}
+
+fn holds_file(dir: &Path, depth: usize) -> bool {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return false;
+    };
+    entries.flatten().any(|entry| match entry.file_type() {
+        Ok(file_type) if file_type.is_dir() => depth > 1 && holds_file(&entry.path(), depth - 1),
+        Ok(_) => true,
+        Err(_) => false,
+    })
+}
+
+/// Converts a line of text to PDF with `converter`
+pub async fn check_conversion(
+    converter: &dyn Converter,
+    work_dir: &Path,
+) -> Result<(), SelfTestError> {
+    let mut text: &[u8] = b"libreoffice-rest self-test";
+    let input = InputFile::from_reader(&mut text, work_dir)
+        .await
+        .map_err(|e| SelfTestError::TrialConversion(LibreOfficeError::from_io(e)))?;
+    converter
+        .convert(
+            input,
+            &"txt".parse().expect("txt is a supported input format"),
+            &"pdf".parse().expect("pdf is a supported output format"),
+            &ConversionOptions::default(),
+        )
+        .await
+        .map_err(SelfTestError::TrialConversion)?;
+    Ok(())
+}
+
+/// Major and minor version of `LibreOffice 24.8.4.2 480(Build:2)`
+fn parse_version(version: &str) -> Option<(u32, u32)> {
+    let number = version
+        .split_whitespace()
+        .find(|word| word.starts_with(|c: char| c.is_ascii_digit()))?;
+    let mut parts = number.split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next().map_or(Some(0), |minor| minor.parse().ok())?;
+    Some((major, minor))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::converter::fake::FakeConverter;
+
+    #[test]
+    fn test_check_binary() {
+        assert!(matches!(
+            check_binary(None),
+            Err(SelfTestError::BinaryNotFound)
+        ));
+        let binary = Path::new("/usr/bin/soffice");
+        assert_eq!(check_binary(Some(binary)).unwrap(), binary);
+    }
+
+    #[test]
+    fn test_check_version() {
+        let binary = Path::new("/usr/bin/soffice");
+        assert!(check_version(binary, Some("LibreOffice 24.8.4.2 480(Build:2)")).is_ok());
+        assert!(check_version(binary, Some("LibreOffice 7.0.4.2 00(Build:2)")).is_ok());
+        assert!(matches!(
+            check_version(binary, Some("LibreOffice 6.4.7.2 40(Build:2)")),
+            Err(SelfTestError::VersionTooOld { .. })
+        ));
+        assert!(matches!(
+            check_version(binary, Some("not an office suite")),
+            Err(SelfTestError::UnknownVersion(_))
+        ));
+        assert!(matches!(
+            check_version(binary, None),
+            Err(SelfTestError::UnknownVersion(_))
+        ));
+    }
+
+    #[test]
+    fn test_check_work_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(check_work_dir(dir.path()).is_ok());
+        assert!(matches!(
+            check_work_dir(&dir.path().join("missing")),
+            Err(SelfTestError::WorkDirNotWritable(_))
+        ));
+    }
+
+    #[test]
+    fn test_has_fonts() {
+        let dir = tempfile::tempdir().unwrap();
+        let truetype = dir.path().join("truetype/dejavu");
+        std::fs::create_dir_all(&truetype).unwrap();
+        assert!(!has_fonts(&[dir.path(), &dir.path().join("missing")]));
+
+        std::fs::write(truetype.join("DejaVuSans.ttf"), b"").unwrap();
+        assert!(has_fonts(&[&dir.path().join("missing"), dir.path()]));
+    }
+
+    #[tokio::test]
+    async fn test_check_conversion() {
+        let converter = FakeConverter::returning_bytes("pdf", b"%PDF");
+        assert!(
+            check_conversion(&converter, &std::env::temp_dir())
+                .await
+                .is_ok()
+        );
+        assert_eq!(converter.calls(), 1);
+
+        let failing = FakeConverter::failing(LibreOfficeError::BinaryNotFound);
+        let error = check_conversion(&failing, &std::env::temp_dir())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Trial txt -> pdf conversion failed: LibreOffice executable not found"
+        );
+    }
+}
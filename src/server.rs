@@ -0,0 +1,244 @@
+use std::net::{IpAddr, SocketAddr};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::Path;
+use std::sync::Arc;
+
+use axum::Router;
+use tokio::net::{TcpListener, UnixListener};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+use crate::{config::Config, connections, state::AppState};
+
+/// Parses `HOST` (IPv4 or IPv6, optionally in brackets) into the address to bind
+pub fn socket_addr(host: &str, port: u16) -> Result<SocketAddr, String> {
+    let host = host.trim();
+    let unbracketed = host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host);
+
+    unbracketed
+        .parse::<IpAddr>()
+        .map(|ip| SocketAddr::new(ip, port))
+        .map_err(|_| format!("HOST {:?} is not a valid IPv4 or IPv6 address", host))
+}
+
+/// Removes a socket file left behind by an earlier run, refusing to touch anything else
+fn remove_stale_socket(path: &Path) -> Result<(), String> {
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path)
+            .map_err(|e| format!("Cannot remove stale socket {:?}: {}", path, e)),
+        Ok(_) => Err(format!(
+            "LISTEN_UNIX_SOCKET {:?} exists and is not a socket",
+            path
+        )),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(format!("Cannot inspect {:?}: {}", path, e)),
+    }
+}
+
+fn bind_unix(path: &Path, mode: u32) -> Result<UnixListener, String> {
+    remove_stale_socket(path)?;
+    let listener =
+        UnixListener::bind(path).map_err(|e| format!("Cannot bind {:?}: {}", path, e))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+        .map_err(|e| format!("Cannot set permissions of {:?}: {}", path, e))?;
+    Ok(listener)
+}
+
+/// Resolves on SIGINT or SIGTERM
+async fn shutdown_signal() {
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::warn!("Cannot listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate => {}
+    }
+    tracing::info!("Shutting down");
+}
+
+fn fail(message: String) -> ! {
+    tracing::error!("{}", message);
+    std::process::exit(1);
+}
+
+/// Broadcasts SIGINT/SIGTERM so the API and admin servers drain together
+fn shutdown_channel() -> watch::Receiver<bool> {
+    let (sender, receiver) = watch::channel(false);
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        let _ = sender.send(true);
+    });
+    receiver
+}
+
+/// Resolves once shutdown was signalled on `receiver`
+async fn shutdown_requested(mut receiver: watch::Receiver<bool>) {
+    let _ = receiver.wait_for(|requested| *requested).await;
+}
+
+/// Serves the admin routes on `HOST`:`ADMIN_PORT` in the background
+async fn spawn_admin(
+    admin: Router,
+    host: &str,
+    port: u16,
+    shutdown: watch::Receiver<bool>,
+) -> JoinHandle<()> {
+    let addr = socket_addr(host, port).unwrap_or_else(|e| fail(e));
+    let listener = TcpListener::bind(addr)
+        .await
+        .unwrap_or_else(|e| fail(format!("Cannot bind admin port {}: {}", addr, e)));
+    tracing::info!(
+        "Starting admin server on {}",
+        listener.local_addr().unwrap_or(addr)
+    );
+
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, admin)
+            .with_graceful_shutdown(shutdown_requested(shutdown))
+            .await
+        {
+            tracing::error!("Admin server error: {}", e);
+        }
+    })
+}
+
+/// Serves the gRPC service on `HOST`:`GRPC_PORT` in the background
+#[cfg(feature = "grpc")]
+async fn spawn_grpc(
+    state: Arc<AppState>,
+    port: u16,
+    shutdown: watch::Receiver<bool>,
+) -> Option<JoinHandle<()>> {
+    use crate::grpc::{ConversionServer, Service};
+
+    let addr = socket_addr(&state.config().host, port).unwrap_or_else(|e| fail(e));
+    let listener = TcpListener::bind(addr)
+        .await
+        .unwrap_or_else(|e| fail(format!("Cannot bind gRPC port {}: {}", addr, e)));
+    tracing::info!(
+        "Starting gRPC server on {}",
+        listener.local_addr().unwrap_or(addr)
+    );
+
+    let request_timeout = state.config().server_limits.request_timeout;
+    Some(tokio::spawn(async move {
+        if let Err(e) = tonic::transport::Server::builder()
+            .timeout(request_timeout)
+            .add_service(ConversionServer::new(Service::new(state)))
+            .serve_with_incoming_shutdown(
+                tonic::transport::server::TcpIncoming::from(listener),
+                shutdown_requested(shutdown),
+            )
+            .await
+        {
+            tracing::error!("gRPC server error: {}", e);
+        }
+    }))
+}
+
+#[cfg(not(feature = "grpc"))]
+async fn spawn_grpc(
+    _state: Arc<AppState>,
+    port: u16,
+    _shutdown: watch::Receiver<bool>,
+) -> Option<JoinHandle<()>> {
+    tracing::warn!(
+        "GRPC_PORT is {} but the service was built without the grpc feature",
+        port
+    );
+    None
+}
+
+/// Serves `app` on `LISTEN_UNIX_SOCKET` when set, otherwise on `HOST`:`PORT`,
+/// `admin` on `ADMIN_PORT` and the gRPC service of `state` on `GRPC_PORT` when
+/// set, all as configured in `state`. All stop on the same signal.
+pub async fn serve(app: Router, admin: Router, state: Arc<AppState>) {
+    let config = state.config();
+    let shutdown = shutdown_channel();
+    let admin = match config.admin_port {
+        Some(port) => Some(spawn_admin(admin, &config.host, port, shutdown.clone()).await),
+        None => None,
+    };
+    let grpc = match config.grpc_port {
+        Some(port) => spawn_grpc(state.clone(), port, shutdown.clone()).await,
+        None => None,
+    };
+
+    serve_api(app, config, shutdown).await;
+    for server in [admin, grpc].into_iter().flatten() {
+        let _ = server.await;
+    }
+}
+
+async fn serve_api(app: Router, config: &Config, shutdown: watch::Receiver<bool>) {
+    if let Some(path) = &config.unix_socket {
+        let listener = bind_unix(path, config.unix_socket_mode).unwrap_or_else(|e| fail(e));
+        tracing::info!(
+            "Starting server on unix socket {:?} (mode {:o})",
+            path,
+            config.unix_socket_mode
+        );
+
+        connections::serve(listener, app, config.server_limits, shutdown).await;
+        if let Err(e) = std::fs::remove_file(path) {
+            tracing::warn!("Cannot remove socket {:?}: {}", path, e);
+        }
+        return;
+    }
+
+    let addr = socket_addr(&config.host, config.port).unwrap_or_else(|e| fail(e));
+    let listener = TcpListener::bind(addr)
+        .await
+        .unwrap_or_else(|e| fail(format!("Cannot bind {}: {}", addr, e)));
+    let bound = listener.local_addr().unwrap_or(addr);
+    tracing::info!("Starting server on {}", bound);
+
+    connections::serve(listener, app, config.server_limits, shutdown).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_socket_addr() {
+        assert_eq!(
+            socket_addr("127.0.0.1", 1234).unwrap().to_string(),
+            "127.0.0.1:1234"
+        );
+        assert_eq!(socket_addr("::1", 1234).unwrap().to_string(), "[::1]:1234");
+        assert_eq!(socket_addr("[::]", 1234).unwrap().to_string(), "[::]:1234");
+        assert!(socket_addr("localhost", 1234).is_err());
+        assert!(socket_addr("300.0.0.1", 1234).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_unix_socket_replaces_stale_socket_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("service.sock");
+
+        let listener = bind_unix(&path, 0o660).unwrap();
+        drop(listener);
+        // The socket file outlives the listener, as after a crash
+        let _listener = bind_unix(&path, 0o600).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let file = dir.path().join("not-a-socket");
+        std::fs::write(&file, b"data").unwrap();
+        assert!(bind_unix(&file, 0o660).is_err());
+        assert!(file.exists());
+    }
+}
@@ -0,0 +1,395 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use metrics_exporter_prometheus::PrometheusHandle;
+use serde::Serialize;
+
+use crate::{
+    audit::Auditor,
+    backend::{self, BackendChain, ConversionBackend},
+    config::{self, Config},
+    converter::Converter,
+    formats::ConversionPolicy,
+    latency::LatencyProfile,
+    libreoffice::{self, LibreOfficeConverter},
+    queue::Scheduler,
+    results::Results,
+    usage::UsageLedger,
+};
+
+/// Completed conversions kept for `/status`
+const RECENT_CONVERSIONS: usize = 20;
+
+/// Longest filename shown in `/status`
+const MAX_FILENAME_LEN: usize = 64;
+
+/// A finished conversion as listed by `/status`
+#[derive(Debug, Clone, Serialize)]
+pub struct CompletedConversion {
+    pub filename: String,
+    pub input_format: String,
+    pub output_format: String,
+    pub duration_ms: u64,
+    /// `success` or the error code
+    pub outcome: &'static str,
+    /// Unix timestamp in seconds
+    pub finished_at: u64,
+}
+
+/// State shared by the handlers: configuration, the converter, metrics and the
+/// conversions handled so far
+pub struct AppState {
+    config: Arc<Config>,
+    converter: Arc<dyn Converter>,
+    backends: Arc<BackendChain>,
+    scheduler: Arc<Scheduler>,
+    libreoffice_binary: Option<PathBuf>,
+    libreoffice_version: tokio::sync::OnceCell<Option<String>>,
+    metrics: Option<PrometheusHandle>,
+    auditor: Option<Auditor>,
+    results: Option<Results>,
+    latency: LatencyProfile,
+    usage: UsageLedger,
+    started: Instant,
+    total_conversions: AtomicU64,
+    recent: Mutex<VecDeque<CompletedConversion>>,
+    policy: ConversionPolicy,
+}
+
+impl AppState {
+    /// State backed by the global configuration and backend chain
+    pub fn new() -> Self {
+        Self::builder().build()
+    }
+
+    /// Builder of a state with a converter or configuration of its own
+    pub fn builder() -> AppStateBuilder {
+        AppStateBuilder::default()
+    }
+
+    /// Configuration the handlers run with
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub(crate) fn policy(&self) -> &ConversionPolicy {
+        &self.policy
+    }
+
+    /// Converter of `/convert` and the other conversion endpoints
+    pub fn converter(&self) -> &dyn Converter {
+        self.converter.as_ref()
+    }
+
+    /// Backends of the LibreOffice converter, reported by /ready and /version
+    pub fn backends(&self) -> &BackendChain {
+        &self.backends
+    }
+
+    pub(crate) fn scheduler(&self) -> &Scheduler {
+        &self.scheduler
+    }
+
+    /// LibreOffice executable of the configuration, from `LIBREOFFICE_BIN` or PATH
+    pub fn libreoffice_binary(&self) -> Option<&Path> {
+        self.libreoffice_binary.as_deref()
+    }
+
+    /// Version reported by the LibreOffice executable, queried once
+    pub async fn libreoffice_version(&self) -> Option<&str> {
+        self.libreoffice_version
+            .get_or_init(|| async {
+                libreoffice::libreoffice_version(self.libreoffice_binary()?).await
+            })
+            .await
+            .as_deref()
+    }
+
+    /// Handle of the Prometheus recorder, unset when it could not be installed
+    pub(crate) fn metrics(&self) -> Option<&PrometheusHandle> {
+        self.metrics.as_ref()
+    }
+
+    /// Audit trail of conversions, unset when auditing is off
+    pub(crate) fn auditor(&self) -> Option<&Auditor> {
+        self.auditor.as_ref()
+    }
+
+    /// Outputs kept for downloading again, unset without `RESULT_RETENTION_SECS`
+    pub(crate) fn results(&self) -> Option<&Results> {
+        self.results.as_ref()
+    }
+
+    /// Durations of recent conversions, what `/estimate` predicts from
+    pub(crate) fn latency(&self) -> &LatencyProfile {
+        &self.latency
+    }
+
+    /// Conversion volume per API key, served by `/usage`
+    pub(crate) fn usage(&self) -> &UsageLedger {
+        &self.usage
+    }
+
+    /// Time since the state was built
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Conversions attempted so far, failed ones included
+    pub fn total_conversions(&self) -> u64 {
+        self.total_conversions.load(Ordering::Relaxed)
+    }
+
+    /// Most recent first
+    pub(crate) fn recent_conversions(&self) -> Vec<CompletedConversion> {
+        self.recent.lock().unwrap().iter().rev().cloned().collect()
+    }
+
+    /// Average duration of the recent successful conversions, none without any
+    pub fn average_conversion_duration(&self) -> Option<Duration> {
+        let recent = self.recent.lock().unwrap();
+        let durations: Vec<u64> = recent
+            .iter()
+            .filter(|conversion| conversion.outcome == "success")
+            .map(|conversion| conversion.duration_ms)
+            .collect();
+        let count = durations.len() as u64;
+        (count > 0).then(|| Duration::from_millis(durations.iter().sum::<u64>() / count))
+    }
+
+    /// Records a finished conversion, dropping the oldest beyond [`RECENT_CONVERSIONS`]
+    pub(crate) fn record_conversion(
+        &self,
+        filename: &str,
+        input_format: &str,
+        output_format: &str,
+        duration: Duration,
+        outcome: &'static str,
+    ) {
+        self.total_conversions.fetch_add(1, Ordering::Relaxed);
+
+        let conversion = CompletedConversion {
+            filename: truncate(filename, MAX_FILENAME_LEN),
+            input_format: input_format.to_string(),
+            output_format: output_format.to_string(),
+            duration_ms: duration.as_millis() as u64,
+            outcome,
+            finished_at: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        };
+
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == RECENT_CONVERSIONS {
+            recent.pop_front();
+        }
+        recent.push_back(conversion);
+    }
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Builds an [`AppState`], anything not set falls back to the global instance
+#[derive(Default)]
+pub struct AppStateBuilder {
+    config: Option<Config>,
+    converter: Option<Arc<dyn Converter>>,
+    backends: Vec<Box<dyn ConversionBackend>>,
+    scheduler: Option<Scheduler>,
+    metrics: Option<PrometheusHandle>,
+    auditor: Option<Auditor>,
+}
+
+impl AppStateBuilder {
+    /// Replaces the global configuration read from the environment
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Replaces the LibreOffice converter, e.g. with a fake in handler tests
+    pub fn converter(mut self, converter: Arc<dyn Converter>) -> Self {
+        self.converter = Some(converter);
+        self
+    }
+
+    /// Adds a backend to the chain, replacing the configured backends
+    pub fn backend(mut self, backend: impl ConversionBackend + 'static) -> Self {
+        self.backends.push(Box::new(backend));
+        self
+    }
+
+    #[cfg(test)]
+    pub(crate) fn scheduler(mut self, scheduler: Scheduler) -> Self {
+        self.scheduler = Some(scheduler);
+        self
+    }
+
+    /// Replaces the auditor of the configured sink
+    #[cfg(test)]
+    pub(crate) fn auditor(mut self, auditor: Auditor) -> Self {
+        self.auditor = Some(auditor);
+        self
+    }
+
+    pub(crate) fn metrics(mut self, metrics: Option<PrometheusHandle>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Builds the state, the converter falling back to a [`LibreOfficeConverter`]
+    /// over the backends
+    pub fn build(self) -> AppState {
+        let injected = self.config.map(Arc::new);
+        let backends = match &injected {
+            _ if !self.backends.is_empty() => Arc::new(BackendChain::new(self.backends)),
+            // The backends run LibreOffice with the given config, not the global one
+            Some(config) => Arc::new(BackendChain::from_config(config.clone())),
+            None => backend::chain().clone(),
+        };
+        let config = injected.unwrap_or_else(|| Arc::new(config::get().clone()));
+        let scheduler = Arc::new(
+            self.scheduler
+                .unwrap_or_else(|| Scheduler::new(config.interactive_weight)),
+        );
+
+        let converter = self.converter.unwrap_or_else(|| {
+            Arc::new(LibreOfficeConverter::new(
+                config.clone(),
+                backends.clone(),
+                scheduler.clone(),
+            ))
+        });
+
+        let auditor = self.auditor.or_else(|| Auditor::from_config(&config));
+        let policy = ConversionPolicy::from_config(&config);
+        let results = Results::from_config(&config);
+        let latency = LatencyProfile::from_config(&config);
+        let libreoffice_binary = libreoffice::find_libreoffice(&config);
+
+        AppState {
+            config,
+            converter,
+            backends,
+            scheduler,
+            libreoffice_binary,
+            libreoffice_version: tokio::sync::OnceCell::new(),
+            metrics: self.metrics,
+            auditor,
+            results,
+            latency,
+            usage: UsageLedger::new(),
+            started: Instant::now(),
+            total_conversions: AtomicU64::new(0),
+            recent: Mutex::new(VecDeque::with_capacity(RECENT_CONVERSIONS)),
+            policy,
+        }
+    }
+}
+
+/// Shortens `value` to `max` characters, marking the cut with an ellipsis
+fn truncate(value: &str, max: usize) -> String {
+    if value.chars().count() <= max {
+        return value.to_string();
+    }
+
+    let mut truncated: String = value.chars().take(max - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::Lane;
+
+    #[test]
+    fn test_recent_conversions_are_bounded() {
+        let state = AppState::new();
+        for i in 0..RECENT_CONVERSIONS + 5 {
+            state.record_conversion(
+                &format!("file-{}.docx", i),
+                "docx",
+                "pdf",
+                Duration::from_millis(10),
+                "success",
+            );
+        }
+
+        let recent = state.recent_conversions();
+        assert_eq!(recent.len(), RECENT_CONVERSIONS);
+        assert_eq!(
+            recent[0].filename,
+            format!("file-{}.docx", RECENT_CONVERSIONS + 4)
+        );
+        assert_eq!(state.total_conversions(), RECENT_CONVERSIONS as u64 + 5);
+    }
+
+    #[test]
+    fn test_average_conversion_duration_ignores_failures() {
+        let state = AppState::new();
+        assert_eq!(state.average_conversion_duration(), None);
+
+        for (millis, outcome) in [(100, "success"), (300, "success"), (5000, "timeout")] {
+            let duration = Duration::from_millis(millis);
+            state.record_conversion("a.docx", "docx", "pdf", duration, outcome);
+        }
+        assert_eq!(
+            state.average_conversion_duration(),
+            Some(Duration::from_millis(200))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_libreoffice_binary_of_the_config() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let program = dir.path().join("soffice");
+        std::fs::write(&program, "#!/bin/sh\necho 'LibreOffice 7.6.4.1'\n").unwrap();
+        std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let state = AppState::builder()
+            .config(Config {
+                libreoffice_bin: Some(program.display().to_string()),
+                ..config::get().clone()
+            })
+            .build();
+
+        assert_eq!(state.libreoffice_binary(), Some(program.as_path()));
+        assert_eq!(
+            state.libreoffice_version().await,
+            Some("LibreOffice 7.6.4.1")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_states_have_a_scheduler_of_their_own() {
+        let first = AppState::new();
+        let second = AppState::new();
+
+        let _permit = first.scheduler().acquire(Lane::Interactive).await;
+        assert_eq!(second.scheduler().snapshot().running, 0);
+        let acquired = tokio::time::timeout(
+            Duration::from_secs(1),
+            second.scheduler().acquire(Lane::Interactive),
+        )
+        .await;
+        assert!(acquired.is_ok());
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("short.pdf", 64), "short.pdf");
+        let long = "x".repeat(100);
+        let truncated = truncate(&long, 64);
+        assert_eq!(truncated.chars().count(), 64);
+        assert!(truncated.ends_with('…'));
+    }
+}
@@ -0,0 +1,284 @@
+//! Filling of `{{name}}` placeholders in docx and odt templates, done on the XML of
+//! the document without LibreOffice. Word splits text into runs wherever the
+//! formatting or the editing session changes, so placeholders are matched on the
+//! text of a whole paragraph; the value takes the place of the first run the
+//! placeholder touches and the rest of the placeholder is cut from the others.
+
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::{
+    detect_filetype::FileType,
+    error::{LibreOfficeError, Result},
+    office_xml::{self, escape, tag_name},
+};
+
+/// Elements whose text placeholders can't span
+const PARAGRAPHS: &[&str] = &["w:p", "text:p", "text:h"];
+
+/// Whether documents of this type can be filled
+pub fn applies_to(file_type: FileType) -> bool {
+    matches!(file_type, FileType::Word | FileType::OpenDocumentText)
+}
+
+/// Values of the `values` form field, a JSON object of strings, numbers or booleans
+pub fn parse_values(json: &str) -> Result<HashMap<String, String>> {
+    let invalid = || {
+        LibreOfficeError::InvalidOption(
+            "values must be a JSON object of strings, numbers or booleans".to_string(),
+        )
+    };
+
+    let values: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_str(json).map_err(|_| invalid())?;
+    values
+        .into_iter()
+        .map(|(name, value)| match value {
+            serde_json::Value::String(value) => Ok((name, value)),
+            serde_json::Value::Number(_) | serde_json::Value::Bool(_) => {
+                Ok((name, value.to_string()))
+            }
+            _ => Err(invalid()),
+        })
+        .collect()
+}
+
+/// Fills the placeholders of the document at `path` in place, returning the names
+/// of those without a value, which are left as they are
+pub fn fill(path: &Path, values: &HashMap<String, String>) -> Result<Vec<String>> {
+    let unmatched = RefCell::new(BTreeSet::new());
+    office_xml::rewrite_entries(path, is_content_part, |_, xml| {
+        fill_part(xml, values, &mut unmatched.borrow_mut())
+    })?;
+    Ok(unmatched.into_inner().into_iter().collect())
+}
+
+/// Body, headers, footers and notes of docx documents, content and the styles
+/// holding headers and footers of odt documents
+fn is_content_part(name: &str) -> bool {
+    if matches!(name, "content.xml" | "styles.xml") {
+        return true;
+    }
+    name.strip_prefix("word/").is_some_and(|rest| {
+        !rest.contains('/')
+            && rest.ends_with(".xml")
+            && ["document", "header", "footer", "footnotes", "endnotes"]
+                .iter()
+                .any(|part| rest.starts_with(part))
+    })
+}
+
+fn fill_part(
+    xml: &str,
+    values: &HashMap<String, String>,
+    unmatched: &mut BTreeSet<String>,
+) -> String {
+    let mut output = String::with_capacity(xml.len());
+    let mut copied = 0;
+
+    for paragraph in paragraphs(xml) {
+        let text: String = paragraph.iter().map(|node| &xml[node.clone()]).collect();
+        let fills: Vec<_> = placeholders(&text)
+            .filter_map(|(range, name)| match values.get(name) {
+                Some(value) => Some((range, escape(value))),
+                None => {
+                    unmatched.insert(name.to_string());
+                    None
+                }
+            })
+            .collect();
+        if fills.is_empty() {
+            continue;
+        }
+
+        let mut offset = 0;
+        for node in paragraph {
+            output.push_str(&xml[copied..node.start]);
+            output.push_str(&fill_node(&xml[node.clone()], offset, &fills));
+            copied = node.end;
+            offset += node.len();
+        }
+    }
+
+    output.push_str(&xml[copied..]);
+    output
+}
+
+/// Text nodes of every paragraph, as ranges of `xml`
+fn paragraphs(xml: &str) -> Vec<Vec<Range<usize>>> {
+    let mut paragraphs = vec![Vec::new()];
+    let mut from = 0;
+
+    while let Some(offset) = xml[from..].find('<') {
+        let start = from + offset;
+        if start > from {
+            paragraphs.last_mut().unwrap().push(from..start);
+        }
+        let Some(len) = xml[start..].find('>') else {
+            return paragraphs;
+        };
+        let name = tag_name(&xml[start..start + len + 1]);
+        if PARAGRAPHS.contains(&name.trim_start_matches('/')) {
+            paragraphs.push(Vec::new());
+        }
+        from = start + len + 1;
+    }
+    paragraphs
+}
+
+/// `{{name}}` placeholders of a paragraph's text, spaces inside the braces allowed
+fn placeholders(text: &str) -> impl Iterator<Item = (Range<usize>, &str)> {
+    let mut from = 0;
+    std::iter::from_fn(move || {
+        while let Some(offset) = text[from..].find("{{") {
+            let start = from + offset;
+            from = start + 2;
+            let len = text[from..].find("}}")?;
+            let name = text[from..from + len].trim();
+            let valid = !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "_.-".contains(c));
+            if valid {
+                from += len + 2;
+                return Some((start..from, name));
+            }
+        }
+        None
+    })
+}
+
+/// Text of the node starting at `offset` in the paragraph's text, with the fills
+/// that overlap it applied
+fn fill_node(node: &str, offset: usize, fills: &[(Range<usize>, String)]) -> String {
+    let end = offset + node.len();
+    let mut filled = String::with_capacity(node.len());
+    let mut position = offset;
+
+    for (range, value) in fills {
+        if range.end <= offset || range.start >= end {
+            continue;
+        }
+        if range.start > position {
+            filled.push_str(&node[position - offset..range.start - offset]);
+        }
+        if range.start >= offset {
+            filled.push_str(value);
+        }
+        position = range.end.min(end);
+    }
+    filled.push_str(&node[position - offset..]);
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::Read;
+
+    fn values(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn fill_xml(xml: &str, pairs: &[(&str, &str)]) -> (String, Vec<String>) {
+        let mut unmatched = BTreeSet::new();
+        let filled = fill_part(xml, &values(pairs), &mut unmatched);
+        (filled, unmatched.into_iter().collect())
+    }
+
+    #[test]
+    fn test_placeholder_in_one_run() {
+        let (filled, unmatched) = fill_xml(
+            "<w:p><w:r><w:t>Dear {{ name }}, welcome</w:t></w:r></w:p>",
+            &[("name", "Ada")],
+        );
+        assert_eq!(filled, "<w:p><w:r><w:t>Dear Ada, welcome</w:t></w:r></w:p>");
+        assert!(unmatched.is_empty());
+    }
+
+    #[test]
+    fn test_placeholder_split_across_runs() {
+        let xml = "<w:p><w:r><w:t>Salary: {{sal</w:t></w:r><w:r><w:rPr><w:b/></w:rPr><w:t>ary}</w:t></w:r><w:r><w:t>} per year</w:t></w:r></w:p>";
+        let (filled, _) = fill_xml(xml, &[("salary", "50 000")]);
+        assert_eq!(
+            filled,
+            "<w:p><w:r><w:t>Salary: 50 000</w:t></w:r><w:r><w:rPr><w:b/></w:rPr><w:t></w:t></w:r><w:r><w:t> per year</w:t></w:r></w:p>"
+        );
+    }
+
+    #[test]
+    fn test_values_are_escaped() {
+        let (filled, _) = fill_xml(
+            "<text:p>{{company}}</text:p>",
+            &[("company", "Smith & <Sons>")],
+        );
+        assert_eq!(filled, "<text:p>Smith &amp; &lt;Sons&gt;</text:p>");
+    }
+
+    #[test]
+    fn test_unmatched_and_malformed_placeholders() {
+        let xml = "<w:p><w:t>{{first}} {{missing}} {{not a name}} {{</w:t></w:p><w:p><w:t>first}}</w:t></w:p>";
+        let (filled, unmatched) = fill_xml(xml, &[("first", "1")]);
+        assert_eq!(
+            filled,
+            "<w:p><w:t>1 {{missing}} {{not a name}} {{</w:t></w:p><w:p><w:t>first}}</w:t></w:p>"
+        );
+        assert_eq!(unmatched, ["missing"]);
+    }
+
+    #[test]
+    fn test_parse_values() {
+        let values = parse_values(r#"{"name": "Ada", "salary": 50000, "remote": true}"#).unwrap();
+        assert_eq!(values["name"], "Ada");
+        assert_eq!(values["salary"], "50000");
+        assert_eq!(values["remote"], "true");
+
+        for json in ["[]", r#"{"name": null}"#, r#"{"name": ["Ada"]}"#, "{"] {
+            assert!(
+                matches!(parse_values(json), Err(LibreOfficeError::InvalidOption(_))),
+                "{}",
+                json
+            );
+        }
+    }
+
+    #[test]
+    fn test_fill_fixture() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("template.docx");
+        std::fs::copy(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/template.docx"),
+            &path,
+        )
+        .unwrap();
+
+        let unmatched = fill(
+            &path,
+            &values(&[("name", "Ada Lovelace"), ("start", "1 May")]),
+        )
+        .unwrap();
+
+        assert_eq!(unmatched, ["salary"]);
+        let mut archive = zip::ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        let mut read = |name: &str| {
+            let mut xml = String::new();
+            archive
+                .by_name(name)
+                .unwrap()
+                .read_to_string(&mut xml)
+                .unwrap();
+            xml
+        };
+        let document = read("word/document.xml");
+        assert!(document.contains("Ada Lovelace"));
+        assert!(document.contains("{{salary}}"));
+        assert!(!document.contains("name}}"));
+        assert!(read("word/header1.xml").contains("1 May"));
+    }
+}
@@ -0,0 +1,190 @@
+//! Tracked changes of Word documents, accepted or rejected before the export by
+//! rewriting the revision markup of the document parts. Insertions, deletions and
+//! moves are resolved; formatting changes always keep the current formatting, and
+//! deleted paragraph marks or table rows only lose their revision mark.
+
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::{
+    detect_filetype::FileType,
+    error::{LibreOfficeError, Result},
+    office_xml::{self, remove_elements, rename_elements, unwrap_elements},
+};
+
+/// What happens to the tracked changes of a document
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TrackedChanges {
+    /// Rendered with revision marks, what LibreOffice does by default
+    Show,
+    Accept,
+    Reject,
+}
+
+impl TrackedChanges {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TrackedChanges::Show => "show",
+            TrackedChanges::Accept => "accept",
+            TrackedChanges::Reject => "reject",
+        }
+    }
+}
+
+impl fmt::Display for TrackedChanges {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for TrackedChanges {
+    type Err = LibreOfficeError;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "show" => Ok(TrackedChanges::Show),
+            "accept" => Ok(TrackedChanges::Accept),
+            "reject" => Ok(TrackedChanges::Reject),
+            _ => Err(LibreOfficeError::InvalidOption(
+                "changes must be one of accept, reject, show".to_string(),
+            )),
+        }
+    }
+}
+
+/// Whether changes of documents of this type can be accepted or rejected
+pub fn applies_to(file_type: FileType) -> bool {
+    matches!(file_type, FileType::Word | FileType::WordMacro)
+}
+
+/// Accepts or rejects the tracked changes of the docx document at `path` in place
+pub fn apply(path: &Path, changes: TrackedChanges) -> Result<()> {
+    if changes == TrackedChanges::Show {
+        return Ok(());
+    }
+    office_xml::rewrite_entries(path, is_document_part, |_, xml| rewrite(xml, changes))
+}
+
+/// Body, headers, footers, notes and comments, styles can hold formatting changes
+fn is_document_part(name: &str) -> bool {
+    name.strip_prefix("word/")
+        .is_some_and(|rest| rest.ends_with(".xml") && !rest.contains('/'))
+}
+
+/// Records of formatting changes, holding the properties as they were before
+const PROPERTY_CHANGES: &[&str] = &[
+    "w:rPrChange",
+    "w:pPrChange",
+    "w:sectPrChange",
+    "w:tblPrChange",
+    "w:tblPrExChange",
+    "w:tblGridChange",
+    "w:trPrChange",
+    "w:tcPrChange",
+    "w:numberingChange",
+];
+
+/// Bookmarks delimiting the source and destination of a move
+const MOVE_RANGES: &[&str] = &[
+    "w:moveFromRangeStart",
+    "w:moveFromRangeEnd",
+    "w:moveToRangeStart",
+    "w:moveToRangeEnd",
+];
+
+fn rewrite(xml: &str, changes: TrackedChanges) -> String {
+    let (removed, kept) = match changes {
+        TrackedChanges::Show => return xml.to_string(),
+        TrackedChanges::Accept => (["w:del", "w:moveFrom"], ["w:ins", "w:moveTo"]),
+        TrackedChanges::Reject => (["w:ins", "w:moveTo"], ["w:del", "w:moveFrom"]),
+    };
+
+    let mut xml = xml.to_string();
+    for name in removed.iter().chain(PROPERTY_CHANGES).chain(MOVE_RANGES) {
+        xml = remove_elements(&xml, name);
+    }
+    for name in kept {
+        xml = unwrap_elements(&xml, name);
+    }
+    if changes == TrackedChanges::Reject {
+        // Deleted text is kept in its own elements, which would stay invisible
+        xml = rename_elements(&xml, "w:delText", "w:t");
+        xml = rename_elements(&xml, "w:delInstrText", "w:instrText");
+    }
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::Read;
+
+    const PARAGRAPH: &str = r#"<w:p><w:pPr><w:rPr><w:ins w:id="4" w:author="A"/></w:rPr></w:pPr><w:r><w:t>Kept </w:t></w:r><w:del w:id="1" w:author="A"><w:r><w:delText xml:space="preserve">removed </w:delText></w:r></w:del><w:ins w:id="2" w:author="A"><w:r><w:rPr><w:b/><w:rPrChange w:id="3" w:author="A"><w:rPr/></w:rPrChange></w:rPr><w:t>added</w:t></w:r></w:ins></w:p>"#;
+
+    #[test]
+    fn test_accept() {
+        assert_eq!(
+            rewrite(PARAGRAPH, TrackedChanges::Accept),
+            r#"<w:p><w:pPr><w:rPr></w:rPr></w:pPr><w:r><w:t>Kept </w:t></w:r><w:r><w:rPr><w:b/></w:rPr><w:t>added</w:t></w:r></w:p>"#
+        );
+    }
+
+    #[test]
+    fn test_reject() {
+        assert_eq!(
+            rewrite(PARAGRAPH, TrackedChanges::Reject),
+            r#"<w:p><w:pPr><w:rPr></w:rPr></w:pPr><w:r><w:t>Kept </w:t></w:r><w:r><w:t xml:space="preserve">removed </w:t></w:r></w:p>"#
+        );
+    }
+
+    #[test]
+    fn test_moves() {
+        let xml = r#"<w:moveFromRangeStart w:id="1" w:name="move1"/><w:moveFrom w:id="2"><w:r><w:t>moved</w:t></w:r></w:moveFrom><w:moveFromRangeEnd w:id="1"/><w:moveTo w:id="3"><w:r><w:t>moved</w:t></w:r></w:moveTo>"#;
+        assert_eq!(
+            rewrite(xml, TrackedChanges::Accept),
+            "<w:r><w:t>moved</w:t></w:r>"
+        );
+        assert_eq!(
+            rewrite(xml, TrackedChanges::Reject),
+            "<w:r><w:t>moved</w:t></w:r>"
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "Accept".parse::<TrackedChanges>().unwrap(),
+            TrackedChanges::Accept
+        );
+        assert!(matches!(
+            "hide".parse::<TrackedChanges>(),
+            Err(LibreOfficeError::InvalidOption(_))
+        ));
+    }
+
+    #[test]
+    fn test_apply_to_fixture() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("document.docx");
+        std::fs::copy(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tracked-changes.docx"),
+            &path,
+        )
+        .unwrap();
+
+        apply(&path, TrackedChanges::Accept).unwrap();
+
+        let mut archive = zip::ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        let mut document = String::new();
+        archive
+            .by_name("word/document.xml")
+            .unwrap()
+            .read_to_string(&mut document)
+            .unwrap();
+        assert!(!document.contains("<w:del "));
+        assert!(!document.contains("<w:ins "));
+        assert!(document.contains("Hello from libreoffice-rest"));
+    }
+}
@@ -0,0 +1,261 @@
+//! Conversion volume per API key, what internal teams are billed by. Keys are only
+//! ever reported by their label; the service has no authentication yet, so every
+//! conversion is accounted to [`ANONYMOUS`].
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use serde::Serialize;
+
+use crate::{audit, state::AppState};
+
+/// Label of conversions made without an API key
+pub const ANONYMOUS: &str = "anonymous";
+
+/// Counters of one API key
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct KeyUsage {
+    /// Conversion requests, refused ones included
+    pub requests: u64,
+    pub successes: u64,
+    pub failures: u64,
+    /// Size of every upload
+    pub input_bytes: u64,
+    /// Size of the primary output of successful conversions
+    pub output_bytes: u64,
+    /// Time LibreOffice took for the successful conversions
+    pub conversion_seconds: f64,
+}
+
+/// Usage since the counters were last reset, as served by `/usage`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UsageReport {
+    /// RFC 3339 time the counters were reset
+    pub since: String,
+    /// Keyed by API key label
+    pub keys: BTreeMap<String, KeyUsage>,
+}
+
+/// Usage of one API key over an export interval, written to the audit sink
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UsageRecord {
+    /// Always `usage`, telling these apart from conversion records
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    /// RFC 3339 start and end of the interval
+    pub period_start: String,
+    pub period_end: String,
+    pub api_key: String,
+    #[serde(flatten)]
+    pub usage: KeyUsage,
+}
+
+/// Counters per API key label since the last reset
+pub struct UsageLedger {
+    period: Mutex<(SystemTime, BTreeMap<String, KeyUsage>)>,
+}
+
+impl UsageLedger {
+    pub fn new() -> Self {
+        Self {
+            period: Mutex::new((SystemTime::now(), BTreeMap::new())),
+        }
+    }
+
+    /// Accounts a successful conversion to `key`
+    pub fn record_success(
+        &self,
+        key: &str,
+        input_bytes: u64,
+        output_bytes: u64,
+        conversion: Duration,
+    ) {
+        self.update(key, |usage| {
+            usage.successes += 1;
+            usage.input_bytes += input_bytes;
+            usage.output_bytes += output_bytes;
+            usage.conversion_seconds += conversion.as_secs_f64();
+        });
+        metrics::counter!("usage_successes_total", "api_key" => key.to_string()).increment(1);
+        metrics::counter!("usage_input_bytes_total", "api_key" => key.to_string())
+            .increment(input_bytes);
+        metrics::counter!("usage_output_bytes_total", "api_key" => key.to_string())
+            .increment(output_bytes);
+        metrics::counter!("usage_conversion_milliseconds_total", "api_key" => key.to_string())
+            .increment(conversion.as_millis() as u64);
+    }
+
+    /// Accounts a conversion request to `key` that failed or was refused
+    pub fn record_failure(&self, key: &str, input_bytes: u64) {
+        self.update(key, |usage| {
+            usage.failures += 1;
+            usage.input_bytes += input_bytes;
+        });
+        metrics::counter!("usage_failures_total", "api_key" => key.to_string()).increment(1);
+        metrics::counter!("usage_input_bytes_total", "api_key" => key.to_string())
+            .increment(input_bytes);
+    }
+
+    fn update(&self, key: &str, f: impl FnOnce(&mut KeyUsage)) {
+        let mut period = self.period.lock().unwrap();
+        let usage = period.1.entry(key.to_string()).or_default();
+        usage.requests += 1;
+        f(usage);
+        metrics::counter!("usage_requests_total", "api_key" => key.to_string()).increment(1);
+    }
+
+    /// Counters since the last reset
+    pub fn report(&self) -> UsageReport {
+        let period = self.period.lock().unwrap();
+        UsageReport {
+            since: audit::rfc3339(period.0),
+            keys: period.1.clone(),
+        }
+    }
+
+    /// Resets the counters, returning what they held as one record per key
+    pub fn take(&self) -> Vec<UsageRecord> {
+        let now = SystemTime::now();
+        let (start, keys) =
+            std::mem::replace(&mut *self.period.lock().unwrap(), (now, BTreeMap::new()));
+        keys.into_iter()
+            .map(|(api_key, usage)| UsageRecord {
+                kind: "usage",
+                period_start: audit::rfc3339(start),
+                period_end: audit::rfc3339(now),
+                api_key,
+                usage,
+            })
+            .collect()
+    }
+}
+
+impl Default for UsageLedger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Exports the usage every `USAGE_EXPORT_INTERVAL_SECS`
+pub fn spawn_exporter(state: Arc<AppState>) {
+    let Some(interval) = state.config().usage_export_interval else {
+        return;
+    };
+    if state.auditor().is_none() {
+        tracing::warn!(
+            "USAGE_EXPORT_INTERVAL_SECS is set without AUDIT_SINK, usage is reset without being exported"
+        );
+    }
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            export(&state).await;
+        }
+    });
+}
+
+/// Writes the usage since the last export to the audit sink and resets it
+pub async fn export(state: &AppState) {
+    if state.config().usage_export_interval.is_none() {
+        return;
+    }
+    let records = state.usage().take();
+    let Some(auditor) = state.auditor() else {
+        return;
+    };
+    for record in records {
+        auditor.record_usage(record).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        audit::{Auditor, FailureMode, fake::MemorySink},
+        config::{self, Config},
+        routes::metrics::fake,
+    };
+
+    #[test]
+    fn test_ledger() {
+        let ledger = UsageLedger::new();
+        let ((), recorded) = fake::record(|| {
+            ledger.record_success(ANONYMOUS, 1000, 400, Duration::from_millis(1500));
+            ledger.record_success(ANONYMOUS, 3000, 600, Duration::from_millis(500));
+            ledger.record_failure(ANONYMOUS, 50);
+            ledger.record_failure("reporting", 10);
+        });
+
+        let report = ledger.report();
+        assert_eq!(
+            report.keys[ANONYMOUS],
+            KeyUsage {
+                requests: 3,
+                successes: 2,
+                failures: 1,
+                input_bytes: 4050,
+                output_bytes: 1000,
+                conversion_seconds: 2.0,
+            }
+        );
+        assert_eq!(report.keys["reporting"].failures, 1);
+
+        let anonymous = [("api_key", ANONYMOUS)];
+        assert_eq!(recorded.counter("usage_requests_total", &anonymous), 3);
+        assert_eq!(
+            recorded.counter("usage_input_bytes_total", &anonymous),
+            4050
+        );
+        assert_eq!(
+            recorded.counter("usage_conversion_milliseconds_total", &anonymous),
+            2000
+        );
+        assert_eq!(
+            recorded.counter("usage_failures_total", &[("api_key", "reporting")]),
+            1
+        );
+    }
+
+    #[test]
+    fn test_take_resets() {
+        let ledger = UsageLedger::new();
+        ledger.record_success(ANONYMOUS, 1000, 400, Duration::from_secs(1));
+
+        let records = ledger.take();
+        assert_eq!(records.len(), 1);
+        let value = serde_json::to_value(&records[0]).unwrap();
+        assert_eq!(value["type"], "usage");
+        assert_eq!(value["api_key"], ANONYMOUS);
+        assert_eq!(value["successes"], 1);
+        assert_eq!(value["output_bytes"], 400);
+        assert_eq!(value["period_end"], ledger.report().since);
+
+        assert!(ledger.report().keys.is_empty());
+        assert!(ledger.take().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_export_writes_to_the_audit_sink() {
+        let sink = Arc::new(MemorySink::default());
+        let state = AppState::builder()
+            .config(Config {
+                usage_export_interval: Some(Duration::from_secs(3600)),
+                ..config::get().clone()
+            })
+            .auditor(Auditor::new(sink.clone(), FailureMode::Open))
+            .build();
+        state.usage().record_failure(ANONYMOUS, 10);
+
+        export(&state).await;
+        let exported = sink.usage.lock().unwrap().clone();
+        assert_eq!(exported.len(), 1);
+        assert_eq!(exported[0].api_key, ANONYMOUS);
+        assert_eq!(exported[0].usage.failures, 1);
+        assert!(sink.records.lock().unwrap().is_empty());
+        assert!(state.usage().report().keys.is_empty());
+    }
+}
@@ -0,0 +1,67 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+use crate::{
+    config::Config, converter::ConversionOptions, error::LibreOfficeError, libreoffice::InputFile,
+    state::AppState,
+};
+
+// Set once the warmup conversion finished, successfully or not
+static WARMUP_DONE: AtomicBool = AtomicBool::new(false);
+
+// Set when the warmup conversion succeeded
+static WARMED_UP: AtomicBool = AtomicBool::new(false);
+
+/// Whether a warmup conversion has completed successfully
+pub fn is_warmed_up() -> bool {
+    WARMED_UP.load(Ordering::SeqCst)
+}
+
+/// Whether the service is still waiting for the warmup conversion `config` asks for
+pub fn is_pending(config: &Config) -> bool {
+    config.warmup && !WARMUP_DONE.load(Ordering::SeqCst)
+}
+
+/// Runs a tiny txt -> pdf conversion with the converter of `state` in the
+/// background so LibreOffice builds its profile and font cache before the first
+/// real request
+pub fn spawn_warmup(state: Arc<AppState>) {
+    if !state.config().warmup {
+        return;
+    }
+
+    tokio::spawn(async move {
+        tracing::info!("Warming up LibreOffice...");
+        let started = Instant::now();
+
+        let mut text: &[u8] = b"LibreOffice warmup";
+        let result = match InputFile::from_reader(&mut text, &state.config().work_dir).await {
+            Ok(input) => {
+                state
+                    .converter()
+                    .convert(
+                        input,
+                        &"txt".parse().expect("txt is a supported input format"),
+                        &"pdf".parse().expect("pdf is a supported output format"),
+                        &ConversionOptions::default(),
+                    )
+                    .await
+            }
+            Err(e) => Err(LibreOfficeError::from_io(e)),
+        };
+
+        match result {
+            Ok(_) => {
+                WARMED_UP.store(true, Ordering::SeqCst);
+                tracing::info!("LibreOffice warmup completed in {:?}", started.elapsed());
+            }
+            Err(e) => tracing::error!(
+                "LibreOffice warmup failed after {:?}: {}",
+                started.elapsed(),
+                e
+            ),
+        }
+        WARMUP_DONE.store(true, Ordering::SeqCst);
+    });
+}
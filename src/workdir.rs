@@ -0,0 +1,301 @@
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tempfile::TempDir;
+
+use crate::{
+    config::Config,
+    error::{LibreOfficeError, Result},
+};
+
+/// Prefix of every per-conversion temp directory, used to find leftovers.
+/// The full name is `lo-rest-{unix timestamp}-{random}`.
+pub const TEMP_DIR_PREFIX: &str = "lo-rest-";
+
+/// How often the janitor looks for abandoned temp directories
+const JANITOR_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+// Temp directories of conversions that are still running
+static ACTIVE_DIRS: OnceLock<Mutex<HashSet<PathBuf>>> = OnceLock::new();
+
+fn active_dirs() -> &'static Mutex<HashSet<PathBuf>> {
+    ACTIVE_DIRS.get_or_init(|| Mutex::new(HashSet::new()))
+}
+
+/// Per-conversion temp directory, protected from the janitor while alive and
+/// removed on drop
+#[derive(Debug)]
+pub struct WorkDir {
+    temp_dir: TempDir,
+}
+
+impl WorkDir {
+    pub fn path(&self) -> &Path {
+        self.temp_dir.path()
+    }
+}
+
+impl Drop for WorkDir {
+    fn drop(&mut self) {
+        active_dirs().lock().unwrap().remove(self.temp_dir.path());
+    }
+}
+
+/// Permission error on the work dir itself, answered as `workdir_permission` by
+/// [`LibreOfficeError::from_io`]
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct NotWritable(std::io::Error);
+
+/// Marks permission and read-only filesystem errors of a work dir operation,
+/// other errors pass through
+pub fn not_writable(error: std::io::Error) -> std::io::Error {
+    match error.kind() {
+        std::io::ErrorKind::PermissionDenied | std::io::ErrorKind::ReadOnlyFilesystem => {
+            std::io::Error::new(error.kind(), NotWritable(error))
+        }
+        _ => error,
+    }
+}
+
+/// Creates a per-conversion temp directory in `dir`, the configured work dir
+pub fn create_temp_dir(dir: &Path) -> std::io::Result<WorkDir> {
+    let created = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    let temp_dir = tempfile::Builder::new()
+        .prefix(&format!("{}{}-", TEMP_DIR_PREFIX, created))
+        .tempdir_in(dir)
+        .map_err(not_writable)?;
+    active_dirs()
+        .lock()
+        .unwrap()
+        .insert(temp_dir.path().to_path_buf());
+
+    Ok(WorkDir { temp_dir })
+}
+
+/// Age of a temp directory from the timestamp in its name, falling back to its mtime
+fn dir_age(path: &Path) -> Option<Duration> {
+    let name = path.file_name()?.to_string_lossy();
+    let created = name
+        .strip_prefix(TEMP_DIR_PREFIX)
+        .and_then(|rest| rest.split('-').next())
+        .and_then(|timestamp| timestamp.parse::<u64>().ok())
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+
+    let created = match created {
+        Some(created) => created,
+        None => path.metadata().and_then(|meta| meta.modified()).ok()?,
+    };
+
+    SystemTime::now().duration_since(created).ok()
+}
+
+/// Total size of the files below `path`
+fn dir_size(path: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return 0;
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| match entry.metadata() {
+            Ok(meta) if meta.is_dir() => dir_size(&entry.path()),
+            Ok(meta) => meta.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+/// Temp directories currently in the work dir and the bytes they hold
+pub fn usage(dir: &Path) -> (usize, u64) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return (0, 0);
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with(TEMP_DIR_PREFIX)
+        })
+        .fold((0, 0), |(dirs, bytes), entry| {
+            (dirs + 1, bytes + dir_size(&entry.path()))
+        })
+}
+
+/// Free bytes available to unprivileged users on the filesystem holding `path`
+pub fn available_space(path: &Path) -> std::io::Result<u64> {
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Whether files can be created in `dir`
+pub fn is_writable(dir: &Path) -> bool {
+    tempfile::tempfile_in(dir).is_ok()
+}
+
+/// Fails with [`LibreOfficeError::InsufficientStorage`] unless the work dir can hold
+/// the output expected for an input of `input_len` bytes
+pub fn ensure_free_space(config: &Config, input_len: u64) -> Result<()> {
+    let needed = input_len.saturating_mul(config.free_space_multiplier);
+    let available = available_space(&config.work_dir).map_err(LibreOfficeError::from_io)?;
+
+    if available < needed {
+        tracing::warn!(
+            "Not enough space in {:?}: {} bytes needed, {} available",
+            config.work_dir,
+            needed,
+            available
+        );
+        return Err(LibreOfficeError::InsufficientStorage);
+    }
+
+    Ok(())
+}
+
+/// Removes temp directories older than `max_age` that no running conversion uses
+pub fn sweep_stale_dirs(dir: &Path, max_age: Duration) -> usize {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+
+    let mut removed = 0;
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        if !entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(TEMP_DIR_PREFIX)
+            || active_dirs().lock().unwrap().contains(&path)
+        {
+            continue;
+        }
+
+        let Some(age) = dir_age(&path).filter(|age| *age > max_age) else {
+            continue;
+        };
+
+        let size = dir_size(&path);
+        if std::fs::remove_dir_all(&path).is_ok() {
+            tracing::warn!(
+                "Reclaimed abandoned temp directory {:?} ({} bytes, {}s old)",
+                path,
+                size,
+                age.as_secs()
+            );
+            metrics::counter!("workdir_reclaimed_dirs_total").increment(1);
+            metrics::counter!("workdir_reclaimed_bytes_total").increment(size);
+            removed += 1;
+        }
+    }
+
+    removed
+}
+
+/// Periodically reclaims temp directories in `work_dir` abandoned for over `max_age`
+pub fn spawn_janitor(work_dir: PathBuf, max_age: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(JANITOR_INTERVAL);
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+            let work_dir = work_dir.clone();
+            let _ = tokio::task::spawn_blocking(move || sweep_stale_dirs(&work_dir, max_age)).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_available_space() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(available_space(dir.path()).unwrap() > 0);
+        assert!(available_space(Path::new("/nonexistent/path")).is_err());
+    }
+
+    #[test]
+    fn test_usage_counts_temp_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let temp_dir = create_temp_dir(dir.path()).unwrap();
+        std::fs::write(temp_dir.path().join("document.pdf"), b"12345").unwrap();
+        std::fs::write(dir.path().join("unrelated"), b"ignored").unwrap();
+
+        assert_eq!(usage(dir.path()), (1, 5));
+    }
+
+    #[test]
+    fn test_read_only_work_dir_is_told_apart() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::set_permissions(dir.path(), std::fs::Permissions::from_mode(0o555)).unwrap();
+        match create_temp_dir(dir.path()) {
+            Err(e) => assert_eq!(LibreOfficeError::from_io(e).code(), "workdir_permission"),
+            // Root ignores the mode bits
+            Ok(_) => assert_eq!(unsafe { libc::geteuid() }, 0),
+        }
+    }
+
+    #[test]
+    fn test_is_writable() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(is_writable(dir.path()));
+        assert!(!is_writable(Path::new("/nonexistent/path")));
+    }
+
+    #[test]
+    fn test_sweep_only_removes_prefixed_stale_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let abandoned = dir.path().join("lo-rest-1000-abc");
+        std::fs::create_dir(&abandoned).unwrap();
+        std::fs::write(abandoned.join("document.docx"), b"leftover").unwrap();
+        std::fs::create_dir(dir.path().join("other")).unwrap();
+
+        assert_eq!(sweep_stale_dirs(dir.path(), Duration::from_secs(60)), 1);
+        assert!(!abandoned.exists());
+        assert!(dir.path().join("other").exists());
+    }
+
+    #[test]
+    fn test_sweep_skips_active_and_recent_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let active = create_temp_dir(dir.path()).unwrap();
+
+        // Recent by its name timestamp
+        assert_eq!(sweep_stale_dirs(dir.path(), Duration::from_secs(60)), 0);
+        // Old enough, but still referenced by a running conversion
+        std::thread::sleep(Duration::from_millis(1100));
+        assert_eq!(sweep_stale_dirs(dir.path(), Duration::ZERO), 0);
+        assert!(active.path().exists());
+
+        let path = active.path().to_path_buf();
+        drop(active);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_dir_age_from_name() {
+        let age = dir_age(Path::new("/tmp/lo-rest-1000-abc")).unwrap();
+        assert!(age > Duration::from_secs(60 * 60 * 24 * 365));
+    }
+}
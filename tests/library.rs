@@ -0,0 +1,112 @@
+//! The crate embedded the way another service would, through its public API only
+
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use axum::{
+    body::{Body, to_bytes},
+    http::{Request, StatusCode},
+};
+use libreoffice_rest::{
+    AppState, ConversionOptions, ConversionOutput, ConvertedOutput, Converter, InputFile,
+    OutputFile, build_router,
+    detect_filetype::{self, Confidence, FileType},
+    error::{LibreOfficeError, Result},
+    formats::{InputFormat, OutputFormat},
+};
+use tower::ServiceExt;
+
+const BOUNDARY: &str = "library-test-boundary";
+
+/// Converter of its own, upper-casing text instead of running LibreOffice
+#[derive(Default)]
+struct Shouting {
+    conversions: Mutex<Vec<(String, String)>>,
+}
+
+#[async_trait]
+impl Converter for Shouting {
+    async fn convert(
+        &self,
+        input: InputFile,
+        from: &InputFormat,
+        to: &OutputFormat,
+        _options: &ConversionOptions,
+    ) -> Result<ConversionOutput> {
+        self.conversions
+            .lock()
+            .unwrap()
+            .push((from.to_string(), to.to_string()));
+        let text = tokio::fs::read_to_string(input.path()).await?;
+        if text.contains("secret") {
+            return Err(LibreOfficeError::PasswordProtected);
+        }
+        Ok(ConversionOutput::new(OutputFile {
+            name: format!("document.{}", to),
+            data: ConvertedOutput::Bytes(text.to_uppercase().into_bytes()),
+        }))
+    }
+}
+
+async fn post_convert(state: AppState, content: &str) -> (StatusCode, Vec<u8>) {
+    let body = format!(
+        "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"notes.txt\"\r\n\r\n{content}\r\n\
+         --{BOUNDARY}\r\nContent-Disposition: form-data; name=\"output_format\"\r\n\r\npdf\r\n\
+         --{BOUNDARY}--\r\n"
+    );
+    let request = Request::builder()
+        .method("POST")
+        .uri("/convert")
+        .header(
+            "content-type",
+            format!("multipart/form-data; boundary={BOUNDARY}"),
+        )
+        .body(Body::from(body))
+        .unwrap();
+    let response = build_router(state).oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, body.to_vec())
+}
+
+#[tokio::test]
+async fn test_router_serves_a_converter_of_its_own() {
+    let converter = Arc::new(Shouting::default());
+    let state = AppState::builder().converter(converter.clone()).build();
+
+    let (status, body) = post_convert(state, "hello").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, b"HELLO");
+    assert_eq!(
+        *converter.conversions.lock().unwrap(),
+        [("txt".to_string(), "pdf".to_string())]
+    );
+}
+
+#[tokio::test]
+async fn test_converter_errors_become_error_bodies() {
+    let converter = Arc::new(Shouting::default());
+    let state = AppState::builder().converter(converter).build();
+
+    let (status, body) = post_convert(state, "secret").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(error["code"], LibreOfficeError::PasswordProtected.code());
+    assert_eq!(error["retryable"], false);
+}
+
+#[tokio::test]
+async fn test_detection() {
+    let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/sample.docx");
+    let detected = detect_filetype::detect_file_type_from_bytes(&std::fs::read(&fixture).unwrap());
+    assert_eq!(detected.file_type, FileType::Word);
+    assert_eq!(detected.confidence, Confidence::Certain);
+
+    let mut reader = tokio::fs::File::open(&fixture).await.unwrap();
+    let input = InputFile::from_reader(&mut reader, &std::env::temp_dir())
+        .await
+        .unwrap();
+    assert_eq!(input.detect_file_type().await.unwrap(), detected);
+    assert!(!input.is_password_protected().await);
+}